@@ -0,0 +1,28 @@
+// The plumbing of rust-git as a library: the object database, refs, the index
+// and the commands built on them. The binary in `main.rs` is the command line
+// over it.
+
+pub use plumbing::{Blob, EntryType, ObjectReader, ObjectStore, ObjectType, Repository, Tree, TreeEntry};
+
+// Like `print!` and `println!`, but a write to the standard output that fails,
+// most often as the reader of a pipe went away, is an error to return rather
+// than a panic.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        write!(std::io::stdout(), $($arg)*)
+    };
+}
+
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        writeln!(std::io::stdout(), $($arg)*)
+    };
+}
+
+pub mod plumbing;
+
+// the module's name before its typo was fixed
+#[deprecated(note = "renamed to `plumbing`")]
+pub mod plumming {
+    pub use super::plumbing::*;
+}
@@ -0,0 +1,692 @@
+use super::commit::Commit;
+use super::commit_graph::CommitGraph;
+use super::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// Parent links as history walks see them: those `info/grafts` gives a
+// commit, none for a shallow commit, whose parents were never fetched, and
+// read from the commit-graph when it has the commit. Like git, the graph is
+// left alone when grafts, shallow commits or replace refs make the history
+// differ from what it describes. Walks of what to send another repository
+// read the commits as stored instead, replace refs or not.
+pub struct Parents<'a> {
+    repo: &'a Repository,
+    grafts: HashMap<String, Vec<String>>,
+    shallow: HashSet<String>,
+    graph: Option<CommitGraph>,
+    stored: bool,
+}
+
+// `info/grafts`, one `<commit> <parent>...` line per commit whose parents are
+// replaced
+fn grafts(repo: &Repository) -> std::io::Result<HashMap<String, Vec<String>>> {
+    let grafts = match fs::read_to_string(repo.common_dir().join("info/grafts")) {
+        Ok(grafts) => grafts,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut parsed = HashMap::new();
+    for line in grafts.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let mut ids = line.split_whitespace().map(str::to_string);
+        let commit = ids.next().unwrap_or_default();
+        let parents: Vec<String> = ids.collect();
+        if !is_object_id(&commit) || !parents.iter().all(|parent| is_object_id(parent)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("bad graft data: {}", line),
+            ));
+        }
+        parsed.insert(commit, parents);
+    }
+    Ok(parsed)
+}
+
+impl<'a> Parents<'a> {
+    pub fn new(repo: &'a Repository) -> std::io::Result<Self> {
+        Self::open(repo, false)
+    }
+
+    // the parents of the commits as stored, which replace refs leave alone
+    pub fn stored(repo: &'a Repository) -> std::io::Result<Self> {
+        Self::open(repo, true)
+    }
+
+    fn open(repo: &'a Repository, stored: bool) -> std::io::Result<Self> {
+        let grafts = grafts(repo)?;
+        let shallow = repo.shallow_commits()?;
+        let enabled = repo.config().get_bool("core.commitgraph")?.unwrap_or(true);
+        let replaced = !stored && repo.replaces_objects();
+        let graph = match grafts.is_empty() && shallow.is_empty() && !replaced && enabled {
+            true => match CommitGraph::open(&repo.objects_dir()) {
+                Ok(graph) => graph,
+                // the graph only saves reading the commits, which are all there
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+        Ok(Parents { repo, grafts, shallow, graph, stored })
+    }
+
+    fn commit(&self, sha: &str) -> std::io::Result<Commit> {
+        match self.stored {
+            true => Commit::read_stored(self.repo, sha),
+            false => Commit::read(self.repo, sha),
+        }
+    }
+
+    pub fn of(&self, sha: &str) -> std::io::Result<Vec<String>> {
+        if let Some(parents) = self.grafts.get(sha) {
+            return Ok(parents.clone());
+        }
+        if self.shallow.contains(sha) {
+            return Ok(Vec::new());
+        }
+        if let Some(graph) = &self.graph {
+            if let Some(position) = graph.position(sha) {
+                let parents = graph.commit(position)?.parents;
+                return Ok(parents.into_iter().map(|parent| graph.id(parent)).collect());
+            }
+        }
+        Ok(self.commit(sha)?.parents)
+    }
+}
+
+// Every commit reachable from `sha`, `sha` included.
+pub fn ancestors(repo: &Repository, sha: &str) -> std::io::Result<HashSet<String>> {
+    walk(&Parents::new(repo)?, sha)
+}
+
+// The commits reachable from `tips` and not from `excluded`, the most
+// recently committed first as git lists them. Commits committed at the same
+// time come in the order they were reached. The commits are read as stored,
+// as what is packed for another repository.
+pub fn rev_list(repo: &Repository, tips: &[String], excluded: &[String]) -> std::io::Result<Vec<String>> {
+    Ok(dated_walk(&Parents::stored(repo)?, tips, excluded)?.0)
+}
+
+// Walks from `tips` and `excluded` at once, most recently committed first,
+// hiding the parents of every hidden commit: the excluded ones to start
+// with. The walk stops once only hidden commits are left to visit, so the
+// history both sides share isn't walked whole. Returns the commits left
+// visible in the order they were met, and the hidden commits met.
+fn dated_walk(
+    parents: &Parents,
+    tips: &[String],
+    excluded: &[String],
+) -> std::io::Result<(Vec<String>, HashSet<String>)> {
+    let mut queue = BinaryHeap::new();
+    let mut seen = HashSet::new();
+    let mut walked = HashSet::new();
+    let mut hidden: HashSet<String> = excluded.iter().cloned().collect();
+    let mut reached = 0;
+    let mut pending: Vec<String> = tips.iter().chain(excluded).cloned().collect();
+    let mut commits = Vec::new();
+    loop {
+        for sha in pending.drain(..) {
+            if seen.insert(sha.clone()) {
+                queue.push((parents.commit(&sha)?.committer.time, Reverse(reached), sha));
+                reached += 1;
+            }
+        }
+        if queue.iter().all(|(_, _, sha)| hidden.contains(sha)) {
+            break;
+        }
+        let (_, _, sha) = queue.pop().expect("a visible commit is queued");
+        pending = parents.of(&sha)?;
+        if hidden.contains(&sha) {
+            // commits walked already, out of date order, are hidden too
+            let mut hiding = pending.clone();
+            while let Some(parent) = hiding.pop() {
+                if hidden.insert(parent.clone()) && walked.contains(&parent) {
+                    hiding.extend(parents.of(&parent)?);
+                }
+            }
+        } else {
+            commits.push(sha.clone());
+        }
+        walked.insert(sha);
+    }
+    commits.retain(|sha| !hidden.contains(sha));
+    Ok((commits, hidden))
+}
+
+// The committer dates `--since` and `--until` limit a history to, both
+// included.
+#[derive(Default)]
+pub struct DateRange {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl DateRange {
+    pub fn contains(&self, time: i64) -> bool {
+        !matches!(self.since, Some(since) if time < since) && !matches!(self.until, Some(until) if time > until)
+    }
+
+    // The commits of `commits` committed within the range.
+    pub fn filter(&self, repo: &Repository, commits: Vec<String>) -> std::io::Result<Vec<String>> {
+        let mut within = Vec::new();
+        for sha in commits {
+            if self.contains(Commit::read(repo, &sha)?.committer.time) {
+                within.push(sha);
+            }
+        }
+        Ok(within)
+    }
+}
+
+// The `--author` and `--grep` patterns limiting a history. A commit is kept
+// when its `Name <email>` contains one of the author patterns and its
+// message one of the grep patterns, either kind passing when none are given.
+#[derive(Default)]
+pub struct Patterns {
+    pub authors: Vec<String>,
+    pub greps: Vec<String>,
+}
+
+impl Patterns {
+    pub fn matches(&self, commit: &Commit) -> bool {
+        let author = format!("{} <{}>", commit.author.name, commit.author.email);
+        let any = |patterns: &[String], text: &str| patterns.is_empty() || patterns.iter().any(|pattern| text.contains(pattern.as_str()));
+        any(&self.authors, &author) && any(&self.greps, &commit.message)
+    }
+}
+
+// A commit and the parents of it a history shows, as `log` walks them.
+pub type Shown = (String, Vec<String>);
+
+// The commits `rev_list` gives, replace refs put in place, each with its
+// parents that aren't excluded.
+pub fn history(repo: &Repository, tips: &[String], excluded: &[String]) -> std::io::Result<Vec<Shown>> {
+    let parents = Parents::new(repo)?;
+    let (commits, hidden) = dated_walk(&parents, tips, excluded)?;
+    let mut history = Vec::new();
+    for sha in commits {
+        let of = parents.of(&sha)?.into_iter().filter(|parent| !hidden.contains(parent)).collect();
+        history.push((sha, of));
+    }
+    Ok(history)
+}
+
+// The commits of `rev_list` that change something at one of `paths`, in its
+// order, their parents rewritten to the nearest such ancestors. Like git's default history simplification, a merge with
+// the same paths as one of its parents is only followed to that parent:
+// what the others did to the paths never made it.
+pub fn touching(
+    repo: &Repository,
+    tips: &[String],
+    excluded: &[String],
+    paths: &[String],
+) -> std::io::Result<Vec<Shown>> {
+    let parents = Parents::new(repo)?;
+    let visible: HashSet<String> = dated_walk(&parents, tips, excluded)?.0.into_iter().collect();
+    let at = |sha: &str| -> std::io::Result<Vec<Option<(String, String)>>> {
+        let tree = Commit::read(repo, sha)?.tree;
+        paths
+            .iter()
+            .map(|path| match repo.resolve_path(&tree, path) {
+                Ok(entry) => Ok(Some(entry)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            })
+            .collect()
+    };
+    let mut queue = BinaryHeap::new();
+    let mut seen = HashSet::new();
+    let mut reached = 0;
+    let mut pending = tips.to_vec();
+    let mut walked = Vec::new();
+    let mut followed: HashMap<String, Vec<String>> = HashMap::new();
+    let mut changing = HashSet::new();
+    loop {
+        for sha in pending.drain(..) {
+            if visible.contains(&sha) && seen.insert(sha.clone()) {
+                queue.push((Commit::read(repo, &sha)?.committer.time, Reverse(reached), sha));
+                reached += 1;
+            }
+        }
+        let (_, _, sha) = match queue.pop() {
+            Some(next) => next,
+            None => break,
+        };
+        let own = at(&sha)?;
+        let all = parents.of(&sha)?;
+        let mut same = None;
+        for parent in &all {
+            if at(parent)? == own {
+                same = Some(parent.clone());
+                break;
+            }
+        }
+        pending = match same {
+            Some(parent) => vec![parent],
+            None => {
+                if !all.is_empty() || own.iter().any(Option::is_some) {
+                    changing.insert(sha.clone());
+                }
+                all
+            }
+        };
+        followed.insert(sha.clone(), pending.clone());
+        walked.push(sha);
+    }
+    // commits changing nothing have a parent at most, the one followed, and
+    // excluded ones weren't walked
+    let nearest = |sha: &String| {
+        let mut sha = sha.clone();
+        while !changing.contains(&sha) {
+            sha = followed.get(&sha)?.first()?.clone();
+        }
+        Some(sha)
+    };
+    let mut shown = Vec::new();
+    for sha in walked.into_iter().filter(|sha| changing.contains(sha)) {
+        let mut rewritten: Vec<String> = Vec::new();
+        for parent in followed[&sha].iter().filter_map(nearest) {
+            if !rewritten.contains(&parent) {
+                rewritten.push(parent);
+            }
+        }
+        shown.push((sha, rewritten));
+    }
+    Ok(shown)
+}
+
+// `history` reordered so that every commit comes before its parents and a
+// line of history isn't interrupted by another: after a commit come the
+// commits only its last parent leads to, as `log --graph` shows them.
+pub fn topo_order(history: Vec<Shown>) -> Vec<Shown> {
+    let mut children: HashMap<String, usize> = HashMap::new();
+    for (_, parents) in &history {
+        for parent in parents {
+            *children.entry(parent.clone()).or_insert(0) += 1;
+        }
+    }
+    let count = history.len();
+    let mut commits: HashMap<String, Vec<String>> = HashMap::new();
+    let mut stack = Vec::new();
+    for (sha, parents) in history.into_iter().rev() {
+        if !children.contains_key(&sha) {
+            stack.push(sha.clone());
+        }
+        commits.insert(sha, parents);
+    }
+    let mut ordered = Vec::with_capacity(count);
+    while let Some(sha) = stack.pop() {
+        let parents = commits.remove(&sha).unwrap_or_default();
+        for parent in &parents {
+            if let Some(count) = children.get_mut(parent) {
+                *count -= 1;
+                if *count == 0 && commits.contains_key(parent) {
+                    stack.push(parent.clone());
+                }
+            }
+        }
+        ordered.push((sha, parents));
+    }
+    ordered
+}
+
+// The trees and blobs of `commits`, each with the path it was first met at
+// and `""` for a root tree, in the order a walk of the commits' trees
+// meets them. Submodule commits aren't objects of the repository. Like
+// `rev_list`, the walk reads the objects as stored.
+pub fn objects(repo: &Repository, commits: &[String]) -> std::io::Result<Vec<(String, String)>> {
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    for commit in commits {
+        tree_objects(repo, Commit::read_stored(repo, commit)?.tree, &mut seen, &mut objects)?;
+    }
+    Ok(objects)
+}
+
+// The trees being read are kept on a stack, each with the entry to go on
+// from, rather than the call stack, however deep they nest.
+fn tree_objects(
+    repo: &Repository,
+    sha: String,
+    seen: &mut HashSet<String>,
+    objects: &mut Vec<(String, String)>,
+) -> std::io::Result<()> {
+    let mut open: Vec<(Tree, usize, String)> = Vec::new();
+    let mut next = Some((sha, String::new()));
+    loop {
+        if let Some((sha, path)) = next.take() {
+            if seen.insert(sha.clone()) {
+                let blob = Blob::from_store(repo.objects(), &sha)?;
+                objects.push((sha, path.clone()));
+                let tree = Tree::try_pars(&blob)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+                open.push((tree, 0, path));
+            }
+        }
+        let (tree, at, path) = match open.last_mut() {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+        let entry = match tree.entries().get(*at) {
+            Some(entry) => entry,
+            None => {
+                open.pop();
+                continue;
+            }
+        };
+        *at += 1;
+        let entry_path = if path.is_empty() { entry.name.clone() } else { format!("{}/{}", path, entry.name) };
+        let sha = Blob::string_hash(&entry.sha);
+        match entry.entry_type {
+            EntryType::Tree => next = Some((sha, entry_path)),
+            EntryType::Gitlink => {}
+            _ if seen.insert(sha.clone()) => objects.push((sha, entry_path)),
+            _ => {}
+        }
+    }
+}
+
+fn walk(parents: &Parents, sha: &str) -> std::io::Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![sha.to_string()];
+    while let Some(sha) = pending.pop() {
+        if seen.contains(&sha) {
+            continue;
+        }
+        pending.extend(parents.of(&sha)?);
+        seen.insert(sha);
+    }
+    Ok(seen)
+}
+
+// The best common ancestors of `a` and `b`, in id order: the common ancestors
+// that aren't reachable from another one. With a commit-graph the walk stops as
+// soon as every commit left to visit is below a common ancestor.
+pub fn merge_bases(repo: &Repository, a: &str, b: &str) -> std::io::Result<Vec<String>> {
+    let parents = Parents::new(repo)?;
+    if let Some(graph) = &parents.graph {
+        if let (Some(a), Some(b)) = (graph.position(a), graph.position(b)) {
+            return graph_merge_bases(graph, a, b);
+        }
+    }
+    naive_merge_bases(&parents, a, b)
+}
+
+// How many commits `a` has that `b` hasn't, and how many `b` has that `a`
+// hasn't: what each gained since their merge bases.
+pub fn ahead_behind(repo: &Repository, a: &str, b: &str) -> std::io::Result<(usize, usize)> {
+    let parents = Parents::new(repo)?;
+    let mut common = HashSet::new();
+    for base in merge_bases(repo, a, b)? {
+        common.extend(walk(&parents, &base)?);
+    }
+    let gained = |sha: &str| -> std::io::Result<usize> { Ok(walk(&parents, sha)?.difference(&common).count()) };
+    Ok((gained(a)?, gained(b)?))
+}
+
+// Walks both histories whole. The parents of a common ancestor are common
+// ancestors too, so the ones that aren't a parent of another are the best.
+fn naive_merge_bases(parents: &Parents, a: &str, b: &str) -> std::io::Result<Vec<String>> {
+    let theirs = walk(parents, b)?;
+    let common: Vec<String> = walk(parents, a)?.into_iter().filter(|sha| theirs.contains(sha)).collect();
+    let mut shadowed = HashSet::new();
+    for sha in &common {
+        shadowed.extend(parents.of(sha)?);
+    }
+    let mut bases: Vec<String> = common.into_iter().filter(|sha| !shadowed.contains(sha)).collect();
+    bases.sort();
+    Ok(bases)
+}
+
+const FROM_A: u8 = 1;
+const FROM_B: u8 = 2;
+// below a common ancestor, so no better than it
+const STALE: u8 = 4;
+
+// git's paint_down_to_common: visits commits highest generation first, marking
+// which side reaches them. Every child of a commit has a higher generation, so
+// its marks are complete by the time it's visited, and once only stale commits
+// are left nothing better can be found.
+fn graph_merge_bases(graph: &CommitGraph, a: u32, b: u32) -> std::io::Result<Vec<String>> {
+    if a == b {
+        return Ok(vec![graph.id(a)]);
+    }
+    let mut marks: HashMap<u32, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for (position, mark) in [(a, FROM_A), (b, FROM_B)] {
+        marks.insert(position, mark);
+        queue.push((graph.commit(position)?.generation, position));
+    }
+    let mut visited = HashSet::new();
+    let mut candidates = Vec::new();
+    while queue.iter().any(|(_, position)| marks[position] & STALE == 0) {
+        let (_, position) = queue.pop().unwrap();
+        if !visited.insert(position) {
+            continue;
+        }
+        let mut mark = marks[&position];
+        if mark & (FROM_A | FROM_B) == FROM_A | FROM_B {
+            candidates.push(position);
+            mark |= STALE;
+        }
+        for parent in graph.commit(position)?.parents {
+            let marked = marks.entry(parent).or_insert(0);
+            if *marked & mark != mark {
+                *marked |= mark;
+                queue.push((graph.commit(parent)?.generation, parent));
+            }
+        }
+    }
+    // a candidate reached from another one was marked stale after it was found
+    let mut bases: Vec<String> = candidates
+        .into_iter()
+        .filter(|position| marks[position] & STALE == 0)
+        .map(|position| graph.id(position))
+        .collect();
+    bases.sort();
+    Ok(bases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_merge_bases() {
+        let dir = TempDir::new("ancestry");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let commit = |parents: &[&String], message: &str| test_commit(&repo, EMPTY_TREE, parents, 0, message);
+        // root - left - merge
+        //    \- right -/  \- after
+        let root = commit(&[], "root");
+        let left = commit(&[&root], "left");
+        let right = commit(&[&root], "right");
+        let merge = commit(&[&left, &right], "merge");
+        let after = commit(&[&merge], "after");
+        let bases = (
+            merge_bases(&repo, &left, &right).unwrap(),
+            merge_bases(&repo, &after, &right).unwrap(),
+            merge_bases(&repo, &root, &after).unwrap(),
+            ancestors(&repo, &after).unwrap().len(),
+        );
+        // history stops at shallow commits, their parents may not even exist
+        fs::write(dir.join("shallow"), format!("{}\n", merge)).unwrap();
+        fs::remove_file(dir.join("objects").join(&left[..2]).join(&left[2..])).unwrap();
+        let shallow = (ancestors(&repo, &after).unwrap().len(), merge_bases(&repo, &after, &merge).unwrap());
+        assert_eq!(bases, (vec![root.clone()], vec![right], vec![root], 5));
+        assert_eq!(shallow, (2, vec![merge]));
+    }
+
+    #[test]
+    fn testing_grafts() {
+        let dir = TempDir::new("grafts");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        fs::create_dir_all(dir.join("info")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let commit = |parents: &[&String], message: &str| test_commit(&repo, EMPTY_TREE, parents, 0, message);
+        // two unrelated histories, grafted together
+        let old = commit(&[], "old");
+        let root = commit(&[], "root");
+        let tip = commit(&[&root], "tip");
+        let other = commit(&[&old], "other");
+        commit_graph::write(&repo).unwrap();
+        let unrelated = merge_bases(&repo, &tip, &other).unwrap();
+        fs::write(dir.join("info/grafts"), format!("# grafted\n{} {}\n", root, old)).unwrap();
+        let grafted = (merge_bases(&repo, &tip, &other).unwrap(), rev_list(&repo, &[tip], &[]).unwrap().len());
+        fs::write(dir.join("info/grafts"), format!("{} nonsense\n", root)).unwrap();
+        let bad = Parents::new(&repo).is_err();
+
+        assert!(unrelated.is_empty());
+        assert_eq!(grafted, (vec![old], 3));
+        assert!(bad);
+    }
+
+    #[test]
+    fn testing_rev_list_objects() {
+        let repo = Repository::in_memory();
+        let blob = |content: &str| {
+            let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+            hash::write_to_database(&repo, &blob).unwrap();
+            blob.hash_string
+        };
+        let (shared, old, new) = (blob("shared\n"), blob("old\n"), blob("new\n"));
+        let file = |sha: &String| ("100644".to_string(), sha.clone());
+        let mut files = std::collections::BTreeMap::new();
+        files.insert("dir/shared".to_string(), file(&shared));
+        files.insert("top".to_string(), file(&old));
+        let first_tree = tree::write_tree(&repo, &files).unwrap();
+        files.insert("top".to_string(), file(&new));
+        files.insert("copy".to_string(), file(&shared));
+        files.insert("module".to_string(), ("160000".to_string(), old.clone()));
+        let second_tree = tree::write_tree(&repo, &files).unwrap();
+        let commit = |tree: &String, parents: Vec<String>, time: i64| test_commit(&repo, tree, &parents, time, "m");
+        // the side branch is committed last, so it's listed before the main line
+        let root = commit(&first_tree, vec![], 1);
+        let main = commit(&second_tree, vec![root.clone()], 2);
+        let side = commit(&first_tree, vec![root.clone()], 3);
+        let commits = rev_list(&repo, &[main.clone(), side.clone()], &[]).unwrap();
+        let objects = objects(&repo, &commits).unwrap();
+
+        assert_eq!(commits, vec![side, main, root]);
+        let dir_tree = objects[1].0.clone();
+        let expected = vec![
+            (first_tree, ""),
+            (dir_tree, "dir"),
+            (shared, "dir/shared"),
+            (old, "top"),
+            (second_tree, ""),
+            (new, "top"),
+        ];
+        let expected: Vec<(String, String)> = expected.into_iter().map(|(sha, path)| (sha, path.to_string())).collect();
+        assert_eq!(objects, expected);
+    }
+
+    #[test]
+    fn testing_author_and_grep_patterns() {
+        let commit = Commit::parse(b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                    author Alice <alice@example.com> 0 +0000\ncommitter Bob <bob@b> 0 +0000\n\nFix the bug\n").unwrap();
+        let patterns = |authors: &[&str], greps: &[&str]| Patterns {
+            authors: authors.iter().map(|pattern| pattern.to_string()).collect(),
+            greps: greps.iter().map(|pattern| pattern.to_string()).collect(),
+        };
+        assert!(patterns(&[], &[]).matches(&commit));
+        assert!(patterns(&["example.com"], &["bug", "feature"]).matches(&commit));
+        assert!(!patterns(&["Bob"], &[]).matches(&commit));
+        assert!(!patterns(&["Alice"], &["feature"]).matches(&commit));
+    }
+
+    #[test]
+    fn testing_history_of_paths() {
+        let repo = Repository::in_memory();
+        let tree = |files: &[(&str, &str)]| {
+            let mut entries = std::collections::BTreeMap::new();
+            for (path, content) in files {
+                let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+                hash::write_to_database(&repo, &blob).unwrap();
+                entries.insert(path.to_string(), ("100644".to_string(), blob.hash_string));
+            }
+            tree::write_tree(&repo, &entries).unwrap()
+        };
+        let commit = |tree: String, parents: Vec<String>, time: i64| test_commit(&repo, &tree, &parents, time, "m");
+        // the merge keeps the main line's `f`, so the side's change to it is
+        // left out, and only `g` changes on the other branch
+        let root = commit(tree(&[("f", "1")]), vec![], 1);
+        let main = commit(tree(&[("f", "2")]), vec![root.clone()], 2);
+        let side = commit(tree(&[("f", "3")]), vec![root.clone()], 3);
+        let other = commit(tree(&[("f", "1"), ("g", "1")]), vec![root.clone()], 4);
+        let merge = commit(tree(&[("f", "2")]), vec![main.clone(), side], 5);
+        let tip = commit(tree(&[("f", "2"), ("g", "1")]), vec![merge, other.clone()], 6);
+        let tips = [tip.clone()];
+        let of_f = touching(&repo, &tips, &[], &["f".to_string()]).unwrap();
+        let of_g = touching(&repo, &tips, &[], &["g".to_string()]).unwrap();
+        let all = topo_order(history(&repo, &tips, &[]).unwrap());
+
+        assert_eq!(of_f, vec![(main.clone(), vec![root.clone()]), (root.clone(), vec![])]);
+        assert_eq!(of_g, vec![(other.clone(), vec![])]);
+        let order: Vec<&String> = all.iter().map(|(sha, _)| sha).collect();
+        assert_eq!(order[..2], [&tip, &other]);
+        assert_eq!(order.last(), Some(&&root));
+    }
+
+    #[test]
+    fn testing_merge_bases_with_commit_graph() {
+        let dir = TempDir::new("generations");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        // a tangle of branches and merges, criss-crosses and octopuses included
+        let mut random = 12345u32;
+        let mut next = |bound: usize| {
+            random = random.wrapping_mul(1103515245).wrapping_add(12345);
+            (random >> 16) as usize % bound
+        };
+        let mut commits: Vec<String> = Vec::new();
+        for i in 0..30 {
+            let mut parents: Vec<String> = Vec::new();
+            for _ in 0..[0, 1, 1, 1, 2, 2, 3][if i == 0 { 0 } else { 1 + next(6) }] {
+                let parent = commits[commits.len() - 1 - next(commits.len().min(8))].clone();
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+            let sha = test_commit(&repo, EMPTY_TREE, &parents, i, &format!("{}", i));
+            refs::update(&repo, &format!("refs/heads/b{}", i), &sha, None).unwrap();
+            commits.push(sha);
+        }
+        let naive: Vec<Vec<String>> = commits
+            .iter()
+            .flat_map(|a| commits.iter().map(move |b| (a, b)))
+            .map(|(a, b)| merge_bases(&repo, a, b).unwrap())
+            .collect();
+        commit_graph::write(&repo).unwrap();
+        let graph = CommitGraph::open(&repo.objects_dir()).unwrap().unwrap();
+        let with_graph: Vec<Vec<String>> = commits
+            .iter()
+            .flat_map(|a| commits.iter().map(move |b| (a, b)))
+            .map(|(a, b)| graph_merge_bases(&graph, graph.position(a).unwrap(), graph.position(b).unwrap()).unwrap())
+            .collect();
+        assert!(naive.iter().any(|bases| bases.len() > 1));
+        assert_eq!(with_graph, naive);
+    }
+
+    #[test]
+    fn testing_ahead_behind() {
+        let repo = Repository::in_memory();
+        let commit = |parents: &[&String], message: &str| test_commit(&repo, EMPTY_TREE, parents, 0, message);
+        // the topic merged the main line once and moved on
+        let root = commit(&[], "root");
+        let main = commit(&[&root], "main");
+        let topic = commit(&[&root], "topic");
+        let merged = commit(&[&topic, &main], "merge");
+        let main_tip = commit(&[&main], "main again");
+        let topic_tip = commit(&[&merged], "topic again");
+        let counts = (
+            ahead_behind(&repo, &topic_tip, &main_tip).unwrap(),
+            ahead_behind(&repo, &main, &topic_tip).unwrap(),
+            ahead_behind(&repo, &root, &root).unwrap(),
+        );
+
+        assert_eq!(counts, ((3, 1), (0, 3), (0, 0)));
+    }
+}
@@ -0,0 +1,256 @@
+use super::*;
+
+// One `@@ -old_start,old_count +new_start,new_count @@` hunk. Lines keep their
+// `\n`, the last line of a file without a trailing newline doesn't have one.
+pub struct Hunk {
+    old_start: usize,
+    new_start: usize,
+    lines: Vec<(u8, String)>,
+}
+
+// The hunks for one file, `None` paths stand for `/dev/null`.
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    // Swaps the sides of the patch, turning additions into deletions.
+    pub fn reverse(&mut self) {
+        std::mem::swap(&mut self.old_path, &mut self.new_path);
+        for hunk in &mut self.hunks {
+            std::mem::swap(&mut hunk.old_start, &mut hunk.new_start);
+            for (kind, _) in &mut hunk.lines {
+                *kind = match *kind {
+                    b'-' => b'+',
+                    b'+' => b'-',
+                    kind => kind,
+                };
+            }
+        }
+    }
+
+    // Applies the hunks to `content`, finding each one by its context at or
+    // around the line it names. `path` is only used in the error.
+    pub fn apply_to(&self, path: &str, content: &str) -> std::io::Result<String> {
+        let old: Vec<&str> = content.split_inclusive('\n').collect();
+        let mut result = String::new();
+        let mut copied = 0;
+        for hunk in &self.hunks {
+            let expected: Vec<&str> = hunk
+                .lines
+                .iter()
+                .filter(|(kind, _)| *kind != b'+')
+                .map(|(_, line)| line.as_str())
+                .collect();
+            let wanted = hunk.old_start.saturating_sub(1).max(copied);
+            let at = find_hunk(&old, &expected, copied, wanted).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("patch failed: {}:{}", path, hunk.old_start),
+                )
+            })?;
+            old[copied..at].iter().for_each(|line| result.push_str(line));
+            for (kind, line) in &hunk.lines {
+                if *kind != b'-' {
+                    result.push_str(line);
+                }
+            }
+            copied = at + expected.len();
+        }
+        old[copied..].iter().for_each(|line| result.push_str(line));
+        Ok(result)
+    }
+}
+
+// The first position, trying `wanted` and then further and further away from it,
+// where `expected` matches `lines` without going back before `from`.
+fn find_hunk(lines: &[&str], expected: &[&str], from: usize, wanted: usize) -> Option<usize> {
+    let fits = |at: usize| at + expected.len() <= lines.len() && lines[at..at + expected.len()] == *expected;
+    for distance in 0..=lines.len() {
+        if wanted + distance <= lines.len() && fits(wanted + distance) {
+            return Some(wanted + distance);
+        }
+        if distance > 0 && distance <= wanted && wanted - distance >= from && fits(wanted - distance) {
+            return Some(wanted - distance);
+        }
+    }
+    None
+}
+
+fn corrupt(line: usize) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("corrupt patch at line {}", line),
+    )
+}
+
+// `a/file.txt` -> `file.txt`, `/dev/null` -> None
+fn patch_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or("").trim_end();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = match path.find('/') {
+        Some(slash) => &path[slash + 1..],
+        None => path,
+    };
+    Some(path.to_string())
+}
+
+// `-12,3` -> (12, 3), a missing count means 1
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let mut parts = range[1..].splitn(2, ',');
+    let start = parts.next()?.parse().ok()?;
+    let count = match parts.next() {
+        Some(count) => count.parse().ok()?,
+        None => 1,
+    };
+    Some((start, count))
+}
+
+// Parses the file patches of a unified diff. Text before a file's `---` line
+// (commit messages, `diff --git` and `index` lines) is skipped.
+pub fn parse(patch: &str) -> std::io::Result<Vec<FilePatch>> {
+    let lines: Vec<&str> = patch.split_inclusive('\n').collect();
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.starts_with("--- ") && i + 1 < lines.len() && lines[i + 1].starts_with("+++ ") {
+            files.push(FilePatch {
+                old_path: patch_path(&line[4..]),
+                new_path: patch_path(&lines[i + 1][4..]),
+                hunks: Vec::new(),
+            });
+            i += 2;
+            continue;
+        }
+        if !line.starts_with("@@ ") {
+            i += 1;
+            continue;
+        }
+        let file = files.last_mut().ok_or_else(|| corrupt(i + 1))?;
+        let mut ranges = line[3..].split(' ');
+        let (old_start, mut old_left) = ranges
+            .next()
+            .filter(|range| range.starts_with('-'))
+            .and_then(parse_range)
+            .ok_or_else(|| corrupt(i + 1))?;
+        let (new_start, mut new_left) = ranges
+            .next()
+            .filter(|range| range.starts_with('+'))
+            .and_then(parse_range)
+            .ok_or_else(|| corrupt(i + 1))?;
+        let mut hunk = Hunk { old_start, new_start, lines: Vec::new() };
+        i += 1;
+        while old_left > 0 || new_left > 0 || (i < lines.len() && lines[i].starts_with('\\')) {
+            let line = *lines.get(i).ok_or_else(|| corrupt(i + 1))?;
+            // an empty context line can lose its leading space in transit
+            let (kind, text) = match line.as_bytes()[0] {
+                b'\n' => (b' ', line),
+                kind => (kind, &line[1..]),
+            };
+            match kind {
+                b' ' if old_left > 0 && new_left > 0 => {
+                    old_left -= 1;
+                    new_left -= 1;
+                }
+                b'-' if old_left > 0 => old_left -= 1,
+                b'+' if new_left > 0 => new_left -= 1,
+                // `\ No newline at end of file` applies to the line before it
+                b'\\' => {
+                    let last = hunk.lines.last_mut().ok_or_else(|| corrupt(i + 1))?;
+                    if last.1.ends_with('\n') {
+                        last.1.pop();
+                    }
+                    i += 1;
+                    continue;
+                }
+                _ => return Err(corrupt(i + 1)),
+            }
+            hunk.lines.push((kind, text.to_string()));
+            i += 1;
+        }
+        file.hunks.push(hunk);
+    }
+    if files.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "no patch found in input",
+        ));
+    }
+    Ok(files)
+}
+
+// Applies `patch` to the files under `root`. Every file is patched in memory
+// before anything is written, so a hunk that doesn't apply leaves the tree
+// untouched; with `check` nothing is written at all.
+pub fn apply(root: &Path, patch: &str, reverse: bool, check: bool) -> std::io::Result<()> {
+    let mut files = parse(patch)?;
+    let mut results = Vec::new();
+    for file in &mut files {
+        if reverse {
+            file.reverse();
+        }
+        let content = match &file.old_path {
+            Some(path) => fs::read_to_string(root.join(path))?,
+            None => String::new(),
+        };
+        let name = file.old_path.as_ref().or(file.new_path.as_ref()).map_or("/dev/null", String::as_str);
+        let patched = file.apply_to(name, &content)?;
+        results.push((file.old_path.clone(), file.new_path.clone(), patched));
+    }
+    if check {
+        return Ok(());
+    }
+    for (old_path, new_path, patched) in results {
+        match new_path {
+            Some(path) => {
+                let path = root.join(path);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, patched)?;
+            }
+            None => fs::remove_file(root.join(old_path.unwrap()))?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "diff --git a/f.txt b/f.txt\n\
+                --- a/f.txt\n\
+                +++ b/f.txt\n\
+                @@ -2,3 +2,3 @@ two\n \
+                three\n\
+                -four\n\
+                +FOUR\n \
+                five\n\
+                @@ -7 +7 @@\n\
+                -seven\n\
+                \\ No newline at end of file\n\
+                +SEVEN\n";
+
+    #[test]
+    fn testing_apply_and_reverse() {
+        let original = "one\nextra\nthree\nfour\nfive\nsix\nseven";
+        let patched = "one\nextra\nthree\nFOUR\nfive\nsix\nSEVEN\n";
+        let mut files = parse(PATCH).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].new_path.as_deref(), Some("f.txt"));
+        assert_eq!(files[0].apply_to("f.txt", original).unwrap(), patched);
+        // the hunk has moved down a line, and still applies by its context
+        let shifted = format!("zero\n{}", original);
+        assert_eq!(files[0].apply_to("f.txt", &shifted).unwrap(), format!("zero\n{}", patched));
+        let err = files[0].apply_to("f.txt", "something else\n").unwrap_err();
+        assert_eq!(err.to_string(), "patch failed: f.txt:2");
+        files[0].reverse();
+        assert_eq!(files[0].apply_to("f.txt", patched).unwrap(), original);
+    }
+}
@@ -0,0 +1,165 @@
+use super::wildmatch::wildmatch;
+use super::*;
+use std::collections::BTreeMap;
+
+// An attribute's state for a path, an unspecified attribute is just absent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Set,
+    Unset,
+    Value(String),
+}
+
+// A line of an attributes file: the pattern, relative to the directory
+// `base` holding the file, and the states it gives, `None` for `!attr`.
+struct Rule {
+    base: String,
+    pattern: String,
+    anchored: bool,
+    states: Vec<(String, Option<State>)>,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next()?;
+        // negative patterns are ignored, macro definitions aren't supported and
+        // attributes don't apply to directories
+        if pattern.starts_with('#') || pattern.starts_with('!') || pattern.starts_with("[attr]") || pattern.ends_with('/') {
+            return None;
+        }
+        let mut states = Vec::new();
+        for field in fields {
+            let (name, state) = match (field.strip_prefix('-'), field.strip_prefix('!'), field.split_once('=')) {
+                (Some(name), _, _) => (name, Some(State::Unset)),
+                (_, Some(name), _) => (name, None),
+                (_, _, Some((name, value))) => (name, Some(State::Value(value.to_string()))),
+                _ => (field, Some(State::Set)),
+            };
+            // the one built in macro
+            if name == "binary" && state == Some(State::Set) {
+                for unset in ["diff", "merge", "text"] {
+                    states.push((unset.to_string(), Some(State::Unset)));
+                }
+            }
+            states.push((name.to_string(), state));
+        }
+        Some(Rule {
+            base: base.to_string(),
+            anchored: pattern.contains('/'),
+            pattern: pattern.trim_start_matches('/').to_string(),
+            states,
+        })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let relative = match path.strip_prefix(&self.base) {
+            Some(relative) => relative,
+            None => return false,
+        };
+        let text = match self.anchored {
+            true => relative,
+            false => relative.rsplit('/').next().unwrap_or(relative),
+        };
+        wildmatch(self.pattern.as_bytes(), text.as_bytes(), false)
+    }
+}
+
+fn read_rules(file: &Path, base: &str, rules: &mut Vec<Rule>) -> std::io::Result<()> {
+    match fs::read_to_string(file) {
+        Ok(text) => rules.extend(text.lines().filter_map(|line| Rule::parse(line, base))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+// The attributes of `path` as git collects them: from `core.attributesFile`,
+// the `.gitattributes` files from the top of the working tree down to the
+// path's directory, then `info/attributes`, each line overriding the ones
+// before it.
+pub fn lookup(repo: &Repository, path: &str) -> std::io::Result<BTreeMap<String, State>> {
+    Ok(lookup_ordered(repo, path)?.into_iter().collect())
+}
+
+// The attributes of `path` in the order git lists them with `check-attr -a`,
+// that of their first definition: those of the `binary` macro come first,
+// then the others as the files are read, `info/attributes` before the
+// `.gitattributes` files below the top.
+pub fn lookup_ordered(repo: &Repository, path: &str) -> std::io::Result<Vec<(String, State)>> {
+    let mut top = Vec::new();
+    if let Some(global) = config::user_file(repo.config(), "core.attributesfile", "attributes") {
+        read_rules(&global, "", &mut top)?;
+    }
+    let root = repo.work_tree();
+    read_rules(&root.join(".gitattributes"), "", &mut top)?;
+    let mut info = Vec::new();
+    read_rules(&repo.common_dir().join("info/attributes"), "", &mut info)?;
+    let mut below = Vec::new();
+    let mut base = String::new();
+    let mut dirs: Vec<&str> = path.split('/').collect();
+    dirs.pop();
+    for dir in dirs {
+        base = format!("{}{}/", base, dir);
+        read_rules(&root.join(&base).join(".gitattributes"), &base, &mut below)?;
+    }
+
+    let mut attributes = BTreeMap::new();
+    for rule in top.iter().chain(&below).chain(&info).filter(|rule| rule.matches(path)) {
+        for (name, state) in &rule.states {
+            match state {
+                Some(value) => attributes.insert(name.clone(), value.clone()),
+                None => attributes.remove(name),
+            };
+        }
+    }
+    let mut defined: Vec<&str> = vec!["binary", "diff", "merge", "text"];
+    for rule in top.iter().chain(&info).chain(&below) {
+        for (name, _) in &rule.states {
+            if !defined.contains(&name.as_str()) {
+                defined.push(name);
+            }
+        }
+    }
+    Ok(defined.into_iter().filter_map(|name| attributes.remove(name).map(|state| (name.to_string(), state))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_attribute_lookup() {
+        let dir = TempDir::new("attributes");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::create_dir_all(git_dir.join("info")).unwrap();
+        fs::create_dir_all(dir.join("src/deep")).unwrap();
+        fs::write(git_dir.join("config"), "[core]\n\tattributesFile = /nonexistent\n").unwrap();
+        fs::write(dir.join(".gitattributes"), "* text=auto\n*.png binary\n/top eol=crlf\n# comment\n").unwrap();
+        fs::write(dir.join("src/.gitattributes"), "*.rs diff=rust -text\ndeep/*.rs !diff\n").unwrap();
+        fs::write(git_dir.join("info/attributes"), "src/deep/x.rs text\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let lookup = |path: &str| lookup(&repo, path).unwrap().into_iter().collect::<Vec<_>>();
+        let value = |value: &str| State::Value(value.to_string());
+        let png = lookup("src/a.png");
+        let rs = lookup("src/a.rs");
+        let deep = lookup("src/deep/x.rs");
+        let top = (lookup("top"), lookup("src/top"));
+
+        let attribute = |name: &str, value: State| (name.to_string(), value);
+        assert_eq!(
+            png,
+            vec![
+                attribute("binary", State::Set),
+                attribute("diff", State::Unset),
+                attribute("merge", State::Unset),
+                attribute("text", State::Unset),
+            ]
+        );
+        assert_eq!(rs, vec![attribute("diff", value("rust")), attribute("text", State::Unset)]);
+        assert_eq!(deep, vec![attribute("text", State::Set)]);
+        assert_eq!(top.0, vec![attribute("eol", value("crlf")), attribute("text", value("auto"))]);
+        assert_eq!(top.1, vec![attribute("text", value("auto"))]);
+    }
+}
@@ -0,0 +1,258 @@
+use super::commit::Commit;
+use super::tag::Tag;
+use super::*;
+
+const SIGNATURE: &str = "# v2 git bundle\n";
+const SIGNATURE_V3: &str = "# v3 git bundle\n";
+
+// What a bundle says before its pack: the commits a repository must have
+// to take it, each with its subject, and the refs it carries.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Header {
+    pub prerequisites: Vec<(String, String)>,
+    pub refs: Vec<(String, String)>,
+}
+
+// The refs the positive revisions of `revs` name, `HEAD` as itself, and
+// every ref and HEAD for `--all`. Object ids aren't refs.
+fn named_refs(repo: &Repository, revs: &[&str]) -> std::io::Result<Vec<(String, String)>> {
+    let mut refs = Vec::new();
+    for rev in revs {
+        let names: Vec<String> = match *rev {
+            "--all" => std::iter::once("HEAD".to_string()).chain(refs::list(repo)?.into_iter().map(|(name, _)| name)).collect(),
+            rev if rev.starts_with('^') => continue,
+            rev => match rev.split_once("...") {
+                Some((a, b)) => vec![a.to_string(), b.to_string()],
+                None => vec![rev.rsplit("..").next().unwrap_or(rev).to_string()],
+            },
+        };
+        for name in names {
+            let name = match name.as_str() {
+                "" | "HEAD" => "HEAD".to_string(),
+                _ => match refs::full_name(repo, &name)? {
+                    Some(full) => full,
+                    None => continue,
+                },
+            };
+            if let Some(sha) = refs::resolve(repo, &name)? {
+                if !refs.iter().any(|(known, _)| *known == name) {
+                    refs.push((name, sha));
+                }
+            }
+        }
+    }
+    Ok(refs)
+}
+
+// Writes to `path` a bundle of the refs `revs` name and the objects they
+// reach, like `bundle create`. With `A..B` or `^A`, what `A` reaches is
+// left out and the boundary commits become prerequisites; `--all` bundles
+// every ref.
+pub fn create(repo: &Repository, path: &Path, revs: &[&str]) -> std::io::Result<Header> {
+    let refs = named_refs(repo, revs)?;
+    if refs.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "refusing to create empty bundle"));
+    }
+    let walked: Vec<&str> = revs
+        .iter()
+        .filter(|rev| **rev != "--all")
+        .copied()
+        .chain(revs.contains(&"--all").then(|| refs.iter().map(|(name, _)| name.as_str())).into_iter().flatten())
+        .collect();
+    let range = revision::Range::parse(repo, &walked)?;
+    let commits = ancestry::rev_list(repo, &range.tips, &range.excluded)?;
+    let included: std::collections::HashSet<&String> = commits.iter().collect();
+    let mut prerequisites = Vec::new();
+    for sha in &commits {
+        for parent in Commit::read_stored(repo, sha)?.parents {
+            if !included.contains(&parent) && !prerequisites.iter().any(|(known, _)| *known == parent) {
+                let subject = Commit::read_stored(repo, &parent)?.subject();
+                prerequisites.push((parent, subject));
+            }
+        }
+    }
+    // the receiving side has what the prerequisites reach
+    let boundary: Vec<String> = prerequisites.iter().map(|(sha, _)| sha.clone()).collect();
+    let known: std::collections::HashSet<String> =
+        ancestry::objects(repo, &boundary)?.into_iter().map(|(sha, _)| sha).collect();
+    let mut ids = Vec::new();
+    for (_, sha) in &refs {
+        // annotated tags come along, down to what they point at
+        let mut sha = sha.clone();
+        let mut object = Blob::from_store(repo.objects(), &sha)?;
+        while object.object_type() == ObjectType::Tag {
+            ids.push(sha.clone());
+            sha = Tag::parse(&object.content)?.object;
+            object = Blob::from_store(repo.objects(), &sha)?;
+        }
+    }
+    ids.extend(commits.iter().cloned());
+    ids.extend(ancestry::objects(repo, &commits)?.into_iter().map(|(sha, _)| sha).filter(|sha| !known.contains(sha)));
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|sha| seen.insert(sha.clone()));
+
+    let mut out = Vec::new();
+    out.extend(SIGNATURE.as_bytes());
+    for (sha, subject) in &prerequisites {
+        out.extend(format!("-{} {}\n", sha, subject).into_bytes());
+    }
+    for (name, sha) in &refs {
+        out.extend(format!("{} {}\n", sha, name).into_bytes());
+    }
+    out.push(b'\n');
+    pack::write(repo, &ids, &mut out)?;
+    write_locked(path, &out)?;
+    Ok(Header { prerequisites, refs })
+}
+
+// The header of the bundle `data` and the pack following it. Version 3
+// bundles are taken too, as long as their capabilities don't ask for more
+// than a version 2 one.
+pub fn parse(data: &[u8]) -> std::io::Result<(Header, &[u8])> {
+    let invalid = |what: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid bundle: {}", what));
+    let rest = match data {
+        _ if data.starts_with(SIGNATURE.as_bytes()) => &data[SIGNATURE.len()..],
+        _ if data.starts_with(SIGNATURE_V3.as_bytes()) => &data[SIGNATURE_V3.len()..],
+        _ => return Err(invalid("not a v2 or v3 git bundle".to_string())),
+    };
+    let mut header = Header::default();
+    let mut at = 0;
+    loop {
+        let end = rest[at..].iter().position(|&b| b == b'\n').ok_or_else(|| invalid("header not terminated".to_string()))?;
+        let line = String::from_utf8_lossy(&rest[at..at + end]).into_owned();
+        at += end + 1;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(capability) = line.strip_prefix('@') {
+            if capability != "object-format=sha1" {
+                return Err(invalid(format!("unsupported capability '{}'", capability)));
+            }
+            continue;
+        }
+        let (sha, rest) = match line.strip_prefix('-') {
+            Some(prerequisite) => (prerequisite, true),
+            None => (line.as_str(), false),
+        };
+        let (sha, name) = sha.split_once(' ').unwrap_or((sha, ""));
+        if !is_object_id(sha) {
+            return Err(invalid(format!("bad line '{}'", line)));
+        }
+        match rest {
+            true => header.prerequisites.push((sha.to_string(), name.to_string())),
+            false if !name.is_empty() => header.refs.push((sha.to_string(), name.to_string())),
+            false => return Err(invalid(format!("ref without a name '{}'", line))),
+        }
+    }
+    // the refs are `(name, id)` like everywhere else
+    header.refs = header.refs.into_iter().map(|(sha, name)| (name, sha)).collect();
+    Ok((header, &rest[at..]))
+}
+
+// Stores the objects of the bundle at `path` in the repository, which must
+// have its prerequisites, and returns the bundle's header; the refs aren't
+// updated, as in `bundle unbundle`.
+pub fn unbundle(repo: &Repository, path: &Path) -> std::io::Result<Header> {
+    let data = fs::read(path)?;
+    let (header, pack) = parse(&data)?;
+    let missing: Vec<String> = header
+        .prerequisites
+        .iter()
+        .filter(|(sha, _)| !repo.has_object(sha))
+        .map(|(sha, subject)| format!("{} {}", sha, subject))
+        .collect();
+    if !missing.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("repository lacks these prerequisite commits: {}", missing.join(", ")),
+        ));
+    }
+    pack::store(&repo.objects_dir(), pack)?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_bundles() {
+        let dir = TempDir::new("bundle");
+        fs::create_dir_all(dir.join("from/objects")).unwrap();
+        fs::create_dir_all(dir.join("to/objects")).unwrap();
+        let from = Repository::open(dir.join("from")).unwrap();
+        let commit = |message: &str, parents: Vec<String>| {
+            let mut files = std::collections::BTreeMap::new();
+            let blob = Blob::new(ObjectType::Blob, message.as_bytes().to_vec());
+            hash::write_to_database(&from, &blob).unwrap();
+            files.insert(format!("{}.txt", message), ("100644".to_string(), blob.hash_string));
+            let tree = tree::write_tree(&from, &files).unwrap();
+            test_commit(&from, &tree, &parents, 0, &format!("{}\n", message))
+        };
+        let first = commit("first", Vec::new());
+        let second = commit("second", vec![first.clone()]);
+        refs::update(&from, "refs/heads/main", &second, None).unwrap();
+        refs::update(&from, "refs/tags/start", &first, None).unwrap();
+        fs::write(dir.join("from/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let full = dir.join("full.bundle");
+        let all = create(&from, &full, &["--all"]).unwrap();
+        let incremental = dir.join("incremental.bundle");
+        let since = create(&from, &incremental, &["start..main"]).unwrap();
+        let empty = create(&from, &dir.join("empty.bundle"), &[&first]);
+
+        let to = Repository::open(dir.join("to")).unwrap();
+        let lacking = unbundle(&to, &incremental).map_err(|e| e.to_string());
+        let unbundled = unbundle(&to, &full).unwrap();
+        let second_tree = Commit::read(&to, &second).map(|commit| commit.tree);
+        let again = unbundle(&to, &incremental).unwrap();
+        let data = fs::read(&incremental).unwrap();
+        let parsed = parse(&data).map(|(header, pack)| (header, pack.len())).unwrap();
+
+        let refs = |names: &[(&str, &str)]| names.iter().map(|(name, sha)| (name.to_string(), sha.to_string())).collect::<Vec<_>>();
+        assert_eq!(all.refs, refs(&[("HEAD", &second), ("refs/heads/main", &second), ("refs/tags/start", &first)]));
+        assert!(all.prerequisites.is_empty());
+        assert_eq!(since.refs, refs(&[("refs/heads/main", &second)]));
+        assert_eq!(since.prerequisites, vec![(first.clone(), "first".to_string())]);
+        assert!(empty.is_err());
+        assert!(lacking.unwrap_err().contains(&first));
+        assert_eq!(unbundled, all);
+        assert!(second_tree.is_ok());
+        assert_eq!(again, since);
+        // the commit, its tree and the new blob, but not the first commit's blob
+        assert_eq!(parsed.0, since);
+        let (objects, _) = pack::index(&data[data.len() - parsed.1..]).unwrap();
+        assert_eq!(objects.len(), 3);
+    }
+
+    // A bundle holds the objects as stored, even when a replace ref stands in
+    // for one of them.
+    #[test]
+    fn testing_bundles_with_replace_refs() {
+        let dir = TempDir::new("replaced-bundle");
+        fs::create_dir_all(dir.join("from/objects")).unwrap();
+        fs::create_dir_all(dir.join("to/objects")).unwrap();
+        let from = Repository::open(dir.join("from")).unwrap();
+        let tree = from.write_tree(&[]).unwrap();
+        let first = test_commit(&from, &tree, &[] as &[&str], 0, "first\n");
+        let second = test_commit(&from, &tree, &[&first], 1, "second\n");
+        let replacement = test_commit(&from, &tree, &[] as &[&str], 2, "replacement\n");
+        refs::update(&from, "refs/heads/main", &second, None).unwrap();
+        refs::update(&from, &format!("refs/replace/{}", second), &replacement, None).unwrap();
+        let from = Repository::open(dir.join("from")).unwrap();
+
+        let path = dir.join("main.bundle");
+        let header = create(&from, &path, &["main"]).unwrap();
+        let data = fs::read(&path).unwrap();
+        let (_, pack) = parse(&data).unwrap();
+        let (objects, _) = pack::index(pack).unwrap();
+        let to = Repository::open(dir.join("to")).unwrap();
+        unbundle(&to, &path).unwrap();
+
+        assert!(header.prerequisites.is_empty());
+        // the two commits and the empty tree, not the replacement
+        assert_eq!(objects.len(), 3);
+        assert_eq!(Commit::read(&to, &second).unwrap().parents, vec![first.clone()]);
+        assert!(!to.has_object(&replacement));
+    }
+}
@@ -0,0 +1,105 @@
+use super::*;
+// This function takes a `sha` of an object and prints the content of the
+// file with the same `sha`. Blobs are copied out as they are inflated, as
+// they are, so big files are never held in memory.
+pub fn pretty_print(repo: &Repository, sha_object: &str) -> std::io::Result<()> {
+    let mut object = repo.open_object(sha_object)?;
+    if object.object_type() == ObjectType::Blob {
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        std::io::copy(&mut object, &mut out)?;
+        return out.flush();
+    }
+    // a tree is listed as `ls-tree` does, the others are already text
+    if object.object_type() == ObjectType::Tree {
+        let listing = tree::Listing {
+            name_only: false,
+            recursive: false,
+            trees_only: false,
+            long: false,
+            terminator: '\n',
+            paths: Vec::new(),
+        };
+        return tree::print_tree(repo, sha_object, &listing);
+    }
+    let blob = Blob::from_sha(repo, sha_object)?;
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&blob.content)?;
+    stdout.flush()
+}
+
+// Writes the inflated object, header included, to the standard output
+// without checking its type, for inspecting objects that fail to parse.
+pub fn raw_dump(repo: &Repository, sha_object: &str) -> std::io::Result<()> {
+    let raw = Blob::raw_from_sha(repo, sha_object)?;
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&raw)?;
+    stdout.flush()
+}
+
+// Writes the blob `sha` as checking it out at `path` would.
+pub fn filtered(repo: &Repository, sha: &str, path: &str) -> std::io::Result<()> {
+    let blob = Blob::from_sha(repo, sha)?;
+    if blob.object_type() != ObjectType::Blob {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is a {}, not a blob", sha, blob.object_type()),
+        ));
+    }
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&convert::to_worktree(repo, path, blob.content)?)?;
+    stdout.flush()
+}
+
+pub const BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+const BATCH_ATOMS: &[&str] = &["objectname", "objecttype", "objectsize", "rest"];
+
+// Prints a line in `format` (`BATCH_FORMAT` is git's default) for every object
+// named on the standard input, or for every object in the repository with
+// `all_objects`, followed by the content when `contents` is set. Unknown
+// names are reported as missing. When the format uses `%(rest)` an input
+// line is split after the name, otherwise it is the name whole.
+pub fn batch(repo: &Repository, contents: bool, format: &str, all_objects: bool) -> std::io::Result<()> {
+    let pieces = format::parse(format, BATCH_ATOMS)?;
+    let split = pieces.iter().any(|piece| matches!(piece, format::Piece::Atom(atom) if atom == "rest"));
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut print = |line: &str| -> std::io::Result<()> {
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(end) if split => (&line[..end], line[end..].trim_start()),
+            _ => (line, ""),
+        };
+        let object = revision::resolve(repo, name).and_then(|sha| Ok((repo.open_object(&sha)?, sha)));
+        match object {
+            Ok((mut object, sha)) => {
+                let line = format::expand(&pieces, |atom| {
+                    Ok(match atom {
+                        "objectname" => sha.clone(),
+                        "objecttype" => object.object_type().to_string(),
+                        "objectsize" => object.size().to_string(),
+                        _ => rest.to_string(),
+                    })
+                })?;
+                writeln!(out, "{}", line)?;
+                if contents {
+                    std::io::copy(&mut object, &mut out)?;
+                    writeln!(out)?;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => writeln!(out, "{} missing", name)?,
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    };
+    if all_objects {
+        for sha in repo.all_objects() {
+            print(&sha?)?;
+        }
+    } else {
+        for line in std::io::stdin().lock().lines() {
+            print(line?.trim())?;
+        }
+    }
+    Ok(())
+}
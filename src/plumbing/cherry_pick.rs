@@ -0,0 +1,129 @@
+use super::commit::Commit;
+use super::identity::Identity;
+use super::*;
+use std::collections::BTreeMap;
+
+// Applies the changes `rev` made to its parent on top of HEAD, with a three-way
+// merge of the trees, and commits them with the original author and message.
+// On conflicts the working tree is left with conflict markers, the index
+// with the paths unmerged, and CHERRY_PICK_HEAD and MERGE_MSG are written
+// for finishing the commit by hand.
+pub fn cherry_pick(repo: &Repository, rev: &str) -> std::io::Result<()> {
+    let sha = revision::resolve(repo, rev)?;
+    let picked = Commit::read(repo, &sha)?;
+    let base = match &picked.parents[..] {
+        [] => BTreeMap::new(),
+        [parent] => tree::files(repo, &Commit::read(repo, parent)?.tree)?,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("commit {} is a merge, which cherry-pick doesn't support", sha),
+            ))
+        }
+    };
+    let head = refs::resolve(repo, "HEAD")?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "HEAD does not point to a commit yet")
+    })?;
+    let head_commit = Commit::read(repo, &head)?;
+    let ours = tree::files(repo, &head_commit.tree)?;
+    let theirs = tree::files(repo, &picked.tree)?;
+
+    let subject = format!("{}... {}", &sha[..7], picked.subject());
+    let labels = merge::Labels { ours: "HEAD", theirs: &subject };
+    let merged = merge::merge_trees(repo, &base, &ours, &theirs, &labels)?;
+    if !merged.conflicts.is_empty() {
+        worktree::update(repo, &ours, &merged.files, &merged.conflicts)?;
+        merge::write_index(repo, &merged.files, &merged.conflicts)?;
+        refs::update(repo, "CHERRY_PICK_HEAD", &sha, None)?;
+        fs::write(repo.git_dir().join("MERGE_MSG"), &picked.message)?;
+        for conflict in &merged.conflicts {
+            outln!("CONFLICT ({}): Merge conflict in {}", conflict.kind, conflict.path)?;
+        }
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("could not apply {}, fix the conflicts and commit the result", subject),
+        ));
+    }
+
+    let tree = tree::write_tree(repo, &merged.files)?;
+    if tree == head_commit.tree {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("the changes of {} are already in HEAD", subject),
+        ));
+    }
+    worktree::update(repo, &ours, &merged.files, &[])?;
+    merge::write_index(repo, &merged.files, &[])?;
+    let commit = Commit {
+        tree,
+        parents: vec![head.clone()],
+        author: picked.author.clone(),
+        committer: Identity::from_env("COMMITTER", repo.config())?,
+        // a signature wouldn't match the new commit
+        extra_headers: picked.extra_headers.into_iter().filter(|(key, _)| key == "encoding").collect(),
+        message: picked.message,
+    };
+    let new_sha = commit.write(repo)?;
+    refs::update(repo, "HEAD", &new_sha, Some(&head))?;
+    let branch = match refs::symbolic_target(repo, "HEAD")? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => "detached HEAD".to_string(),
+    };
+    outln!("[{} {}] {}", branch, &new_sha[..7], commit.subject())?;
+    // like after `commit`, its status doesn't matter
+    hooks::run(repo, "post-commit", &[])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::index::Index;
+
+    // What a pick adds is staged with it, and a conflicting one leaves its
+    // path unmerged.
+    #[test]
+    fn testing_cherry_pick_index() {
+        let dir = TempDir::new("cherry-pick-index");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(git_dir.join("config"), "[user]\n\tname = a\n\temail = a@b\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let commit = |parent: Option<&str>, files: &[(&str, &str)]| {
+            let mut tree = match parent {
+                None => BTreeMap::new(),
+                Some(parent) => tree::files(&repo, &Commit::read(&repo, parent).unwrap().tree).unwrap(),
+            };
+            for (path, content) in files {
+                let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+                hash::write_to_database(&repo, &blob).unwrap();
+                tree.insert(path.to_string(), ("100644".to_string(), blob.hash_string));
+            }
+            let tree = tree::write_tree(&repo, &tree).unwrap();
+            test_commit(&repo, &tree, &parent.into_iter().collect::<Vec<_>>(), 0, "commit\n")
+        };
+        let base = commit(None, &[("a.txt", "a\n")]);
+        refs::update(&repo, "refs/heads/side", &commit(Some(&base), &[("new.txt", "new\n")]), None).unwrap();
+        refs::update(&repo, "refs/heads/other", &commit(Some(&base), &[("a.txt", "other\n")]), None).unwrap();
+        let ours = commit(Some(&base), &[("a.txt", "ours\n")]);
+        refs::update(&repo, "refs/heads/master", &ours, None).unwrap();
+        let files = tree::files(&repo, &Commit::read(&repo, &ours).unwrap().tree).unwrap();
+        worktree::update(&repo, &BTreeMap::new(), &files, &[]).unwrap();
+        merge::write_index(&repo, &files, &[]).unwrap();
+        let staged = |repo: &Repository| {
+            let index = Index::read(repo).unwrap();
+            index.entries.iter().map(|entry| format!("{} {}", entry.stage(), entry.path)).collect::<Vec<_>>()
+        };
+
+        cherry_pick(&repo, "side").unwrap();
+        let head = Commit::read(&repo, &refs::resolve(&repo, "HEAD").unwrap().unwrap()).unwrap();
+        let picked = (staged(&repo), Index::read(&repo).unwrap().files() == tree::files(&repo, &head.tree).unwrap());
+        let conflicted = cherry_pick(&repo, "other").is_err();
+        let unmerged = staged(&repo);
+
+        assert_eq!(picked, (vec!["0 a.txt".to_string(), "0 new.txt".to_string()], true));
+        assert!(conflicted);
+        assert_eq!(unmerged, vec!["1 a.txt", "2 a.txt", "3 a.txt", "0 new.txt"]);
+    }
+}
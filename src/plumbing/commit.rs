@@ -0,0 +1,442 @@
+use super::identity::Identity;
+use super::*;
+
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Identity,
+    pub committer: Identity,
+    // headers we don't interpret (`encoding`, `gpgsig`, `mergetag`...) in the
+    // order they appear, multi-line values joined with `\n`
+    pub extra_headers: Vec<(String, String)>,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn parse(content: &[u8]) -> std::io::Result<Self> {
+        let invalid = |msg: &str| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid commit: {}", msg))
+        };
+        let content = String::from_utf8_lossy(content);
+        let (headers, message) = match content.find("\n\n") {
+            Some(index) => (&content[..index], &content[index + 2..]),
+            None => (content.trim_end_matches('\n'), ""),
+        };
+        let mut fields: Vec<(String, String)> = Vec::new();
+        for line in headers.lines() {
+            // continuation lines of a multi-line header start with a space
+            if let Some(continuation) = line.strip_prefix(' ') {
+                let last = fields.last_mut().ok_or_else(|| invalid("dangling continuation line"))?;
+                last.1.push('\n');
+                last.1.push_str(continuation);
+                continue;
+            }
+            let (key, value) = line.split_at(line.find(' ').ok_or_else(|| invalid(line))?);
+            fields.push((key.to_string(), value[1..].to_string()));
+        }
+        let mut fields = fields.into_iter().peekable();
+        let tree = match fields.next() {
+            Some((key, value)) if key == "tree" => value,
+            _ => return Err(invalid("missing tree")),
+        };
+        if !is_object_id(&tree) {
+            return Err(invalid(&format!("bad tree {}", tree)));
+        }
+        let mut parents = Vec::new();
+        while let Some((_, parent)) = fields.next_if(|(key, _)| key == "parent") {
+            if !is_object_id(&parent) {
+                return Err(invalid(&format!("bad parent {}", parent)));
+            }
+            parents.push(parent);
+        }
+        let mut identity = |name: &str| match fields.next() {
+            Some((key, value)) if key == name => {
+                Identity::parse(&value).ok_or_else(|| invalid(&format!("bad {} line", name)))
+            }
+            _ => Err(invalid(&format!("missing {}", name))),
+        };
+        let author = identity("author")?;
+        let committer = identity("committer")?;
+        Ok(Self {
+            tree,
+            parents,
+            author,
+            committer,
+            extra_headers: fields.collect(),
+            message: message.to_string(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut content = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            content += &format!("parent {}\n", parent);
+        }
+        content += &format!("author {}\ncommitter {}\n", self.author, self.committer);
+        for (key, value) in &self.extra_headers {
+            content += &format!("{} {}\n", key, value.replace('\n', "\n "));
+        }
+        content += "\n";
+        content += &self.message;
+        content.into_bytes()
+    }
+
+    // Reads the commit `sha` from the object database.
+    pub fn read(repo: &Repository, sha: &str) -> std::io::Result<Self> {
+        Self::from_object(Blob::from_sha(repo, sha)?, sha)
+    }
+
+    // Reads the commit `sha` as stored, whatever a replace ref puts in its place.
+    pub fn read_stored(repo: &Repository, sha: &str) -> std::io::Result<Self> {
+        Self::from_object(Blob::from_store(repo.objects(), sha)?, sha)
+    }
+
+    fn from_object(blob: Blob, sha: &str) -> std::io::Result<Self> {
+        if blob.object_type() != ObjectType::Commit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not a commit", sha),
+            ));
+        }
+        Self::parse(&blob.content)
+    }
+
+    // The commit `sha` is, or the one the annotated tag `sha` points to.
+    pub fn peel(repo: &Repository, sha: &str) -> std::io::Result<String> {
+        let mut sha = sha.to_string();
+        loop {
+            let blob = Blob::from_sha(repo, &sha)?;
+            match blob.object_type() {
+                ObjectType::Commit => return Ok(sha),
+                ObjectType::Tag => sha = super::tag::Tag::parse(&blob.content)?.object,
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{} is not a commit", sha),
+                    ))
+                }
+            }
+        }
+    }
+
+    // the first paragraph of the message, on one line
+    pub fn subject(&self) -> String {
+        subject(&self.message)
+    }
+
+    // Writes the commit to the object database and returns its sha.
+    pub fn write(&self, repo: &Repository) -> std::io::Result<String> {
+        repo.write_commit(self)
+    }
+}
+
+// The subject of a commit or tag message as git shows it: the first paragraph,
+// its lines joined with spaces.
+pub fn subject(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Creates a commit object for `tree` with the given parents and prints its sha.
+pub fn commit_tree(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    message: String,
+    signing_key: Option<&str>,
+) -> std::io::Result<()> {
+    let not_a = |sha: &str, object_type: ObjectType| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a valid '{}' object", sha, object_type),
+        )
+    };
+    if Blob::from_sha(repo, tree)?.object_type() != ObjectType::Tree {
+        return Err(not_a(tree, ObjectType::Tree));
+    }
+    for parent in parents {
+        let blob = Blob::from_sha(repo, parent)?;
+        if blob.object_type() != ObjectType::Commit {
+            return Err(not_a(parent, ObjectType::Commit));
+        }
+        Commit::parse(&blob.content)?;
+    }
+    let mut commit = Commit {
+        tree: tree.to_string(),
+        parents: parents.to_vec(),
+        author: Identity::from_env("AUTHOR", repo.config())?,
+        committer: Identity::from_env("COMMITTER", repo.config())?,
+        extra_headers: Vec::new(),
+        message,
+    };
+    if let Some(key) = signing_key {
+        sign(repo, &mut commit, key)?;
+    }
+    outln!("{}", commit.write(repo)?)?;
+    Ok(())
+}
+
+// Adds a `gpgsig` header to `commit`, signed with `key` or, when it's
+// empty, user.signingkey or the committer identity. The signature covers
+// the commit as it would be written without it.
+fn sign(repo: &Repository, commit: &mut Commit, key: &str) -> std::io::Result<()> {
+    let key = match (key, repo.config().get("user.signingkey")) {
+        ("", Some(configured)) => configured.to_string(),
+        ("", None) => format!("{} <{}>", commit.committer.name, commit.committer.email),
+        (key, _) => key.to_string(),
+    };
+    let signature = gpg::sign(repo.config(), &commit.serialize(), &key)?;
+    commit
+        .extra_headers
+        .push(("gpgsig".to_string(), signature.trim_end_matches('\n').to_string()));
+    Ok(())
+}
+
+// A message the way `commit` cleans it up: trailing whitespace cut from
+// every line, blank lines at the ends dropped and runs of them shortened to
+// one, then one newline at the end. Only whitespace leaves nothing.
+fn clean_message(message: &str) -> String {
+    let mut cleaned = String::new();
+    let mut blank = false;
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = !cleaned.is_empty();
+            continue;
+        }
+        if blank {
+            cleaned.push('\n');
+            blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+// Commits the index on top of HEAD as `commit` does and returns the new
+// commit. A merge or cherry-pick stopped for conflicts is finished: the
+// commit gets MERGE_HEAD as another parent, and those files go. With
+// `verify` the pre-commit hook may stop the commit before anything is
+// written and the commit-msg hook check and edit the message; the
+// post-commit hook runs either way. A commit with HEAD's tree is refused
+// unless `allow_empty`. With a `signing_key` the commit is GPG-signed, as
+// by commit-tree.
+pub fn commit_index(
+    repo: &Repository,
+    message: &str,
+    verify: bool,
+    allow_empty: bool,
+    signing_key: Option<&str>,
+) -> std::io::Result<String> {
+    let refused = |what: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, what);
+    if verify && !hooks::run(repo, "pre-commit", &[])? {
+        return Err(refused("the pre-commit hook refused the commit".to_string()));
+    }
+    // read after the hook, which may have staged changes
+    let index = index::Index::read(repo)?;
+    if index.entries.iter().any(|entry| entry.stage() != 0) {
+        return Err(refused("committing is not possible because you have unmerged files".to_string()));
+    }
+    let tree = tree::write_tree(repo, &index.files())?;
+    let head = refs::resolve(repo, "HEAD")?;
+    let merge_head = match fs::read_to_string(repo.git_dir().join("MERGE_HEAD")) {
+        Ok(content) => content.lines().map(String::from).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let head_tree = head.as_ref().map(|head| Commit::read(repo, head).map(|commit| commit.tree)).transpose()?;
+    let unchanged = match &head_tree {
+        Some(head_tree) => *head_tree == tree,
+        None => index.entries.is_empty(),
+    };
+    if unchanged && merge_head.is_empty() && !allow_empty {
+        return Err(refused("nothing to commit".to_string()));
+    }
+    let mut message = clean_message(message);
+    if verify && !message.is_empty() {
+        message = hooks::commit_msg(repo, "COMMIT_EDITMSG", &message)?
+            .map(|message| clean_message(&message))
+            .ok_or_else(|| refused("the commit-msg hook refused the commit".to_string()))?;
+    }
+    if message.is_empty() {
+        return Err(refused("aborting commit due to empty commit message".to_string()));
+    }
+    // a cherry-pick keeps the author of the commit it picked
+    let author = match refs::resolve(repo, "CHERRY_PICK_HEAD")? {
+        Some(picked) => Commit::read(repo, &picked)?.author,
+        None => Identity::from_env("AUTHOR", repo.config())?,
+    };
+    let mut commit = Commit {
+        tree,
+        parents: head.iter().cloned().chain(merge_head).collect(),
+        author,
+        committer: Identity::from_env("COMMITTER", repo.config())?,
+        extra_headers: Vec::new(),
+        message,
+    };
+    if let Some(key) = signing_key {
+        sign(repo, &mut commit, key)?;
+    }
+    let sha = commit.write(repo)?;
+    refs::update(repo, "HEAD", &sha, Some(head.as_deref().unwrap_or(refs::NULL_ID)))?;
+    for name in ["MERGE_HEAD", "MERGE_MSG", "CHERRY_PICK_HEAD"] {
+        refs::delete(repo, name)?;
+    }
+    let branch = match refs::symbolic_target(repo, "HEAD")? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => "detached HEAD".to_string(),
+    };
+    let root = if head.is_none() { " (root-commit)" } else { "" };
+    outln!("[{}{} {}] {}", branch, root, &sha[..7], commit.subject())?;
+    // too late to stop anything, its status doesn't matter
+    hooks::run(repo, "post-commit", &[])?;
+    Ok(sha)
+}
+
+// Verifies the `gpgsig` header of `sha` against the commit without it.
+pub fn verify_commit(repo: &Repository, sha: &str, verbose: bool) -> std::io::Result<()> {
+    let blob = Blob::from_sha(repo, sha)?;
+    if blob.object_type() != ObjectType::Commit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{}: cannot verify a non-commit object of type {}", sha, blob.object_type()),
+        ));
+    }
+    let mut commit = Commit::parse(&blob.content)?;
+    let position = commit.extra_headers.iter().position(|(key, _)| key == "gpgsig");
+    let signature = match position {
+        Some(index) => commit.extra_headers.remove(index).1,
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{}: no signature found", sha),
+            ))
+        }
+    };
+    if verbose {
+        out!("{}", String::from_utf8_lossy(&commit.serialize()))?;
+    }
+    if gpg::verify(repo.config(), &commit.serialize(), &format!("{}\n", signature))? {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: bad signature", sha),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn testing_commit_round_trip() {
+        let content = "tree 51912bb58e69f15db8f8b214bc97de8430cfdc02\n\
+                               parent cd591dba9391e2cdfbae51a51800b9689c7ea360\n\
+                               author A U Thor <author@example.com> 1112911993 +0200\n\
+                               committer C O Mitter <committer@example.com> 1112912053 -0700\n\
+                               encoding ISO-8859-1\n\
+                               gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+                               \n\
+                               subject\n\nbody\n";
+        let commit = Commit::parse(content.as_bytes()).unwrap();
+        assert_eq!(commit.parents.len(), 1);
+        assert_eq!(commit.committer.offset, -420);
+        assert_eq!(commit.extra_headers[0], ("encoding".to_string(), "ISO-8859-1".to_string()));
+        assert_eq!(commit.extra_headers[1].1.lines().count(), 4);
+        assert_eq!(commit.message, "subject\n\nbody\n");
+        assert_eq!(commit.serialize(), content.as_bytes());
+        assert!(Commit::parse(b"author A <a> 1 +0000\n\nmsg").is_err());
+        let identities = "author A <a> 1 +0000\ncommitter A <a> 1 +0000\n\nmsg";
+        assert!(Commit::parse(format!("tree 51912bb\n{}", identities).as_bytes()).is_err());
+        let bad_parent = "tree 51912bb58e69f15db8f8b214bc97de8430cfdc02\nparent 1234\n";
+        assert!(Commit::parse(format!("{}{}", bad_parent, identities).as_bytes()).is_err());
+    }
+
+    // `commit` makes a commit of the index, once its hooks agree, and
+    // refuses one that changes nothing.
+    #[test]
+    fn testing_commit_index() {
+        let dir = TempDir::new("commit-index");
+        fs::create_dir_all(&dir).unwrap();
+        init_repository(&dir.join(GIT_DIR), None).unwrap();
+        // a gpg keeping what it signs and answering like the real one
+        let gpg = dir.join("gpg");
+        let script = format!(
+            "#!/bin/sh\ncat > {}\necho '[GNUPG:] SIG_CREATED D' >&2\necho '{}'\n",
+            dir.join("signed").display(),
+            "-----BEGIN PGP SIGNATURE-----\n\nsig\n-----END PGP SIGNATURE-----",
+        );
+        fs::write(&gpg, script).unwrap();
+        fs::set_permissions(&gpg, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let config = format!("[user]\n\tname = A\n\temail = a@b\n[gpg]\n\tprogram = {}\n", gpg.display());
+        fs::write(dir.join(".git/config"), config).unwrap();
+        fs::create_dir_all(dir.join(".git/hooks")).unwrap();
+        let repo = Repository::open(dir.join(GIT_DIR)).unwrap();
+        let hook = |name: &str, script: &str| {
+            let path = dir.join(".git/hooks").join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        };
+        let stage = |path: &str, content: &str| {
+            fs::write(dir.join(path), content).unwrap();
+            let mut index = index::Index::read(&repo).unwrap();
+            index.add(worktree::stage(&repo, path).unwrap());
+            index.write(&repo).unwrap();
+        };
+        let head = || refs::resolve(&repo, "HEAD").unwrap();
+
+        let nothing = commit_index(&repo, "empty\n", true, false, None).map_err(|e| e.to_string());
+        stage("a", "a\n");
+        hook("pre-commit", "#!/bin/sh\nexit 1\n");
+        let refused = commit_index(&repo, "first\n", true, false, None).is_err();
+        let unborn = head();
+        let first = commit_index(&repo, "  first  \n\n\n\nbody\n\n", false, false, None).unwrap();
+        let first_message = Commit::read(&repo, &first).unwrap().message;
+        hook("pre-commit", "#!/bin/sh\ntouch pre-commit-ran\n");
+        hook("commit-msg", "#!/bin/sh\necho 'Signed-off-by: A <a@b>' >> \"$1\"\n");
+        hook("post-commit", "#!/bin/sh\ntouch post-commit-ran\n");
+        stage("b", "b\n");
+        let second = commit_index(&repo, "second", true, false, None).unwrap();
+        let second_commit = Commit::read(&repo, &second).unwrap();
+        let files = tree::files(&repo, &second_commit.tree).unwrap().into_keys().collect::<Vec<_>>();
+        let ran = (dir.join("pre-commit-ran").exists(), dir.join("post-commit-ran").exists());
+        let unchanged = commit_index(&repo, "third\n", true, false, None).map_err(|e| e.to_string());
+        hook("commit-msg", "#!/bin/sh\nexit 1\n");
+        stage("c", "c\n");
+        let rejected = commit_index(&repo, "third\n", true, false, None).is_err();
+        let skipped = commit_index(&repo, "third\n", false, false, None).is_ok();
+        let blank = commit_index(&repo, " \n\n", false, true, None).map_err(|e| e.to_string());
+        let empty = commit_index(&repo, "empty\n", false, true, None).unwrap();
+        let empty_commit = Commit::read(&repo, &empty).unwrap();
+        let kept_tree = empty_commit.tree == Commit::read(&repo, &empty_commit.parents[0]).unwrap().tree;
+        let signed = commit_index(&repo, "signed\n", false, true, Some("")).unwrap();
+        let mut signed_commit = Commit::read(&repo, &signed).unwrap();
+        let signature = signed_commit.extra_headers.pop();
+        let payload = fs::read(dir.join("signed")).unwrap();
+
+        assert_eq!(nothing.unwrap_err(), "nothing to commit");
+        assert!(refused);
+        assert_eq!(unborn, None);
+        // indentation is kept
+        assert_eq!(first_message, "  first\n\nbody\n");
+        assert_eq!(second_commit.parents, vec![first]);
+        assert_eq!(second_commit.message, "second\nSigned-off-by: A <a@b>\n");
+        assert_eq!(files, vec!["a", "b"]);
+        assert_eq!(ran, (true, true));
+        assert_eq!(unchanged.unwrap_err(), "nothing to commit");
+        assert!(rejected && skipped);
+        assert_eq!(blank.unwrap_err(), "aborting commit due to empty commit message");
+        assert!(kept_tree);
+        let armored = "-----BEGIN PGP SIGNATURE-----\n\nsig\n-----END PGP SIGNATURE-----";
+        assert_eq!(signature, Some(("gpgsig".to_string(), armored.to_string())));
+        // what was signed is the commit without its signature
+        assert!(signed_commit.extra_headers.is_empty());
+        assert_eq!(payload, signed_commit.serialize());
+    }
+}
@@ -0,0 +1,343 @@
+use super::commit::Commit;
+use super::*;
+use std::collections::HashMap;
+
+const SIGNATURE: &[u8] = b"CGPH";
+// the file format version, with SHA-1 (hash version 1) ids
+pub const VERSION: u8 = 1;
+const OID_FANOUT: &[u8] = b"OIDF";
+const OID_LOOKUP: &[u8] = b"OIDL";
+const COMMIT_DATA: &[u8] = b"CDAT";
+const EXTRA_EDGES: &[u8] = b"EDGE";
+// parent positions with these values mean no parent, or an index into EDGE
+const PARENT_NONE: u32 = 0x7000_0000;
+const EDGE_FLAG: u32 = 0x8000_0000;
+// tree id, two parent positions, generation and commit time
+const COMMIT_DATA_SIZE: usize = 36;
+
+// A commit as the graph records it, parents given by their positions.
+pub struct GraphCommit {
+    pub tree: String,
+    pub parents: Vec<u32>,
+    pub generation: u32,
+    pub time: u64,
+}
+
+// git's `objects/info/commit-graph`: the commits sorted by id, with their tree,
+// parents and generation numbers, so history can be walked without inflating
+// commits.
+pub struct CommitGraph {
+    data: Vec<u8>,
+    count: usize,
+    fanout: usize,
+    lookup: usize,
+    commit_data: usize,
+    extra_edges: Option<usize>,
+}
+
+fn corrupt(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("corrupt commit-graph: {}", what))
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+impl CommitGraph {
+    // The commit-graph of `objects_dir`, `None` when it has none. Split graph
+    // chains (`info/commit-graphs/`) aren't read.
+    pub fn open(objects_dir: &Path) -> std::io::Result<Option<Self>> {
+        match fs::read(objects_dir.join("info/commit-graph")) {
+            Ok(data) => Self::parse(data).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(data: Vec<u8>) -> std::io::Result<Self> {
+        if data.len() < 8 || &data[..4] != SIGNATURE {
+            return Err(corrupt("bad signature"));
+        }
+        if data[4] != VERSION || data[5] != 1 {
+            return Err(corrupt("unsupported version or hash"));
+        }
+        let chunks = data[6] as usize;
+        if data.len() < 8 + (chunks + 1) * 12 {
+            return Err(corrupt("truncated chunk table"));
+        }
+        // each chunk runs up to the offset of the next table entry
+        let mut offsets = HashMap::new();
+        for i in 0..chunks {
+            let entry = 8 + i * 12;
+            let offset = u64::from_be_bytes(data[entry + 4..entry + 12].try_into().unwrap()) as usize;
+            let end = u64::from_be_bytes(data[entry + 16..entry + 24].try_into().unwrap()) as usize;
+            if offset > end || end > data.len() {
+                return Err(corrupt("chunk out of bounds"));
+            }
+            offsets.insert(data[entry..entry + 4].to_vec(), (offset, end - offset));
+        }
+        let chunk = |id: &[u8]| offsets.get(id).copied();
+        let (fanout, fanout_size) = chunk(OID_FANOUT).ok_or_else(|| corrupt("missing OID fanout"))?;
+        let (lookup, lookup_size) = chunk(OID_LOOKUP).ok_or_else(|| corrupt("missing OID lookup"))?;
+        let (commit_data, data_size) = chunk(COMMIT_DATA).ok_or_else(|| corrupt("missing commit data"))?;
+        if fanout_size != 256 * 4 {
+            return Err(corrupt("bad OID fanout size"));
+        }
+        let count = u32_at(&data, fanout + 255 * 4) as usize;
+        if lookup_size != count * 20 || data_size != count * COMMIT_DATA_SIZE {
+            return Err(corrupt("chunk sizes don't match the commit count"));
+        }
+        let extra_edges = chunk(EXTRA_EDGES).map(|(offset, _)| offset);
+        Ok(CommitGraph { data, count, fanout, lookup, commit_data, extra_edges })
+    }
+
+    pub fn commit_count(&self) -> usize {
+        self.count
+    }
+
+    // the position of the commit `sha` in the graph
+    pub fn position(&self, sha: &str) -> Option<u32> {
+        let id = Blob::decode_hex(sha).ok().filter(|id| id.len() == 20)?;
+        let first = id[0] as usize;
+        let start = match first {
+            0 => 0,
+            _ => u32_at(&self.data, self.fanout + (first - 1) * 4) as usize,
+        };
+        let end = u32_at(&self.data, self.fanout + first * 4) as usize;
+        let (mut low, mut high) = (start, end.min(self.count));
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.data[self.lookup + middle * 20..self.lookup + middle * 20 + 20].cmp(&id[..]) {
+                std::cmp::Ordering::Equal => return Some(middle as u32),
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+            }
+        }
+        None
+    }
+
+    // the id of the commit at `position`
+    pub fn id(&self, position: u32) -> String {
+        let at = self.lookup + position as usize * 20;
+        Blob::string_hash(&self.data[at..at + 20])
+    }
+
+    pub fn commit(&self, position: u32) -> std::io::Result<GraphCommit> {
+        if position as usize >= self.count {
+            return Err(corrupt("commit position out of range"));
+        }
+        let at = self.commit_data + position as usize * COMMIT_DATA_SIZE;
+        let mut parents = Vec::new();
+        for parent in [u32_at(&self.data, at + 20), u32_at(&self.data, at + 24)] {
+            if parent == PARENT_NONE {
+                break;
+            }
+            if parent & EDGE_FLAG == 0 {
+                parents.push(parent);
+                continue;
+            }
+            // octopus merges list their second and later parents in EDGE, the
+            // last one flagged
+            let edges = self.extra_edges.ok_or_else(|| corrupt("missing extra edges"))?;
+            let mut edge = edges + (parent & !EDGE_FLAG) as usize * 4;
+            loop {
+                if edge + 4 > self.data.len() {
+                    return Err(corrupt("extra edge out of bounds"));
+                }
+                let value = u32_at(&self.data, edge);
+                parents.push(value & !EDGE_FLAG);
+                if value & EDGE_FLAG != 0 {
+                    break;
+                }
+                edge += 4;
+            }
+        }
+        let high = u32_at(&self.data, at + 28);
+        let low = u32_at(&self.data, at + 32);
+        Ok(GraphCommit {
+            tree: Blob::string_hash(&self.data[at..at + 20]),
+            parents,
+            generation: high >> 2,
+            time: ((high as u64 & 3) << 32) | low as u64,
+        })
+    }
+}
+
+// Checks the graph's checksum and every commit in it against the object
+// store, and that each generation is above its parents'.
+pub fn verify(repo: &Repository) -> std::io::Result<()> {
+    let path = repo.objects_dir().join("info/commit-graph");
+    let data = fs::read(&path)?;
+    if data.len() < 20 || sha1::Sha1::digest(&data[..data.len() - 20])[..] != data[data.len() - 20..] {
+        return Err(corrupt("checksum mismatch"));
+    }
+    let graph = CommitGraph::parse(data)?;
+    for position in 0..graph.commit_count() as u32 {
+        let sha = graph.id(position);
+        if position > 0 && graph.id(position - 1) >= sha {
+            return Err(corrupt("commits out of order"));
+        }
+        let entry = graph.commit(position)?;
+        let commit = Commit::read(repo, &sha)?;
+        let parents: Vec<String> = entry.parents.iter().map(|&parent| graph.id(parent)).collect();
+        if entry.tree != commit.tree || parents != commit.parents {
+            return Err(corrupt(&format!("tree or parents differ for commit {}", sha)));
+        }
+        if entry.time != commit.committer.time.max(0) as u64 {
+            return Err(corrupt(&format!("commit time differs for commit {}", sha)));
+        }
+        for parent in entry.parents {
+            if graph.commit(parent)?.generation >= entry.generation {
+                return Err(corrupt(&format!("generation too low for commit {}", sha)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The commits the refs and HEAD lead to, tags peeled.
+fn reachable_commits(repo: &Repository) -> std::io::Result<HashMap<String, Commit>> {
+    let mut pending: Vec<String> = refs::list(repo)?.into_iter().map(|(_, sha)| sha).collect();
+    pending.extend(refs::resolve(repo, "HEAD")?);
+    let mut commits = HashMap::new();
+    while let Some(sha) = pending.pop() {
+        if commits.contains_key(&sha) {
+            continue;
+        }
+        let object = Blob::from_sha(repo, &sha)?;
+        match object.object_type() {
+            ObjectType::Commit => {
+                let commit = Commit::parse(&object.content)?;
+                pending.extend(commit.parents.iter().cloned());
+                commits.insert(sha, commit);
+            }
+            ObjectType::Tag => pending.push(tag::Tag::parse(&object.content)?.object),
+            _ => {}
+        }
+    }
+    Ok(commits)
+}
+
+// Writes `objects/info/commit-graph` for every commit reachable from the refs
+// and HEAD, and returns how many it holds.
+pub fn write(repo: &Repository) -> std::io::Result<usize> {
+    let commits = reachable_commits(repo)?;
+    let mut ids: Vec<&String> = commits.keys().collect();
+    ids.sort();
+    let positions: HashMap<&str, u32> = ids.iter().enumerate().map(|(i, id)| (id.as_str(), i as u32)).collect();
+
+    // a commit's generation is one more than its highest parent's
+    let mut generations: HashMap<&str, u32> = HashMap::new();
+    for id in &ids {
+        let mut stack = vec![id.as_str()];
+        while let Some(&sha) = stack.last() {
+            if generations.contains_key(sha) {
+                stack.pop();
+                continue;
+            }
+            let parents = &commits[sha].parents;
+            let pending: Vec<&str> =
+                parents.iter().map(String::as_str).filter(|parent| !generations.contains_key(parent)).collect();
+            if pending.is_empty() {
+                let generation = parents.iter().map(|parent| generations[parent.as_str()]).max().unwrap_or(0) + 1;
+                generations.insert(sha, generation);
+                stack.pop();
+            } else {
+                stack.extend(pending);
+            }
+        }
+    }
+
+    let mut fanout = Vec::with_capacity(256 * 4);
+    for byte in 0..=255u8 {
+        let count = ids.iter().filter(|id| u8::from_str_radix(&id[..2], 16).unwrap() <= byte).count();
+        fanout.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    let mut lookup = Vec::with_capacity(ids.len() * 20);
+    let mut commit_data = Vec::with_capacity(ids.len() * COMMIT_DATA_SIZE);
+    let mut extra_edges: Vec<u8> = Vec::new();
+    for id in &ids {
+        let commit = &commits[id.as_str()];
+        lookup.extend_from_slice(&Blob::decode_hex(id)?);
+        commit_data.extend_from_slice(&Blob::decode_hex(&commit.tree)?);
+        let parents: Vec<u32> = commit.parents.iter().map(|parent| positions[parent.as_str()]).collect();
+        let second = match parents.len() {
+            0 | 1 => PARENT_NONE,
+            2 => parents[1],
+            _ => {
+                let index = (extra_edges.len() / 4) as u32 | EDGE_FLAG;
+                for (i, parent) in parents[1..].iter().enumerate() {
+                    let last = if i == parents.len() - 2 { EDGE_FLAG } else { 0 };
+                    extra_edges.extend_from_slice(&(parent | last).to_be_bytes());
+                }
+                index
+            }
+        };
+        commit_data.extend_from_slice(&parents.first().copied().unwrap_or(PARENT_NONE).to_be_bytes());
+        commit_data.extend_from_slice(&second.to_be_bytes());
+        let time = commit.committer.time.max(0) as u64;
+        let generation = generations[id.as_str()];
+        commit_data.extend_from_slice(&((generation << 2) | ((time >> 32) & 3) as u32).to_be_bytes());
+        commit_data.extend_from_slice(&(time as u32).to_be_bytes());
+    }
+
+    let mut chunks = vec![(OID_FANOUT, fanout), (OID_LOOKUP, lookup), (COMMIT_DATA, commit_data)];
+    if !extra_edges.is_empty() {
+        chunks.push((EXTRA_EDGES, extra_edges));
+    }
+    let mut content = SIGNATURE.to_vec();
+    content.extend_from_slice(&[VERSION, 1, chunks.len() as u8, 0]);
+    let mut offset = (8 + (chunks.len() + 1) * 12) as u64;
+    for (id, chunk) in &chunks {
+        content.extend_from_slice(id);
+        content.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    content.extend_from_slice(&[0; 4]);
+    content.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in &chunks {
+        content.extend_from_slice(chunk);
+    }
+    let checksum = sha1::Sha1::digest(&content);
+    content.extend_from_slice(&checksum);
+
+    let info = repo.objects_dir().join("info");
+    fs::create_dir_all(&info)?;
+    write_locked(&info.join("commit-graph"), &content)?;
+    Ok(ids.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_commit_graph() {
+        let dir = TempDir::new("commit-graph");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let commit = |parents: &[&String], message: &str| test_commit(&repo, EMPTY_TREE, parents, 1 << 33, message);
+        let root = commit(&[], "root");
+        let (a, b, c) = (commit(&[&root], "a"), commit(&[&root], "b"), commit(&[&root], "c"));
+        let octopus = commit(&[&a, &b, &c], "octopus");
+        refs::update(&repo, "refs/heads/master", &octopus, None).unwrap();
+        assert_eq!(write(&repo).unwrap(), 5);
+        verify(&repo).unwrap();
+
+        let graph = CommitGraph::open(&repo.objects_dir()).unwrap().unwrap();
+        let entry = graph.commit(graph.position(&octopus).unwrap()).unwrap();
+        let parents: Vec<String> = entry.parents.iter().map(|&parent| graph.id(parent)).collect();
+        assert_eq!(parents, vec![a.clone(), b, c]);
+        assert_eq!((entry.generation, entry.time), (3, 1 << 33));
+        assert_eq!(graph.commit(graph.position(&root).unwrap()).unwrap().generation, 1);
+        assert_eq!(graph.position(&"0".repeat(40)), None);
+        assert_eq!(ancestry::ancestors(&repo, &octopus).unwrap().len(), 5);
+        assert!(CommitGraph::parse(b"CGPH\x02\x01\x00\x00".to_vec()).is_err());
+        // a graph that doesn't parse is left out of walks
+        let path = dir.join("objects/info/commit-graph");
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+        assert!(CommitGraph::open(&repo.objects_dir()).is_err());
+        assert_eq!(ancestry::ancestors(&repo, &octopus).unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,563 @@
+use super::*;
+
+// git gives up on includes nested deeper than this
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+// The entries of a git config file, keyed `section.name` or
+// `section.subsection.name` with the section and name lowercased.
+#[derive(Default)]
+pub struct Config {
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    // A missing config file is the same as an empty one. `include.path` and
+    // `includeIf.<condition>.path` are followed, `git_dir` is the repository
+    // the `gitdir:` conditions are checked against.
+    pub fn from_file(path: &Path, git_dir: Option<&Path>) -> std::io::Result<Self> {
+        Self::from_file_with_includes(path, git_dir, &mut Vec::new())
+    }
+
+    // Reads `paths` in order, values from later files take precedence.
+    pub fn from_files(paths: &[PathBuf], git_dir: Option<&Path>) -> std::io::Result<Self> {
+        let mut config = Self::default();
+        for path in paths {
+            config.merge(Self::from_file(path, git_dir)?);
+        }
+        Ok(config)
+    }
+
+    // `including` holds the files being read, to detect include cycles
+    fn from_file_with_includes(
+        path: &Path,
+        git_dir: Option<&Path>,
+        including: &mut Vec<PathBuf>,
+    ) -> std::io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let parsed = Self::parse(&text).map_err(|e| {
+            std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+        })?;
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if including.contains(&canonical) || including.len() >= MAX_INCLUDE_DEPTH {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("include cycle or maximum include depth reached at {}", path.display()),
+            ));
+        }
+        including.push(canonical);
+        let mut entries = Vec::new();
+        for (key, value) in parsed.entries {
+            let include = if key == "include.path" {
+                true
+            } else if let Some(condition) =
+                key.strip_prefix("includeif.").and_then(|key| key.strip_suffix(".path"))
+            {
+                Self::condition_holds(condition, path, git_dir)
+            } else {
+                false
+            };
+            let target = Self::include_target(&value, path);
+            entries.push((key, value));
+            // the included entries take the place of the include line
+            if include {
+                let included = Self::from_file_with_includes(&target, git_dir, including)?;
+                entries.extend(included.entries);
+            }
+        }
+        including.pop();
+        Ok(Self { entries })
+    }
+
+    // include paths are relative to the including file, `~/` is the home directory
+    fn include_target(value: &str, including: &Path) -> PathBuf {
+        match (value.strip_prefix("~/"), std::env::var_os("HOME")) {
+            (Some(rest), Some(home)) => Path::new(&home).join(rest),
+            _ => including.parent().unwrap_or_else(|| Path::new("")).join(value),
+        }
+    }
+
+    // Only the `gitdir:` and `gitdir/i:` conditions are supported, others
+    // (`onbranch:`...) never hold. Like git a pattern that isn't anchored
+    // matches at any depth and a trailing `/` matches everything below.
+    fn condition_holds(condition: &str, including: &Path, git_dir: Option<&Path>) -> bool {
+        let (pattern, icase) = match (condition.strip_prefix("gitdir:"), condition.strip_prefix("gitdir/i:")) {
+            (Some(pattern), _) => (pattern, false),
+            (_, Some(pattern)) => (pattern, true),
+            _ => return false,
+        };
+        let git_dir = match git_dir {
+            Some(git_dir) => fs::canonicalize(git_dir).unwrap_or_else(|_| git_dir.to_path_buf()),
+            None => return false,
+        };
+        let mut pattern = if pattern.starts_with("~/") || pattern.starts_with("./") {
+            let base = if pattern.starts_with('~') {
+                PathBuf::from(std::env::var_os("HOME").unwrap_or_default())
+            } else {
+                including.parent().unwrap_or_else(|| Path::new("")).to_path_buf()
+            };
+            base.join(&pattern[2..]).to_string_lossy().into_owned()
+        } else if pattern.starts_with('/') {
+            pattern.to_string()
+        } else {
+            format!("**/{}", pattern)
+        };
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        wildmatch::wildmatch(pattern.as_bytes(), git_dir.to_string_lossy().as_bytes(), icase)
+    }
+
+    // The config files read before the repository's own, lowest precedence
+    // first: the system config, `$XDG_CONFIG_HOME/git/config` (defaulting to
+    // `~/.config/git/config`) and `~/.gitconfig`. `GIT_CONFIG_NOSYSTEM` skips
+    // the system config and `GIT_CONFIG_GLOBAL` replaces the two global ones.
+    pub fn global_files() -> Vec<PathBuf> {
+        let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+        let mut files = Vec::new();
+        if var("GIT_CONFIG_NOSYSTEM").is_none() {
+            files.push(PathBuf::from("/etc/gitconfig"));
+        }
+        if let Some(global) = var("GIT_CONFIG_GLOBAL") {
+            files.push(PathBuf::from(global));
+            return files;
+        }
+        let home = var("HOME").map(PathBuf::from);
+        match (var("XDG_CONFIG_HOME"), &home) {
+            (Some(xdg), _) => files.push(PathBuf::from(xdg).join("git/config")),
+            (None, Some(home)) => files.push(home.join(".config/git/config")),
+            (None, None) => {}
+        }
+        if let Some(home) = home {
+            files.push(home.join(".gitconfig"));
+        }
+        files
+    }
+
+    // appends the entries of `other`, which then override ours
+    pub fn merge(&mut self, other: Config) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let mut entries = Vec::new();
+        let mut section = String::new();
+        let mut lines = text.lines().enumerate();
+        while let Some((number, line)) = lines.next() {
+            let bad_line = || {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("bad config line {}", number + 1),
+                )
+            };
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') {
+                let end = line.find(']').ok_or_else(bad_line)?;
+                section = Self::section_name(&line[1..end]).ok_or_else(bad_line)?;
+                let rest = line[end + 1..].trim_start();
+                if rest.is_empty() || rest.starts_with('#') || rest.starts_with(';') {
+                    continue;
+                }
+                return Err(bad_line());
+            }
+            if section.is_empty() {
+                return Err(bad_line());
+            }
+            let (name, raw_value) = match line.find('=') {
+                Some(index) => (line[..index].trim(), Some(&line[index + 1..])),
+                None => (line.split(['#', ';']).next().unwrap().trim(), None),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(bad_line());
+            }
+            let value = match raw_value {
+                // a key without `=` is a boolean set to true
+                None => "true".to_string(),
+                Some(raw) => {
+                    let mut raw = raw.to_string();
+                    // a trailing backslash continues the value on the next line
+                    while Self::continues(&raw) {
+                        raw.pop();
+                        match lines.next() {
+                            Some((_, next)) => raw.push_str(next),
+                            None => break,
+                        }
+                    }
+                    Self::parse_value(&raw).ok_or_else(bad_line)?
+                }
+            };
+            entries.push((format!("{}.{}", section, name.to_lowercase()), value));
+        }
+        Ok(Self { entries })
+    }
+
+    // `core` -> `core`, `remote "origin"` -> `remote.origin`
+    fn section_name(header: &str) -> Option<String> {
+        let header = header.trim();
+        match header.find('"') {
+            None => Some(header.to_lowercase()),
+            Some(quote) => {
+                let name = header[..quote].trim();
+                let sub = header[quote + 1..].strip_suffix('"')?;
+                let sub = sub.replace("\\\"", "\"").replace("\\\\", "\\");
+                Some(format!("{}.{}", name.to_lowercase(), sub))
+            }
+        }
+    }
+
+    fn continues(raw: &str) -> bool {
+        let trailing = raw.len() - raw.trim_end_matches('\\').len();
+        trailing % 2 == 1
+    }
+
+    // strips comments and surrounding whitespace, honoring quotes and escapes
+    fn parse_value(raw: &str) -> Option<String> {
+        let mut value = String::new();
+        let mut pending_space = String::new();
+        let mut quoted = false;
+        let mut chars = raw.trim().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '#' | ';' if !quoted => break,
+                '\\' => {
+                    value.push_str(&pending_space);
+                    pending_space.clear();
+                    match chars.next()? {
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        'b' => {
+                            value.pop();
+                        }
+                        c @ ('"' | '\\') => value.push(c),
+                        _ => return None,
+                    }
+                }
+                c if c.is_whitespace() && !quoted => pending_space.push(c),
+                c => {
+                    value.push_str(&pending_space);
+                    pending_space.clear();
+                    value.push(c);
+                }
+            }
+        }
+        if quoted {
+            return None;
+        }
+        Some(value)
+    }
+
+    // the last value set for `key` wins
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = Self::normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // The size `key` is set to, which may end in `k`, `m` or `g` for that many
+    // KiB, MiB or GiB as in git, `None` when it isn't set.
+    pub fn get_size(&self, key: &str) -> std::io::Result<Option<u64>> {
+        let value = match self.get(key) {
+            Some(value) => value.trim(),
+            None => return Ok(None),
+        };
+        let (number, unit) = match value.char_indices().last() {
+            Some((at, 'k')) | Some((at, 'K')) => (&value[..at], 1 << 10),
+            Some((at, 'm')) | Some((at, 'M')) => (&value[..at], 1 << 20),
+            Some((at, 'g')) | Some((at, 'G')) => (&value[..at], 1 << 30),
+            _ => (value, 1),
+        };
+        number.parse::<u64>().ok().and_then(|number| number.checked_mul(unit)).map(Some).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("bad numeric config value '{}' for '{}'", value, key))
+        })
+    }
+
+    // Whether `key` is set to true by git's rules: `true`, `yes` and `on` are,
+    // `false`, `no`, `off` and the empty value aren't, nor is the integer 0,
+    // while a key without any value is. `None` when it isn't set.
+    pub fn get_bool(&self, key: &str) -> std::io::Result<Option<bool>> {
+        let value = match self.get(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match value.to_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(Some(true)),
+            "false" | "no" | "off" | "" => Ok(Some(false)),
+            number => number.trim().parse::<i64>().map(|number| Some(number != 0)).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("bad boolean config value '{}' for '{}'", value, key))
+            }),
+        }
+    }
+
+    // every value set for `key`, in file order
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = Self::normalize_key(key);
+        self.entries.iter().filter(|(k, _)| *k == key).map(|(_, v)| v.as_str()).collect()
+    }
+
+    // all the entries of `section` (without subsections), in file order
+    pub fn section<'a>(&'a self, section: &str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        let prefix = format!("{}.", section.to_lowercase());
+        self.entries
+            .iter()
+            .filter(move |(k, _)| {
+                k.starts_with(&prefix) && !k[prefix.len()..].contains('.')
+            })
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    // the distinct subsections of `section`, in the order they first appear
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let prefix = format!("{}.", section.to_lowercase());
+        let mut subsections: Vec<&str> = Vec::new();
+        for (key, _) in &self.entries {
+            if let (true, Some(last)) = (key.starts_with(&prefix), key.rfind('.')) {
+                if last > prefix.len() && !subsections.contains(&&key[prefix.len()..last]) {
+                    subsections.push(&key[prefix.len()..last]);
+                }
+            }
+        }
+        subsections
+    }
+
+    // section and name are case insensitive, the subsection is not
+    fn normalize_key(key: &str) -> String {
+        match (key.find('.'), key.rfind('.')) {
+            (Some(first), Some(last)) if first != last => format!(
+                "{}{}{}",
+                key[..first].to_lowercase(),
+                &key[first..last],
+                key[last..].to_lowercase()
+            ),
+            _ => key.to_lowercase(),
+        }
+    }
+}
+
+// The file `config --global` reads and writes: `GIT_CONFIG_GLOBAL`, else
+// `~/.gitconfig` unless only the XDG file exists.
+pub fn global_file() -> Option<PathBuf> {
+    if let Some(global) = std::env::var_os("GIT_CONFIG_GLOBAL").filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(global));
+    }
+    let home = PathBuf::from(std::env::var_os("HOME").filter(|v| !v.is_empty())?);
+    let xdg = match std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(xdg) => PathBuf::from(xdg).join("git/config"),
+        None => home.join(".config/git/config"),
+    };
+    let dotfile = home.join(".gitconfig");
+    if !dotfile.exists() && xdg.exists() {
+        Some(xdg)
+    } else {
+        Some(dotfile)
+    }
+}
+
+// The per-user file named by `key`, like `core.excludesFile`, with a leading
+// `~/` expanded, else `name` in git's XDG config directory.
+pub fn user_file(config: &Config, key: &str, name: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME");
+    match (config.get(key), home) {
+        (Some(file), home) => match (file.strip_prefix("~/"), home) {
+            (Some(rest), Some(home)) => Some(Path::new(&home).join(rest)),
+            _ => Some(PathBuf::from(file)),
+        },
+        (None, home) => match std::env::var_os("XDG_CONFIG_HOME").filter(|xdg| !xdg.is_empty()) {
+            Some(xdg) => Some(Path::new(&xdg).join("git").join(name)),
+            None => home.map(|home| Path::new(&home).join(".config/git").join(name)),
+        },
+    }
+}
+
+// Sets `key` to `value` in the config file at `path`. The last existing
+// assignment is rewritten in place, otherwise the entry is added at the end
+// of the last matching section, which is created if needed. Everything
+// else in the file is kept as it is.
+pub fn set(path: &Path, key: &str, value: &str) -> std::io::Result<()> {
+    let invalid = || {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid key: {}", key))
+    };
+    let dot = key.rfind('.').ok_or_else(invalid)?;
+    let (section, name) = (&key[..dot], &key[dot + 1..]);
+    let valid_name = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_name(name) || !valid_name(section.split('.').next().unwrap()) {
+        return Err(invalid());
+    }
+    let wanted = Config::normalize_key(key);
+    let wanted = &wanted[..wanted.rfind('.').unwrap()];
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let mut current = String::new();
+    let mut assignment = None;
+    let mut section_end = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let start = i;
+        // a value continued with a trailing backslash spans several lines
+        while Config::continues(&lines[i]) && i + 1 < lines.len() {
+            i += 1;
+        }
+        let line = lines[start].trim_start();
+        if line.starts_with('[') {
+            current = line
+                .find(']')
+                .and_then(|end| Config::section_name(&line[1..end]))
+                .unwrap_or_default();
+            if current == wanted {
+                section_end = Some(i);
+            }
+        } else if current == wanted && !line.is_empty() && !line.starts_with('#') && !line.starts_with(';') {
+            let line_name: String = line.chars().take_while(|&c| c.is_ascii_alphanumeric() || c == '-').collect();
+            if line_name.eq_ignore_ascii_case(name) {
+                assignment = Some((start, i));
+            }
+            section_end = Some(i);
+        }
+        i += 1;
+    }
+    let entry = format!("\t{} = {}", name, quote_value(value));
+    match (assignment, section_end) {
+        (Some((start, end)), _) => {
+            lines.splice(start..=end, std::iter::once(entry));
+        }
+        (None, Some(end)) => lines.insert(end + 1, entry),
+        (None, None) => {
+            let mut parts = section.splitn(2, '.');
+            let header = match (parts.next().unwrap(), parts.next()) {
+                (name, None) => format!("[{}]", name),
+                (name, Some(sub)) => {
+                    format!("[{} \"{}\"]", name, sub.replace('\\', "\\\\").replace('"', "\\\""))
+                }
+            };
+            lines.push(header);
+            lines.push(entry);
+        }
+    }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    write_locked(path, text.as_bytes())
+}
+
+// quotes values that would otherwise lose whitespace or be cut at a comment
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    if needs_quotes {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn testing_config_parsing() {
+        let config = Config::parse(
+            "# comment\n[core]\n\tRepositoryFormatVersion = 0\n\tbare\n\
+                     [remote \"Origin\"]\n\turl = \"/tmp/a b\" ; trailing\n\
+                     [user]\n\tname = first\n\tname = Tab\\tbed \\\n  name\n",
+        )
+        .unwrap();
+        assert_eq!(config.get("core.repositoryformatversion"), Some("0"));
+        assert_eq!(config.get("CORE.bare"), Some("true"));
+        assert_eq!(config.get("remote.Origin.url"), Some("/tmp/a b"));
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(config.get("user.name"), Some("Tab\tbed   name"));
+        assert_eq!(config.subsections("remote"), vec!["Origin"]);
+        let sizes = Config::parse("[core]\n\tlimit = 96m\n\tsmall = 512\n\tbad = 1x\n").unwrap();
+        assert_eq!(sizes.get_size("core.limit").unwrap(), Some(96 << 20));
+        assert_eq!(sizes.get_size("core.small").unwrap(), Some(512));
+        assert!(sizes.get_size("core.bad").is_err());
+        assert_eq!(sizes.get_size("core.unset").unwrap(), None);
+        let bools = Config::parse("[core]\n\ta = Yes\n\tb = off\n\tc =\n\td\n\te = 0\n\tf = -1\n\tg = maybe\n").unwrap();
+        let values: Vec<_> = "abcdef".chars().map(|key| bools.get_bool(&format!("core.{}", key)).unwrap()).collect();
+        assert_eq!(values, vec![Some(true), Some(false), Some(false), Some(true), Some(false), Some(true)]);
+        assert!(bools.get_bool("core.g").is_err());
+        assert_eq!(bools.get_bool("core.unset").unwrap(), None);
+        assert!(config.subsections("user").is_empty());
+        assert!(Config::parse("[core\n").is_err());
+        assert!(Config::parse("key = outside a section\n").is_err());
+    }
+
+    #[test]
+    fn testing_config_precedence() {
+        let dir = TempDir::new("config");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("global"), "[user]\n\tname = Global\n\temail = global@example.com\n").unwrap();
+        fs::write(dir.join("repo"), "[user]\n\tname = Repo\n").unwrap();
+        let files = [dir.join("missing"), dir.join("global"), dir.join("repo")];
+        let config = Config::from_files(&files, None).unwrap();
+        assert_eq!(config.get("user.name"), Some("Repo"));
+        assert_eq!(config.get("user.email"), Some("global@example.com"));
+    }
+
+    #[test]
+    fn testing_config_set() {
+        let dir = TempDir::new("config-set");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config");
+        fs::write(&path, "# keep me\n[core]\n\tbare = false ; comment\n[User]\n\tname = Old \\\n  Name\n\n[other]\n").unwrap();
+        set(&path, "user.name", "New Name").unwrap();
+        set(&path, "user.email", "new@example.com").unwrap();
+        set(&path, "core.bare", "true").unwrap();
+        set(&path, "remote.my\"origin.url", " /tmp/repo # x").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# keep me\n[core]\n\tbare = true\n[User]\n\tname = New Name\n\temail = new@example.com\n\n\
+                     [other]\n[remote \"my\\\"origin\"]\n\turl = \" /tmp/repo # x\"\n"
+        );
+        let config = Config::from_file(&path, None).unwrap();
+        assert_eq!(config.get("remote.my\"origin.url"), Some(" /tmp/repo # x"));
+        assert!(set(&path, "nodot", "x").is_err());
+    }
+
+    #[test]
+    fn testing_config_includes() {
+        let dir = TempDir::new("include");
+        let git_dir = dir.join("work/project/.git");
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::create_dir_all(&git_dir).unwrap();
+        fs::write(
+            dir.join("main"),
+            "[user]\n\tname = Before\n[include]\n\tpath = shared/identity\n\
+                     [includeIf \"gitdir:work/\"]\n\tpath = shared/work\n\
+                     [includeIf \"gitdir:elsewhere/\"]\n\tpath = shared/other\n",
+        )
+        .unwrap();
+        fs::write(dir.join("shared/identity"), "[user]\n\tname = Included\n\temail = a@example.com\n").unwrap();
+        fs::write(dir.join("shared/work"), "[user]\n\temail = work@example.com\n").unwrap();
+        fs::write(dir.join("shared/other"), "[user]\n\temail = other@example.com\n").unwrap();
+
+        let config = Config::from_file(&dir.join("main"), Some(&git_dir)).unwrap();
+        assert_eq!(config.get("user.name"), Some("Included"));
+        assert_eq!(config.get("user.email"), Some("work@example.com"));
+        let config = Config::from_file(&dir.join("main"), None).unwrap();
+        assert_eq!(config.get("user.email"), Some("a@example.com"));
+
+        fs::write(dir.join("shared/identity"), "[include]\n\tpath = ../main\n").unwrap();
+        let cycle = Config::from_file(&dir.join("main"), None);
+        assert_eq!(cycle.err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
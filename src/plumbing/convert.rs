@@ -0,0 +1,197 @@
+// Conversions between the content of blobs and of working tree files, as
+// attributes and configuration ask for them.
+
+use super::attributes::{self, State};
+use super::*;
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+
+// The `filter.<name>.<which>` command ("clean" or "smudge") of the path's
+// `filter=<name>` attribute, and whether the filter is required.
+fn filter_command(
+    config: &config::Config,
+    attributes: &BTreeMap<String, State>,
+    which: &str,
+) -> std::io::Result<Option<(String, bool)>> {
+    let name = match attributes.get("filter") {
+        Some(State::Value(name)) => name,
+        _ => return Ok(None),
+    };
+    let required = config.get_bool(&format!("filter.{}.required", name))?.unwrap_or(false);
+    Ok(config.get(&format!("filter.{}.{}", name, which)).map(|command| (command.to_string(), required)))
+}
+
+// Pipes `content` through the shell command `command`, in which `%f` is the
+// path being filtered. A filter that fails is skipped unless it's required.
+fn run_filter(repo: &Repository, command: &str, required: bool, path: &str, content: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let quoted = format!("'{}'", path.replace('\'', "'\\''"));
+    let failed = |message: String| match required {
+        true => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message)),
+        false => Ok(None),
+    };
+    let child = Command::new("sh")
+        .args(["-c", &command.replace("%f", &quoted)])
+        .current_dir(repo.work_tree().canonicalize().unwrap_or_else(|_| PathBuf::from(".")))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn();
+    let output = child.and_then(|mut child| {
+        // written from another thread so that a filter streaming its output
+        // can't block on a full pipe
+        let mut stdin = child.stdin.take().unwrap();
+        let input = content.clone();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output()?;
+        // a filter may exit without reading everything
+        let _ = writer.join();
+        Ok(output)
+    });
+    let filtered = match output {
+        Ok(output) if output.status.success() => Some(output.stdout),
+        Ok(_) => failed(format!("{}: filter '{}' failed", path, command))?,
+        Err(e) => failed(format!("cannot run filter '{}': {}", command, e))?,
+    };
+    Ok(filtered.unwrap_or(content))
+}
+
+// Whether text files get CRLF line endings when checked out, and so whether
+// `content` does.
+fn needs_crlf(config: &config::Config, attributes: &BTreeMap<String, State>, content: &[u8]) -> bool {
+    let autocrlf = config.get("core.autocrlf").map(str::to_lowercase);
+    let eol = match attributes.get("eol") {
+        Some(State::Value(eol)) => Some(eol.as_str()),
+        _ => None,
+    };
+    // text=auto and core.autocrlf leave files that look binary alone
+    let auto = match (attributes.get("text"), eol) {
+        (Some(State::Unset), _) => return false,
+        (Some(State::Set), _) | (None, Some("crlf")) | (None, Some("lf")) => false,
+        (Some(State::Value(text)), _) if text == "auto" => true,
+        _ if autocrlf.as_deref() == Some("true") => true,
+        _ => return false,
+    };
+    if auto && (content.contains(&0) || content.contains(&b'\r')) {
+        return false;
+    }
+    match eol {
+        Some(eol) => eol == "crlf",
+        None => match autocrlf.as_deref() {
+            Some("true") => true,
+            Some("input") => false,
+            _ => matches!(config.get("core.eol"), Some("crlf")),
+        },
+    }
+}
+
+// Whether `content` has its CRLFs turned into LFs when it's added, when the
+// file is text and git is asked to normalize it.
+fn normalizes(config: &config::Config, attributes: &BTreeMap<String, State>, content: &[u8]) -> bool {
+    let auto = match (attributes.get("text"), attributes.get("eol")) {
+        (Some(State::Unset), _) => return false,
+        (Some(State::Set), _) | (None, Some(State::Value(_))) => false,
+        (Some(State::Value(text)), _) if text == "auto" => true,
+        _ => match config.get("core.autocrlf").map(str::to_lowercase).as_deref() {
+            Some("true") | Some("input") => true,
+            _ => return false,
+        },
+    };
+    // lone CRs or NULs make a file binary
+    let binary = content.contains(&0)
+        || content.iter().enumerate().any(|(i, &byte)| byte == b'\r' && content.get(i + 1) != Some(&b'\n'));
+    !(auto && binary)
+}
+
+// What checking out `content` at `path` writes: LFs become CRLFs when the
+// file is text that should have them, then the smudge filter runs.
+pub fn to_worktree(repo: &Repository, path: &str, content: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let attributes = attributes::lookup(repo, path)?;
+    let content = match needs_crlf(repo.config(), &attributes, &content) {
+        true => {
+            let mut converted = Vec::with_capacity(content.len() + content.len() / 16);
+            for (i, &byte) in content.iter().enumerate() {
+                if byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+                    converted.push(b'\r');
+                }
+                converted.push(byte);
+            }
+            converted
+        }
+        false => content,
+    };
+    match filter_command(repo.config(), &attributes, "smudge")? {
+        Some((command, required)) => run_filter(repo, &command, required, path, content),
+        None => Ok(content),
+    }
+}
+
+// What adding the working tree file `path` with `content` stores, the
+// reverse of `to_worktree`: the clean filter runs, then CRLFs become LFs.
+pub fn to_git(repo: &Repository, path: &str, content: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let attributes = attributes::lookup(repo, path)?;
+    let content = match filter_command(repo.config(), &attributes, "clean")? {
+        Some((command, required)) => run_filter(repo, &command, required, path, content)?,
+        None => content,
+    };
+    if !normalizes(repo.config(), &attributes, &content) || !content.contains(&b'\r') {
+        return Ok(content);
+    }
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, &byte) in content.iter().enumerate() {
+        if !(byte == b'\r' && content.get(i + 1) == Some(&b'\n')) {
+            converted.push(byte);
+        }
+    }
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_line_ending_conversion() {
+        let crlf = |config: &str, attributes: &[(&str, State)], content: &[u8]| {
+            let attributes = attributes.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+            needs_crlf(&config::Config::parse(config).unwrap(), &attributes, content)
+        };
+        let value = |value: &str| State::Value(value.to_string());
+        let autocrlf = "[core]\n\tautocrlf = true\n";
+        assert!(!crlf("", &[], b"a\n"));
+        assert!(crlf(autocrlf, &[], b"a\n"));
+        assert!(!crlf(autocrlf, &[], b"a\0\n"));
+        assert!(!crlf(autocrlf, &[("text", State::Unset)], b"a\n"));
+        assert!(!crlf("[core]\n\tautocrlf = input\n", &[("text", State::Set)], b"a\n"));
+        assert!(crlf("[core]\n\teol = crlf\n", &[("text", State::Set)], b"a\n"));
+        assert!(crlf("", &[("eol", value("crlf"))], b"a\r\n"));
+        assert!(!crlf("", &[("text", value("auto")), ("eol", value("crlf"))], b"a\r\n"));
+        assert!(!crlf(autocrlf, &[("eol", value("lf"))], b"a\n"));
+    }
+
+    #[test]
+    fn testing_clean_and_smudge_filters() {
+        let dir = TempDir::new("filters");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::write(
+            git_dir.join("config"),
+            "[core]\n\tattributesFile = /nonexistent\n\
+                     [filter \"upper\"]\n\tclean = tr a-z A-Z\n\tsmudge = printf '%s: ' %f && cat\n\
+                     [filter \"broken\"]\n\tclean = false\n\
+                     [filter \"needed\"]\n\tclean = false\n\trequired = true\n",
+        )
+        .unwrap();
+        fs::write(dir.join(".gitattributes"), "*.up filter=upper text\n*.b filter=broken\n*.n filter=needed\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let cleaned = to_git(&repo, "a.up", b"one\r\ntwo\n".to_vec());
+        let smudged = to_worktree(&repo, "it's.up", b"ONE\n".to_vec());
+        let broken = to_git(&repo, "a.b", b"kept\r\n".to_vec());
+        let needed = to_git(&repo, "a.n", b"lost\n".to_vec());
+        let plain = to_git(&repo, "a.txt", b"a\r\n".to_vec());
+
+        assert_eq!(cleaned.unwrap(), b"ONE\nTWO\n");
+        assert_eq!(smudged.unwrap(), b"it's.up: ONE\n");
+        assert_eq!(broken.unwrap(), b"kept\r\n");
+        assert!(needed.is_err());
+        assert_eq!(plain.unwrap(), b"a\r\n");
+    }
+}
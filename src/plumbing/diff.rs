@@ -0,0 +1,559 @@
+use super::index::Index;
+use super::*;
+use std::collections::BTreeMap;
+
+// how many unchanged lines are printed around each change
+pub const CONTEXT: usize = 3;
+
+// what `diff` prints of each changed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Patch,
+    // the path, followed by `terminator`
+    NameOnly { terminator: char },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// The shortest edit script turning `old` into `new`, found with Myers' O(ND)
+// algorithm.
+pub fn line_diff(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    // `v[k + max + 1]` is the furthest x reached on diagonal k
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let index = |k: isize| (k + max + 1) as usize;
+    // the part of `v` around the diagonals each round started from
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v[index(-d - 1)..=index(d + 1)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x { Edit::Insert } else { Edit::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+// git's default hunk header function line: the closest line before the hunk
+// starting with a letter, `_` or `$`, cut to 80 bytes
+fn function_line(lines: &[&[u8]], before: usize) -> Option<Vec<u8>> {
+    let line = lines[..before]
+        .iter()
+        .rev()
+        .find(|line| matches!(line.first(), Some(c) if c.is_ascii_alphabetic() || *c == b'_' || *c == b'$'))?;
+    let mut line = line[..line.len().min(80)].to_vec();
+    while matches!(line.last(), Some(c) if c.is_ascii_whitespace()) {
+        line.pop();
+    }
+    Some(line)
+}
+
+// `-12,3`, with the count left out when it's 1 and the start pointing at the
+// line before the hunk when it's empty
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+// The `@@` hunks of a unified diff between `old` and `new`, with `context`
+// unchanged lines around each change.
+pub fn unified(old: &[u8], new: &[u8], context: usize) -> Vec<u8> {
+    let old_lines: Vec<&[u8]> = split_lines(old);
+    let new_lines: Vec<&[u8]> = split_lines(new);
+    let edits = line_diff(&old_lines, &new_lines);
+    // the old and new line numbers each edit starts at
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for edit in &edits {
+        positions.push((o, n));
+        match edit {
+            Edit::Equal => {
+                o += 1;
+                n += 1;
+            }
+            Edit::Delete => o += 1,
+            Edit::Insert => n += 1,
+        }
+    }
+    positions.push((o, n));
+
+    let changes: Vec<usize> = (0..edits.len()).filter(|&i| edits[i] != Edit::Equal).collect();
+    let mut out = Vec::new();
+    let mut c = 0;
+    while c < changes.len() {
+        let first = changes[c];
+        while c + 1 < changes.len() && changes[c + 1] - changes[c] - 1 <= 2 * context {
+            c += 1;
+        }
+        let start = first.saturating_sub(context);
+        let end = (changes[c] + context + 1).min(edits.len());
+        c += 1;
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        out.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@",
+                range(old_start, old_end - old_start),
+                range(new_start, new_end - new_start)
+            )
+            .as_bytes(),
+        );
+        if let Some(function) = function_line(&old_lines, old_start) {
+            out.push(b' ');
+            out.extend_from_slice(&function);
+        }
+        out.push(b'\n');
+        for i in start..end {
+            let (o, n) = positions[i];
+            let (prefix, line) = match edits[i] {
+                Edit::Equal => (b' ', old_lines[o]),
+                Edit::Delete => (b'-', old_lines[o]),
+                Edit::Insert => (b'+', new_lines[n]),
+            };
+            out.push(prefix);
+            out.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                out.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n').map_or(rest.len(), |i| i + 1);
+        lines.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    lines
+}
+
+// git treats content with a NUL in its first 8000 bytes as binary
+fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(8000)].contains(&0)
+}
+
+// The tree a commit, tag or tree `sha` points to.
+pub fn peel_to_tree(repo: &Repository, sha: &str) -> std::io::Result<String> {
+    let mut sha = sha.to_string();
+    loop {
+        let object = Blob::from_sha(repo, &sha)?;
+        sha = match object.object_type() {
+            ObjectType::Tree => return Ok(sha),
+            ObjectType::Commit => commit::Commit::parse(&object.content)?.tree,
+            ObjectType::Tag => tag::Tag::parse(&object.content)?.object,
+            ObjectType::Blob => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("object {} is not a tree-ish", sha),
+                ))
+            }
+        };
+    }
+}
+
+const NULL_SHA: &str = "0000000";
+
+fn content(repo: &Repository, mode: &str, sha: &str) -> std::io::Result<Vec<u8>> {
+    // gitlinks point at commits of another repository
+    if mode == "160000" {
+        return Ok(format!("Subproject commit {}\n", sha).into_bytes());
+    }
+    Ok(Blob::from_sha(repo, sha)?.content)
+}
+
+// One side of a file pair: its path, mode and blob id.
+pub struct Side<'a> {
+    pub path: &'a str,
+    pub mode: &'a str,
+    pub sha: &'a str,
+    // for a working tree file, which has no object to read
+    pub content: Option<&'a [u8]>,
+}
+
+fn side<'a>((path, (mode, sha)): (&'a String, &'a (String, String))) -> Side<'a> {
+    Side { path, mode, sha, content: None }
+}
+
+// Writes the `diff --git` of a file pair, `None` being the side it doesn't exist
+// on. `similarity` marks a rename, as a percentage.
+fn diff_file(
+    repo: &Repository,
+    old: Option<Side>,
+    new: Option<Side>,
+    similarity: Option<u64>,
+    out: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    let (old_path, new_path) = match (&old, &new) {
+        (Some(old), Some(new)) => (old.path, new.path),
+        (Some(old), None) => (old.path, old.path),
+        (None, Some(new)) => (new.path, new.path),
+        (None, None) => return Ok(()),
+    };
+    let mut header = format!("diff --git a/{} b/{}\n", old_path, new_path);
+    let (old_sha, new_sha) = match (&old, &new) {
+        (None, Some(new)) => {
+            header.push_str(&format!("new file mode {}\n", new.mode));
+            (NULL_SHA, &new.sha[..7])
+        }
+        (Some(old), None) => {
+            header.push_str(&format!("deleted file mode {}\n", old.mode));
+            (&old.sha[..7], NULL_SHA)
+        }
+        (Some(old), Some(new)) => {
+            if old.mode != new.mode {
+                header.push_str(&format!("old mode {}\nnew mode {}\n", old.mode, new.mode));
+            }
+            (&old.sha[..7], &new.sha[..7])
+        }
+        (None, None) => return Ok(()),
+    };
+    if let Some(similarity) = similarity {
+        header.push_str(&format!(
+            "similarity index {}%\nrename from {}\nrename to {}\n",
+            similarity, old_path, new_path
+        ));
+    }
+    let read = |side: &Option<Side>| match side {
+        Some(Side { content: Some(content), .. }) => Ok(content.to_vec()),
+        Some(side) => content(repo, side.mode, side.sha),
+        None => Ok(Vec::new()),
+    };
+    let old_content = read(&old)?;
+    let new_content = read(&new)?;
+    out.extend_from_slice(header.as_bytes());
+    if old.is_some() && new.is_some() && old_content == new_content {
+        // only the mode or the name changed
+        return Ok(());
+    }
+    out.extend_from_slice(format!("index {}..{}", old_sha, new_sha).as_bytes());
+    match (&old, &new) {
+        (Some(old), Some(new)) if old.mode == new.mode => {
+            out.extend_from_slice(format!(" {}", old.mode).as_bytes())
+        }
+        _ => {}
+    }
+    out.push(b'\n');
+    let old_name = old.as_ref().map_or("/dev/null".to_string(), |old| format!("a/{}", old.path));
+    let new_name = new.as_ref().map_or("/dev/null".to_string(), |new| format!("b/{}", new.path));
+    if is_binary(&old_content) || is_binary(&new_content) {
+        out.extend_from_slice(format!("Binary files {} and {} differ\n", old_name, new_name).as_bytes());
+    } else {
+        out.extend_from_slice(format!("--- {}\n+++ {}\n", old_name, new_name).as_bytes());
+        out.extend_from_slice(&unified(&old_content, &new_content, CONTEXT));
+    }
+    Ok(())
+}
+
+// rename scores are fractions of this, as in git
+pub const MAX_SCORE: u64 = 60000;
+// renames need at least 50% similarity unless `-M<n>` says otherwise
+pub const DEFAULT_RENAME_SCORE: u64 = MAX_SCORE / 2;
+
+// Parses the `<n>` of `-M<n>`: digits read as a decimal fraction (`-M9` is
+// 90%), or a percentage when followed by `%`.
+pub fn parse_rename_score(arg: &str) -> Option<u64> {
+    if arg.is_empty() {
+        return Some(DEFAULT_RENAME_SCORE);
+    }
+    let digits = arg.trim_end_matches('%');
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) || digits.len() > 9 {
+        return None;
+    }
+    let num: u64 = digits.parse().ok()?;
+    let scale = if arg.ends_with('%') { 100 } else { 10u64.pow(digits.len() as u32) };
+    Some(if num >= scale { MAX_SCORE } else { num * MAX_SCORE / scale })
+}
+
+// git's content fingerprint for rename detection: the number of bytes in each
+// chunk of the content, keyed by the chunk's hash. Chunks end after a newline
+// or at 64 bytes, and a CR before LF is ignored in text.
+fn spans(content: &[u8]) -> std::collections::HashMap<u32, u64> {
+    const HASHBASE: u32 = 107927;
+    let text = !is_binary(content);
+    let mut spans = std::collections::HashMap::new();
+    let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0u64);
+    for (i, &c) in content.iter().enumerate() {
+        if text && c == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old_1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old_1 >> 25);
+        accum1 = accum1.wrapping_add(c as u32);
+        n += 1;
+        if n < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+        *spans.entry(hash).or_insert(0) += n;
+        n = 0;
+        accum1 = 0;
+        accum2 = 0;
+    }
+    if n > 0 {
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+        *spans.entry(hash).or_insert(0) += n;
+    }
+    spans
+}
+
+// How much of `dst` was copied from `src`, out of MAX_SCORE. Pairs whose sizes
+// alone rule out reaching `min_score` score 0 without being compared.
+pub fn similarity(src: &[u8], dst: &[u8], min_score: u64) -> u64 {
+    let max_size = src.len().max(dst.len()) as u64;
+    let delta_size = max_size - src.len().min(dst.len()) as u64;
+    if dst.is_empty() || max_size * (MAX_SCORE - min_score) < delta_size * MAX_SCORE {
+        return 0;
+    }
+    let dst_spans = spans(dst);
+    let copied: u64 = spans(src)
+        .iter()
+        .map(|(hash, &count)| dst_spans.get(hash).map_or(0, |&dst_count| count.min(dst_count)))
+        .sum();
+    copied * MAX_SCORE / max_size
+}
+
+// Pairs deleted files with added ones: first those with the same blob, then
+// regular files whose similarity reaches `min_score`, best scores first.
+// Returns `(deleted index, added index, score)`.
+pub fn find_renames(
+    repo: &Repository,
+    deleted: &[(&String, &(String, String))],
+    added: &[(&String, &(String, String))],
+    min_score: u64,
+) -> std::io::Result<Vec<(usize, usize, u64)>> {
+    let is_regular = |mode: &str| mode.starts_with("100");
+    let mut renames = Vec::new();
+    let mut src_used = vec![false; deleted.len()];
+    let mut dst_used = vec![false; added.len()];
+    for (d, (_, (_, dst_sha))) in added.iter().enumerate() {
+        let src = (0..deleted.len()).find(|&s| !src_used[s] && deleted[s].1 .1 == *dst_sha);
+        if let Some(s) = src {
+            src_used[s] = true;
+            dst_used[d] = true;
+            renames.push((s, d, MAX_SCORE));
+        }
+    }
+
+    let load = |entries: &[(&String, &(String, String))], used: &[bool]| {
+        entries
+            .iter()
+            .zip(used)
+            .map(|((_, (mode, sha)), used)| match !used && is_regular(mode) {
+                true => Blob::from_sha(repo, sha).map(|blob| Some(blob.content)),
+                false => Ok(None),
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+    };
+    let sources = load(deleted, &src_used)?;
+    let destinations = load(added, &dst_used)?;
+    let mut candidates = Vec::new();
+    for (d, dst) in destinations.iter().enumerate() {
+        for (s, src) in sources.iter().enumerate() {
+            if let (Some(src), Some(dst)) = (src, dst) {
+                let score = similarity(src, dst, min_score);
+                if score >= min_score && score > 0 {
+                    candidates.push((s, d, score));
+                }
+            }
+        }
+    }
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.2));
+    for (s, d, score) in candidates {
+        if !src_used[s] && !dst_used[d] {
+            src_used[s] = true;
+            dst_used[d] = true;
+            renames.push((s, d, score));
+        }
+    }
+    Ok(renames)
+}
+
+// Prints the differences between the trees of the tree-ishes `old` and `new`
+// as a git-style unified diff, with renames detected when `rename_score` is set.
+pub fn diff_trees(
+    repo: &Repository,
+    old: &str,
+    new: &str,
+    rename_score: Option<u64>,
+    format: Format,
+) -> std::io::Result<()> {
+    let old_files = tree::files(repo, &peel_to_tree(repo, old)?)?;
+    let new_files = tree::files(repo, &peel_to_tree(repo, new)?)?;
+    diff_files(repo, &old_files, &new_files, rename_score, format)
+}
+
+// Diffs the index against the tree `rev`, as `diff --cached` does.
+pub fn diff_cached(repo: &Repository, rev: &str, rename_score: Option<u64>, format: Format) -> std::io::Result<()> {
+    let old_files = tree::files(repo, &peel_to_tree(repo, rev)?)?;
+    diff_files(repo, &old_files, &Index::read(repo)?.files(), rename_score, format)
+}
+
+// Diffs the working tree against the index. Like the index, it only covers
+// tracked files.
+pub fn diff_worktree(repo: &Repository, format: Format) -> std::io::Result<()> {
+    let index = Index::read(repo)?;
+    let files = index.files();
+    let changes = worktree::changes(repo, &index)?;
+    if let Format::NameOnly { terminator } = format {
+        let names = changes
+            .iter()
+            .map(|(path, _)| Ok(format!("{}{}", name(repo, path, terminator)?, terminator)))
+            .collect::<std::io::Result<String>>()?;
+        out!("{}", names)?;
+        return std::io::stdout().flush();
+    }
+    let hashes: Vec<Option<String>> = changes
+        .iter()
+        .map(|(_, file)| file.as_ref().map(|(_, content)| Blob::new(ObjectType::Blob, content.clone()).hash_string))
+        .collect();
+    let mut out = Vec::new();
+    for ((path, file), hash) in changes.iter().zip(&hashes) {
+        let old = side((path, &files[path]));
+        let new = match (file, hash) {
+            (Some((mode, content)), Some(sha)) => Some(Side { path, mode, sha, content: Some(content) }),
+            _ => None,
+        };
+        diff_file(repo, Some(old), new, None, &mut out)?;
+    }
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&out)?;
+    stdout.flush()
+}
+
+// a path as `--name-only` lists it, quoted unless NUL terminated
+fn name(repo: &Repository, path: &str, terminator: char) -> std::io::Result<String> {
+    match terminator {
+        '\0' => Ok(path.to_string()),
+        _ => Ok(quote_path(path, quotes_non_ascii(repo)?)),
+    }
+}
+
+fn diff_files(
+    repo: &Repository,
+    old_files: &BTreeMap<String, (String, String)>,
+    new_files: &BTreeMap<String, (String, String)>,
+    rename_score: Option<u64>,
+    format: Format,
+) -> std::io::Result<()> {
+    let deleted: Vec<_> = old_files.iter().filter(|(path, _)| !new_files.contains_key(*path)).collect();
+    let added: Vec<_> = new_files.iter().filter(|(path, _)| !old_files.contains_key(*path)).collect();
+    let renames = match rename_score {
+        Some(min_score) => find_renames(repo, &deleted, &added, min_score)?,
+        None => Vec::new(),
+    };
+
+    // every pair to print, sorted under its new path like git does
+    let mut pairs = Vec::new();
+    for (path, old_entry) in old_files {
+        match new_files.get(path) {
+            Some(new_entry) if new_entry != old_entry => {
+                pairs.push((path, Some(side((path, old_entry))), Some(side((path, new_entry))), None))
+            }
+            _ => {}
+        }
+    }
+    for &(s, d, score) in &renames {
+        pairs.push((added[d].0, Some(side(deleted[s])), Some(side(added[d])), Some(score * 100 / MAX_SCORE)));
+    }
+    for (s, &entry) in deleted.iter().enumerate() {
+        if !renames.iter().any(|rename| rename.0 == s) {
+            pairs.push((entry.0, Some(side(entry)), None, None));
+        }
+    }
+    for (d, &entry) in added.iter().enumerate() {
+        if !renames.iter().any(|rename| rename.1 == d) {
+            pairs.push((entry.0, None, Some(side(entry)), None));
+        }
+    }
+    pairs.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut out = Vec::new();
+    for (path, old, new, similarity) in pairs {
+        match format {
+            Format::Patch => diff_file(repo, old, new, similarity, &mut out)?,
+            Format::NameOnly { terminator } => write!(out, "{}{}", name(repo, path, terminator)?, terminator)?,
+        }
+    }
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&out)?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_unified_diff() {
+        let old = b"fn main() {\n    one();\n    two();\n    three();\n    four();\n    five();\n}\nlast";
+        let new = b"fn main() {\n    one();\n    two();\n    three();\n    FOUR();\n    five();\n}\nlast\n";
+        let expected = "@@ -2,7 +2,7 @@ fn main() {\n     one();\n     two();\n     three();\n-    four();\n+    FOUR();\n     five();\n }\n-last\n\\ No newline at end of file\n+last\n";
+        assert_eq!(String::from_utf8(unified(old, new, CONTEXT)).unwrap(), expected);
+        assert!(unified(old, old, CONTEXT).is_empty());
+        // the edit script keeps every line of both sides in order
+        let edits = line_diff(&split_lines(b"a\nb\nc\n"), &split_lines(b"b\nc\nd\n"));
+        assert_eq!(edits, vec![Edit::Delete, Edit::Equal, Edit::Equal, Edit::Insert]);
+    }
+
+    #[test]
+    fn testing_rename_scores() {
+        assert_eq!(parse_rename_score(""), Some(DEFAULT_RENAME_SCORE));
+        assert_eq!(parse_rename_score("9"), Some(54000));
+        assert_eq!(parse_rename_score("90%"), Some(54000));
+        assert_eq!(parse_rename_score("100%"), Some(MAX_SCORE));
+        assert_eq!(parse_rename_score("x"), None);
+        let old = b"one\ntwo\nthree\nfour\n";
+        assert_eq!(similarity(old, old, DEFAULT_RENAME_SCORE), MAX_SCORE);
+        assert_eq!(similarity(old, b"one\ntwo\nthree\nfive\n", DEFAULT_RENAME_SCORE), 44210);
+        assert_eq!(similarity(old, b"something else entirely\n", DEFAULT_RENAME_SCORE), 0);
+    }
+}
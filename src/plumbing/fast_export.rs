@@ -0,0 +1,126 @@
+use super::commit::Commit;
+use super::tag::Tag;
+use super::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// `path` as a fast-import stream has it
+fn quote(path: &str) -> String {
+    quote_spaced_path(path, true)
+}
+
+// git lists a commit's changes by path, a path before the ones it's a
+// prefix of
+fn depth_first(a: &str, b: &str) -> std::cmp::Ordering {
+    let length = a.len().min(b.len());
+    a.as_bytes()[..length].cmp(&b.as_bytes()[..length]).then(b.len().cmp(&a.len()))
+}
+
+fn data(out: &mut impl Write, content: &[u8]) -> std::io::Result<()> {
+    writeln!(out, "data {}", content.len())?;
+    out.write_all(content)
+}
+
+// Writes the history of `refs`, full ref names with the object each points
+// to, as the stream `git fast-export` gives: every commit not reachable from
+// `excluded`, parents first, with the blobs it brings just before it.
+// A commit is given for the first ref it was reached from, which is
+// reset to it before a root commit; refs pointing to commits given for
+// another one are reset at the end, and annotated tags come last. Excluded
+// parents are left out, a commit without its first parent listing all its
+// files.
+pub fn export(repo: &Repository, refs: &[(String, String)], excluded: &[String], out: &mut impl Write) -> std::io::Result<()> {
+    let mut sources: HashMap<String, String> = HashMap::new();
+    let mut tips = Vec::new();
+    let mut branches = Vec::new();
+    let mut tags = Vec::new();
+    for (name, sha) in refs {
+        let commit = Commit::peel(repo, sha)?;
+        match Blob::from_sha(repo, sha)?.object_type() {
+            ObjectType::Tag => tags.push((name, sha)),
+            _ => branches.push((name, commit.clone())),
+        }
+        sources.entry(commit.clone()).or_insert_with(|| name.clone());
+        tips.push(commit);
+    }
+    let history = ancestry::history(repo, &tips, excluded)?;
+    for (sha, parents) in &history {
+        let source = sources[sha].clone();
+        for parent in parents {
+            sources.entry(parent.clone()).or_insert_with(|| source.clone());
+        }
+    }
+
+    let mut marks: HashMap<String, usize> = HashMap::new();
+    let mut given = HashSet::new();
+    for (sha, _) in ancestry::topo_order(history).into_iter().rev() {
+        let commit = Commit::read(repo, &sha)?;
+        let parents: Vec<usize> = commit.parents.iter().filter_map(|parent| marks.get(parent).copied()).collect();
+        let base = match commit.parents.first() {
+            Some(parent) if marks.contains_key(parent) => tree::files(repo, &Commit::read(repo, parent)?.tree)?,
+            _ => BTreeMap::new(),
+        };
+        let files = tree::files(repo, &commit.tree)?;
+        let mut changes: Vec<(&String, Option<&(String, String)>)> =
+            base.keys().filter(|path| !files.contains_key(*path)).map(|path| (path, None)).collect();
+        for (path, file) in &files {
+            if base.get(path) != Some(file) {
+                changes.push((path, Some(file)));
+            }
+        }
+        // the blobs come in tree order, before the changes get sorted
+        changes.sort_by(|a, b| a.0.cmp(b.0));
+        for (_, file) in &changes {
+            if let Some((mode, blob)) = file {
+                if mode != "160000" && !marks.contains_key(blob) {
+                    marks.insert(blob.clone(), marks.len() + 1);
+                    writeln!(out, "blob\nmark :{}", marks.len())?;
+                    data(out, &Blob::from_sha(repo, blob)?.content)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+        }
+        changes.sort_by(|a, b| depth_first(a.0, b.0));
+
+        let name = &sources[&sha];
+        given.insert(name.clone());
+        if commit.parents.is_empty() {
+            writeln!(out, "reset {}", name)?;
+        }
+        marks.insert(sha.clone(), marks.len() + 1);
+        writeln!(out, "commit {}\nmark :{}", name, marks.len())?;
+        writeln!(out, "author {}\ncommitter {}", commit.author, commit.committer)?;
+        data(out, commit.message.as_bytes())?;
+        for (i, parent) in parents.iter().enumerate() {
+            writeln!(out, "{} :{}", if i == 0 { "from" } else { "merge" }, parent)?;
+        }
+        for (path, file) in changes {
+            match file {
+                None => writeln!(out, "D {}", quote(path))?,
+                Some((mode, blob)) if mode == "160000" => writeln!(out, "M {} {} {}", mode, blob, quote(path))?,
+                Some((mode, blob)) => writeln!(out, "M {:0>6} :{} {}", mode, marks[blob], quote(path))?,
+            }
+        }
+        out.write_all(b"\n")?;
+    }
+
+    for (name, commit) in branches.into_iter().rev().filter(|(name, _)| !given.contains(*name)) {
+        match marks.get(&commit) {
+            Some(mark) => write!(out, "reset {}\nfrom :{}\n\n", name, mark)?,
+            // all of its history was excluded
+            None => write!(out, "reset {}\nfrom {}\n\n", name, "0".repeat(40))?,
+        }
+    }
+    for (name, sha) in tags.into_iter().rev() {
+        let tag = Tag::parse(&Blob::from_sha(repo, sha)?.content)?;
+        let mark = marks.get(&tag.object).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("tag {} tags unexported object", name))
+        })?;
+        writeln!(out, "tag {}\nfrom :{}", name.trim_start_matches("refs/tags/"), mark)?;
+        if let Some(tagger) = &tag.tagger {
+            writeln!(out, "tagger {}", tagger)?;
+        }
+        data(out, tag.message.as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,420 @@
+use super::commit::Commit;
+use super::identity::Identity;
+use super::tag::Tag;
+use super::*;
+use std::collections::{BTreeMap, HashMap};
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+// The lines of a fast-import stream, and the raw bytes of its data.
+struct Stream<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Stream<'a> {
+    fn next_bytes(&mut self) -> Option<&'a [u8]> {
+        let rest = self.data.get(self.position..).filter(|rest| !rest.is_empty())?;
+        let end = rest.iter().position(|&byte| byte == b'\n').unwrap_or(rest.len());
+        self.position += end + 1;
+        Some(&rest[..end])
+    }
+
+    // the next line, without its `\n`
+    fn peek(&self) -> Option<String> {
+        let mut stream = Stream { data: self.data, position: self.position };
+        stream.next_bytes().map(|line| String::from_utf8_lossy(line).into_owned())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        self.next_bytes().map(|line| String::from_utf8_lossy(line).into_owned())
+    }
+
+    // the rest of the next line when it starts with `prefix`, which it's then
+    // taken
+    fn take(&mut self, prefix: &str) -> Option<String> {
+        let rest = self.peek()?.strip_prefix(prefix)?.to_string();
+        self.next();
+        Some(rest)
+    }
+
+    // `data <count>` and as many bytes, or `data <<<delimiter>` and the
+    // lines up to the delimiter
+    fn data(&mut self) -> std::io::Result<Vec<u8>> {
+        let line = self.next().unwrap_or_default();
+        let spec = line
+            .strip_prefix("data ")
+            .ok_or_else(|| invalid(format!("expected 'data n' command, found: {}", line)))?;
+        if let Some(delimiter) = spec.strip_prefix("<<") {
+            let mut content = Vec::new();
+            loop {
+                match self.next_bytes() {
+                    Some(line) if line == delimiter.as_bytes() => return Ok(content),
+                    Some(line) => {
+                        content.extend_from_slice(line);
+                        content.push(b'\n');
+                    }
+                    None => return Err(invalid(format!("EOF in data (terminator '{}' not found)", delimiter))),
+                }
+            }
+        }
+        let count: usize = spec.parse().map_err(|_| invalid(format!("invalid data length: {}", spec)))?;
+        let content = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or_else(|| invalid(format!("EOF in data ({} bytes remaining)", count)))?;
+        self.position += count;
+        if self.data.get(self.position) == Some(&b'\n') {
+            self.position += 1;
+        }
+        Ok(content.to_vec())
+    }
+}
+
+// Reads the path `text` starts with, C-style quoted up to its closing
+// quote or else up to a space when `spaced`, and the rest after it.
+fn path(text: &str, spaced: bool) -> std::io::Result<(String, &str)> {
+    let quoted = match text.strip_prefix('"') {
+        Some(quoted) => quoted.as_bytes(),
+        None => {
+            let end = match spaced {
+                true => text.find(' ').unwrap_or(text.len()),
+                false => text.len(),
+            };
+            return Ok((text[..end].to_string(), text[end..].trim_start_matches(' ')));
+        }
+    };
+    let mut path = Vec::new();
+    let mut i = 0;
+    while i < quoted.len() {
+        match quoted[i] {
+            b'"' => {
+                let rest = &text[i + 2..];
+                return Ok((String::from_utf8_lossy(&path).into_owned(), rest.trim_start_matches(' ')));
+            }
+            b'\\' => {
+                let escaped = *quoted.get(i + 1).ok_or_else(|| invalid(format!("invalid path: {}", text)))?;
+                let byte = match escaped {
+                    b'a' => b'\x07',
+                    b'b' => b'\x08',
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => b'\x0b',
+                    b'f' => b'\x0c',
+                    b'r' => b'\r',
+                    b'0'..=b'3' => {
+                        let digits = quoted.get(i + 1..i + 4).ok_or_else(|| invalid(format!("invalid path: {}", text)))?;
+                        i += 2;
+                        u8::from_str_radix(&String::from_utf8_lossy(digits), 8)
+                            .map_err(|_| invalid(format!("invalid path: {}", text)))?
+                    }
+                    other => other,
+                };
+                path.push(byte);
+                i += 2;
+            }
+            byte => {
+                path.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Err(invalid(format!("invalid path: {}", text)))
+}
+
+// the files below `path`, or the file at it
+fn below<'f>(files: &'f BTreeMap<String, (String, String)>, path: &'f str) -> impl Iterator<Item = String> + 'f {
+    files.keys().filter(move |file| *file == path || path.is_empty() || file.starts_with(&format!("{}/", path))).cloned()
+}
+
+// A ref the stream builds, with the files of its last commit.
+#[derive(Default, Clone)]
+struct Branch {
+    tip: Option<String>,
+    files: BTreeMap<String, (String, String)>,
+}
+
+struct Importer<'r> {
+    repo: &'r Repository,
+    marks: HashMap<String, String>,
+    branches: BTreeMap<String, Branch>,
+    tags: BTreeMap<String, String>,
+}
+
+impl Importer<'_> {
+    // `:<mark>`, a ref the stream built, or any other object name
+    fn object(&self, name: &str) -> std::io::Result<String> {
+        if name.starts_with(':') {
+            return self.marks.get(name).cloned().ok_or_else(|| invalid(format!("mark {} not declared", name)));
+        }
+        match self.branches.get(name).and_then(|branch| branch.tip.clone()) {
+            Some(tip) => Ok(tip),
+            None => match self.tags.get(name) {
+                Some(tag) => Ok(tag.clone()),
+                None => revision::resolve(self.repo, name),
+            },
+        }
+    }
+
+    fn mark(&mut self, mark: Option<String>, sha: &str) {
+        if let Some(mark) = mark {
+            self.marks.insert(mark, sha.to_string());
+        }
+    }
+
+    fn blob(&mut self, stream: &mut Stream) -> std::io::Result<()> {
+        let mark = stream.take("mark ");
+        stream.take("original-oid ");
+        let blob = Blob::from_vec(stream.data()?);
+        hash::write_to_database(self.repo, &blob)?;
+        self.mark(mark, &blob.hash_string);
+        Ok(())
+    }
+
+    fn commit(&mut self, stream: &mut Stream, name: &str) -> std::io::Result<()> {
+        let mark = stream.take("mark ");
+        stream.take("original-oid ");
+        let identity = |line: Option<String>, role: &str| match line {
+            Some(line) => Identity::parse(&line).map(Some).ok_or_else(|| invalid(format!("bad {}: {}", role, line))),
+            None => Ok(None),
+        };
+        let author = identity(stream.take("author "), "author")?;
+        let committer = identity(stream.take("committer "), "committer")?
+            .ok_or_else(|| invalid(format!("expected committer in commit {}", name)))?;
+        let extra_headers = stream.take("encoding ").map(|encoding| ("encoding".to_string(), encoding)).into_iter().collect();
+        let message = String::from_utf8_lossy(&stream.data()?).into_owned();
+
+        let mut branch = self.branches.get(name).cloned().unwrap_or_default();
+        let mut parents = Vec::new();
+        match stream.take("from ") {
+            Some(from) => {
+                let from = Commit::peel(self.repo, &self.object(&from)?)?;
+                branch.files = tree::files(self.repo, &Commit::read(self.repo, &from)?.tree)?;
+                parents.push(from);
+            }
+            None => parents.extend(branch.tip.clone()),
+        }
+        while let Some(merge) = stream.take("merge ") {
+            parents.push(Commit::peel(self.repo, &self.object(&merge)?)?);
+        }
+        while let Some(line) = stream.peek() {
+            match line.split_once(' ') {
+                Some(("M", rest)) => {
+                    stream.next();
+                    self.modify(stream, &mut branch.files, rest)?;
+                }
+                Some(("D", rest)) => {
+                    stream.next();
+                    let (path, _) = path(rest, false)?;
+                    for file in below(&branch.files.clone(), &path) {
+                        branch.files.remove(&file);
+                    }
+                }
+                Some((copy @ ("C" | "R"), rest)) => {
+                    stream.next();
+                    let (source, rest) = path(rest, true)?;
+                    let (destination, _) = path(rest, false)?;
+                    let files = branch.files.clone();
+                    let moved: Vec<String> = below(&files, &source).collect();
+                    if moved.is_empty() {
+                        return Err(invalid(format!("path {} not in branch", source)));
+                    }
+                    for file in moved {
+                        if copy == "R" {
+                            branch.files.remove(&file);
+                        }
+                        branch.files.insert(format!("{}{}", destination, &file[source.len()..]), files[&file].clone());
+                    }
+                }
+                None if line == "deleteall" => {
+                    stream.next();
+                    branch.files.clear();
+                }
+                _ => break,
+            }
+        }
+
+        let commit = Commit {
+            tree: tree::write_tree(self.repo, &branch.files)?,
+            parents,
+            author: author.unwrap_or_else(|| committer.clone()),
+            committer,
+            extra_headers,
+            message,
+        };
+        let sha = commit.write(self.repo)?;
+        self.mark(mark, &sha);
+        branch.tip = Some(sha);
+        self.branches.insert(name.to_string(), branch);
+        Ok(())
+    }
+
+    // `M <mode> <dataref> <path>`, the data inline, a blob, a submodule's
+    // commit, or a tree to put at the path
+    fn modify(&mut self, stream: &mut Stream, files: &mut BTreeMap<String, (String, String)>, rest: &str) -> std::io::Result<()> {
+        let mut fields = rest.splitn(3, ' ');
+        let (mode, dataref, path_field) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(mode), Some(dataref), Some(path)) => (mode, dataref, path),
+            _ => return Err(invalid(format!("invalid filemodify: M {}", rest))),
+        };
+        let mode = match mode {
+            "644" | "100644" => "100644",
+            "755" | "100755" => "100755",
+            "120000" | "160000" => mode,
+            "40000" | "040000" => "40000",
+            _ => return Err(invalid(format!("corrupt mode: M {}", rest))),
+        };
+        let (path, _) = path(path_field, false)?;
+        let sha = match dataref {
+            "inline" => {
+                let blob = Blob::from_vec(stream.data()?);
+                hash::write_to_database(self.repo, &blob)?;
+                blob.hash_string
+            }
+            _ if mode == "160000" && is_object_id(dataref) => dataref.to_string(),
+            _ => self.object(dataref)?,
+        };
+        for file in below(&files.clone(), &path) {
+            files.remove(&file);
+        }
+        match mode {
+            "40000" => {
+                let prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+                tree::flatten(self.repo, &sha, &prefix, files)?;
+            }
+            _ => {
+                files.insert(path, (mode.to_string(), sha));
+            }
+        }
+        Ok(())
+    }
+
+    fn tag(&mut self, stream: &mut Stream, name: &str) -> std::io::Result<()> {
+        let mark = stream.take("mark ");
+        let from = stream.take("from ").ok_or_else(|| invalid(format!("expected from command in tag {}", name)))?;
+        let object = self.object(&from)?;
+        stream.take("original-oid ");
+        let tagger = match stream.take("tagger ") {
+            Some(line) => Some(Identity::parse(&line).ok_or_else(|| invalid(format!("bad tagger: {}", line)))?),
+            None => None,
+        };
+        let tag = Tag {
+            object_type: Blob::from_sha(self.repo, &object)?.object_type(),
+            object,
+            tag: name.to_string(),
+            tagger,
+            extra_headers: Vec::new(),
+            message: String::from_utf8_lossy(&stream.data()?).into_owned(),
+        };
+        let blob = Blob::new(ObjectType::Tag, tag.serialize());
+        hash::write_to_database(self.repo, &blob)?;
+        self.mark(mark, &blob.hash_string);
+        self.tags.insert(format!("refs/tags/{}", name), blob.hash_string);
+        Ok(())
+    }
+
+    fn reset(&mut self, stream: &mut Stream, name: &str) -> std::io::Result<()> {
+        let mut branch = Branch::default();
+        if let Some(from) = stream.take("from ") {
+            let from = self.object(&from)?;
+            branch.files = tree::files(self.repo, &Commit::read(self.repo, &Commit::peel(self.repo, &from)?)?.tree)?;
+            branch.tip = Some(from);
+        }
+        self.branches.insert(name.to_string(), branch);
+        Ok(())
+    }
+}
+
+// Creates the objects a fast-import `stream` describes, then points the
+// refs it built at them. Unless `force` is set a ref is only updated to a
+// descendant of the commit it points to; the refs that weren't are
+// returned.
+pub fn import(repo: &Repository, stream: &[u8], force: bool) -> std::io::Result<Vec<String>> {
+    let mut stream = Stream { data: stream, position: 0 };
+    let mut importer = Importer { repo, marks: HashMap::new(), branches: BTreeMap::new(), tags: BTreeMap::new() };
+    while let Some(line) = stream.next() {
+        let (command, argument) = line.split_once(' ').unwrap_or((&line, ""));
+        match command {
+            "" | "#" | "checkpoint" => {}
+            _ if line.starts_with('#') => {}
+            "blob" => importer.blob(&mut stream)?,
+            "commit" => importer.commit(&mut stream, argument)?,
+            "tag" => importer.tag(&mut stream, argument)?,
+            "reset" => importer.reset(&mut stream, argument)?,
+            "progress" => outln!("{}", line)?,
+            "done" => break,
+            "feature" if argument == "done" || argument == "date-format=raw" => {}
+            "feature" => return Err(invalid(format!("this fast-import does not support feature {}", argument))),
+            // options for other importers are for them to use
+            "option" if !argument.starts_with("git ") => {}
+            _ => return Err(invalid(format!("unsupported command: {}", line))),
+        }
+    }
+
+    let mut refused = Vec::new();
+    for (name, branch) in &importer.branches {
+        let tip = match &branch.tip {
+            Some(tip) => tip,
+            None => continue,
+        };
+        if let Some(old) = refs::resolve(repo, name)? {
+            if !force && old != *tip && !ancestry::ancestors(repo, &Commit::peel(repo, tip)?)?.contains(&Commit::peel(repo, &old)?) {
+                eprintln!("warning: Not updating {} (new tip {} does not contain {})", name, tip, old);
+                refused.push(name.clone());
+                continue;
+            }
+        }
+        refs::update(repo, name, tip, None)?;
+    }
+    for (name, tag) in &importer.tags {
+        refs::update(repo, name, tag, None)?;
+    }
+    Ok(refused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_fast_import_round_trip() {
+        let dir = TempDir::new("fast-import");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let stream = "blob\nmark :1\ndata 4\none\n\n\
+                              reset refs/heads/main\ncommit refs/heads/main\nmark :2\n\
+                              author A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\ndata 6\nfirst\n\
+                              M 100644 :1 \"a b\"\nM 100644 :1 \"tab\\there\"\n\n\
+                              blob\nmark :3\ndata 4\ntwo\n\n\
+                              commit refs/heads/main\nmark :4\n\
+                              author A <a@b> 1 +0000\ncommitter A <a@b> 1 +0000\ndata 7\nsecond\nfrom :2\n\
+                              D \"a b\"\nM 100644 :3 dir/c\n\n\
+                              tag v1\nfrom :4\ntagger A <a@b> 2 +0000\ndata 4\ntag\n\n";
+        let refused = import(&repo, stream.as_bytes(), false).unwrap();
+        // the same history given differently
+        let rewritten = "commit refs/heads/other\ncommitter A <a@b> 0 +0000\ndata <<EOM\nfirst\nEOM\n\
+                                 M 644 inline \"tab\\there\"\ndata 4\none\n\
+                                 C \"tab\\there\" \"a b\"\n\
+                                 commit refs/heads/other\ncommitter A <a@b> 1 +0000\ndata 7\nsecond\n\
+                                 R \"a b\" dir/c\nM 644 inline dir/c\ndata 4\ntwo\n";
+        import(&repo, rewritten.as_bytes(), false).unwrap();
+        let main = refs::resolve(&repo, "refs/heads/main").unwrap().unwrap();
+        let other = refs::resolve(&repo, "refs/heads/other").unwrap();
+        let tag = refs::resolve(&repo, "refs/tags/v1").unwrap().unwrap();
+        let exported = vec![("refs/heads/main".to_string(), main.clone()), ("refs/tags/v1".to_string(), tag)];
+        let mut out = Vec::new();
+        fast_export::export(&repo, &exported, &[], &mut out).unwrap();
+        let diverging = "commit refs/heads/main\ncommitter A <a@b> 5 +0000\ndata 0\nfrom :404\n";
+        let unknown_mark = import(&repo, diverging.as_bytes(), false).unwrap_err().to_string();
+        let root = "commit refs/heads/main\ncommitter A <a@b> 5 +0000\ndata 0\n";
+        let refused_root = import(&repo, root.as_bytes(), false).unwrap();
+
+        assert!(refused.is_empty());
+        assert_eq!(String::from_utf8(out).unwrap(), stream);
+        assert_eq!(other, Some(main));
+        assert_eq!(unknown_mark, "mark :404 not declared");
+        assert_eq!(refused_root, vec!["refs/heads/main"]);
+    }
+}
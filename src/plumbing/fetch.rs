@@ -0,0 +1,341 @@
+use super::commit::Commit;
+use super::refspec::{self, RefSpec};
+use super::tag::Tag;
+use super::*;
+use std::collections::HashSet;
+
+// Copies the objects reachable from `sha` that `to` doesn't have from `from`,
+// as stored: a replace ref leaves them alone, and is only copied as a ref. An
+// object `to` already has is taken to come with everything it references.
+// Referenced objects are written before the objects referencing them. The
+// parents of the `boundary` commits are left out.
+fn copy_objects(from: &Repository, to: &Repository, sha: &str, boundary: &HashSet<String>) -> std::io::Result<()> {
+    let mut missing = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![sha.to_string()];
+    while let Some(sha) = pending.pop() {
+        if to.has_object(&sha) || !seen.insert(sha.clone()) {
+            continue;
+        }
+        let object = Blob::from_store(from.objects(), &sha)?;
+        match object.object_type() {
+            ObjectType::Commit => {
+                let commit = Commit::parse(&object.content)?;
+                pending.push(commit.tree);
+                if !boundary.contains(&sha) {
+                    pending.extend(commit.parents);
+                }
+            }
+            ObjectType::Tree => {
+                let tree = Tree::try_pars(&object)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+                // submodule commits live in another repository
+                let entries = tree.entries().iter().filter(|entry| entry.entry_type != EntryType::Gitlink);
+                pending.extend(entries.map(|entry| Blob::string_hash(&entry.sha)));
+            }
+            ObjectType::Tag => pending.push(Tag::parse(&object.content)?.object),
+            ObjectType::Blob => {}
+        }
+        missing.push(object);
+    }
+    for object in missing.iter().rev() {
+        hash::write_to_database(to, object)?;
+    }
+    Ok(())
+}
+
+// The commits `depth` commits down from `tips` along their parents, which
+// a history cut there ends with. The remote's own shallow commits end it
+// earlier.
+fn shallow_boundary(from: &Repository, tips: &[String], depth: u32) -> std::io::Result<HashSet<String>> {
+    let parents = ancestry::Parents::stored(from)?;
+    let mut seen = HashSet::new();
+    let mut level: Vec<String> = tips.iter().filter(|tip| seen.insert(tip.to_string())).cloned().collect();
+    for _ in 1..depth.max(1) {
+        let mut next = Vec::new();
+        for sha in &level {
+            for parent in parents.of(sha)? {
+                if seen.insert(parent.clone()) {
+                    next.push(parent);
+                }
+            }
+        }
+        level = next;
+    }
+    Ok(level.into_iter().collect())
+}
+
+// `refs/heads/main` -> `main`, as fetch prints ref names
+fn short_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+// where a fetch gets its objects: a repository on this machine, whose
+// objects are copied, or a smart http server sending a pack
+enum Source {
+    Local(Repository),
+    Http(protocol::Http, protocol::Advertisement),
+}
+
+// Fetches from the configured remote `name`, a local repository or a smart
+// http url: its refs are mapped through `remote.<name>.fetch`, the objects
+// they need are copied or sent in a pack and the local refs updated.
+// Updates that aren't fast-forwards need a `+` refspec, and existing tags
+// are never moved without one.
+pub fn fetch(repo: &Repository, name: &str) -> std::io::Result<()> {
+    fetch_to_depth(repo, name, None).map(|_| ())
+}
+
+// `fetch`, with a `depth` cutting the history fetched that many commits
+// down from the remote's refs, the commits it ends with written to
+// `shallow`. Returns the ref the remote's HEAD points to, when it says.
+pub fn fetch_to_depth(repo: &Repository, name: &str, depth: Option<u32>) -> std::io::Result<Option<String>> {
+    let config = repo.config();
+    let url = config.get(&format!("remote.{}.url", name)).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, format!("'{}' is not a configured remote", name))
+    })?;
+    let http = url.starts_with("http://") || url.starts_with("https://");
+    if url.contains("://") && !url.starts_with("file://") && !http {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("cannot fetch from {}, only local repositories and smart http are supported", url),
+        ));
+    }
+    let (advertised, mut source, head_ref) = match http {
+        true => {
+            let mut transport = protocol::Http::new(url);
+            let advertisement = protocol::Advertisement::parse(&protocol::Transport::advertisement(&mut transport)?)?;
+            let head_ref = advertisement.head_ref().map(String::from);
+            (advertisement.refs.clone(), Source::Http(transport, advertisement), head_ref)
+        }
+        false => {
+            let path = Path::new(url.trim_start_matches("file://"));
+            let remote = match path.join(GIT_DIR) {
+                git_dir if git_dir.exists() => Repository::open(git_dir)?,
+                _ => Repository::open(path)?,
+            };
+            let mut advertised = refs::list(&remote)?;
+            if let Some(head) = refs::resolve(&remote, "HEAD")? {
+                advertised.insert(0, ("HEAD".to_string(), head));
+            }
+            let head_ref = refs::symbolic_target(&remote, "HEAD")?;
+            (advertised, Source::Local(remote), head_ref)
+        }
+    };
+    let specs = config
+        .get_all(&format!("remote.{}.fetch", name))
+        .into_iter()
+        .map(RefSpec::parse)
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let names: Vec<String> = advertised.iter().map(|(name, _)| name.clone()).collect();
+    let updates = refspec::match_refs(&specs, &names);
+    let new_id = |update: &refspec::Update| advertised.iter().find(|(name, _)| *name == update.src).unwrap().1.clone();
+    match &mut source {
+        Source::Local(remote) => {
+            let tips: Vec<String> = updates.iter().map(new_id).collect();
+            let mut boundary = remote.shallow_commits()?;
+            if let Some(depth) = depth {
+                boundary.extend(shallow_boundary(remote, &tips, depth)?);
+            }
+            for tip in &tips {
+                copy_objects(remote, repo, tip, &boundary)?;
+            }
+            // the boundary commits that came without their parents
+            let mut shallow = repo.shallow_commits()?;
+            for sha in boundary {
+                let parents = Commit::read(repo, &sha).map(|commit| commit.parents);
+                if matches!(parents, Ok(parents) if parents.is_empty() || !parents.iter().all(|parent| repo.has_object(parent))) {
+                    shallow.insert(sha);
+                }
+            }
+            repo.set_shallow_commits(&shallow)?;
+        }
+        Source::Http(transport, advertisement) => {
+            let mut wants: Vec<String> = Vec::new();
+            for sha in updates.iter().map(new_id) {
+                if !repo.has_object(&sha) && !wants.contains(&sha) {
+                    wants.push(sha);
+                }
+            }
+            if !wants.is_empty() {
+                protocol::fetch_pack(repo, transport, advertisement, &wants, depth, std::io::stderr())?;
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut rejected = false;
+    for update in updates {
+        let new = &new_id(&update);
+        let dst = match &update.dst {
+            Some(dst) => dst,
+            None => continue,
+        };
+        let tag = dst.starts_with("refs/tags/");
+        let (flag, summary, note) = match refs::resolve(repo, dst)? {
+            Some(old) if old == *new => continue,
+            None if tag => ('*', "[new tag]".to_string(), ""),
+            None if update.src.starts_with("refs/heads/") => ('*', "[new branch]".to_string(), ""),
+            None => ('*', "[new ref]".to_string(), ""),
+            Some(old) if !tag && ancestry::ancestors(repo, new)?.contains(&old) => {
+                (' ', format!("{}..{}", &old[..7], &new[..7]), "")
+            }
+            Some(old) if update.force => ('+', format!("{}...{}", &old[..7], &new[..7]), "  (forced update)"),
+            Some(_) => {
+                rejected = true;
+                let note = if tag { "  (would clobber existing tag)" } else { "  (non-fast-forward)" };
+                lines.push(('!', "[rejected]".to_string(), update.src.clone(), dst.clone(), note));
+                continue;
+            }
+        };
+        refs::update(repo, dst, new, None)?;
+        lines.push((flag, summary, update.src.clone(), dst.clone(), note));
+    }
+
+    if !lines.is_empty() {
+        eprintln!("From {}", url);
+    }
+    let width = lines.iter().map(|line| short_name(&line.2).len()).max().unwrap_or(0);
+    for (flag, summary, src, dst, note) in &lines {
+        eprintln!(" {} {:<17} {:<width$} -> {}{}", flag, summary, short_name(src), short_name(dst), note, width = width);
+    }
+    if rejected {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("some refs could not be updated from {}", url),
+        ));
+    }
+    Ok(head_ref)
+}
+
+// Clones `url` into `dir` as clone does: a repository is made there with
+// `url` as its `origin`, which is fetched, to `depth` when given, and the
+// branch the remote's HEAD points to is created from it and checked out.
+// `dir` may exist as long as it is empty; what was made goes again when
+// the clone fails. A remote without that branch leaves nothing checked
+// out, like the clone of an empty repository.
+pub fn clone(url: &str, dir: &Path, depth: Option<u32>) -> std::io::Result<Repository> {
+    if matches!(fs::read_dir(dir).map(|mut entries| entries.next()), Ok(Some(_))) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("destination path '{}' already exists and is not an empty directory", dir.display()),
+        ));
+    }
+    // a local remote is found from the clone as well
+    let url = match url.contains("://") {
+        true => url.to_string(),
+        false => fs::canonicalize(url)?.to_string_lossy().into_owned(),
+    };
+    let existed = dir.exists();
+    fs::create_dir_all(dir)?;
+    let cloned = clone_into(&url, dir, depth);
+    if cloned.is_err() {
+        let _ = match existed {
+            true => fs::remove_dir_all(dir.join(GIT_DIR)),
+            false => fs::remove_dir_all(dir),
+        };
+    }
+    cloned
+}
+
+fn clone_into(url: &str, dir: &Path, depth: Option<u32>) -> std::io::Result<Repository> {
+    let git_dir = dir.join(GIT_DIR);
+    init_repository(&git_dir, None)?;
+    remote::add(&Repository::open(&git_dir)?, "origin", url)?;
+    let repo = Repository::open(&git_dir)?;
+    let head_ref = fetch_to_depth(&repo, "origin", depth)?;
+    let branch = match head_ref.as_deref().and_then(|name| name.strip_prefix("refs/heads/")) {
+        Some(branch) => branch.to_string(),
+        None => return Ok(repo),
+    };
+    let tracking = format!("refs/remotes/origin/{}", branch);
+    let sha = match refs::resolve(&repo, &tracking)? {
+        Some(sha) => sha,
+        None => return Ok(repo),
+    };
+    refs::update(&repo, &format!("refs/heads/{}", branch), &sha, None)?;
+    fs::write(repo.ref_path("refs/remotes/origin/HEAD"), format!("ref: {}\n", tracking))?;
+    fs::write(git_dir.join("HEAD"), format!("ref: refs/heads/{}\n", branch))?;
+    let config = git_dir.join("config");
+    config::set(&config, &format!("branch.{}.remote", branch), "origin")?;
+    config::set(&config, &format!("branch.{}.merge", branch), &format!("refs/heads/{}", branch))?;
+    let repo = Repository::open(&git_dir)?.with_work_tree(dir);
+    reset::read_tree(&repo, "HEAD", true, false)?;
+    Ok(repo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A clone to a depth has the history down to it, the commits there being
+    // shallow and walks stopping at them. A depth past the root clones everything, and a directory in
+    // use isn't cloned into.
+    #[test]
+    fn testing_shallow_clone() {
+        let dir = TempDir::new("shallow-clone");
+        fs::create_dir_all(dir.join("remote/.git/objects")).unwrap();
+        fs::write(dir.join("remote/.git/HEAD"), "ref: refs/heads/master\n").unwrap();
+        let remote = Repository::open(dir.join("remote").join(GIT_DIR)).unwrap();
+        let mut tip: Option<String> = None;
+        let mut commits = Vec::new();
+        for i in 0..4 {
+            let blob = Blob::new(ObjectType::Blob, format!("{}\n", i).into_bytes());
+            hash::write_to_database(&remote, &blob).unwrap();
+            let mut files = std::collections::BTreeMap::new();
+            files.insert(format!("{}.txt", i), ("100644".to_string(), blob.hash_string.clone()));
+            let tree = tree::write_tree(&remote, &files).unwrap();
+            tip = Some(test_commit(&remote, &tree, &tip.iter().collect::<Vec<_>>(), i, &format!("{}\n", i)));
+            commits.push(tip.clone().unwrap());
+        }
+        refs::update(&remote, "refs/heads/master", tip.as_ref().unwrap(), None).unwrap();
+
+        let url = dir.join("remote").to_string_lossy().into_owned();
+        let shallow = clone(&url, &dir.join("shallow"), Some(2)).unwrap();
+        let boundary = fs::read_to_string(dir.join("shallow/.git/shallow")).unwrap();
+        let history = ancestry::ancestors(&shallow, &commits[3]).unwrap().len();
+        let copied: Vec<bool> = commits.iter().map(|sha| shallow.has_object(sha)).collect();
+        let checked_out = fs::read_to_string(dir.join("shallow/3.txt")).unwrap();
+        let branch = refs::resolve(&shallow, "HEAD").unwrap();
+        let all = clone(&url, &dir.join("all"), Some(10)).unwrap();
+        let complete = all.git_dir().join("shallow").exists();
+        fs::create_dir_all(dir.join("taken")).unwrap();
+        fs::write(dir.join("taken/file"), "").unwrap();
+        let taken = clone(&url, &dir.join("taken"), None).err().map(|e| e.kind());
+
+        assert_eq!(boundary, format!("{}\n", commits[2]));
+        assert_eq!(history, 2);
+        assert_eq!(copied, vec![false, false, true, true]);
+        assert_eq!(checked_out, "3\n");
+        assert_eq!(branch, tip);
+        assert!(!complete);
+        assert_eq!(taken, Some(std::io::ErrorKind::AlreadyExists));
+    }
+
+    // A replace ref on the remote doesn't change what is cloned: the commits
+    // come as stored, with the history they have.
+    #[test]
+    fn testing_clone_with_replace_refs() {
+        let dir = TempDir::new("replaced-clone");
+        fs::create_dir_all(dir.join("remote/.git/objects")).unwrap();
+        fs::write(dir.join("remote/.git/HEAD"), "ref: refs/heads/master\n").unwrap();
+        let remote = Repository::open(dir.join("remote").join(GIT_DIR)).unwrap();
+        let tree = remote.write_tree(&[]).unwrap();
+        let root = test_commit(&remote, &tree, &[] as &[&str], 0, "root\n");
+        let tip = test_commit(&remote, &tree, &[&root], 1, "tip\n");
+        let replacement = test_commit(&remote, &tree, &[] as &[&str], 2, "replacement\n");
+        refs::update(&remote, "refs/heads/master", &tip, None).unwrap();
+        refs::update(&remote, &format!("refs/replace/{}", tip), &replacement, None).unwrap();
+
+        let url = dir.join("remote").to_string_lossy().into_owned();
+        let cloned = clone(&url, &dir.join("clone"), None).unwrap();
+
+        assert_eq!(Commit::read(&cloned, &tip).unwrap().parents, vec![root.clone()]);
+        assert!(cloned.has_object(&root));
+        assert!(!cloned.has_object(&replacement));
+    }
+}
@@ -0,0 +1,233 @@
+use super::commit::Commit;
+use super::tag::Tag;
+use super::*;
+
+// a piece of a `%(<atom>)` format: text printed as is, or an atom's name
+pub enum Piece {
+    Text(String),
+    Atom(String),
+}
+
+// Splits `format` into its pieces, `%%` standing for a `%`. An atom that
+// isn't `known` is an error, reported before anything is printed.
+pub fn parse(format: &str, known: &[&str]) -> std::io::Result<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut rest = format;
+    while let Some(percent) = rest.find('%') {
+        text.push_str(&rest[..percent]);
+        rest = &rest[percent..];
+        if let Some(after) = rest.strip_prefix("%%") {
+            text.push('%');
+            rest = after;
+            continue;
+        }
+        let atom = match rest.strip_prefix("%(").and_then(|after| after.find(')').map(|end| &after[..end])) {
+            Some(atom) => atom,
+            None => {
+                text.push('%');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        if !known.contains(&atom) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("unknown format element: {}", atom),
+            ));
+        }
+        pieces.push(Piece::Text(std::mem::take(&mut text)));
+        pieces.push(Piece::Atom(atom.to_string()));
+        rest = &rest[atom.len() + 3..];
+    }
+    text.push_str(rest);
+    pieces.push(Piece::Text(text));
+    Ok(pieces)
+}
+
+// the text of `pieces` with each atom replaced by what `value` gives for it
+pub fn expand<F>(pieces: &[Piece], mut value: F) -> std::io::Result<String>
+where
+    F: FnMut(&str) -> std::io::Result<String>,
+{
+    let mut expanded = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => expanded.push_str(text),
+            Piece::Atom(atom) => expanded.push_str(&value(atom)?),
+        }
+    }
+    Ok(expanded)
+}
+
+pub const REF_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+pub const REF_ATOMS: &[&str] = &[
+    "refname",
+    "refname:short",
+    "objectname",
+    "objectname:short",
+    "objecttype",
+    "subject",
+    "authordate",
+];
+
+// The value of a `REF_ATOMS` atom for the ref `refname` pointing at `object`.
+// Objects without a subject or an author leave those empty.
+pub fn ref_atom(refname: &str, object: &Blob, atom: &str) -> std::io::Result<String> {
+    Ok(match (atom, object.object_type()) {
+        ("refname", _) => refname.to_string(),
+        ("refname:short", _) => {
+            let short = ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+                .iter()
+                .find_map(|prefix| refname.strip_prefix(prefix));
+            short.unwrap_or(refname).to_string()
+        }
+        ("objectname", _) => object.hash_string.clone(),
+        ("objectname:short", _) => object.hash_string[..7].to_string(),
+        ("objecttype", object_type) => object_type.to_string(),
+        ("subject", ObjectType::Commit) => Commit::parse(&object.content)?.subject(),
+        ("subject", ObjectType::Tag) => commit::subject(&Tag::parse(&object.content)?.message),
+        ("authordate", ObjectType::Commit) => Commit::parse(&object.content)?.author.date(),
+        _ => String::new(),
+    })
+}
+
+// How `log` shows a commit: git's default `medium` format, `oneline`, or a
+// `--format` of placeholders. A `format:` is put between commits, a
+// `tformat:` (also any format given without a prefix) after each one.
+pub enum Pretty {
+    Medium,
+    Oneline,
+    Format(String),
+    TFormat(String),
+}
+
+impl Pretty {
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "medium" => Pretty::Medium,
+            "oneline" => Pretty::Oneline,
+            _ => match (value.strip_prefix("format:"), value.strip_prefix("tformat:")) {
+                (Some(format), _) => Pretty::Format(format.to_string()),
+                (_, Some(format)) => Pretty::TFormat(format.to_string()),
+                _ if value.contains('%') => Pretty::TFormat(value.to_string()),
+                _ => return None,
+            },
+        })
+    }
+
+    // the text printed between two commits
+    pub fn separator(&self) -> &str {
+        match self {
+            Pretty::Medium | Pretty::Format(_) => "\n",
+            Pretty::Oneline | Pretty::TFormat(_) => "",
+        }
+    }
+
+    // the text printed after each commit
+    pub fn terminator(&self) -> &str {
+        match self {
+            Pretty::Medium | Pretty::Format(_) => "",
+            Pretty::Oneline | Pretty::TFormat(_) => "\n",
+        }
+    }
+
+    // The commit `sha` shown in this format, without the terminator.
+    pub fn show(&self, sha: &str, commit: &Commit) -> String {
+        match self {
+            Pretty::Medium => {
+                let mut shown = format!("commit {}\n", sha);
+                if commit.parents.len() > 1 {
+                    let parents: Vec<&str> = commit.parents.iter().map(|parent| &parent[..7]).collect();
+                    shown += &format!("Merge: {}\n", parents.join(" "));
+                }
+                shown += &expand_commit("Author: %an <%ae>%nDate:   %ad%n", sha, commit);
+                for line in commit.message.trim_end().lines() {
+                    shown += "\n    ";
+                    shown += line;
+                }
+                shown + "\n"
+            }
+            Pretty::Oneline => expand_commit("%h %s", sha, commit),
+            Pretty::Format(format) | Pretty::TFormat(format) => expand_commit(format, sha, commit),
+        }
+    }
+}
+
+// Replaces the placeholders git's `--format` knows in `format` with what
+// they say about the commit `sha`. Unknown placeholders are kept as is.
+pub fn expand_commit(format: &str, sha: &str, commit: &Commit) -> String {
+    let mut expanded = String::new();
+    let mut rest = format;
+    while let Some(percent) = rest.find('%') {
+        expanded.push_str(&rest[..percent]);
+        rest = &rest[percent + 1..];
+        let (value, length) = match rest.get(..2).unwrap_or(rest) {
+            "an" => (commit.author.name.clone(), 2),
+            "ae" => (commit.author.email.clone(), 2),
+            "ad" => (commit.author.date(), 2),
+            "cn" => (commit.committer.name.clone(), 2),
+            "ce" => (commit.committer.email.clone(), 2),
+            "cd" => (commit.committer.date(), 2),
+            placeholder => match placeholder.get(..1).unwrap_or("") {
+                "H" => (sha.to_string(), 1),
+                "h" => (sha[..7].to_string(), 1),
+                "T" => (commit.tree.clone(), 1),
+                "t" => (commit.tree[..7].to_string(), 1),
+                "P" => (commit.parents.join(" "), 1),
+                "p" => (commit.parents.iter().map(|parent| &parent[..7]).collect::<Vec<_>>().join(" "), 1),
+                "s" => (commit.subject(), 1),
+                "n" => ("\n".to_string(), 1),
+                "%" => ("%".to_string(), 1),
+                _ => ("%".to_string(), 0),
+            },
+        };
+        expanded.push_str(&value);
+        rest = &rest[length..];
+    }
+    expanded + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_formats() {
+        let pieces = parse("%(objecttype) %(objectname) 100%% %x [%(rest)] %(", &["objecttype", "objectname", "rest"]).unwrap();
+        let expanded = expand(&pieces, |atom| Ok(format!("<{}>", atom))).unwrap();
+        assert_eq!(expanded, "<objecttype> <objectname> 100% %x [<rest>] %(");
+        let unknown = parse("%(objectname) %(foo)", &["objectname"]).err().unwrap();
+        assert_eq!(unknown.to_string(), "unknown format element: foo");
+
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                    author A <a@b> 1700000000 +0130\ncommitter A <a@b> 1700000000 +0000\n\nSubject line\n\nBody\n";
+        let commit = Blob::new(ObjectType::Commit, commit.to_vec());
+        let atoms: Vec<String> = REF_ATOMS
+            .iter()
+            .map(|atom| ref_atom("refs/heads/main", &commit, atom).unwrap())
+            .collect();
+        let expected = vec![
+            "refs/heads/main".to_string(),
+            "main".to_string(),
+            commit.hash_string.clone(),
+            commit.hash_string[..7].to_string(),
+            "commit".to_string(),
+            "Subject line".to_string(),
+            "Tue Nov 14 23:43:20 2023 +0130".to_string(),
+        ];
+        assert_eq!(atoms, expected);
+
+        let parsed = Commit::parse(&commit.content).unwrap();
+        let sha = &commit.hash_string;
+        let expanded = expand_commit("%h %s%n%an <%ae> %ad %% %x %", sha, &parsed);
+        assert_eq!(expanded, format!("{} Subject line\nA <a@b> Tue Nov 14 23:43:20 2023 +0130 % %x %", &sha[..7]));
+        let medium = Pretty::parse("medium").unwrap().show(sha, &parsed);
+        assert_eq!(
+            medium,
+            format!("commit {}\nAuthor: A <a@b>\nDate:   Tue Nov 14 23:43:20 2023 +0130\n\n    Subject line\n    \n    Body\n", sha)
+        );
+        assert!(Pretty::parse("fuller").is_none());
+    }
+}
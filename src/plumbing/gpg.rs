@@ -0,0 +1,72 @@
+use super::*;
+use std::process::{Command, Stdio};
+
+// Runs `gpg.program` (`gpg` by default) the way git does to produce an
+// armored detached signature of `payload` with `key`.
+pub fn sign(config: &config::Config, payload: &[u8], key: &str) -> std::io::Result<String> {
+    let program = config.get("gpg.program").unwrap_or("gpg");
+    let mut child = Command::new(program)
+        .args(["--status-fd=2", "-bsau", key])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| std::io::Error::new(e.kind(), format!("cannot run {}: {}", program, e)))?;
+    child.stdin.take().unwrap().write_all(payload)?;
+    let output = child.wait_with_output()?;
+    let status = String::from_utf8_lossy(&output.stderr);
+    let created = status.lines().any(|line| line.starts_with("[GNUPG:] SIG_CREATED "));
+    if !output.status.success() || !created {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} failed to sign the data", program),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Checks `signature` over `payload` with `gpg --verify`, forwarding gpg's
+// report to the standard error. Returns whether the signature is good.
+pub fn verify(config: &config::Config, payload: &[u8], signature: &str) -> std::io::Result<bool> {
+    let program = config.get("gpg.program").unwrap_or("gpg");
+    let signature_file = write_signature_file(signature)?;
+    let child = Command::new(program)
+        .args(["--status-fd=1", "--keyid-format=long", "--verify"])
+        .arg(&signature_file)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let output = child.and_then(|mut child| {
+        child.stdin.take().unwrap().write_all(payload)?;
+        child.wait_with_output()
+    });
+    fs::remove_file(&signature_file)?;
+    let output =
+        output.map_err(|e| std::io::Error::new(e.kind(), format!("cannot run {}: {}", program, e)))?;
+    std::io::stderr().write_all(&output.stderr)?;
+    let status = String::from_utf8_lossy(&output.stdout);
+    let good = status.lines().any(|line| line.starts_with("[GNUPG:] GOODSIG "));
+    Ok(output.status.success() && good)
+}
+
+// writes `signature` for gpg to read to a new file in the temp dir
+fn write_signature_file(signature: &str) -> std::io::Result<PathBuf> {
+    write_temp(&std::env::temp_dir(), ".git_vtag_tmp", signature.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn testing_signature_files() {
+        let first = write_signature_file("signature\n").unwrap();
+        let second = write_signature_file("signature\n").unwrap();
+        let contents = (fs::read_to_string(&first).unwrap(), fs::read_to_string(&second).unwrap());
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(contents, ("signature\n".to_string(), "signature\n".to_string()));
+    }
+}
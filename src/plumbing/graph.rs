@@ -0,0 +1,467 @@
+// What the next line of a commit's graph shows: a line of branches going
+// straight down, the `...` of a skipped part, the lines widening for an
+// octopus merge, the commit itself, the edges to a merge's parents, or
+// branches moving left to where they belong now.
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Padding,
+    Skip,
+    PreCommit,
+    Commit,
+    PostMerge,
+    Collapsing,
+}
+
+// The ascii history `log --graph` draws next to each commit, laid out the
+// way git's graph.c does it, without the colors. Each column is a branch
+// line waiting for the commit it names; a merge adds columns for its other
+// parents and lines for the same commit are collapsed into one.
+// `mapping` says, for each screen column, which column the line there
+// will end up in, -1 for none.
+pub struct Graph {
+    commit: String,
+    parents: Vec<String>,
+    width: usize,
+    expansion_row: usize,
+    state: State,
+    prev_state: State,
+    commit_index: usize,
+    prev_commit_index: usize,
+    merge_layout: isize,
+    edges_added: isize,
+    prev_edges_added: isize,
+    columns: Vec<String>,
+    new_columns: Vec<String>,
+    mapping: Vec<isize>,
+    old_mapping: Vec<isize>,
+    mapping_size: usize,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self {
+            commit: String::new(),
+            parents: Vec::new(),
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: Vec::new(),
+            new_columns: Vec::new(),
+            mapping: Vec::new(),
+            old_mapping: Vec::new(),
+            mapping_size: 0,
+        }
+    }
+
+    // Moves on to the commit `sha`, with the parents of it that are shown.
+    pub fn update(&mut self, sha: &str, parents: Vec<String>) {
+        self.commit = sha.to_string();
+        self.parents = parents;
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    // `text` about the current commit with the graph in front of each line,
+    // the commit's line on the first one. Lines are added when the graph
+    // needs more of them than `text` has.
+    pub fn show(&mut self, text: &str) -> String {
+        let mut shown = String::new();
+        while self.state != State::Padding {
+            let (line, commit_line) = self.next_line();
+            shown += &line;
+            if commit_line {
+                break;
+            }
+            shown += "\n";
+        }
+        let mut lines = text.split_inclusive('\n').peekable();
+        while let Some(line) = lines.next() {
+            shown += line;
+            if line.ends_with('\n') && lines.peek().is_some() {
+                shown += &self.next_line().0;
+            }
+        }
+        if self.state != State::Padding {
+            if !text.ends_with('\n') {
+                shown += "\n";
+            }
+            loop {
+                shown += &self.next_line().0;
+                if self.state == State::Padding {
+                    break;
+                }
+                shown += "\n";
+            }
+            if text.ends_with('\n') {
+                shown += "\n";
+            }
+        }
+        shown
+    }
+
+    // The graph for a line that goes between two commits or after a commit's
+    // text, leaving every branch line as it is.
+    pub fn padding(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+        let mut line = String::new();
+        for column in &self.columns {
+            line.push('|');
+            if *column == self.commit && self.parents.len() > 2 {
+                line += &" ".repeat((self.parents.len() - 2) * 2);
+            } else {
+                line.push(' ');
+            }
+        }
+        self.prev_state = State::Padding;
+        self.pad(line)
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn update_columns(&mut self) {
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+        let max_new_columns = self.columns.len() + self.parents.len();
+        if self.mapping.len() < 2 * max_new_columns {
+            self.mapping.resize(2 * max_new_columns, -1);
+            self.old_mapping.resize(2 * max_new_columns, -1);
+        }
+        self.mapping_size = 2 * max_new_columns;
+        for target in &mut self.mapping[..self.mapping_size] {
+            *target = -1;
+        }
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column.clone(),
+                None if seen_this => break,
+                None => self.commit.clone(),
+            };
+            if column == self.commit {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    self.insert_into_new_columns(&parent, Some(i));
+                }
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(&column, None);
+            }
+        }
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    // Adds a line for `commit`, unless it has one, and maps the screen column
+    // it starts at next to it. `index` is the commit's column when `commit`
+    // is one of its parents.
+    fn insert_into_new_columns(&mut self, commit: &str, index: Option<usize>) {
+        let i = match self.new_columns.iter().position(|column| column == commit) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(commit.to_string());
+                self.new_columns.len() - 1
+            }
+        };
+        let mapping_index = match index {
+            Some(index) if self.parents.len() > 1 && self.merge_layout == -1 => {
+                // whether the first parent's line is left of the merge
+                // decides how the edges to the parents are drawn
+                let distance = index as isize - i as isize;
+                let shift = if distance > 1 { 2 * distance - 3 } else { 1 };
+                self.merge_layout = if distance > 0 { 0 } else { 1 };
+                self.edges_added = self.parents.len() as isize + self.merge_layout - 2;
+                let mapping_index = self.width as isize + (self.merge_layout - 1) * shift;
+                self.width += 2 * self.merge_layout as usize;
+                mapping_index as usize
+            }
+            _ if self.edges_added > 0 && self.width >= 2 && self.mapping[self.width - 2] == i as isize => {
+                // an edge the merge added joins the last line right away
+                self.edges_added = -1;
+                self.width - 2
+            }
+            _ => {
+                self.width += 2;
+                self.width - 2
+            }
+        };
+        self.mapping[mapping_index] = i as isize;
+    }
+
+    fn dashed_parents(&self) -> isize {
+        self.parents.len() as isize + self.merge_layout - 3
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && (self.commit_index as isize) < self.columns.len() as isize - 1
+            && (self.expansion_row as isize) < self.dashed_parents() * 2
+    }
+
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, &target)| target < 0 || target == i as isize / 2)
+    }
+
+    fn pad(&self, mut line: String) -> String {
+        while line.len() < self.width {
+            line.push(' ');
+        }
+        line
+    }
+
+    // the next line of the graph, and whether it is the commit's line
+    fn next_line(&mut self) -> (String, bool) {
+        let commit_line = self.state == State::Commit;
+        let line = match self.state {
+            State::Padding => self.new_columns.iter().map(|_| "| ").collect(),
+            State::Skip => {
+                let state = if self.needs_pre_commit_line() { State::PreCommit } else { State::Commit };
+                self.update_state(state);
+                "...".to_string()
+            }
+            State::PreCommit => self.pre_commit_line(),
+            State::Commit => self.commit_line(),
+            State::PostMerge => self.post_merge_line(),
+            State::Collapsing => self.collapsing_line(),
+        };
+        (self.pad(line), commit_line)
+    }
+
+    // a line moving the branches right of an octopus merge out of the way
+    // of its edges
+    fn pre_commit_line(&mut self) -> String {
+        let mut line = String::new();
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if *column == self.commit {
+                seen_this = true;
+                line.push('|');
+                line += &" ".repeat(self.expansion_row);
+            } else if seen_this && self.expansion_row == 0 {
+                let after_merge = self.prev_state == State::PostMerge && self.prev_commit_index < i;
+                line.push(if after_merge { '\\' } else { '|' });
+            } else if seen_this {
+                line.push('\\');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+        line
+    }
+
+    fn commit_line(&mut self) -> String {
+        let mut line = String::new();
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let is_commit = match self.columns.get(i) {
+                Some(column) => *column == self.commit,
+                None if seen_this => break,
+                None => true,
+            };
+            if is_commit {
+                seen_this = true;
+                line.push('*');
+                if self.parents.len() > 2 {
+                    let dashed = self.dashed_parents();
+                    for i in 0..dashed {
+                        line.push('-');
+                        line.push(if i == dashed - 1 { '.' } else { '-' });
+                    }
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push('\\');
+            } else if seen_this && self.edges_added == 1 {
+                let after_merge = self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i;
+                line.push(if after_merge { '\\' } else { '|' });
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping[2 * i + 1] == i as isize
+                && self.mapping[2 * i] < i as isize
+            {
+                line.push('/');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+        let state = if self.parents.len() > 1 {
+            State::PostMerge
+        } else if self.is_mapping_correct() {
+            State::Padding
+        } else {
+            State::Collapsing
+        };
+        self.update_state(state);
+        line
+    }
+
+    // the line with the edges from a merge to its parents
+    fn post_merge_line(&mut self) -> String {
+        let mut line = String::new();
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let is_commit = match self.columns.get(i) {
+                Some(column) => *column == self.commit,
+                None if seen_this => break,
+                None => true,
+            };
+            if is_commit {
+                seen_this = true;
+                let mut edge = self.merge_layout as usize;
+                for j in 0..self.parents.len() {
+                    line.push(['/', '|', '\\'][edge]);
+                    if edge < 2 {
+                        edge += 1;
+                    } else if self.edges_added > 0 || j < self.parents.len() - 1 {
+                        line.push(' ');
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                line.push(if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push('|');
+                if self.merge_layout != 0 || i + 1 != self.commit_index {
+                    line.push(' ');
+                }
+            }
+        }
+        let state = if self.is_mapping_correct() { State::Padding } else { State::Collapsing };
+        self.update_state(state);
+        line
+    }
+
+    // A line moving each branch line one step to the left, towards the
+    // column it belongs in. One line may move further, drawn with `_`.
+    fn collapsing_line(&mut self) -> String {
+        let mut horizontal_edge = None;
+        let mut horizontal_edge_target = -1;
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        for target in &mut self.mapping[..self.mapping_size] {
+            *target = -1;
+        }
+        for i in 0..self.mapping_size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+            if target * 2 == i as isize {
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                self.mapping[i - 1] = target;
+                if horizontal_edge.is_none() {
+                    horizontal_edge = Some(i);
+                    horizontal_edge_target = target;
+                    for j in (target as usize * 2 + 3..i.saturating_sub(2)).step_by(2) {
+                        self.mapping[j] = target;
+                    }
+                }
+            } else if self.mapping[i - 1] != target {
+                // cross the line to the left, to the one it joins
+                self.mapping[i - 2] = target;
+                if horizontal_edge.is_none() {
+                    horizontal_edge = Some(i - 1);
+                    horizontal_edge_target = target;
+                    for j in (target as usize * 2 + 3..i.saturating_sub(2)).step_by(2) {
+                        self.mapping[j] = target;
+                    }
+                }
+            }
+        }
+        let size = self.mapping_size;
+        self.old_mapping[..size].copy_from_slice(&self.mapping[..size]);
+        if self.mapping[size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+        let mut line = String::new();
+        let mut used_horizontal = false;
+        let horizontal_edge = horizontal_edge.unwrap_or(0);
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+            if target < 0 {
+                line.push(' ');
+            } else if target * 2 == i as isize {
+                line.push('|');
+            } else if target == horizontal_edge_target && i + 1 != horizontal_edge {
+                // only the first segment goes on to the next line
+                if i as isize != target * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.push('_');
+            } else {
+                if used_horizontal && i < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                line.push('/');
+            }
+        }
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_graph() {
+        let mut graph = Graph::new();
+        let history: &[(&str, &[&str])] =
+            &[("m", &["b", "s"]), ("s", &["a"]), ("b", &["a"]), ("a", &[])];
+        let mut shown = String::new();
+        for (commit, parents) in history {
+            graph.update(commit, parents.iter().map(|parent| parent.to_string()).collect());
+            shown += &graph.show(commit);
+            shown += "\n";
+        }
+        assert_eq!(shown, "*   m\n|\\  \n| * s\n* | b\n|/  \n* a\n");
+    }
+}
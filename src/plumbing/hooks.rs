@@ -0,0 +1,86 @@
+use super::*;
+use std::process::Command;
+
+// `.git/hooks/<name>`, or the one in `core.hooksPath`, which is relative to
+// the top of the working tree.
+fn path(repo: &Repository, name: &str) -> PathBuf {
+    match repo.config().get("core.hookspath") {
+        Some(dir) => top(repo).join(dir).join(name),
+        None => repo.common_dir().join("hooks").join(name),
+    }
+}
+
+fn top(repo: &Repository) -> PathBuf {
+    match repo.work_tree() {
+        top if top.as_os_str().is_empty() => PathBuf::from("."),
+        top => top,
+    }
+}
+
+// Runs the hook `name` with `args` from the top of the working tree, the
+// way git does, and returns whether it succeeded. A missing hook
+// succeeds; one that isn't executable is skipped with a hint, as git does.
+pub fn run(repo: &Repository, name: &str, args: &[&str]) -> std::io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    let path = path(repo, name);
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    if metadata.permissions().mode() & 0o111 == 0 {
+        eprintln!("hint: The '{}' hook was ignored because it's not set as executable.", path.display());
+        return Ok(true);
+    }
+    let status = Command::new(fs::canonicalize(&path)?).args(args).current_dir(top(repo)).status()?;
+    Ok(status.success())
+}
+
+// Lets the `commit-msg` hook check and edit `message`, through the file
+// `file` in the repository that it's given the path of. The file is left
+// behind when the hook fails. Returns the message the hook left, `None`
+// when it failed.
+pub fn commit_msg(repo: &Repository, file: &str, message: &str) -> std::io::Result<Option<String>> {
+    let path = repo.git_dir().join(file);
+    fs::write(&path, message)?;
+    let absolute = fs::canonicalize(&path)?;
+    if !run(repo, "commit-msg", &[&absolute.to_string_lossy()])? {
+        return Ok(None);
+    }
+    let message = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_hooks() {
+        let dir = TempDir::new("hooks");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("hooks")).unwrap();
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        let hook = |name: &str, script: &str, mode: u32| {
+            let path = git_dir.join("hooks").join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(mode)).unwrap();
+        };
+        hook("commit-msg", "#!/bin/sh\necho \"Signed-off-by: $(basename $PWD)\" >> \"$1\"\n", 0o755);
+        hook("pre-merge-commit", "#!/bin/sh\nexit 1\n", 0o755);
+        hook("post-merge", "#!/bin/sh\nexit 1\n", 0o644);
+        let repo = Repository::open(&git_dir).unwrap();
+        let message = commit_msg(&repo, "MERGE_MSG", "Merge\n").unwrap();
+        let cleaned = !git_dir.join("MERGE_MSG").exists();
+        let failed = run(&repo, "pre-merge-commit", &[]).unwrap();
+        let ignored = run(&repo, "post-merge", &["0"]).unwrap();
+        let missing = run(&repo, "post-commit", &[]).unwrap();
+
+        let top = dir.file_name().unwrap().to_string_lossy();
+        assert_eq!(message, Some(format!("Merge\nSigned-off-by: {}\n", top)));
+        assert!(cleaned);
+        assert!(!failed);
+        assert!(ignored && missing);
+    }
+}
@@ -0,0 +1,528 @@
+use super::*;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The `Name <email> <unix-seconds> <+HHMM>` identity found in the author,
+// committer and tagger lines. `offset` is the timezone offset in minutes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+    pub time: i64,
+    pub offset: i32,
+}
+
+impl Identity {
+    pub fn parse(line: &str) -> Option<Self> {
+        let open = line.find('<')?;
+        let close = open + line[open..].find('>')?;
+        let mut date = line[close + 1..].split_whitespace();
+        let time = date.next()?.parse().ok()?;
+        let offset = parse_offset(date.next()?)?;
+        Some(Self {
+            name: line[..open].trim().to_string(),
+            email: line[open + 1..close].to_string(),
+            time,
+            offset,
+        })
+    }
+
+    // Builds the identity for `role` ("AUTHOR" or "COMMITTER") the way git does:
+    // `GIT_<ROLE>_NAME`/`_EMAIL` override `user.name`/`user.email` and
+    // `GIT_<ROLE>_DATE` overrides the current time.
+    pub fn from_env(role: &str, config: &config::Config) -> std::io::Result<Self> {
+        let var = |name: &str| env::var(format!("GIT_{}_{}", role, name)).ok();
+        let unknown = || {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "{} identity unknown, please set user.name and user.email",
+                    role.to_lowercase()
+                ),
+            )
+        };
+        let name = var("NAME")
+            .or_else(|| config.get("user.name").map(String::from))
+            .ok_or_else(unknown)?;
+        let email = var("EMAIL")
+            .or_else(|| config.get("user.email").map(String::from))
+            .or_else(|| env::var("EMAIL").ok())
+            .ok_or_else(unknown)?;
+        let (time, offset) = match var("DATE") {
+            Some(date) => parse_date(&date).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid date format: {}", date),
+                )
+            })?,
+            None => {
+                let now = now();
+                (now, local_offset(now))
+            }
+        };
+        Ok(Self { name, email, time, offset })
+    }
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+impl Identity {
+    // The time in git's default date format, `Thu Apr 7 15:13:13 2005 -0700`,
+    // in the identity's own timezone.
+    pub fn date(&self) -> String {
+        const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+        let local = self.time + self.offset as i64 * 60;
+        let (days, seconds) = (local.div_euclid(86400), local.rem_euclid(86400));
+        let (year, month, day) = civil_from_days(days);
+        format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            DAYS[days.rem_euclid(7) as usize],
+            MONTHS[month as usize - 1],
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            year,
+            format_offset(self.offset)
+        )
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} <{}> {} {}", self.name, self.email, self.time, format_offset(self.offset))
+    }
+}
+
+// minutes east of UTC -> `+HHMM`/`-HHMM`
+pub fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{}{:02}{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+// `+HHMM`, `-HHMM` or `+HH:MM` -> minutes east of UTC
+pub fn parse_offset(offset: &str) -> Option<i32> {
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = offset[1..].chars().filter(|&c| c != ':').collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+// the current time in unix seconds
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Like `parse_date`, but also takes the relative dates `--since` and `--until`
+// are mostly given: `now`, `yesterday` and `<n> <unit>s ago`, the spaces
+// possibly dots. Months and years go back on the calendar, other units by
+// their length in seconds.
+pub fn approxidate(date: &str, now: i64) -> Option<i64> {
+    if let Some((time, _)) = parse_date(date) {
+        return Some(time);
+    }
+    let date = date.to_lowercase();
+    let words: Vec<&str> = date.split(|c: char| c == '.' || c.is_whitespace()).filter(|word| !word.is_empty()).collect();
+    let (count, unit) = match words.as_slice() {
+        ["now"] => return Some(now),
+        ["yesterday"] => return Some(now - 86400),
+        [count, unit, "ago"] => (count.parse::<i64>().ok()?, unit.strip_suffix('s').unwrap_or(unit)),
+        _ => return None,
+    };
+    let seconds = match unit {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        "month" => return Some(months_before(now, count)),
+        "year" => return Some(months_before(now, count * 12)),
+        _ => return None,
+    };
+    Some(now - count * seconds)
+}
+
+// `time` moved back `months` calendar months, a day past the end of the
+// month it lands in spilling over into the next one
+fn months_before(time: i64, months: i64) -> i64 {
+    let (year, month, day) = civil_from_days(time.div_euclid(86400));
+    let month = year * 12 + month - 1 - months;
+    days_from_civil(month.div_euclid(12), month.rem_euclid(12) + 1, day) * 86400 + time.rem_euclid(86400)
+}
+
+// Accepts git's internal `[@]<unix-seconds> [<offset>]` format, ISO 8601
+// (`2005-04-07T22:13:13+0200`, the `T` can be a space) and RFC 2822
+// (`Thu, 07 Apr 2005 22:13:13 +0200`). A missing offset in the ISO form
+// means local time.
+pub fn parse_date(date: &str) -> Option<(i64, i32)> {
+    let date = date.trim();
+    let mut parts = date.trim_start_matches('@').split_whitespace();
+    if let Some(Ok(time)) = parts.next().map(str::parse::<i64>) {
+        // the day of an RFC 2822 date is a number too
+        if let (Some(offset), None) = (parts.next().map_or(Some(0), parse_offset), parts.next()) {
+            return Some((time, offset));
+        }
+    }
+    parse_iso_date(date).or_else(|| parse_rfc2822_date(date))
+}
+
+// a date's field of digits only, no sign
+fn number(s: &str) -> Option<i64> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// `hh:mm[:ss]` as seconds into the day
+fn parse_clock(clock: &str) -> Option<i64> {
+    let mut hms = clock.split(':');
+    let hour = hms.next().filter(|h| !h.is_empty()).map_or(Some(0), number)?;
+    let minute = hms.next().map_or(Some(0), number)?;
+    let second = hms.next().map_or(Some(0), number)?;
+    if hms.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some(hour * 3600 + minute * 60 + second)
+}
+
+fn parse_iso_date(date: &str) -> Option<(i64, i32)> {
+    let (day, rest) = date.split_at(date.find(['T', ' ']).unwrap_or(date.len()));
+    let mut ymd = day.split('-');
+    let year = number(ymd.next()?)?;
+    let month = number(ymd.next()?)?;
+    let mday = number(ymd.next()?)?;
+    if ymd.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&mday) {
+        return None;
+    }
+    let rest = rest.get(1..).unwrap_or("").trim();
+    let zone_at = rest.find(['Z', '+', '-']).unwrap_or(rest.len());
+    let (clock, zone) = rest.split_at(zone_at);
+    let local = days_from_civil(year, month, mday) * 86400 + parse_clock(clock.trim())?;
+    let offset = match zone.trim() {
+        "" => local_offset(local),
+        "Z" => 0,
+        zone => parse_offset(zone)?,
+    };
+    Some((local - offset as i64 * 60, offset))
+}
+
+// `[<weekday>,] <day> <month> <year> <hh:mm[:ss]> <zone>`, the zone an
+// offset or one of the names for UTC
+fn parse_rfc2822_date(date: &str) -> Option<(i64, i32)> {
+    let date = match date.split_once(',') {
+        Some((weekday, rest)) if weekday.len() == 3 && weekday.chars().all(|c| c.is_ascii_alphabetic()) => rest,
+        _ => date,
+    };
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    if parts.len() != 5 {
+        return None;
+    }
+    let mday = number(parts[0]).filter(|mday| (1..=31).contains(mday))?;
+    let month = MONTHS.iter().position(|month| month.eq_ignore_ascii_case(parts[1]))? as i64 + 1;
+    let year = number(parts[2])?;
+    let local = days_from_civil(year, month, mday) * 86400 + parse_clock(parts[3])?;
+    let offset = match parts[4] {
+        "GMT" | "UT" | "UTC" | "Z" => 0,
+        zone => parse_offset(zone)?,
+    };
+    Some((local - offset as i64 * 60, offset))
+}
+
+// days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// the proleptic gregorian `(year, month, day)` of a day since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// The local offset in minutes at `time`, read from the zoneinfo file named by
+// `TZ` or from /etc/localtime. Defaults to UTC when neither can be read.
+pub fn local_offset(time: i64) -> i32 {
+    let path = match env::var("TZ") {
+        Ok(tz) if !tz.is_empty() => {
+            let tz = tz.trim_start_matches(':');
+            if tz.starts_with('/') {
+                PathBuf::from(tz)
+            } else {
+                Path::new("/usr/share/zoneinfo").join(tz)
+            }
+        }
+        _ => PathBuf::from("/etc/localtime"),
+    };
+    fs::read(path)
+        .ok()
+        .and_then(|data| tzif_offset(&data, time))
+        .unwrap_or(0)
+}
+
+// Looks `time` up in the transition table of a TZif file (the 64 bit one for
+// version 2 and later). The POSIX TZ rule in the footer of version 2 and later
+// gives the offset after the last transition, or else that one's offset is kept.
+fn tzif_offset(data: &[u8], time: i64) -> Option<i32> {
+    if data.get(..4)? != b"TZif" {
+        return None;
+    }
+    let be_u32 = |data: &[u8], at: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize)
+    };
+    // header counts: isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+    let counts = |header: &[u8]| -> Option<[usize; 6]> {
+        let mut counts = [0; 6];
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count = be_u32(header, 20 + 4 * i)?;
+        }
+        Some(counts)
+    };
+    let mut block = data;
+    let mut time_size = 4;
+    let [isut, isstd, leap, timecnt, typecnt, chars] = counts(block)?;
+    if data[4] >= b'2' {
+        let v1_len = timecnt * 5 + typecnt * 6 + chars + leap * 8 + isstd + isut;
+        block = data.get(44 + v1_len..)?;
+        time_size = 8;
+    }
+    let [isut, isstd, leap, timecnt, typecnt, chars] = counts(block)?;
+    let block = &block[44..];
+    let times = block.get(..timecnt * time_size)?;
+    let indices = block.get(timecnt * time_size..timecnt * (time_size + 1))?;
+    let types = block.get(timecnt * (time_size + 1)..timecnt * (time_size + 1) + typecnt * 6)?;
+    let mut index = 0;
+    let mut after_last = true;
+    for (i, transition) in times.chunks(time_size).enumerate() {
+        let transition = if time_size == 8 {
+            i64::from_be_bytes(transition.try_into().ok()?)
+        } else {
+            i32::from_be_bytes(transition.try_into().ok()?) as i64
+        };
+        if transition > time {
+            after_last = false;
+            break;
+        }
+        index = indices[i] as usize;
+    }
+    if time_size == 8 && after_last {
+        let data_len = timecnt * 9 + typecnt * 6 + chars + leap * 12 + isstd + isut;
+        let footer = block.get(data_len..).and_then(|footer| footer.strip_prefix(b"\n"));
+        let rule = footer
+            .and_then(|footer| footer.split(|&b| b == b'\n').next())
+            .and_then(|rule| std::str::from_utf8(rule).ok());
+        if let Some(offset) = rule.and_then(|rule| posix_tz_offset(rule, time)) {
+            return Some(offset / 60);
+        }
+    }
+    let utoff = i32::from_be_bytes(types.get(index * 6..index * 6 + 4)?.try_into().ok()?);
+    Some(utoff / 60)
+}
+
+// The offset in seconds at `time` of a POSIX TZ rule like
+// `EST5EDT,M3.2.0,M11.1.0`: the standard time's name and offset west of UTC,
+// then for daylight saving time its name, offset (an hour less by default) and
+// the days and local times it starts and ends at, 02:00 by default.
+fn posix_tz_offset(rule: &str, time: i64) -> Option<i32> {
+    // a name is alphabetic, or anything but `>` when within `<...>`
+    let name = |rule: &str| -> Option<usize> {
+        let len = match rule.strip_prefix('<') {
+            Some(quoted) => quoted.find('>')? + 2,
+            None => rule.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rule.len()),
+        };
+        if len < 3 {
+            return None;
+        }
+        Some(len)
+    };
+    // `[+-]hh[:mm[:ss]]` in seconds, and the length it takes
+    let duration = |rule: &str| -> Option<(i64, usize)> {
+        let unsigned = rule.strip_prefix(['+', '-']).unwrap_or(rule);
+        let sign = if rule.starts_with('-') { -1 } else { 1 };
+        let len = unsigned.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(unsigned.len());
+        let mut seconds = 0;
+        let mut unit = 3600;
+        for part in unsigned[..len].split(':') {
+            if unit == 0 || part.is_empty() {
+                return None;
+            }
+            seconds += part.parse::<i64>().ok()? * unit;
+            unit /= 60;
+        }
+        Some((sign * seconds, rule.len() - unsigned.len() + len))
+    };
+    // `Jn`, `n` or `Mm.w.d` with an optional `/time`: the day since
+    // 1970-01-01 it falls on in `year` and the local time on it
+    let date = |rule: &str, year: i64| -> Option<i64> {
+        let (day, at) = match rule.find('/') {
+            Some(slash) => {
+                let (at, len) = duration(&rule[slash + 1..])?;
+                if slash + 1 + len != rule.len() {
+                    return None;
+                }
+                (&rule[..slash], at)
+            }
+            None => (rule, 7200),
+        };
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days = if let Some(julian) = day.strip_prefix('J') {
+            let julian: i64 = julian.parse().ok().filter(|day| (1..=365).contains(day))?;
+            days_from_civil(year, 1, 1) + julian - 1 + if leap && julian >= 60 { 1 } else { 0 }
+        } else if let Some(month_rule) = day.strip_prefix('M') {
+            let mut fields = month_rule.split('.').map(|field| field.parse::<i64>().ok());
+            let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+            if fields.next().is_some()
+                || !(1..=12).contains(&month)
+                || !(1..=5).contains(&week)
+                || !(0..=6).contains(&weekday)
+            {
+                return None;
+            }
+            let first = days_from_civil(year, month, 1);
+            let next_month = if month == 12 {
+                days_from_civil(year + 1, 1, 1)
+            } else {
+                days_from_civil(year, month + 1, 1)
+            };
+            // 1970-01-01 was a Thursday
+            let mut days = first + (weekday - (first + 4).rem_euclid(7)).rem_euclid(7) + 7 * (week - 1);
+            while days >= next_month {
+                days -= 7;
+            }
+            days
+        } else {
+            days_from_civil(year, 1, 1) + day.parse::<i64>().ok().filter(|day| (0..=365).contains(day))?
+        };
+        Some(days * 86400 + at)
+    };
+    let mut rest = &rule[name(rule)?..];
+    let (west, len) = duration(rest)?;
+    rest = &rest[len..];
+    let standard = -west;
+    if rest.is_empty() {
+        return Some(standard as i32);
+    }
+    rest = &rest[name(rest)?..];
+    let daylight = match duration(rest) {
+        Some((west, len)) if len > 0 => {
+            rest = &rest[len..];
+            -west
+        }
+        _ => standard + 3600,
+    };
+    let mut rules = rest.strip_prefix(',')?.split(',');
+    let (start, end) = (rules.next()?, rules.next()?);
+    if rules.next().is_some() {
+        return None;
+    }
+    let (year, _, _) = civil_from_days((time + standard).div_euclid(86400));
+    // the start is in standard time and the end in daylight saving time
+    let start = date(start, year)? - standard;
+    let end = date(end, year)? - daylight;
+    let in_daylight = if start < end {
+        start <= time && time < end
+    } else {
+        time < end || start <= time
+    };
+    let offset = if in_daylight { daylight } else { standard };
+    Some(offset as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn testing_identity_round_trip() {
+        let line = "A U Thor <author@example.com> 1112911993 -0530";
+        let identity = Identity::parse(line).unwrap();
+        assert_eq!(identity.name, "A U Thor");
+        assert_eq!(identity.email, "author@example.com");
+        assert_eq!(identity.time, 1112911993);
+        assert_eq!(identity.offset, -330);
+        assert_eq!(identity.to_string(), line);
+        assert!(Identity::parse("no email 1112911993 +0000").is_none());
+        assert!(Identity::parse("A <a@b> 1112911993 0200").is_none());
+    }
+
+    #[test]
+    fn testing_date_parsing() {
+        assert_eq!(parse_date("1112911993 +0200"), Some((1112911993, 120)));
+        assert_eq!(parse_date("@1112911993"), Some((1112911993, 0)));
+        assert_eq!(parse_date("2005-04-07T22:13:13+0200"), Some((1112904793, 120)));
+        assert_eq!(parse_date("2005-04-07 22:13:13 -05:00"), Some((1112929993, -300)));
+        assert_eq!(parse_date("1970-01-01T00:00:00Z"), Some((0, 0)));
+        assert_eq!(parse_date("2005-13-07T22:13:13Z"), None);
+        assert_eq!(parse_date("Thu, 07 Apr 2005 22:13:13 +0200"), Some((1112904793, 120)));
+        assert_eq!(parse_date("7 Apr 2005 20:13:13 GMT"), Some((1112904793, 0)));
+        assert_eq!(parse_date("Thu, 07 Avr 2005 22:13:13 +0200"), None);
+        assert_eq!(parse_date("yesterday-ish"), None);
+
+        // 2005-04-07T22:13:13Z
+        let now = 1112911993;
+        assert_eq!(approxidate("2005-04-07T22:13:13Z", 0), Some(now));
+        assert_eq!(approxidate("2 weeks ago", now), Some(now - 14 * 86400));
+        assert_eq!(approxidate("3.hours.ago", now), Some(now - 3 * 3600));
+        assert_eq!(approxidate("1 month ago", now), parse_date("2005-03-07T22:13:13Z").map(|(time, _)| time));
+        assert_eq!(approxidate("2 years ago", now), parse_date("2003-04-07T22:13:13Z").map(|(time, _)| time));
+        assert_eq!(approxidate("yesterday", now), Some(now - 86400));
+        assert_eq!(approxidate("2 fortnights ago", now), None);
+    }
+
+    #[test]
+    fn testing_zoneinfo_offsets() {
+        let data = match fs::read("/usr/share/zoneinfo/America/New_York") {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+        // 2005-01-15 is in EST, 2005-07-15 in EDT
+        assert_eq!(tzif_offset(&data, 1105747200), Some(-300));
+        assert_eq!(tzif_offset(&data, 1121385600), Some(-240));
+        // 2026 is past the transitions of slim zoneinfo files, leaving it to
+        // the TZ rule of the footer
+        assert_eq!(tzif_offset(&data, 1768435200), Some(-300));
+        assert_eq!(tzif_offset(&data, 1784073600), Some(-240));
+    }
+
+    #[test]
+    fn testing_posix_tz_rules() {
+        let new_york = "EST5EDT,M3.2.0,M11.1.0";
+        // daylight saving time in 2026 runs from 03-08 07:00Z to 11-01 06:00Z
+        assert_eq!(posix_tz_offset(new_york, 1772953140), Some(-18000));
+        assert_eq!(posix_tz_offset(new_york, 1772953200), Some(-14400));
+        assert_eq!(posix_tz_offset(new_york, 1793512740), Some(-14400));
+        assert_eq!(posix_tz_offset(new_york, 1793512800), Some(-18000));
+        // the southern summer spans the new year; it ends 04-04 16:00Z
+        let sydney = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+        assert_eq!(posix_tz_offset(sydney, 1768435200), Some(39600));
+        assert_eq!(posix_tz_offset(sydney, 1775318340), Some(39600));
+        assert_eq!(posix_tz_offset(sydney, 1775318400), Some(36000));
+        assert_eq!(posix_tz_offset("<+0530>-5:30", 0), Some(19800));
+        assert_eq!(posix_tz_offset("UTC0", 1768435200), Some(0));
+        assert_eq!(posix_tz_offset("EST5EDT,M3.2.0", 0), None);
+        assert_eq!(posix_tz_offset("5EDT", 0), None);
+    }
+}
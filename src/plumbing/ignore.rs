@@ -0,0 +1,165 @@
+use super::sparse::Pattern;
+use super::*;
+
+// a pattern from the `.gitignore` of the directory `base`, which ends in a slash
+struct Rule {
+    base: String,
+    pattern: Pattern,
+    source: String,
+    line: usize,
+    text: String,
+}
+
+// The rule that decided about a path, as `check-ignore -v` shows it: the file
+// it comes from, its line there and the pattern as written.
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    pub source: String,
+    pub line: usize,
+    pub pattern: String,
+    pub negated: bool,
+}
+
+// The ignore rules met while walking the working tree, each directory's
+// `.gitignore` read as the walk enters it. The last rule matching a path
+// decides, so a deeper file overrides its parents, which override
+// `info/exclude` and then `core.excludesFile`. Patterns given on the
+// command line come before all of them.
+pub struct Ignores {
+    root: PathBuf,
+    command_line: Vec<Rule>,
+    rules: Vec<Rule>,
+    standard: bool,
+}
+
+impl Ignores {
+    pub fn new(repo: &Repository) -> std::io::Result<Self> {
+        let mut ignores = Ignores::command_line(repo);
+        ignores.standard = true;
+        if let Some(global) = config::user_file(repo.config(), "core.excludesfile", "ignore") {
+            ignores.read(&global, &global.to_string_lossy(), "")?;
+        }
+        // named from the top of the work tree when it's in there, as git does
+        let exclude = repo.common_dir().join("info/exclude");
+        let work_tree = repo.work_tree();
+        let source = exclude.strip_prefix(&work_tree).unwrap_or(&exclude).to_string_lossy().into_owned();
+        ignores.read(&exclude, &source, "")?;
+        Ok(ignores)
+    }
+
+    // only the patterns given with `exclude`, for `clean -x`
+    pub fn command_line(repo: &Repository) -> Self {
+        Ignores { root: repo.work_tree(), command_line: Vec::new(), rules: Vec::new(), standard: false }
+    }
+
+    pub fn exclude(&mut self, pattern: &str) {
+        let line = self.command_line.len() + 1;
+        self.command_line.extend(Rule::parse(pattern, "", "<command line>", line));
+    }
+
+    // adds the patterns of `file`, which `source` names
+    fn read(&mut self, file: &Path, source: &str, base: &str) -> std::io::Result<()> {
+        let patterns = match fs::read_to_string(file) {
+            Ok(patterns) => patterns,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let rules = patterns.lines().enumerate().filter_map(|(i, text)| Rule::parse(text, base, source, i + 1));
+        self.rules.extend(rules);
+        Ok(())
+    }
+
+    // reads the `.gitignore` of `dir`, "" being the top of the working tree
+    pub fn enter(&mut self, dir: &str) -> std::io::Result<()> {
+        if !self.standard {
+            return Ok(());
+        }
+        let base = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+        let source = format!("{}.gitignore", base);
+        self.read(&self.root.join(dir).join(".gitignore"), &source, &base)
+    }
+
+    // The rule deciding about `path`, if one matches it.
+    pub fn matching(&self, path: &str, is_dir: bool) -> Option<Match> {
+        let matches = |rule: &&Rule| match path.strip_prefix(&rule.base) {
+            Some(path) => rule.pattern.matches(path, is_dir),
+            None => false,
+        };
+        let rule = self.command_line.iter().rev().find(matches).or_else(|| self.rules.iter().rev().find(matches))?;
+        Some(Match {
+            source: rule.source.clone(),
+            line: rule.line,
+            pattern: rule.text.clone(),
+            negated: rule.pattern.negated,
+        })
+    }
+
+    // Whether `path` is ignored. What is inside an ignored directory isn't
+    // looked at, as the walk doesn't go there.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        matches!(self.matching(path, is_dir), Some(rule) if !rule.negated)
+    }
+}
+
+impl Rule {
+    fn parse(text: &str, base: &str, source: &str, line: usize) -> Option<Self> {
+        let pattern = Pattern::parse(text)?;
+        let text = text.trim_end().to_string();
+        Some(Rule { base: base.to_string(), pattern, source: source.to_string(), line, text })
+    }
+}
+
+// The rule deciding about `path` when nothing else is known, reading the
+// `.gitignore` files on the way down to it. A directory above it that is
+// ignored decides for everything inside.
+pub fn check(repo: &Repository, path: &str) -> std::io::Result<Option<Match>> {
+    let mut ignores = Ignores::new(repo)?;
+    ignores.enter("")?;
+    let path = path.trim_end_matches('/');
+    for (i, _) in path.match_indices('/') {
+        let dir = &path[..i];
+        match ignores.matching(dir, true) {
+            Some(rule) if !rule.negated => return Ok(Some(rule)),
+            _ => ignores.enter(dir)?,
+        }
+    }
+    let is_dir = repo.work_tree().join(path).is_dir();
+    Ok(ignores.matching(path, is_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_ignore_precedence() {
+        let dir = TempDir::new("ignore");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("info")).unwrap();
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        let global = dir.join("global-ignore");
+        fs::write(&global, "*.log\n*.tmp\n*.bak\n").unwrap();
+        fs::write(git_dir.join("config"), format!("[core]\n\texcludesFile = {}\n", global.display())).unwrap();
+        fs::write(git_dir.join("info/exclude"), "!keep.tmp\n*.swp\n").unwrap();
+        fs::write(dir.join(".gitignore"), "!keep.log\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let mut ignores = Ignores::new(&repo).unwrap();
+        ignores.enter("").unwrap();
+        ignores.exclude("*.bak");
+        ignores.exclude("!keep.swp");
+        let mut command_line = Ignores::command_line(&repo);
+        command_line.exclude("*.bak");
+        command_line.enter("").unwrap();
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        fs::write(dir.join(".gitignore"), "!keep.log\n/logs/\n").unwrap();
+        let inside = check(&repo, "logs/keep.log").unwrap();
+
+        let paths = ["a.log", "keep.log", "a.tmp", "keep.tmp", "a.swp", "keep.swp", "a.bak", "a.rs"];
+        let ignored: Vec<bool> = paths.iter().map(|path| ignores.is_ignored(path, false)).collect();
+        assert_eq!(ignored, vec![true, false, true, false, true, false, true, false]);
+        assert!(command_line.is_ignored("a.bak", false));
+        assert!(!command_line.is_ignored("a.log", false));
+        let rule = Match { source: ".gitignore".to_string(), line: 2, pattern: "/logs/".to_string(), negated: false };
+        assert_eq!(inside, Some(rule));
+    }
+}
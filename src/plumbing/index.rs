@@ -0,0 +1,419 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::os::unix::fs::MetadataExt;
+
+const SIGNATURE: &[u8] = b"DIRC";
+// the versions read and written, 3 only when an entry has extended flags
+pub const VERSIONS: &[u32] = &[2, 3];
+// flags in the entry's 16 bit flags field
+pub const ASSUME_VALID: u16 = 0x8000;
+const EXTENDED: u16 = 0x4000;
+const STAGE_MASK: u16 = 0x3000;
+const NAME_MASK: u16 = 0x0fff;
+// in the extended flags of version 3 entries
+pub const SKIP_WORKTREE: u16 = 0x4000;
+// stat data, mode, size and id, before the flags
+const ENTRY_HEADER_SIZE: usize = 62;
+
+// One path in the index at one stage: 0 when merged, 1 to 3 for the base,
+// ours and theirs of a conflict.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Entry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub sha: String,
+    // assume-valid and the stage, the name length is worked out on write
+    pub flags: u16,
+    // skip-worktree and intent-to-add, in version 3 indexes
+    pub extended_flags: u16,
+    pub path: String,
+}
+
+impl Entry {
+    // An entry with no stat data, which git compares by content until the
+    // entry is refreshed.
+    pub fn new(path: &str, mode: &str, sha: &str) -> Self {
+        Entry {
+            mode: u32::from_str_radix(mode, 8).unwrap_or(0o100644),
+            sha: sha.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // the file isn't in the working tree, it's outside the sparse checkout
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & SKIP_WORKTREE != 0
+    }
+
+    // the file is taken to be unchanged without looking at it
+    pub fn assume_valid(&self) -> bool {
+        self.flags & ASSUME_VALID != 0
+    }
+
+    // Whether `metadata` is the stat data recorded for the file, in which case
+    // its content is taken to be unchanged.
+    pub fn stat_matches(&self, metadata: &fs::Metadata) -> bool {
+        let executable = metadata.mode() & 0o111 != 0;
+        let mode_matches = match self.mode {
+            0o120000 => metadata.file_type().is_symlink(),
+            0o100755 => metadata.is_file() && executable,
+            _ => metadata.is_file() && !executable,
+        };
+        mode_matches
+            && self.ctime == (metadata.ctime() as u32, metadata.ctime_nsec() as u32)
+            && self.mtime == (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+            && self.ino == metadata.ino() as u32
+            && self.size == metadata.size() as u32
+    }
+
+    pub fn stage(&self) -> u16 {
+        (self.flags & STAGE_MASK) >> 12
+    }
+
+    // the same entry at `stage`, 1 to 3 for the sides of a conflict
+    pub fn with_stage(self, stage: u16) -> Self {
+        Entry { flags: (self.flags & !STAGE_MASK) | ((stage << 12) & STAGE_MASK), ..self }
+    }
+
+    // the mode as trees write it
+    pub fn mode_string(&self) -> String {
+        format!("{:o}", self.mode)
+    }
+
+    // Records `metadata`, the file's stat data, so that an unchanged file can
+    // be told apart from a changed one without reading it.
+    pub fn set_stat(&mut self, metadata: &fs::Metadata) {
+        self.ctime = (metadata.ctime() as u32, metadata.ctime_nsec() as u32);
+        self.mtime = (metadata.mtime() as u32, metadata.mtime_nsec() as u32);
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+}
+
+// `.git/index`, the staging area: every tracked path with its blob and the
+// stat data it had when last written. Versions 2 and 3 are read and written,
+// the extensions (cached trees, resolve-undo...) are optional and dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Index {
+    pub entries: Vec<Entry>,
+}
+
+// the signature of an index extension and its data
+type Extension<'a> = (&'a [u8], &'a [u8]);
+
+fn corrupt(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("corrupt index: {}", what))
+}
+
+impl Index {
+    // the repository's index, empty when there is none yet
+    pub fn read(repo: &Repository) -> std::io::Result<Self> {
+        match fs::read(repo.git_dir().join("index")) {
+            Ok(data) => Self::parse(&data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> std::io::Result<Self> {
+        Ok(Self::parse_with_extensions(data)?.0)
+    }
+
+    // The trees the `TREE` extension has cached for the directories of the
+    // index, those still valid. git takes them as they are when it writes
+    // the index out as trees, so they have to be kept like what it stages.
+    pub fn cached_trees(repo: &Repository) -> std::io::Result<Vec<String>> {
+        let data = match fs::read(repo.git_dir().join("index")) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut trees = Vec::new();
+        for (_, mut rest) in Self::parse_with_extensions(&data)?.1.into_iter().filter(|(name, _)| name == b"TREE") {
+            while !rest.is_empty() {
+                // `<path>\0<entries> <subtrees>\n` and the tree's id, none when
+                // the entry count is -1 for a tree no longer valid
+                let nul = rest.iter().position(|&byte| byte == 0).ok_or_else(|| corrupt("bad cached tree"))?;
+                let newline = rest[nul..]
+                    .iter()
+                    .position(|&byte| byte == b'\n')
+                    .map(|length| nul + length)
+                    .ok_or_else(|| corrupt("bad cached tree"))?;
+                let valid = !rest[nul + 1..newline].starts_with(b"-");
+                rest = &rest[newline + 1..];
+                if valid {
+                    if rest.len() < 20 {
+                        return Err(corrupt("truncated cached tree"));
+                    }
+                    trees.push(Blob::string_hash(&rest[..20]));
+                    rest = &rest[20..];
+                }
+            }
+        }
+        Ok(trees)
+    }
+
+    fn parse_with_extensions(data: &[u8]) -> std::io::Result<(Self, Vec<Extension<'_>>)> {
+        if data.len() < 12 + 20 || &data[..4] != SIGNATURE {
+            return Err(corrupt("bad signature"));
+        }
+        let body = data.len() - 20;
+        if sha1::Sha1::digest(&data[..body])[..] != data[body..] {
+            return Err(corrupt("checksum mismatch"));
+        }
+        let u32_at = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+        let u16_at = |at: usize| u16::from_be_bytes(data[at..at + 2].try_into().unwrap());
+        let version = u32_at(4);
+        if !VERSIONS.contains(&version) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("index version {} is not supported", version),
+            ));
+        }
+        let mut entries = Vec::new();
+        let mut at = 12;
+        for _ in 0..u32_at(8) {
+            if at + ENTRY_HEADER_SIZE + 2 > body {
+                return Err(corrupt("truncated entry"));
+            }
+            let flags = u16_at(at + 60);
+            let mut path_at = at + ENTRY_HEADER_SIZE;
+            let extended_flags = match flags & EXTENDED {
+                0 => 0,
+                _ => {
+                    path_at += 2;
+                    u16_at(at + 62)
+                }
+            };
+            let path_end = data[path_at..body]
+                .iter()
+                .position(|&byte| byte == 0)
+                .map(|length| path_at + length)
+                .ok_or_else(|| corrupt("unterminated path"))?;
+            entries.push(Entry {
+                ctime: (u32_at(at), u32_at(at + 4)),
+                mtime: (u32_at(at + 8), u32_at(at + 12)),
+                dev: u32_at(at + 16),
+                ino: u32_at(at + 20),
+                mode: u32_at(at + 24),
+                uid: u32_at(at + 28),
+                gid: u32_at(at + 32),
+                size: u32_at(at + 36),
+                sha: Blob::string_hash(&data[at + 40..at + 60]),
+                flags: flags & (ASSUME_VALID | STAGE_MASK),
+                extended_flags,
+                path: String::from_utf8_lossy(&data[path_at..path_end]).into_owned(),
+            });
+            // entries are NUL padded to a multiple of eight bytes
+            at += (path_end - at + 8) & !7;
+        }
+        // extensions start with an upper case letter when they can be skipped
+        let mut extensions = Vec::new();
+        while at + 8 <= body {
+            if !data[at].is_ascii_uppercase() {
+                let name = String::from_utf8_lossy(&data[at..at + 4]).into_owned();
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("index extension {} is not supported", name),
+                ));
+            }
+            let end = at + 8 + u32_at(at + 4) as usize;
+            if end > body {
+                return Err(corrupt("truncated extension"));
+            }
+            extensions.push((&data[at..at + 4], &data[at + 8..end]));
+            at = end;
+        }
+        Ok((Index { entries }, extensions))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let version: u32 = if self.entries.iter().any(|entry| entry.extended_flags != 0) { 3 } else { 2 };
+        let mut data = SIGNATURE.to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| (a.path.as_bytes(), a.stage()).cmp(&(b.path.as_bytes(), b.stage())));
+        for entry in entries {
+            let start = data.len();
+            for value in [
+                entry.ctime.0,
+                entry.ctime.1,
+                entry.mtime.0,
+                entry.mtime.1,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            data.extend_from_slice(&Blob::decode_hex(&entry.sha).unwrap_or_else(|_| vec![0; 20]));
+            let mut flags = entry.flags & (ASSUME_VALID | STAGE_MASK);
+            flags |= entry.path.len().min(NAME_MASK as usize) as u16;
+            if entry.extended_flags != 0 {
+                flags |= EXTENDED;
+            }
+            data.extend_from_slice(&flags.to_be_bytes());
+            if entry.extended_flags != 0 {
+                data.extend_from_slice(&entry.extended_flags.to_be_bytes());
+            }
+            data.extend_from_slice(entry.path.as_bytes());
+            let length = data.len() - start;
+            data.resize(start + ((length + 8) & !7), 0);
+        }
+        let checksum = sha1::Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+
+    pub fn write(&self, repo: &Repository) -> std::io::Result<()> {
+        write_locked(&repo.git_dir().join("index"), &self.serialize())
+    }
+
+    // An index holding `files`, as read-tree makes it. Entries that `self`
+    // has with the same blob and mode keep their stat data.
+    pub fn with_files(&self, files: &BTreeMap<String, (String, String)>) -> Self {
+        let old: BTreeMap<&str, &Entry> =
+            self.entries.iter().filter(|entry| entry.stage() == 0).map(|entry| (entry.path.as_str(), entry)).collect();
+        let entries = files
+            .iter()
+            .map(|(path, (mode, sha))| match old.get(path.as_str()) {
+                Some(entry) if entry.sha == *sha && entry.mode_string() == *mode => (*entry).clone(),
+                _ => Entry::new(path, mode, sha),
+            })
+            .collect();
+        Index { entries }
+    }
+
+    // Sets skip-worktree on the entries outside `sparse`, and clears it
+    // everywhere without a sparse checkout.
+    pub fn apply_sparse(&mut self, sparse: Option<&sparse::Sparse>) {
+        for entry in &mut self.entries {
+            match sparse {
+                Some(sparse) if !sparse.includes(&entry.path) => entry.extended_flags |= SKIP_WORKTREE,
+                _ => entry.extended_flags &= !SKIP_WORKTREE,
+            }
+        }
+    }
+
+    // `path` spelled the way the index has it when only its case differs, or
+    // else with its leading directories spelled like those of an entry, for
+    // case-folding file systems (`core.ignorecase`).
+    pub fn fold_case(&self, path: &str) -> String {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.path.eq_ignore_ascii_case(path)) {
+            return entry.path.clone();
+        }
+        // the longest directory wins
+        for (slash, _) in path.match_indices('/').rev() {
+            let dir = &path.as_bytes()[..=slash];
+            let existing = self
+                .entries
+                .iter()
+                .find(|entry| matches!(entry.path.as_bytes().get(..=slash), Some(prefix) if prefix.eq_ignore_ascii_case(dir)));
+            if let Some(entry) = existing {
+                return format!("{}{}", &entry.path[..=slash], &path[slash + 1..]);
+            }
+        }
+        path.to_string()
+    }
+
+    // Puts `entry` in place of whatever is at its path, conflicts included.
+    pub fn add(&mut self, entry: Entry) {
+        self.entries.retain(|old| old.path != entry.path);
+        self.entries.push(entry);
+    }
+
+    // the merged entries that belong in the working tree, like `files`
+    pub fn checked_out(&self) -> BTreeMap<String, (String, String)> {
+        let mut files = self.files();
+        for entry in self.entries.iter().filter(|entry| entry.skip_worktree()) {
+            files.remove(&entry.path);
+        }
+        files
+    }
+
+    // the merged (stage 0) entries as path -> (mode, id), like tree::files
+    pub fn files(&self) -> BTreeMap<String, (String, String)> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.stage() == 0)
+            .map(|entry| (entry.path.clone(), (entry.mode_string(), entry.sha.clone())))
+            .collect()
+    }
+
+    // Records the stat data of the working tree files whose content matches
+    // their entry, as git does after writing them.
+    pub fn refresh(&mut self, repo: &Repository) -> std::io::Result<()> {
+        let root = repo.work_tree();
+        for entry in self.entries.iter_mut().filter(|entry| !entry.skip_worktree()) {
+            let path = root.join(&entry.path);
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if worktree::is_unchanged(&path, Some(&(entry.mode_string(), entry.sha.clone())))? {
+                entry.set_stat(&metadata);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_index_round_trip() {
+        let blob = "e69de29bb2d1d6434b8b29ae775ad8c2e48c391d";
+        let files: BTreeMap<String, (String, String)> = ["b", "a/c", "a-b", "twelve chars"]
+            .iter()
+            .map(|path| (path.to_string(), ("100644".to_string(), blob.to_string())))
+            .collect();
+        let mut index = Index::default().with_files(&files);
+        index.entries[0].mtime = (1, 2);
+        let data = index.serialize();
+        assert_eq!(&data[..12], b"DIRC\x00\x00\x00\x02\x00\x00\x00\x04");
+        // 62 bytes of entry header, the path and one to eight NULs
+        assert_eq!(data.len(), 12 + 72 + 72 + 64 + 80 + 20);
+        let parsed = Index::parse(&data).unwrap();
+        assert_eq!(parsed, index);
+        assert_eq!(parsed.files(), files);
+        let paths: Vec<&str> = parsed.entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, vec!["a-b", "a/c", "b", "twelve chars"]);
+        // stat data is kept for entries that didn't change
+        assert_eq!(parsed.with_files(&files).entries[0].mtime, (1, 2));
+        let mut conflicted = parsed.clone();
+        conflicted.entries[2].flags |= 2 << 12;
+        conflicted.entries.push(Entry { flags: 3 << 12, ..conflicted.entries[2].clone() });
+        conflicted.add(Entry::new("b", "100755", blob));
+        assert_eq!(conflicted.files()["b"].0, "100755");
+        assert_eq!(conflicted.entries.len(), 4);
+        assert_eq!(parsed.fold_case("B"), "b");
+        assert_eq!(parsed.fold_case("A/new"), "a/new");
+        assert_eq!(parsed.fold_case("Twelve Chars/x"), "Twelve Chars/x");
+
+        let mut extended = parsed;
+        extended.entries[1].extended_flags = 0x4000;
+        let data = extended.serialize();
+        assert_eq!(data[7], 3);
+        assert_eq!(Index::parse(&data).unwrap(), extended);
+        let mut corrupted = data.clone();
+        corrupted[20] ^= 1;
+        assert!(Index::parse(&corrupted).is_err());
+    }
+}
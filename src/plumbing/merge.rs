@@ -0,0 +1,520 @@
+use super::commit::Commit;
+use super::diff::{line_diff, split_lines, Edit};
+use super::identity::Identity;
+use super::index::{Entry, Index};
+use super::*;
+use std::collections::BTreeMap;
+
+// A base range `[start, end)` that one side replaced with `lines`.
+struct Change<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a [u8]>,
+}
+
+fn changes<'a>(base: &[&[u8]], side: &[&'a [u8]]) -> Vec<Change<'a>> {
+    let mut changes: Vec<Change> = Vec::new();
+    let (mut b, mut s) = (0, 0);
+    let mut in_change = false;
+    for edit in line_diff(base, side) {
+        if edit == Edit::Equal {
+            in_change = false;
+            b += 1;
+            s += 1;
+            continue;
+        }
+        if !in_change {
+            changes.push(Change { start: b, end: b, lines: Vec::new() });
+            in_change = true;
+        }
+        let change = changes.last_mut().unwrap();
+        if edit == Edit::Delete {
+            b += 1;
+            change.end = b;
+        } else {
+            change.lines.push(side[s]);
+            s += 1;
+        }
+    }
+    changes
+}
+
+// A base range `[start, end)` changed by one or both sides, with each
+// side's version of it.
+struct Region<'a> {
+    start: usize,
+    end: usize,
+    ours: Vec<&'a [u8]>,
+    theirs: Vec<&'a [u8]>,
+    conflict: bool,
+}
+
+// A side's version of the base range `[start, end)`, given its changes there.
+fn side_lines<'a>(base: &[&'a [u8]], changes: &[&Change<'a>], start: usize, end: usize) -> Vec<&'a [u8]> {
+    let mut lines = Vec::new();
+    let mut at = start;
+    for change in changes {
+        lines.extend_from_slice(&base[at..change.start]);
+        lines.extend_from_slice(&change.lines);
+        at = change.end;
+    }
+    lines.extend_from_slice(&base[at..end]);
+    lines
+}
+
+// The names written after the conflict markers.
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub theirs: &'a str,
+}
+
+// A merge that needs manual resolution: `merged` is the result with conflict
+// markers around each of the `count` conflicting regions.
+#[derive(Debug)]
+pub struct Conflicts {
+    pub merged: Option<Vec<u8>>,
+    pub count: usize,
+}
+
+impl std::fmt::Display for Conflicts {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} conflict(s)", self.count)
+    }
+}
+
+fn push_lines(out: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+}
+
+// Three-way merges the contents of a file, `None` meaning the file doesn't exist
+// on that side. Changes to separate parts of the base are combined, changes
+// touching the same lines conflict unless both sides made the same change. A
+// file added on both sides is merged against an empty base, and a file deleted
+// on one side and modified on the other conflicts with the modified content as
+// the result. `Ok(None)` means the file is deleted.
+pub fn merge_blobs(
+    base: Option<&[u8]>,
+    ours: Option<&[u8]>,
+    theirs: Option<&[u8]>,
+    labels: &Labels,
+) -> Result<Option<Vec<u8>>, Conflicts> {
+    let (ours, theirs) = match (ours, theirs) {
+        (Some(ours), Some(theirs)) => (ours, theirs),
+        (None, None) => return Ok(None),
+        (Some(kept), None) | (None, Some(kept)) => {
+            return match base {
+                // added on one side only
+                None => Ok(Some(kept.to_vec())),
+                Some(base) if base == kept => Ok(None),
+                Some(_) => Err(Conflicts { merged: Some(kept.to_vec()), count: 1 }),
+            }
+        }
+    };
+    let base_lines = split_lines(base.unwrap_or(b""));
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+    let our_changes = changes(&base_lines, &our_lines);
+    let their_changes = changes(&base_lines, &their_lines);
+
+    let mut regions: Vec<Region> = Vec::new();
+    let (mut o, mut t) = (0, 0);
+    while o < our_changes.len() || t < their_changes.len() {
+        // start a region at the earliest change, then pull in every change of
+        // either side that overlaps or touches it
+        let first_ours = o < our_changes.len()
+            && (t == their_changes.len() || our_changes[o].start <= their_changes[t].start);
+        let start = if first_ours { our_changes[o].start } else { their_changes[t].start };
+        let mut end = start;
+        let (o_first, t_first) = (o, t);
+        loop {
+            if o < our_changes.len() && our_changes[o].start <= end {
+                end = end.max(our_changes[o].end);
+                o += 1;
+            } else if t < their_changes.len() && their_changes[t].start <= end {
+                end = end.max(their_changes[t].end);
+                t += 1;
+            } else {
+                break;
+            }
+        }
+        let ours_in: Vec<&Change> = our_changes[o_first..o].iter().collect();
+        let theirs_in: Vec<&Change> = their_changes[t_first..t].iter().collect();
+        let ours = side_lines(&base_lines, &ours_in, start, end);
+        let theirs = side_lines(&base_lines, &theirs_in, start, end);
+        let conflict = !ours_in.is_empty() && !theirs_in.is_empty() && ours != theirs;
+        let region = Region { start, end, ours, theirs, conflict };
+        // like git, conflicts only a few lines apart (or apart by lines without
+        // letters or digits) become one
+        match regions.last_mut() {
+            Some(last) if last.conflict && region.conflict && {
+                let gap = &base_lines[last.end..region.start];
+                gap.len() <= 3 || !gap.iter().any(|line| line.iter().any(u8::is_ascii_alphanumeric))
+            } =>
+            {
+                let gap = &base_lines[last.end..region.start];
+                last.ours.extend_from_slice(gap);
+                last.ours.extend(region.ours);
+                last.theirs.extend_from_slice(gap);
+                last.theirs.extend(region.theirs);
+                last.end = region.end;
+            }
+            _ => regions.push(region),
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut count = 0;
+    let mut at = 0;
+    for region in regions {
+        push_lines(&mut out, &base_lines[at..region.start]);
+        at = region.end;
+        let (ours, theirs) = (region.ours, region.theirs);
+        if !region.conflict {
+            push_lines(&mut out, if ours == base_lines[region.start..region.end] { &theirs } else { &ours });
+            continue;
+        }
+        count += 1;
+        // lines both sides agree on stay outside the markers
+        let prefix = ours.iter().zip(&theirs).take_while(|(a, b)| a == b).count();
+        let suffix = ours[prefix..]
+            .iter()
+            .rev()
+            .zip(theirs[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        push_lines(&mut out, &ours[..prefix]);
+        for (marker, lines) in [
+            (format!("<<<<<<< {}\n", labels.ours), &ours[prefix..ours.len() - suffix]),
+            ("=======\n".to_string(), &theirs[prefix..theirs.len() - suffix]),
+        ] {
+            out.extend_from_slice(marker.as_bytes());
+            push_lines(&mut out, lines);
+            if !out.ends_with(b"\n") {
+                out.push(b'\n');
+            }
+        }
+        out.extend_from_slice(format!(">>>>>>> {}\n", labels.theirs).as_bytes());
+        push_lines(&mut out, &ours[ours.len() - suffix..]);
+    }
+    push_lines(&mut out, &base_lines[at..]);
+    match count {
+        0 => Ok(Some(out)),
+        count => Err(Conflicts { merged: Some(out), count }),
+    }
+}
+
+// A path the tree merge couldn't resolve, with what to leave in the working
+// tree for it.
+pub struct PathConflict {
+    pub path: String,
+    pub mode: String,
+    pub content: Vec<u8>,
+    // `content`, `add/add` or `modify/delete`, as in git's CONFLICT lines
+    pub kind: &'static str,
+    // the `(mode, sha)` of the base, ours and theirs, for the index stages
+    pub stages: [Option<(String, String)>; 3],
+}
+
+// The cleanly merged files `path -> (mode, sha)` and the conflicting ones.
+pub struct TreeMerge {
+    pub files: BTreeMap<String, (String, String)>,
+    pub conflicts: Vec<PathConflict>,
+}
+
+// Three-way merges flattened trees path by path. A path changed on one side
+// takes that side's version, one changed on both is merged with `merge_blobs`,
+// and merged contents are written to the object database.
+pub fn merge_trees(
+    repo: &Repository,
+    base: &BTreeMap<String, (String, String)>,
+    ours: &BTreeMap<String, (String, String)>,
+    theirs: &BTreeMap<String, (String, String)>,
+    labels: &Labels,
+) -> std::io::Result<TreeMerge> {
+    let mut paths: Vec<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    let mut merged = TreeMerge { files: BTreeMap::new(), conflicts: Vec::new() };
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        if o == t || b == t || b == o {
+            if let Some(entry) = if b == o { t } else { o } {
+                merged.files.insert(path.clone(), entry.clone());
+            }
+            continue;
+        }
+        let mode = match (b, o, t) {
+            (Some(b), Some(o), Some(t)) if o.0 == b.0 => t.0.clone(),
+            _ => o.or(t).map_or(String::new(), |entry| entry.0.clone()),
+        };
+        let read = |entry: Option<&(String, String)>| {
+            entry.map(|(_, sha)| Blob::from_sha(repo, sha).map(|blob| blob.content)).transpose()
+        };
+        let (base_content, our_content, their_content) = (read(b)?, read(o)?, read(t)?);
+        let regular = [b, o, t].iter().flatten().all(|(mode, _)| mode.starts_with("100"));
+        let outcome = if regular {
+            merge_blobs(base_content.as_deref(), our_content.as_deref(), their_content.as_deref(), labels)
+        } else {
+            // symlinks and submodules can't be merged line by line
+            Err(Conflicts { merged: our_content.or(their_content), count: 1 })
+        };
+        match outcome {
+            Ok(Some(content)) => {
+                let blob = Blob::new(ObjectType::Blob, content);
+                hash::write_to_database(repo, &blob)?;
+                merged.files.insert(path.clone(), (mode, blob.hash_string));
+            }
+            Ok(None) => {}
+            Err(conflicts) => merged.conflicts.push(PathConflict {
+                path: path.clone(),
+                mode,
+                content: conflicts.merged.unwrap_or_default(),
+                kind: match (b, o, t) {
+                    (None, _, _) => "add/add",
+                    (Some(_), Some(_), Some(_)) => "content",
+                    _ => "modify/delete",
+                },
+                stages: [b.cloned(), o.cloned(), t.cloned()],
+            }),
+        }
+    }
+    Ok(merged)
+}
+
+// Writes the index a merge leaves, once the working tree has been updated:
+// the merged `files`, keeping the stat data of those that didn't change,
+// and each of the `conflicts` unmerged, at stage 1, 2 and 3 for the base,
+// ours and theirs that have the path.
+pub fn write_index(
+    repo: &Repository,
+    files: &BTreeMap<String, (String, String)>,
+    conflicts: &[PathConflict],
+) -> std::io::Result<()> {
+    let mut index = Index::read(repo)?.with_files(files);
+    for conflict in conflicts {
+        for (stage, side) in conflict.stages.iter().enumerate() {
+            if let Some((mode, sha)) = side {
+                index.entries.push(Entry::new(&conflict.path, mode, sha).with_stage(stage as u16 + 1));
+            }
+        }
+    }
+    index.refresh(repo)?;
+    index.write(repo)
+}
+
+// Merges the branch (or any commit) `name` into HEAD. When HEAD is an ancestor
+// it's fast-forwarded, otherwise the trees are three-way merged against the
+// merge base and a commit with both parents is made. With several merge bases
+// the first one is used, git's recursive merge of the bases isn't done. On
+// conflicts the working tree gets the conflict markers and MERGE_HEAD and
+// MERGE_MSG are written instead of committing. The index follows the
+// working tree, the conflicting paths left unmerged in it.
+// With `verify`, the pre-merge-commit and commit-msg hooks may stop the merge
+// commit; the post-merge hook runs after any merge.
+pub fn merge_branch(repo: &Repository, name: &str, verify: bool) -> std::io::Result<()> {
+    let target = Commit::peel(repo, &revision::resolve(repo, name)?)?;
+    let head = refs::resolve(repo, "HEAD")?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "HEAD does not point to a commit yet")
+    })?;
+    let head_commit = Commit::read(repo, &head)?;
+    let ours = tree::files(repo, &head_commit.tree)?;
+    let target_commit = Commit::read(repo, &target)?;
+    if ancestry::ancestors(repo, &head)?.contains(&target) {
+        outln!("Already up to date.")?;
+        return Ok(());
+    }
+    if ancestry::ancestors(repo, &target)?.contains(&head) {
+        outln!("Updating {}..{}\nFast-forward", &head[..7], &target[..7])?;
+        let files = tree::files(repo, &target_commit.tree)?;
+        worktree::update(repo, &ours, &files, &[])?;
+        write_index(repo, &files, &[])?;
+        refs::update(repo, "HEAD", &target, Some(&head))?;
+        hooks::run(repo, "post-merge", &["0"])?;
+        return Ok(());
+    }
+
+    let base = match ancestry::merge_bases(repo, &head, &target)?.first() {
+        Some(base) => tree::files(repo, &Commit::read(repo, base)?.tree)?,
+        None => BTreeMap::new(),
+    };
+    let theirs = tree::files(repo, &target_commit.tree)?;
+    let merged = merge_trees(repo, &base, &ours, &theirs, &Labels { ours: "HEAD", theirs: name })?;
+    let message = merge_message(repo, name)?;
+    if !merged.conflicts.is_empty() {
+        worktree::update(repo, &ours, &merged.files, &merged.conflicts)?;
+        write_index(repo, &merged.files, &merged.conflicts)?;
+        fs::write(repo.git_dir().join("MERGE_HEAD"), format!("{}\n", target))?;
+        fs::write(repo.git_dir().join("MERGE_MSG"), &message)?;
+        for conflict in &merged.conflicts {
+            outln!("CONFLICT ({}): Merge conflict in {}", conflict.kind, conflict.path)?;
+        }
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "automatic merge failed, fix the conflicts and commit the result",
+        ));
+    }
+    let tree = tree::write_tree(repo, &merged.files)?;
+    worktree::update(repo, &ours, &merged.files, &[])?;
+    write_index(repo, &merged.files, &[])?;
+    // stopped by a hook, the merge is left for `commit` to finish like
+    // one with conflicts
+    let stopped = |message: &str| {
+        fs::write(repo.git_dir().join("MERGE_HEAD"), format!("{}\n", target))?;
+        fs::write(repo.git_dir().join("MERGE_MSG"), message)?;
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not committing merge, a hook refused it"))
+    };
+    if verify && !hooks::run(repo, "pre-merge-commit", &[])? {
+        return stopped(&message);
+    }
+    let message = match verify {
+        true => match hooks::commit_msg(repo, "MERGE_MSG", &message)? {
+            Some(message) => message,
+            None => return stopped(&message),
+        },
+        false => message,
+    };
+    let commit = Commit {
+        tree,
+        parents: vec![head.clone(), target],
+        author: Identity::from_env("AUTHOR", repo.config())?,
+        committer: Identity::from_env("COMMITTER", repo.config())?,
+        extra_headers: Vec::new(),
+        message,
+    };
+    refs::update(repo, "HEAD", &commit.write(repo)?, Some(&head))?;
+    outln!("Merge made by a three-way merge.")?;
+    hooks::run(repo, "post-merge", &["0"])?;
+    Ok(())
+}
+
+// git's default message: `Merge branch 'topic'`, `Merge tag 'v1'`..., followed
+// by ` into <branch>` unless HEAD is on master or main.
+fn merge_message(repo: &Repository, name: &str) -> std::io::Result<String> {
+    let kind = if refs::resolve(repo, &format!("refs/heads/{}", name))?.is_some() {
+        "branch"
+    } else if refs::resolve(repo, &format!("refs/tags/{}", name))?.is_some() {
+        "tag"
+    } else if refs::resolve(repo, &format!("refs/remotes/{}", name))?.is_some() {
+        "remote-tracking branch"
+    } else {
+        "commit"
+    };
+    let mut message = format!("Merge {} '{}'", kind, name);
+    if let Some(branch) = refs::symbolic_target(repo, "HEAD")? {
+        let branch = branch.trim_start_matches("refs/heads/");
+        if branch != "master" && branch != "main" {
+            message += &format!(" into {}", branch);
+        }
+    }
+    Ok(message + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: Labels = Labels { ours: "ours", theirs: "theirs" };
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> Result<Option<Vec<u8>>, Conflicts> {
+        merge_blobs(Some(base.as_bytes()), Some(ours.as_bytes()), Some(theirs.as_bytes()), &LABELS)
+    }
+
+    #[test]
+    fn testing_three_way_merge() {
+        let base = "1\n2\n3\n4\n5\n6\n7\n";
+        let clean = merge(base, "1\nONE-TWO\n3\n4\n5\n6\n7\n", "1\n2\n3\n4\n5\n6\nseven\n").unwrap();
+        assert_eq!(clean.unwrap(), b"1\nONE-TWO\n3\n4\n5\n6\nseven\n");
+        // the same change on both sides is no conflict
+        let same = merge(base, "1\n2\n3\nfour\n5\n6\n7\n", "1\n2\n3\nfour\n5\n6\n7\n").unwrap();
+        assert_eq!(same.unwrap(), b"1\n2\n3\nfour\n5\n6\n7\n");
+        let conflict = merge(base, "1\n2\n3\nfour\n5\n6\n7\n", "1\n2\n3\nFOUR\n5\n6\n7").unwrap_err();
+        assert_eq!(conflict.count, 1);
+        assert_eq!(
+            String::from_utf8(conflict.merged.unwrap()).unwrap(),
+            "1\n2\n3\n<<<<<<< ours\nfour\n=======\nFOUR\n>>>>>>> theirs\n5\n6\n7"
+        );
+    }
+
+    #[test]
+    fn testing_merge_of_added_and_deleted_files() {
+        let add_add = merge_blobs(None, Some(b"a\nb\n"), Some(b"a\nc\n"), &LABELS).unwrap_err();
+        assert_eq!(
+            String::from_utf8(add_add.merged.unwrap()).unwrap(),
+            "a\n<<<<<<< ours\nb\n=======\nc\n>>>>>>> theirs\n"
+        );
+        assert_eq!(merge_blobs(Some(b"a\n"), None, Some(b"a\n"), &LABELS).unwrap(), None);
+        let modify_delete = merge_blobs(Some(b"a\n"), Some(b"b\n"), None, &LABELS).unwrap_err();
+        assert_eq!(modify_delete.merged.unwrap(), b"b\n");
+        assert_eq!(merge_blobs(None, None, Some(b"new\n"), &LABELS).unwrap().unwrap(), b"new\n");
+    }
+
+    // The index follows each kind of merge: a fast-forward, a merge commit
+    // and a merge stopped by a conflict, whose path is left at three stages.
+    #[test]
+    fn testing_merge_index() {
+        let dir = TempDir::new("merge-index");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(git_dir.join("config"), "[user]\n\tname = a\n\temail = a@b\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let commit = |parent: &str, files: &[(&str, &str)]| {
+            let mut tree = match parent {
+                "" => BTreeMap::new(),
+                parent => tree::files(&repo, &Commit::read(&repo, parent).unwrap().tree).unwrap(),
+            };
+            for (path, content) in files {
+                let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+                hash::write_to_database(&repo, &blob).unwrap();
+                tree.insert(path.to_string(), ("100644".to_string(), blob.hash_string));
+            }
+            let tree = tree::write_tree(&repo, &tree).unwrap();
+            let parents: Vec<&str> = Some(parent).into_iter().filter(|parent| !parent.is_empty()).collect();
+            test_commit(&repo, &tree, &parents, 0, "commit\n")
+        };
+        let base = commit("", &[("a.txt", "a\n"), ("c.txt", "1\n")]);
+        refs::update(&repo, "refs/heads/ff", &commit(&base, &[("b.txt", "b\n")]), None).unwrap();
+        refs::update(&repo, "refs/heads/side", &commit(&base, &[("new.txt", "new\n"), ("c.txt", "side\n")]), None).unwrap();
+        refs::update(&repo, "refs/heads/master", &base, None).unwrap();
+        let checkout = |sha: &str| {
+            let files = tree::files(&repo, &Commit::read(&repo, sha).unwrap().tree).unwrap();
+            worktree::update(&repo, &Index::read(&repo).unwrap().files(), &files, &[]).unwrap();
+            write_index(&repo, &files, &[]).unwrap();
+        };
+        checkout(&base);
+        let staged = |repo: &Repository| {
+            let index = Index::read(repo).unwrap();
+            index.entries.iter().map(|entry| (entry.path.clone(), entry.stage())).collect::<Vec<_>>()
+        };
+        let head = |repo: &Repository| refs::resolve(repo, "HEAD").unwrap().unwrap();
+        let head_tree = |repo: &Repository| tree::files(repo, &Commit::read(repo, &head(repo)).unwrap().tree).unwrap();
+
+        merge_branch(&repo, "ff", false).unwrap();
+        let fast_forward = (staged(&repo), Index::read(&repo).unwrap().files() == head_tree(&repo));
+        let ours = commit(&head(&repo), &[("c.txt", "ours\n")]);
+        let clean = commit(&base, &[("d.txt", "d\n")]);
+        refs::update(&repo, "refs/heads/clean", &clean, None).unwrap();
+        checkout(&ours);
+        refs::update(&repo, "HEAD", &ours, None).unwrap();
+        merge_branch(&repo, "clean", false).unwrap();
+        let merged = (staged(&repo), Index::read(&repo).unwrap().files() == head_tree(&repo));
+        let conflicted = merge_branch(&repo, "side", false).is_err();
+        let index = Index::read(&repo).unwrap();
+        let stages: Vec<String> =
+            index.entries.iter().filter(|entry| entry.path == "c.txt").map(|entry| entry.sha.clone()).collect();
+        let unmerged = staged(&repo);
+
+        let paths = |names: &[(&str, u16)]| names.iter().map(|(name, stage)| (name.to_string(), *stage)).collect::<Vec<_>>();
+        assert_eq!(fast_forward, (paths(&[("a.txt", 0), ("b.txt", 0), ("c.txt", 0)]), true));
+        assert_eq!(merged, (paths(&[("a.txt", 0), ("b.txt", 0), ("c.txt", 0), ("d.txt", 0)]), true));
+        assert!(conflicted);
+        assert_eq!(
+            unmerged,
+            paths(&[("a.txt", 0), ("b.txt", 0), ("c.txt", 1), ("c.txt", 2), ("c.txt", 3), ("d.txt", 0), ("new.txt", 0)])
+        );
+        let blob = |content: &str| Blob::new(ObjectType::Blob, content.as_bytes().to_vec()).hash_string;
+        assert_eq!(stages, vec![blob("1\n"), blob("ours\n"), blob("side\n")]);
+    }
+}
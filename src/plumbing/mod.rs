@@ -0,0 +1,3820 @@
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::Digest;
+use std::{fs, fmt};
+use std::io::prelude::*;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+pub mod config;
+pub mod wildmatch;
+pub mod identity;
+pub mod gpg;
+pub mod commit;
+pub mod tag;
+pub mod refs;
+pub mod pack;
+pub mod revision;
+pub mod commit_graph;
+pub mod ancestry;
+pub mod attributes;
+pub mod convert;
+pub mod sparse;
+pub mod ignore;
+pub mod index;
+pub mod unicode;
+pub mod worktree;
+pub mod status;
+pub mod hooks;
+pub mod cherry_pick;
+pub mod stash;
+pub mod notes;
+pub mod fast_export;
+pub mod fast_import;
+pub mod restore;
+pub mod reset;
+pub mod refspec;
+pub mod pkt_line;
+pub mod protocol;
+pub mod fetch;
+pub mod bundle;
+pub mod remote;
+pub mod worktrees;
+pub mod reachable;
+pub mod cat;
+pub mod format;
+pub mod graph;
+pub mod tree;
+pub mod hash;
+pub mod apply;
+pub mod diff;
+pub mod merge;
+
+pub const GIT_DIR: &str = ".git";
+
+// git stops following nested alternates after this many levels
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+// Returns the object directories to search, in order: `objects_dir` itself followed
+// by the directories listed in its `info/alternates` (and in theirs, recursively).
+// Relative alternates are resolved against the objects directory that lists them.
+pub fn object_dirs(objects_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![objects_dir.to_path_buf()];
+    collect_alternates(objects_dir, 0, &mut dirs);
+    dirs
+}
+
+fn collect_alternates(objects_dir: &Path, depth: usize, dirs: &mut Vec<PathBuf>) {
+    if depth >= MAX_ALTERNATE_DEPTH {
+        return;
+    }
+    let alternates = match fs::read_to_string(objects_dir.join("info/alternates")) {
+        Ok(alternates) => alternates,
+        Err(_) => return,
+    };
+    for line in alternates.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let dir = objects_dir.join(line);
+        if !dirs.contains(&dir) {
+            dirs.push(dir.clone());
+            collect_alternates(&dir, depth + 1, dirs);
+        }
+    }
+}
+
+// whether `name` is a full 40 hex digit object id
+pub fn is_object_id(name: &str) -> bool {
+    name.len() == 40 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// the error for `sha` not being a full object id
+fn invalid_object_id(sha: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is not a valid object id", sha))
+}
+
+// `path` as git prints it in lines: C-style escaped within double quotes when
+// it has control characters, a double quote or a backslash, or with
+// `non_ascii` bytes past ASCII.
+pub fn quote_path(path: &str, non_ascii: bool) -> String {
+    let mut quoted = Vec::new();
+    for byte in path.bytes() {
+        let escape = match byte {
+            b'\x07' => Some(b'a'),
+            b'\x08' => Some(b'b'),
+            b'\t' => Some(b't'),
+            b'\n' => Some(b'n'),
+            b'\x0b' => Some(b'v'),
+            b'\x0c' => Some(b'f'),
+            b'\r' => Some(b'r'),
+            b'"' | b'\\' => Some(byte),
+            _ => None,
+        };
+        match escape {
+            Some(escape) => quoted.extend([b'\\', escape]),
+            None if byte < 0x20 || byte == 0x7f || (non_ascii && byte >= 0x80) => {
+                quoted.extend(format!("\\{:03o}", byte).bytes())
+            }
+            None => quoted.push(byte),
+        }
+    }
+    match quoted.len() != path.len() {
+        true => format!("\"{}\"", String::from_utf8_lossy(&quoted)),
+        false => path.to_string(),
+    }
+}
+
+// `quote_path`, also quoting a path with a space where more follows on the line
+pub fn quote_spaced_path(path: &str, non_ascii: bool) -> String {
+    let quoted = quote_path(path, non_ascii);
+    match quoted == path && path.contains(' ') {
+        true => format!("\"{}\"", path),
+        false => quoted,
+    }
+}
+
+// whether paths past ASCII are quoted, as `core.quotePath` says
+pub fn quotes_non_ascii(repo: &Repository) -> std::io::Result<bool> {
+    Ok(repo.config().get_bool("core.quotepath")?.unwrap_or(true))
+}
+
+// Creates `<path>.lock`, failing when it exists already: another process holds
+// the lock, or one died holding it and left it for the user to remove.
+fn lock(path: &Path) -> std::io::Result<(PathBuf, fs::File)> {
+    let mut lock_name = path.as_os_str().to_owned();
+    lock_name.push(".lock");
+    let lock = PathBuf::from(lock_name);
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("could not lock {}: {}", path.display(), e)))?;
+    Ok((lock, file))
+}
+
+// writes `<path>.lock` and renames it over `path`, like git does
+pub fn write_locked(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let (lock, mut file) = lock(path)?;
+    let written = file.write_all(content).and_then(|_| fs::rename(&lock, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&lock);
+    }
+    written
+}
+
+// Writes `content` to a new file in `dir` named `prefix` and random hex
+// digits, and returns its path. The file has to not exist yet, so that
+// nothing else put there is read or written through; a file that couldn't be
+// written is removed.
+fn write_temp(dir: &Path, prefix: &str, content: &[u8]) -> std::io::Result<PathBuf> {
+    loop {
+        let random = RandomState::new().build_hasher().finish();
+        let path = dir.join(format!("{}{:016x}", prefix, random));
+        let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = file.write_all(content) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        return Ok(path);
+    }
+}
+
+// where the loose object `sha` is stored, in `objects_dir` or an alternate
+fn loose_object_path(objects_dir: &Path, sha: &str) -> std::io::Result<PathBuf> {
+    if !is_object_id(sha) {
+        return Err(invalid_object_id(sha));
+    }
+    object_dirs(objects_dir)
+        .into_iter()
+        .map(|dir| dir.join(&sha[..2]).join(&sha[2..]))
+        .find(|path| path.is_file())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("object {} not found", sha)))
+}
+
+// Where a repository keeps its objects, each stored zlib deflated under its
+// id as in a loose object file. The repository hashes, compresses and
+// parses; a store only hands the bytes back.
+pub trait ObjectStore: Send + Sync {
+    // the stored bytes of the object `sha`, a `NotFound` error when it isn't there
+    fn open(&self, sha: &str) -> std::io::Result<Box<dyn Read + Send>>;
+
+    fn contains(&self, sha: &str) -> bool;
+
+    // stores `deflated` as the object `sha`
+    fn write(&self, sha: &str, deflated: &[u8]) -> std::io::Result<()>;
+
+    // the file the object `sha` is in, for telling which one is at fault
+    fn location(&self, _sha: &str) -> Option<PathBuf> {
+        None
+    }
+
+    // The object `sha` inflated, `<type> <size>\0<content>`. Stores that don't
+    // keep each object deflated whole, as packs don't, read them here.
+    fn open_inflated(&self, sha: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(ZlibDecoder::new(self.open(sha)?)))
+    }
+}
+
+// `object <sha> is corrupt`, saying where it is stored when the store knows
+fn corrupt_object(objects: &dyn ObjectStore, sha: &str) -> String {
+    match objects.location(sha) {
+        Some(path) => format!("object {} (stored in {}) is corrupt", sha, path.display()),
+        None => format!("object {} is corrupt", sha),
+    }
+}
+
+// the error for an object failing to inflate, `corrupt` saying which one
+fn inflate_error(corrupt: &str, e: std::io::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", corrupt, e))
+}
+
+// The loose objects of an objects directory and its alternates, written to
+// the directory itself.
+pub struct LooseObjects {
+    objects_dir: PathBuf,
+}
+
+impl LooseObjects {
+    pub fn new<P: AsRef<Path>>(objects_dir: P) -> Self {
+        Self { objects_dir: objects_dir.as_ref().to_path_buf() }
+    }
+}
+
+impl ObjectStore for LooseObjects {
+    fn open(&self, sha: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        let file = fs::File::open(loose_object_path(&self.objects_dir, sha)?)?;
+        Ok(Box::new(std::io::BufReader::new(file)))
+    }
+
+    fn contains(&self, sha: &str) -> bool {
+        is_object_id(sha) && object_dirs(&self.objects_dir).iter().any(|dir| dir.join(&sha[..2]).join(&sha[2..]).is_file())
+    }
+
+    fn write(&self, sha: &str, deflated: &[u8]) -> std::io::Result<()> {
+        if !is_object_id(sha) {
+            return Err(invalid_object_id(sha));
+        }
+        // an object is the same whoever wrote it, one that is there stays
+        if self.contains(sha) {
+            return Ok(());
+        }
+        // written next to where it goes and renamed there, so that no reader
+        // ever finds half an object
+        let dir = self.objects_dir.join(&sha[..2]);
+        fs::create_dir_all(&dir)?;
+        let temp = write_temp(&dir, "tmp_obj_", deflated)?;
+        let renamed = fs::rename(&temp, dir.join(&sha[2..]));
+        if renamed.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        renamed
+    }
+
+    fn location(&self, sha: &str) -> Option<PathBuf> {
+        loose_object_path(&self.objects_dir, sha).ok()
+    }
+}
+
+// A directory of its own under the temp dir, for a test whose repository
+// has to be on disk; it goes when dropped, a failed assert included.
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rust-git-{}-{}", name, std::process::id()));
+        // what a run that was killed left
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// The tree with no entries, which tests can commit without writing it.
+#[cfg(test)]
+pub(crate) const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+// Writes a commit of `tree` with `parents` and `message` by `A <a@b>` at
+// `time`, for the tests that build a history of their own.
+#[cfg(test)]
+pub(crate) fn test_commit<S: AsRef<str>>(
+    repo: &Repository,
+    tree: &str,
+    parents: &[S],
+    time: i64,
+    message: &str,
+) -> String {
+    let identity = identity::Identity { name: "A".to_string(), email: "a@b".to_string(), time, offset: 0 };
+    commit::Commit {
+        tree: tree.to_string(),
+        parents: parents.iter().map(|parent| parent.as_ref().to_string()).collect(),
+        author: identity.clone(),
+        committer: identity,
+        extra_headers: Vec::new(),
+        message: message.to_string(),
+    }
+    .write(repo)
+    .unwrap()
+}
+
+// Objects kept in memory only, for code that reads and writes objects to
+// run without a repository on disk.
+#[derive(Default)]
+pub struct MemoryObjects {
+    objects: std::sync::Mutex<std::collections::HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryObjects {
+    fn objects(&self) -> std::sync::MutexGuard<'_, std::collections::HashMap<String, Vec<u8>>> {
+        // the map is never left half updated, so a panic elsewhere doesn't spoil it
+        self.objects.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ObjectStore for MemoryObjects {
+    fn open(&self, sha: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        match self.objects().get(sha) {
+            Some(deflated) => Ok(Box::new(std::io::Cursor::new(deflated.clone()))),
+            None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("object {} not found", sha))),
+        }
+    }
+
+    fn contains(&self, sha: &str) -> bool {
+        self.objects().contains_key(sha)
+    }
+
+    fn write(&self, sha: &str, deflated: &[u8]) -> std::io::Result<()> {
+        self.objects().insert(sha.to_string(), deflated.to_vec());
+        Ok(())
+    }
+}
+
+// The objects of an objects directory and its alternates: the loose ones, then
+// those in their packs. New objects are written loose to the directory itself.
+pub struct DirectoryObjects {
+    objects_dir: PathBuf,
+    loose: LooseObjects,
+    // the packs of each directory, opened the first time they are looked in
+    packs: std::sync::Mutex<Option<std::sync::Arc<Vec<pack::Packs>>>>,
+    delta_base_cache_limit: u64,
+}
+
+impl DirectoryObjects {
+    pub fn new<P: AsRef<Path>>(objects_dir: P) -> Self {
+        Self {
+            objects_dir: objects_dir.as_ref().to_path_buf(),
+            loose: LooseObjects::new(objects_dir),
+            packs: Default::default(),
+            delta_base_cache_limit: pack::DEFAULT_DELTA_BASE_CACHE_LIMIT,
+        }
+    }
+
+    // the store keeping at most `limit` bytes of delta bases cached in each directory
+    pub fn with_delta_base_cache_limit(self, limit: u64) -> Self {
+        Self { delta_base_cache_limit: limit, ..self }
+    }
+
+    pub fn packs(&self) -> std::io::Result<std::sync::Arc<Vec<pack::Packs>>> {
+        let mut packs = self.packs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if packs.is_none() {
+            let opened = object_dirs(&self.objects_dir)
+                .iter()
+                .map(|dir| pack::Packs::open_with_cache_limit(dir, self.delta_base_cache_limit))
+                .collect::<std::io::Result<_>>()?;
+            *packs = Some(std::sync::Arc::new(opened));
+        }
+        Ok(packs.as_ref().unwrap().clone())
+    }
+
+    // Forgets the packs opened so far when the pack directories no longer
+    // hold the same ones, as a pack written since may have what wasn't
+    // found; they're opened again on the next lookup. Tells whether they were.
+    fn reopen_changed_packs(&self) -> std::io::Result<bool> {
+        let mut packs = self.packs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let opened = match packs.as_ref() {
+            Some(opened) => opened.clone(),
+            None => return Ok(false),
+        };
+        for (dir, known) in object_dirs(&self.objects_dir).iter().zip(opened.iter()) {
+            let names: Vec<String> = known
+                .packs()
+                .iter()
+                .map(|pack| pack.path().with_extension("idx").file_name().unwrap_or_default().to_string_lossy().into_owned())
+                .collect();
+            if pack::index_names(&dir.join("pack"))? != names {
+                *packs = None;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // The pack holding the object `sha` and the offset of its entry, handed to
+    // `found`.
+    fn find_packed<T>(&self, sha: &str, found: impl FnOnce(&pack::Packs, &pack::Pack, u64) -> T) -> std::io::Result<Option<T>> {
+        if !is_object_id(sha) {
+            return Ok(None);
+        }
+        let id = Blob::decode_hex(sha)?;
+        for reopened in [false, true].iter() {
+            if *reopened && !self.reopen_changed_packs()? {
+                break;
+            }
+            for packs in self.packs()?.iter() {
+                if let Some((pack, offset)) = packs.find(&id)? {
+                    return Ok(Some(found(packs, pack, offset)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // the inflated object `sha` from the packs, a `NotFound` error when none has it
+    fn open_packed(&self, sha: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        if !is_object_id(sha) {
+            return Err(invalid_object_id(sha));
+        }
+        let id = Blob::decode_hex(sha)?;
+        let mut found = None;
+        for reopened in [false, true].iter() {
+            if *reopened && !self.reopen_changed_packs()? {
+                break;
+            }
+            for packs in self.packs()?.iter() {
+                found = packs.read(&id).map_err(|e| inflate_error(&corrupt_object(self, sha), e))?;
+                if found.is_some() {
+                    break;
+                }
+            }
+            if found.is_some() {
+                break;
+            }
+        }
+        match found {
+            Some((object_type, content)) => {
+                let mut object = format!("{} {}\0", object_type, content.len()).into_bytes();
+                object.extend(content);
+                Ok(Box::new(std::io::Cursor::new(object)))
+            }
+            _ => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("object {} not found", sha))),
+        }
+    }
+}
+
+impl ObjectStore for DirectoryObjects {
+    fn open(&self, sha: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        match self.loose.open(sha) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            opened => return opened,
+        }
+        let packed = self.open_packed(sha)?;
+        Ok(Box::new(flate2::read::ZlibEncoder::new(packed, Compression::fast())))
+    }
+
+    fn contains(&self, sha: &str) -> bool {
+        self.loose.contains(sha) || matches!(self.find_packed(sha, |_, _, _| ()), Ok(Some(())))
+    }
+
+    fn write(&self, sha: &str, deflated: &[u8]) -> std::io::Result<()> {
+        self.loose.write(sha, deflated)
+    }
+
+    fn location(&self, sha: &str) -> Option<PathBuf> {
+        self.loose.location(sha).or_else(|| self.find_packed(sha, |_, pack, _| pack.path().to_path_buf()).ok()?)
+    }
+
+    fn open_inflated(&self, sha: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        match self.loose.open(sha) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.open_packed(sha),
+            opened => Ok(Box::new(ZlibDecoder::new(opened?))),
+        }
+    }
+}
+
+// The git dir a `.git` file names with its `gitdir: <path>` line, a relative
+// path being relative to the directory of the file.
+fn read_git_file(path: &Path) -> std::io::Result<PathBuf> {
+    let content = fs::read_to_string(path)?;
+    let git_dir = content
+        .strip_prefix("gitdir: ")
+        .map(|git_dir| git_dir.trim_end_matches(['\n', '\r']))
+        .filter(|git_dir| !git_dir.is_empty())
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid gitfile format: {}", path.display()))
+        })?;
+    Ok(path.parent().map(|dir| dir.join(git_dir)).unwrap_or_else(|| PathBuf::from(git_dir)))
+}
+
+// The content of an object, inflated as it is read. Its type and size come
+// from the header, read when the object is opened; content that ends before
+// that size or goes on past it is an error.
+pub struct ObjectReader {
+    object_type: ObjectType,
+    size: u64,
+    content: Box<dyn Read + Send>,
+    // how much of the content is still to be read, `None` once it has been
+    // and nothing was found after it
+    left: Option<u64>,
+    // what a failure to inflate the rest is reported as
+    corrupt: String,
+}
+
+impl ObjectReader {
+    pub fn object_type(&self) -> ObjectType {
+        self.object_type
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mismatch = || std::io::Error::new(std::io::ErrorKind::InvalidData, "size doesn't match the content");
+        let left = match self.left {
+            Some(left) => left,
+            None => return Ok(0),
+        };
+        let read = match left {
+            0 => {
+                // the stream has to end with the content
+                if self.content.read(&mut [0]).map_err(|e| inflate_error(&self.corrupt, e))? != 0 {
+                    return Err(inflate_error(&self.corrupt, mismatch()));
+                }
+                self.left = None;
+                return Ok(0);
+            }
+            _ if buf.is_empty() => return Ok(0),
+            _ => {
+                let end = buf.len().min(left.min(usize::MAX as u64) as usize);
+                self.content.read(&mut buf[..end]).map_err(|e| inflate_error(&self.corrupt, e))?
+            }
+        };
+        if read == 0 {
+            return Err(inflate_error(&self.corrupt, mismatch()));
+        }
+        self.left = Some(left - read as u64);
+        Ok(read)
+    }
+}
+
+// Yields the ids of the loose objects in a set of object directories, sorted and
+// without duplicates, and with `with_packs` those of their packs too. Only one
+// fan-out directory (`objects/??/`) is listed at a time, so the whole store
+// never has to be held in memory.
+pub struct AllObjects {
+    dirs: Vec<PathBuf>,
+    // the indexes of the packs in `dirs`, read when the first id is asked for
+    indexes: Option<Vec<pack::PackIndex>>,
+    next_prefix: usize,
+    pending: std::vec::IntoIter<String>,
+}
+
+impl AllObjects {
+    pub fn new(dirs: Vec<PathBuf>) -> AllObjects {
+        AllObjects {
+            dirs,
+            indexes: Some(Vec::new()),
+            next_prefix: 0,
+            pending: Vec::new().into_iter(),
+        }
+    }
+
+    pub fn with_packs(self) -> AllObjects {
+        AllObjects { indexes: None, ..self }
+    }
+
+    fn list_prefix(&mut self, prefix: &str) -> std::io::Result<Vec<String>> {
+        if self.indexes.is_none() {
+            let mut indexes = Vec::new();
+            for dir in &self.dirs {
+                for name in pack::index_names(&dir.join("pack"))? {
+                    indexes.push(pack::PackIndex::open(&dir.join("pack").join(name))?);
+                }
+            }
+            self.indexes = Some(indexes);
+        }
+        let first = u8::from_str_radix(prefix, 16).unwrap();
+        let mut ids: Vec<String> = self.indexes.iter().flatten().flat_map(|index| index.ids_starting_with(first)).collect();
+        for dir in &self.dirs {
+            let objects = match fs::read_dir(dir.join(prefix)) {
+                Ok(objects) => objects,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for object in objects {
+                let name = object?.file_name().to_string_lossy().into_owned();
+                let id = format!("{}{}", prefix, name);
+                if is_object_id(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+}
+
+impl Iterator for AllObjects {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(id) = self.pending.next() {
+                return Some(Ok(id));
+            }
+            if self.next_prefix > 0xff {
+                return None;
+            }
+            let prefix = format!("{:02x}", self.next_prefix);
+            self.next_prefix += 1;
+            match self.list_prefix(&prefix) {
+                Ok(ids) => self.pending = ids.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// repository format versions whose on-disk layout we understand
+const MAX_REPOSITORY_FORMAT_VERSION: i64 = 1;
+
+// extensions (lowercased) that don't change how objects and refs are read
+const KNOWN_EXTENSIONS: &[&str] = &["noop", "preciousobjects", "objectformat"];
+
+// how many replacements of replacements are followed, as in git
+const MAX_REPLACE_DEPTH: usize = 5;
+
+// how many symlinks a path in a tree may go through, as in git
+const MAX_SYMLINKS: usize = 40;
+
+// the refs each worktree has its own of, besides those outside `refs/`
+const WORKTREE_REFS: &[&str] = &["refs/bisect", "refs/worktree", "refs/rewritten"];
+
+pub struct Repository {
+    git_dir: PathBuf,
+    // where the objects, refs and config are, `git_dir` but in linked worktrees
+    common_dir: PathBuf,
+    // set when the files aren't checked out next to `git_dir`
+    work_tree: Option<PathBuf>,
+    config: config::Config,
+    // `refs/replace/<id>`: the object read in place of `<id>`
+    replacements: std::collections::HashMap<String, String>,
+    objects: Box<dyn ObjectStore>,
+}
+
+impl Repository {
+    // Opens the repository at `git_dir`, refusing to operate on repositories
+    // that use a format version or an extension we don't implement. `git_dir`
+    // may also be a `.git` file, `gitdir: <path>`, as in a linked worktree,
+    // whose files are then the ones next to it. A git dir with a `commondir`
+    // file shares the objects, refs and config of the one it names.
+    pub fn open<P: AsRef<Path>>(git_dir: P) -> std::io::Result<Self> {
+        let git_dir = git_dir.as_ref().to_path_buf();
+        if git_dir.is_file() {
+            let work_tree = git_dir.parent().map(Path::to_path_buf).unwrap_or_default();
+            return Ok(Self::open(read_git_file(&git_dir)?)?.with_work_tree(work_tree));
+        }
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common_dir) => git_dir.join(common_dir.trim_end_matches(['\n', '\r'])),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => git_dir.clone(),
+            Err(e) => return Err(e),
+        };
+        if !common_dir.join("objects").is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("not a git repository: {}", git_dir.display()),
+            ));
+        }
+        // the format is a property of the repository, only its own config counts
+        let repo_config = config::Config::from_file(&common_dir.join("config"), Some(&git_dir))?;
+        Self::check_format(&repo_config)?;
+        let mut config = config::Config::from_files(&config::Config::global_files(), Some(&git_dir))?;
+        config.merge(repo_config);
+        let limit = config.get_size("core.deltabasecachelimit")?.unwrap_or(pack::DEFAULT_DELTA_BASE_CACHE_LIMIT);
+        let objects = Box::new(DirectoryObjects::new(common_dir.join("objects")).with_delta_base_cache_limit(limit));
+        let mut repo = Self { git_dir, common_dir, work_tree: None, config, replacements: Default::default(), objects };
+        let replacing = std::env::var_os("GIT_NO_REPLACE_OBJECTS").is_none()
+            && repo.config.get_bool("core.usereplacerefs")?.unwrap_or(true);
+        if replacing {
+            repo.replacements = refs::list(&repo)?
+                .into_iter()
+                .filter_map(|(name, sha)| Some((name.strip_prefix("refs/replace/")?.to_string(), sha)))
+                .filter(|(replaced, _)| is_object_id(replaced))
+                .collect();
+        }
+        Ok(repo)
+    }
+
+    // Opens the repository `dir` is in, the first of `dir` and the directories
+    // above it to have a `.git`, which is then the work tree.
+    pub fn discover<P: AsRef<Path>>(dir: P) -> std::io::Result<Self> {
+        let start = fs::canonicalize(dir)?;
+        let mut dir = Some(start.as_path());
+        while let Some(current) = dir {
+            let dot_git = current.join(GIT_DIR);
+            if dot_git.exists() {
+                return Ok(Self::open(dot_git)?.with_work_tree(current));
+            }
+            dir = current.parent();
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("not a git repository (or any of the parent directories): {}", start.display()),
+        ))
+    }
+
+    pub fn with_work_tree<P: AsRef<Path>>(self, work_tree: P) -> Self {
+        Self { work_tree: Some(work_tree.as_ref().to_path_buf()), ..self }
+    }
+
+    // The same repository with its objects read from and written to `objects`
+    // instead of the objects directory.
+    pub fn with_object_store<S: ObjectStore + 'static>(self, objects: S) -> Self {
+        Self { objects: Box::new(objects), ..self }
+    }
+
+    // The same repository with its replace refs left alone, as
+    // `--no-replace-objects` asks: every object is read as stored.
+    pub fn without_replacements(self) -> Self {
+        Self { replacements: Default::default(), ..self }
+    }
+
+    // A repository whose objects are only kept in memory. It has no git dir,
+    // so it is for working with objects alone: refs, the index and the
+    // config are those of a repository that doesn't exist yet.
+    pub fn in_memory() -> Self {
+        Self {
+            git_dir: PathBuf::new(),
+            common_dir: PathBuf::new(),
+            work_tree: None,
+            config: Default::default(),
+            replacements: Default::default(),
+            objects: Box::new(MemoryObjects::default()),
+        }
+    }
+
+    pub fn objects(&self) -> &dyn ObjectStore {
+        self.objects.as_ref()
+    }
+
+    fn check_format(config: &config::Config) -> std::io::Result<()> {
+        let unsupported = |msg: String| std::io::Error::new(std::io::ErrorKind::Unsupported, msg);
+        let version = match config.get("core.repositoryformatversion") {
+            Some(value) => value.trim().parse::<i64>().map_err(|_| {
+                unsupported(format!("bad core.repositoryformatversion: {}", value))
+            })?,
+            None => 0,
+        };
+        if version > MAX_REPOSITORY_FORMAT_VERSION {
+            return Err(unsupported(format!("unsupported repository version: {}", version)));
+        }
+        // extensions are only binding from version 1 on
+        if version < 1 {
+            return Ok(());
+        }
+        for (key, value) in config.section("extensions") {
+            let name = &key["extensions.".len()..];
+            if !KNOWN_EXTENSIONS.contains(&name) {
+                return Err(unsupported(format!("unknown repository extension: {}", name)));
+            }
+            if name == "objectformat" && !value.eq_ignore_ascii_case("sha1") {
+                return Err(unsupported(format!("unsupported object format: {}", value)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    // Whether no object may ever be deleted, which `extensions.preciousObjects`
+    // asks of a version 1 repository: prune refuses to run in one.
+    pub fn precious_objects(&self) -> std::io::Result<bool> {
+        let version = self.config.get("core.repositoryformatversion").and_then(|version| version.trim().parse::<i64>().ok());
+        Ok(version.unwrap_or(0) >= 1 && self.config.get_bool("extensions.preciousobjects")?.unwrap_or(false))
+    }
+
+    // the git dir of the main worktree, the same as `git_dir` outside linked ones
+    pub fn common_dir(&self) -> &Path {
+        &self.common_dir
+    }
+
+    pub fn objects_dir(&self) -> PathBuf {
+        self.common_dir.join("objects")
+    }
+
+    // The file of the ref `name`: in `git_dir` for HEAD and the other refs a
+    // worktree has its own of, in `common_dir` for the shared ones.
+    pub fn ref_path(&self, name: &str) -> PathBuf {
+        let own = (name != "refs" && !name.starts_with("refs/"))
+            || WORKTREE_REFS.iter().any(|dir| name == *dir || name.starts_with(&format!("{}/", dir)));
+        match own {
+            true => self.git_dir.join(name),
+            false => self.common_dir.join(name),
+        }
+    }
+
+    pub fn config(&self) -> &config::Config {
+        &self.config
+    }
+
+    // the directory the repository's files are checked out in
+    pub fn work_tree(&self) -> PathBuf {
+        match &self.work_tree {
+            Some(work_tree) => work_tree.clone(),
+            None => self.git_dir.parent().map(Path::to_path_buf).unwrap_or_default(),
+        }
+    }
+
+    // whether any object is read as another one
+    pub fn replaces_objects(&self) -> bool {
+        !self.replacements.is_empty()
+    }
+
+    // The object read when `sha` is asked for: its replacement, or the
+    // replacement's, when replace refs are in use and it has one.
+    pub fn replacement<'a>(&'a self, sha: &'a str) -> std::io::Result<&'a str> {
+        let mut replaced = sha;
+        for _ in 0..=MAX_REPLACE_DEPTH {
+            match self.replacements.get(replaced) {
+                Some(replacement) => replaced = replacement,
+                None => return Ok(replaced),
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("replace depth too high for object {}", sha),
+        ))
+    }
+
+    // Opens the object `sha`, or the one replacing it, to read its content as it
+    // is inflated instead of all at once.
+    pub fn open_object(&self, sha: &str) -> std::io::Result<ObjectReader> {
+        let stored = self.replacement(sha)?;
+        let mut inflated = self.objects.open_inflated(stored)?;
+        let invalid = |msg: &str| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("object {}: {}", sha, msg))
+        };
+        // `<type> <size>\0`, read a byte at a time so no content is consumed
+        let mut header = Vec::new();
+        let mut byte = [0];
+        loop {
+            inflated.read_exact(&mut byte).map_err(|e| inflate_error(&corrupt_object(self.objects(), stored), e))?;
+            match byte[0] {
+                0 => break,
+                _ if header.len() > 32 => return Err(invalid("header too long")),
+                byte => header.push(byte),
+            }
+        }
+        let space = header.iter().position(|&b| b == b' ').ok_or_else(|| invalid("missing object type"))?;
+        let object_type = ObjectType::from_bytes(&header[..space]).ok_or_else(|| invalid("unknown object type"))?;
+        let size = std::str::from_utf8(&header[space + 1..])
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| invalid("bad object size"))?;
+        let corrupt = corrupt_object(self.objects(), stored);
+        Ok(ObjectReader { object_type, size, content: inflated, left: Some(size), corrupt })
+    }
+
+    // whether the object store has the object `sha`
+    pub fn has_object(&self, sha: &str) -> bool {
+        is_object_id(sha) && self.objects.contains(sha)
+    }
+
+    // Writes an object of `object_type` holding `content`, unless it is there
+    // already, and returns its id.
+    pub fn write_object(&self, object_type: ObjectType, content: Vec<u8>) -> std::io::Result<String> {
+        let blob = Blob::new(object_type, content);
+        if !self.has_object(&blob.hash_string) {
+            hash::write_to_database(self, &blob)?;
+        }
+        Ok(blob.hash_string)
+    }
+
+    pub fn write_blob(&self, content: &[u8]) -> std::io::Result<String> {
+        self.write_object(ObjectType::Blob, content.to_vec())
+    }
+
+    // Writes the tree of `entries`, which are sorted the way git sorts them: a
+    // tree as if its name ended with `/`.
+    pub fn write_tree(&self, entries: &[TreeEntry]) -> std::io::Result<String> {
+        let invalid = |name: &str| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid tree entry name '{}'", name))
+        };
+        let mut sorted: Vec<(String, &TreeEntry)> = Vec::new();
+        for entry in entries {
+            if entry.name.is_empty() || entry.name.contains('/') || entry.name.contains('\0') {
+                return Err(invalid(&entry.name));
+            }
+            let name = match entry.entry_type {
+                EntryType::Tree => format!("{}/", entry.name),
+                _ => entry.name.clone(),
+            };
+            sorted.push((name, entry));
+        }
+        sorted.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        let mut content = Vec::new();
+        for (_, entry) in sorted {
+            content.extend_from_slice(format!("{} {}\0", entry.mode.trim_start_matches('0'), entry.name).as_bytes());
+            content.extend_from_slice(&entry.sha);
+        }
+        self.write_object(ObjectType::Tree, content)
+    }
+
+    pub fn write_commit(&self, commit: &commit::Commit) -> std::io::Result<String> {
+        self.write_object(ObjectType::Commit, commit.serialize())
+    }
+
+    // The commits listed in `shallow`: the boundary of a shallow clone, treated as
+    // having no parents.
+    pub fn shallow_commits(&self) -> std::io::Result<std::collections::HashSet<String>> {
+        match fs::read_to_string(self.common_dir.join("shallow")) {
+            Ok(shallow) => Ok(shallow.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Makes `shallow` list `commits`, one a line in id order as git keeps it;
+    // without any, the file goes and the repository is complete again.
+    pub fn set_shallow_commits(&self, commits: &std::collections::HashSet<String>) -> std::io::Result<()> {
+        let path = self.common_dir.join("shallow");
+        if commits.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut sorted: Vec<&String> = commits.iter().collect();
+        sorted.sort();
+        let lines: String = sorted.into_iter().map(|sha| format!("{}\n", sha)).collect();
+        write_locked(&path, lines.as_bytes())
+    }
+
+    // Every object in the repository and its alternates, once each, in id order.
+    pub fn all_objects(&self) -> AllObjects {
+        AllObjects::new(object_dirs(&self.objects_dir())).with_packs()
+    }
+
+    // The `(mode, id)` of `path` in the tree `tree`, found by walking down its
+    // subtrees one component at a time. An empty path is the tree itself.
+    pub fn resolve_path(&self, tree: &str, path: &str) -> std::io::Result<(String, String)> {
+        let mut found = ("40000".to_string(), tree.to_string());
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let not_found = || {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("path '{}' does not exist in '{}'", path, tree),
+                )
+            };
+            if found.0 != "40000" {
+                return Err(not_found());
+            }
+            let object = Blob::from_sha(self, &found.1)?;
+            let entries = Tree::try_pars(&object)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+            let entry = entries.lookup(name).ok_or_else(not_found)?;
+            found = (entry.mode.clone(), Blob::string_hash(&entry.sha));
+        }
+        Ok(found)
+    }
+
+    // Like `resolve_path`, but a symlink met on the way, the last component
+    // included, is followed to what it points at in the same tree, so the
+    // result is never a symlink. Links that are absolute, lead out of the
+    // tree or go round in circles are errors.
+    pub fn resolve_path_following_symlinks(&self, tree: &str, path: &str) -> std::io::Result<(String, String)> {
+        let error = |kind, message: String| std::io::Error::new(kind, format!("path '{}': {}", path, message));
+        // the trees from the root down to the directory being looked in
+        let mut trees = vec![tree.to_string()];
+        let mut found = ("40000".to_string(), tree.to_string());
+        let mut pending: Vec<String> = path.rsplit('/').filter(|name| !name.is_empty()).map(String::from).collect();
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            if found.0 != "40000" {
+                return Err(error(std::io::ErrorKind::NotFound, format!("'{}' is not a directory", name)));
+            }
+            match name.as_str() {
+                "." => continue,
+                ".." if trees.len() == 1 => {
+                    return Err(error(std::io::ErrorKind::InvalidInput, "a symlink leads outside the tree".to_string()))
+                }
+                ".." => {
+                    trees.pop();
+                }
+                _ => {
+                    let (mode, sha) = self.resolve_path(&trees[trees.len() - 1], &name).map_err(|e| match e.kind() {
+                        std::io::ErrorKind::NotFound => error(e.kind(), format!("'{}' does not exist", name)),
+                        _ => e,
+                    })?;
+                    match mode.as_str() {
+                        "40000" => trees.push(sha),
+                        "120000" => {
+                            links += 1;
+                            if links > MAX_SYMLINKS {
+                                return Err(error(std::io::ErrorKind::InvalidInput, "too many levels of symbolic links".to_string()));
+                            }
+                            let target = String::from_utf8_lossy(&Blob::from_sha(self, &sha)?.content).into_owned();
+                            if target.starts_with('/') {
+                                let message = format!("the symlink '{}' points at the absolute path '{}'", name, target);
+                                return Err(error(std::io::ErrorKind::InvalidInput, message));
+                            }
+                            pending.extend(target.rsplit('/').filter(|name| !name.is_empty()).map(String::from));
+                        }
+                        _ => {
+                            found = (mode, sha);
+                            continue;
+                        }
+                    }
+                }
+            }
+            found = ("40000".to_string(), trees[trees.len() - 1].clone());
+        }
+        Ok(found)
+    }
+}
+
+// What a command failed with, which decides the status it exits with the
+// way git has it: 129 when it was used wrongly, 128 for fatal errors like a
+// missing repository or revision and 1 otherwise.
+#[derive(Debug, thiserror::Error)]
+pub enum GitError {
+    #[error("{0}")]
+    InvalidArgs(String),
+    #[error("{0}")]
+    Fatal(String),
+    #[error("{0}")]
+    Failed(String),
+    // the reader of the output went away, which isn't worth a word
+    #[error("")]
+    BrokenPipe,
+}
+
+// Writing the output failed.
+impl From<std::io::Error> for GitError {
+    fn from(error: std::io::Error) -> GitError {
+        match error.kind() {
+            std::io::ErrorKind::BrokenPipe => GitError::BrokenPipe,
+            _ => GitError::Failed(format!("Error: {}", error)),
+        }
+    }
+}
+
+impl GitError {
+    // `error`, which running `command` ended with; a revision that doesn't
+    // exist is fatal.
+    pub fn failed(command: &str, error: std::io::Error) -> GitError {
+        match error.get_ref() {
+            _ if error.kind() == std::io::ErrorKind::BrokenPipe => GitError::BrokenPipe,
+            Some(inner) if inner.is::<refs::UnknownRevision>() => GitError::Fatal(format!("fatal: {}", error)),
+            _ => GitError::Failed(format!("Error: {} command failed with error: '{}'", command, error)),
+        }
+    }
+
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            GitError::InvalidArgs(_) => 129,
+            GitError::Fatal(_) => 128,
+            GitError::Failed(_) => 1,
+            // what a shell reports for git, which SIGPIPE ends
+            GitError::BrokenPipe => 141,
+        }
+    }
+}
+
+// what git's own template has for `description` and `info/exclude`
+const DESCRIPTION: &str = "Unnamed repository; edit this file 'description' to name the repository.\n";
+const EXCLUDE: &str = "# git ls-files --others --exclude-from=.git/info/exclude\n\
+                           # Lines that start with '#' are comments.\n\
+                           # For a project mostly in C, the following would be a good set of\n\
+                           # exclude patterns (uncomment them if you want to use them):\n\
+                           # *.[oa]\n\
+                           # *~\n";
+
+// Creates the repository `git_dir`. The files of `template` are copied into it
+// first, leaving out the ones starting with a dot, then the default
+// description and excludes are written unless the template had them.
+pub fn init_repository(git_dir: &Path, template: Option<&Path>) -> std::io::Result<()> {
+    fs::create_dir(git_dir)?;
+    match template {
+        Some(template) if template.is_dir() => copy_template(template, git_dir)?,
+        Some(template) => eprintln!("warning: templates not found in {}", template.display()),
+        None => {}
+    }
+    for dir in ["objects", "refs", "info"] {
+        fs::create_dir_all(git_dir.join(dir))?;
+    }
+    for (name, content) in [("description", DESCRIPTION), ("info/exclude", EXCLUDE)] {
+        let path = git_dir.join(name);
+        if fs::symlink_metadata(&path).is_err() {
+            fs::write(path, content)?;
+        }
+    }
+    fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n")
+}
+
+// copies what `to` doesn't have yet, hooks staying executable
+fn copy_template(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_template(&entry.path(), &target)?;
+        } else if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// init a git repository by creating the directory structure found in .git
+pub fn init(args: &[String]) -> Result<(), GitError> {
+    let mut template = std::env::var_os("GIT_TEMPLATE_DIR").map(PathBuf::from);
+    for arg in args {
+        match arg.strip_prefix("--template=") {
+            Some("") => template = None,
+            Some(dir) => template = Some(PathBuf::from(dir)),
+            None => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid init command", arg))),
+        }
+    }
+    init_repository(Path::new(GIT_DIR), template.as_deref())
+        .map_err(|e| GitError::failed("init", e))?;
+    outln!("Initialized git directory")?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
+    Blob,
+    Tree,
+    Commit,
+    Tag
+}
+
+impl ObjectType {
+    // parse the type word found at the beginning of an object header
+    pub fn from_bytes(word: &[u8]) -> Option<Self> {
+        match word {
+            b"blob" => Some(ObjectType::Blob),
+            b"tree" => Some(ObjectType::Tree),
+            b"commit" => Some(ObjectType::Commit),
+            b"tag" => Some(ObjectType::Tag),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectType::Blob => "blob",
+            ObjectType::Tree => "tree",
+            ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
+        }
+    }
+}
+
+impl fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    Blob,
+    Tree,
+    Symlink,
+    Gitlink
+}
+
+impl EntryType {
+    // The type a tree entry's mode stands for. Any mode git doesn't give a
+    // meaning of its own, `100755` among them, is a blob's.
+    pub fn from_mode(mode: &str) -> Self {
+        match mode {
+            "40000" | "040000" => EntryType::Tree,
+            "120000" => EntryType::Symlink,
+            "160000" => EntryType::Gitlink,
+            _ => EntryType::Blob,
+        }
+    }
+
+    // the mode git writes for the type, a blob's being that of a plain file
+    pub fn mode_str(&self) -> &'static str {
+        match self {
+            EntryType::Blob => "100644",
+            EntryType::Tree => "40000",
+            EntryType::Symlink => "120000",
+            EntryType::Gitlink => "160000",
+        }
+    }
+
+    // the type of the object the entry points at, a commit for a submodule
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            EntryType::Blob | EntryType::Symlink => ObjectType::Blob,
+            EntryType::Tree => ObjectType::Tree,
+            EntryType::Gitlink => ObjectType::Commit,
+        }
+    }
+}
+
+// The type word `ls-tree` shows for an entry: `blob`, `tree` or `commit`.
+// A symlink's word is `blob`, so the word alone never gives `Symlink`.
+impl std::convert::TryFrom<&[u8]> for EntryType {
+    type Error = NotATreeObject;
+
+    fn try_from(word: &[u8]) -> Result<Self, Self::Error> {
+        match ObjectType::from_bytes(word) {
+            Some(ObjectType::Blob) => Ok(EntryType::Blob),
+            Some(ObjectType::Tree) => Ok(EntryType::Tree),
+            Some(ObjectType::Commit) => Ok(EntryType::Gitlink),
+            _ => Err(NotATreeObject),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NotATreeObject;
+
+pub struct TreeEntry {
+    pub mode: String,
+    pub entry_type: EntryType,
+    pub sha: [u8; 20],
+    pub name: String
+}
+
+pub struct Tree {
+    entries: Vec<TreeEntry>
+}
+
+impl fmt::Display for NotATreeObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "content is not a valid 'tree' object.")
+    }
+}
+
+pub struct Blob {
+    pub content: Vec<u8>,
+    pub header: Vec<u8>,
+    #[allow(dead_code)]
+    pub hash: [u8; 20],
+    pub hash_string: String,
+    object_type: ObjectType,
+}
+
+impl Tree {
+    // A tree object is a list of entries in the form `<mode> <name>\0<20 bytes sha>`
+    pub fn try_pars(blob: &Blob) -> Result<Tree, NotATreeObject> {
+        if blob.object_type() != ObjectType::Tree {
+            return Err(NotATreeObject);
+        }
+        let mut entries = Vec::new();
+        let mut rest = &blob.content[..];
+        while !rest.is_empty() {
+            let space = rest.iter().position(|&x| x == b' ').ok_or(NotATreeObject)?;
+            let nul = rest.iter().position(|&x| x == 0).ok_or(NotATreeObject)?;
+            if nul < space || rest.len() < nul + 21 {
+                return Err(NotATreeObject);
+            }
+            let mode: String = String::from_utf8_lossy(&rest[..space]).into();
+            entries.push(TreeEntry {
+                entry_type: EntryType::from_mode(&mode),
+                mode,
+                sha: rest[nul + 1..nul + 21].try_into().unwrap(),
+                name: String::from_utf8_lossy(&rest[space + 1..nul]).into(),
+            });
+            rest = &rest[nul + 21..];
+        }
+        Ok(Tree { entries })
+    }
+
+    pub fn entries(&self) -> &[TreeEntry] {
+        &self.entries
+    }
+
+    // The entry called `name`. git keeps the entries sorted with a subtree
+    // ordered as if its name ended in `/`, so the name is binary searched for
+    // both as a file and as a subtree; a tree out of that order falls back to
+    // looking at every entry.
+    pub fn lookup(&self, name: &str) -> Option<&TreeEntry> {
+        for is_tree in [false, true] {
+            let key = name.bytes().chain(if is_tree { Some(b'/') } else { None });
+            let found = self.entries.binary_search_by(|entry| {
+                let slash = if entry.entry_type == EntryType::Tree { Some(b'/') } else { None };
+                entry.name.bytes().chain(slash).cmp(key.clone())
+            });
+            match found {
+                Ok(index) if self.entries[index].name == name => return Some(&self.entries[index]),
+                _ => {}
+            }
+        }
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+impl Blob {
+    pub fn from_file(file_name: &str) -> std::io::Result<Self> {
+        let mut file = std::fs::File::open(file_name)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        Ok(Self::from_vec(content))
+    }
+
+    // The bytes of the hex digits `s`, two digits to a byte. An odd number of
+    // them, or anything but hex digits, is invalid data.
+    fn decode_hex(s: &str) -> std::io::Result<Vec<u8>> {
+        let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, format!("'{}' is not hex", s));
+        if s.len() % 2 == 1 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| invalid()))
+            .collect()
+    }
+
+    // The 20 bytes of the object id `sha`, which has to be 40 hex digits.
+    fn object_id(sha: &str) -> std::io::Result<[u8; 20]> {
+        if !is_object_id(sha) {
+            return Err(invalid_object_id(sha));
+        }
+        Blob::decode_hex(sha)?.try_into().map_err(|_| invalid_object_id(sha))
+    }
+
+    // Reads the object `sha`, or the one replacing it, which is then read as `sha`.
+    pub fn from_sha(repo: &Repository, sha: &str) -> std::io::Result<Self> {
+        let id = Self::object_id(sha)?;
+        let replacement = repo.replacement(sha)?;
+        let mut blob = Self::from_store(repo.objects(), replacement)?;
+        if replacement != sha {
+            blob.hash = id;
+            blob.hash_string = sha.to_owned();
+        }
+        Ok(blob)
+    }
+
+    // Reads the object `sha` from the given object directory or one of its alternates.
+    pub fn from_sha_in(objects_dir: &Path, sha: &str) -> std::io::Result<Self> {
+        Self::from_store(&LooseObjects::new(objects_dir), sha)
+    }
+
+    // Reads the object `sha` from `objects` as it is stored, not replaced.
+    // The inflated object is `<type> <size>\0<content>`, the type word is parsed
+    // so that callers can dispatch on `object_type()`.
+    pub fn from_store(objects: &dyn ObjectStore, sha: &str) -> std::io::Result<Self> {
+        let byte_sha = Self::object_id(sha)?;
+        let v = Self::read_from(objects, sha)?;
+        let invalid = |msg: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("object {}: {}", sha, msg),
+            )
+        };
+        // the type and size are only looked for before the NUL, as the
+        // content may hold spaces of its own
+        let end_of_header = v
+            .iter()
+            .position(|&x| x == 0)
+            .ok_or_else(|| invalid("missing header terminator"))?
+            + 1;
+        let end_of_type = v[..end_of_header]
+            .iter()
+            .position(|&x| x == b' ')
+            .ok_or_else(|| invalid("missing object type"))?;
+        let object_type = ObjectType::from_bytes(&v[..end_of_type])
+            .ok_or_else(|| invalid("unknown object type"))?;
+        let size = std::str::from_utf8(&v[end_of_type + 1..end_of_header - 1])
+            .ok()
+            .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|size| size.parse::<usize>().ok())
+            .ok_or_else(|| invalid("bad object size"))?;
+        if size != v.len() - end_of_header {
+            return Err(invalid("size doesn't match the content"));
+        }
+        Ok(Self {
+            content: v[end_of_header..].into(),
+            header: v[0..end_of_header].into(),
+            hash: byte_sha,
+            hash_string: sha.to_owned(),
+            object_type,
+        })
+    }
+
+    pub fn raw_from_sha(repo: &Repository, sha: &str) -> std::io::Result<Vec<u8>> {
+        Self::read_from(repo.objects(), repo.replacement(sha)?)
+    }
+
+    // Looks the object up and inflates it, returning the header and content as
+    // stored without interpreting them.
+    fn read_from(objects: &dyn ObjectStore, sha: &str) -> std::io::Result<Vec<u8>> {
+        let mut v = Vec::new();
+        objects.open_inflated(sha)?.read_to_end(&mut v).map_err(|e| inflate_error(&corrupt_object(objects, sha), e))?;
+        Ok(v)
+    }
+
+    pub fn from_vec(content: Vec<u8>) -> Self {
+        Self::new(ObjectType::Blob, content)
+    }
+
+    // builds an object of any type from its content, computing header and hash
+    pub fn new(object_type: ObjectType, content: Vec<u8>) -> Self {
+        let header = Self::header(object_type, &content);
+        let hash = Self::hash(&header, &content);
+        let hash_string = Self::string_hash(&hash);
+        Self {
+            content,
+            header,
+            hash,
+            hash_string,
+            object_type,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_string(file_content: String) -> Self {
+        Self::from_vec(file_content.as_bytes().into())
+    }
+
+    fn header(object_type: ObjectType, content: &[u8]) -> Vec<u8> {
+        format!("{} {}\0", object_type, content.len()).as_bytes().into()
+    }
+
+    fn hash(header: &[u8], content: &[u8]) -> [u8; 20] {
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(header);
+        hasher.update(content);
+        hasher.finalize().into()
+    }
+
+    // TODO: consider if this function makes sense
+    fn string_hash(hash: &[u8]) -> String {
+        hash.iter()
+            .fold(String::from(""), |partial_hash: String, element| {
+                partial_hash + &format!("{:02x}", element).to_string()
+            })
+            .to_string()
+    }
+
+    pub fn compress(&self) -> std::io::Result<Vec<u8>> {
+        let mut z = ZlibEncoder::new(Vec::new(), Compression::fast());
+        z.write_all(&self.header)?;
+        z.write_all(&self.content)?;
+        z.finish()
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        self.object_type
+    }
+
+    pub fn dir(&self) -> String {
+        self.hash_string.split_at(2).0.to_string()
+    }
+
+    pub fn filename(&self) -> String {
+        self.hash_string.split_at(2).1.to_string()
+    }
+}
+
+// The repository at `$GIT_DIR`, which --git-dir sets, or else the one the
+// current directory is in, found like `Repository::discover` does, `.git`
+// files of linked worktrees included. Like git, a repository named by
+// `$GIT_DIR` is checked out in `$GIT_WORK_TREE` or, without one, the current
+// directory.
+fn open_repository() -> Result<Repository, GitError> {
+    let git_dir = std::env::var_os("GIT_DIR");
+    let repo = match &git_dir {
+        Some(git_dir) => Repository::open(git_dir),
+        None => Repository::discover("."),
+    }
+    .map_err(|e| GitError::Fatal(format!("fatal: {}", e)))?;
+    match std::env::var_os("GIT_WORK_TREE") {
+        Some(work_tree) => Ok(repo.with_work_tree(work_tree)),
+        None if git_dir.is_some() => Ok(repo.with_work_tree(".")),
+        None => Ok(repo),
+    }
+}
+
+// Prints the ids the files would have as objects of the `-t` type, blobs by
+// default, writing them with `-w`. Content that doesn't parse as its type is
+// refused, unless `--literally`, which also takes any type word and leaves
+// the content unconverted.
+pub fn hash_object(args: &[String]) -> Result<(), GitError> {
+    let usage = "Error: usage: hash-object [-w] [-t <type>] [--literally] [--path=<path> | --no-filters] [--stdin] <file>...";
+    let mut write = false;
+    let mut type_name = "blob";
+    let mut literally = false;
+    let mut stdin = false;
+    let mut path = None;
+    let mut no_filters = false;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-w" => write = true,
+            "-t" => type_name = args.next().ok_or_else(|| GitError::InvalidArgs(usage.to_string()))?,
+            "--literally" => literally = true,
+            "--stdin" => stdin = true,
+            "--no-filters" => no_filters = true,
+            _ if arg.starts_with("--path=") => path = Some(&arg["--path=".len()..]),
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid hash-object command", arg))),
+            file => files.push(file),
+        }
+    }
+    if files.is_empty() && !stdin {
+        return Err(GitError::InvalidArgs(usage.to_string()));
+    }
+    if no_filters && path.is_some() {
+        return Err(GitError::Fatal("fatal: Can't use --path with --no-filters".to_string()));
+    }
+    let object_type = ObjectType::from_bytes(type_name.as_bytes());
+    if object_type.is_none() && !literally {
+        return Err(GitError::Fatal(format!("fatal: invalid object type \"{}\"", type_name)));
+    }
+    let fail = |e: std::io::Error| GitError::failed("hash-object", e);
+    let repo = open_repository()?;
+    // stdin comes first, and has no path of its own for the attributes
+    let mut contents = Vec::new();
+    if stdin {
+        let mut content = Vec::new();
+        std::io::stdin().read_to_end(&mut content).map_err(fail)?;
+        contents.push((None, content));
+    }
+    for file in files {
+        contents.push((Some(file), Blob::from_file(file).map_err(fail)?.content));
+    }
+    for (file, content) in contents {
+        let path = match no_filters {
+            true => None,
+            false => path.or(file),
+        };
+        match object_type {
+            Some(ObjectType::Blob) if !literally => hash::write_and_print_hash(&repo, content, path, write).map_err(fail)?,
+            Some(object_type) if !literally => {
+                let object = Blob::new(object_type, content);
+                hash::check(&object).map_err(fail)?;
+                if write {
+                    hash::write_to_database(&repo, &object).map_err(fail)?;
+                }
+                outln!("{}", object.hash_string)?;
+            }
+            _ => outln!("{}", hash::hash_literally(&repo, type_name, &content, write).map_err(fail)?)?,
+        }
+    }
+    Ok(())
+}
+
+pub fn cat_file(args: &[String]) -> Result<(), GitError> {
+    if args.iter().any(|arg| arg == "--follow-symlinks") {
+        let object = match args {
+            [a, b, object] if [a, b].iter().all(|arg| *arg == "-p" || *arg == "--follow-symlinks") && a != b => object,
+            _ => return Err(GitError::InvalidArgs("Error: usage: cat-file -p --follow-symlinks <rev>:<path>".to_string())),
+        };
+        let repo = open_repository()?;
+        return revision::resolve_following_symlinks(&repo, object)
+            .and_then(|sha| cat::pretty_print(&repo, &sha))
+            .map_err(|e| GitError::failed("cat-file -p", e));
+    }
+    if args[0] == "-p" && args.len() == 2 {
+        let repo = open_repository()?;
+        match revision::resolve(&repo, &args[1]).and_then(|sha| cat::pretty_print(&repo, &sha)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(GitError::failed("cat-file -p", e)),
+        }
+    } else if args[0] == "--allow-unknown-type" && args.len() == 2 {
+        let repo = open_repository()?;
+        match revision::resolve(&repo, &args[1]).and_then(|sha| cat::raw_dump(&repo, &sha)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(GitError::failed("cat-file --allow-unknown-type", e)),
+        }
+    } else if args[0] == "--filters" {
+        // the path for the attributes is the one in the name, or --path
+        let (path, object) = match &args[1..] {
+            [path, object] if path.starts_with("--path=") => (&path["--path=".len()..], object),
+            [object] => match revision::path_of(object) {
+                Some(path) => (path, object),
+                None => return Err(GitError::InvalidArgs("Error: usage: cat-file --filters (<rev>:<path> | --path=<path> <blob>)".to_string())),
+            },
+            _ => return Err(GitError::InvalidArgs("Error: usage: cat-file --filters (<rev>:<path> | --path=<path> <blob>)".to_string())),
+        };
+        let repo = open_repository()?;
+        revision::resolve(&repo, object)
+            .and_then(|sha| cat::filtered(&repo, &sha, path))
+            .map_err(|e| GitError::failed("cat-file --filters", e))
+    } else if args.iter().any(|arg| arg.starts_with("--batch") && arg != "--batch-all-objects") {
+        let mut contents = false;
+        let mut format = cat::BATCH_FORMAT;
+        let mut all_objects = false;
+        for arg in args {
+            let (option, value) = match arg.split_once('=') {
+                Some((option, value)) => (option, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            match option {
+                "--batch" => contents = true,
+                "--batch-check" => contents = false,
+                "--batch-all-objects" if value.is_none() => all_objects = true,
+                _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid cat-file batch option", arg))),
+            }
+            if let Some(value) = value {
+                format = value;
+            }
+        }
+        let repo = open_repository()?;
+        cat::batch(&repo, contents, format, all_objects)
+            .map_err(|e| GitError::failed("cat-file batch", e))
+    } else {
+        Err(GitError::InvalidArgs(format!("Error: args {}, not a valid cat-file command", args.join(" "))))
+    }
+}
+
+const COMMIT_USAGE: &str =
+    "Error: usage: commit (-m <message>... | -F <file>) [-n | --no-verify] [--allow-empty] [-S[<key-id>]]";
+
+// `commit` records the index as a new commit on HEAD, with the message of the
+// `-m` paragraphs or of a file, `-` for the standard input. `--no-verify`
+// skips the pre-commit and commit-msg hooks, `--allow-empty` makes a
+// commit even when the index is HEAD's tree, and `-S` signs it.
+pub fn commit(args: &[String]) -> Result<(), GitError> {
+    let mut paragraphs = Vec::new();
+    let mut file = None;
+    let mut verify = true;
+    let mut allow_empty = false;
+    let mut signing_key = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-m" => paragraphs.push(args.next().ok_or_else(|| GitError::InvalidArgs(COMMIT_USAGE.to_string()))?.clone()),
+            _ if arg.starts_with("-m") => paragraphs.push(arg[2..].to_string()),
+            "-F" => file = Some(args.next().ok_or_else(|| GitError::InvalidArgs(COMMIT_USAGE.to_string()))?.clone()),
+            "-n" | "--no-verify" => verify = false,
+            "--allow-empty" => allow_empty = true,
+            "-S" | "--gpg-sign" => signing_key = Some(String::new()),
+            _ if arg.starts_with("-S") => signing_key = Some(arg[2..].to_string()),
+            _ if arg.starts_with("--gpg-sign=") => signing_key = Some(arg["--gpg-sign=".len()..].to_string()),
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid commit option", arg))),
+        }
+    }
+    let message = match (file, paragraphs.is_empty()) {
+        (Some(_), false) => return Err(GitError::Fatal("fatal: options '-m' and '-F' cannot be used together".to_string())),
+        (Some(file), true) => {
+            let mut message = String::new();
+            let read = match file.as_str() {
+                "-" => std::io::stdin().read_to_string(&mut message).map(|_| ()),
+                file => fs::read_to_string(file).map(|content| message = content),
+            };
+            read.map_err(|e| GitError::Fatal(format!("fatal: could not read log file '{}': {}", file, e)))?;
+            message
+        }
+        (None, false) => paragraphs.iter().map(|p| format!("{}\n", p.trim_end_matches('\n'))).collect::<Vec<_>>().join("\n"),
+        (None, true) => return Err(GitError::InvalidArgs(COMMIT_USAGE.to_string())),
+    };
+    let repo = open_repository()?;
+    commit::commit_index(&repo, &message, verify, allow_empty, signing_key.as_deref())
+        .map(|_| ())
+        .map_err(|e| GitError::failed("commit", e))
+}
+
+const COMMIT_TREE_USAGE: &str = "Error: usage: commit-tree <tree> [-p <parent>]... [-m <message>]... [-S[<key-id>]]";
+
+pub fn commit_tree(args: &[String]) -> Result<(), GitError> {
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut paragraphs = Vec::new();
+    let mut signing_key = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-S" | "--gpg-sign" => signing_key = Some(String::new()),
+            _ if arg.starts_with("-S") => signing_key = Some(arg[2..].to_string()),
+            _ if arg.starts_with("--gpg-sign=") => {
+                signing_key = Some(arg["--gpg-sign=".len()..].to_string())
+            }
+            "-p" => match args.next() {
+                Some(parent) => parents.push(parent.clone()),
+                None => return Err(GitError::InvalidArgs(COMMIT_TREE_USAGE.to_string())),
+            },
+            "-m" => match args.next() {
+                Some(message) => paragraphs.push(message.clone()),
+                None => return Err(GitError::InvalidArgs(COMMIT_TREE_USAGE.to_string())),
+            },
+            _ if tree.is_none() => tree = Some(arg.clone()),
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid commit-tree command", arg))),
+        }
+    }
+    let tree = tree.ok_or_else(|| GitError::InvalidArgs(COMMIT_TREE_USAGE.to_string()))?;
+    // like git, each -m is its own paragraph, without -m the message is read from stdin
+    let message = if paragraphs.is_empty() {
+        let mut message = String::new();
+        std::io::stdin()
+            .read_to_string(&mut message)
+            .map_err(|e| GitError::Failed(format!("Error: could not read the commit message: {}", e)))?;
+        message
+    } else {
+        paragraphs
+            .iter()
+            .map(|p| format!("{}\n", p.trim_end_matches('\n')))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let repo = open_repository()?;
+    let resolved = revision::resolve(&repo, &tree).and_then(|tree| {
+        let parents: Vec<String> =
+            parents.iter().map(|parent| revision::resolve(&repo, parent)).collect::<Result<_, _>>()?;
+        Ok((tree, parents))
+    });
+    let committed = resolved
+        .and_then(|(tree, parents)| commit::commit_tree(&repo, &tree, &parents, message, signing_key.as_deref()));
+    match committed {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::failed("commit-tree", e)),
+    }
+}
+
+pub fn config(args: &[String]) -> Result<(), GitError> {
+    let (global, args) = match args {
+        [flag, rest @ ..] if flag == "--global" => (true, rest),
+        _ => (false, args),
+    };
+    let global_file = || config::global_file().ok_or_else(|| GitError::Failed("Error: $HOME is not set".to_string()));
+    match args {
+        [key] => {
+            let config = if global {
+                config::Config::from_file(&global_file()?, None).map_err(|e| GitError::Failed(format!("Error: {}", e)))?
+            } else {
+                open_repository()?.config
+            };
+            match config.get(key) {
+                Some(value) => {
+                    outln!("{}", value)?;
+                    Ok(())
+                }
+                None => Err(GitError::Failed(format!("Error: config key {} is not set", key))),
+            }
+        }
+        [key, value] => {
+            let path = if global {
+                global_file()?
+            } else {
+                open_repository()?.common_dir().join("config")
+            };
+            config::set(&path, key, value)
+                .map_err(|e| GitError::failed("config", e))
+        }
+        _ => Err(GitError::InvalidArgs("Error: usage: config [--global] <key> [<value>]".to_string())),
+    }
+}
+
+pub fn fetch(args: &[String]) -> Result<(), GitError> {
+    let name = match args {
+        [] => "origin",
+        [name] => name.as_str(),
+        _ => return Err(GitError::InvalidArgs("Error: usage: fetch [<remote>]".to_string())),
+    };
+    let repo = open_repository()?;
+    fetch::fetch(&repo, name).map_err(|e| GitError::failed("fetch", e))
+}
+
+// `bundle create <file> <rev>...` writes the refs the revisions name and the
+// objects they need to a file; `bundle unbundle <file>` stores a bundle's
+// objects and prints its refs, for the caller to update.
+pub fn bundle(args: &[String]) -> Result<(), GitError> {
+    let usage = "Error: usage: bundle create <file> <rev>... | bundle unbundle <file>";
+    let fail = |e: std::io::Error| GitError::failed("bundle", e);
+    let repo = open_repository()?;
+    match args {
+        [command, file, revs @ ..] if command == "create" && !revs.is_empty() => {
+            let revs: Vec<&str> = revs.iter().map(String::as_str).collect();
+            bundle::create(&repo, Path::new(file), &revs).map_err(fail)?;
+        }
+        [command, file] if command == "unbundle" => {
+            for (name, sha) in bundle::unbundle(&repo, Path::new(file)).map_err(fail)?.refs {
+                outln!("{} {}", sha, name)?;
+            }
+        }
+        _ => return Err(GitError::InvalidArgs(usage.to_string())),
+    }
+    Ok(())
+}
+
+pub fn remote(args: &[String]) -> Result<(), GitError> {
+    let repo = open_repository()?;
+    let result = match args {
+        [] => remote::list(&repo, false),
+        [flag] if flag == "-v" || flag == "--verbose" => remote::list(&repo, true),
+        [command, name, url] if command == "add" => remote::add(&repo, name, url),
+        _ => return Err(GitError::InvalidArgs("Error: usage: remote [-v] | remote add <name> <url>".to_string())),
+    };
+    result.map_err(|e| GitError::failed("remote", e))
+}
+
+pub fn worktree(args: &[String]) -> Result<(), GitError> {
+    let usage = "Error: usage: worktree add [-b <branch>] [--detach] <path> [<commit-ish>] | worktree list [--porcelain]";
+    let repo = open_repository()?;
+    let result = match args {
+        [command] if command == "list" => worktrees::print(&repo, false),
+        [command, flag] if command == "list" && flag == "--porcelain" => worktrees::print(&repo, true),
+        [command, rest @ ..] if command == "add" => {
+            let mut new_branch = None;
+            let mut detach = false;
+            let mut positional = Vec::new();
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-b" => new_branch = Some(rest.next().ok_or_else(|| GitError::InvalidArgs(usage.to_string()))?.as_str()),
+                    "-d" | "--detach" => detach = true,
+                    _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid worktree add option", arg))),
+                    _ => positional.push(arg.as_str()),
+                }
+            }
+            match positional[..] {
+                [path] => worktrees::add(&repo, Path::new(path), None, new_branch, detach),
+                [path, commit] => worktrees::add(&repo, Path::new(path), Some(commit), new_branch, detach),
+                _ => return Err(GitError::InvalidArgs(usage.to_string())),
+            }
+        }
+        _ => return Err(GitError::InvalidArgs(usage.to_string())),
+    };
+    result.map_err(|e| GitError::failed("worktree", e))
+}
+
+pub fn reset(args: &[String]) -> Result<(), GitError> {
+    let (mode, rev) = match args {
+        [flag, rest @ ..] if flag.starts_with("--") => {
+            let mode = match flag.as_str() {
+                "--soft" => reset::Mode::Soft,
+                "--mixed" => reset::Mode::Mixed,
+                "--hard" => reset::Mode::Hard,
+                _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid reset option", flag))),
+            };
+            (mode, rest)
+        }
+        rest => (reset::Mode::Mixed, rest),
+    };
+    let rev = match rev {
+        [] => "HEAD",
+        [rev] => rev.as_str(),
+        _ => return Err(GitError::InvalidArgs("Error: usage: reset [--soft | --mixed | --hard] [<commit>]".to_string())),
+    };
+    let repo = open_repository()?;
+    reset::reset(&repo, mode, rev).map_err(|e| GitError::failed("reset", e))
+}
+
+pub fn restore(args: &[String]) -> Result<(), GitError> {
+    let (source, paths) = match args {
+        [flag, source, paths @ ..] if flag == "--source" || flag == "-s" => (Some(source.as_str()), paths),
+        [flag, paths @ ..] if flag.starts_with("--source=") => (Some(&flag["--source=".len()..]), paths),
+        paths => (None, paths),
+    };
+    let paths = match paths {
+        [separator, paths @ ..] if separator == "--" => paths,
+        paths => paths,
+    };
+    if paths.is_empty() {
+        return Err(GitError::InvalidArgs("Error: usage: restore [--source=<commit>] [--] <path>...".to_string()));
+    }
+    let repo = open_repository()?;
+    restore::restore(&repo, source, paths, false)
+        .map_err(|e| GitError::failed("restore", e))
+}
+
+// only `checkout [<commit>] -- <path>...`, switching branches isn't supported
+pub fn checkout(args: &[String]) -> Result<(), GitError> {
+    let (source, paths) = match args {
+        [separator, paths @ ..] if separator == "--" => (None, paths),
+        [source, separator, paths @ ..] if separator == "--" => (Some(source.as_str()), paths),
+        _ => return Err(GitError::InvalidArgs("Error: usage: checkout [<commit>] -- <path>...".to_string())),
+    };
+    let repo = open_repository()?;
+    restore::restore(&repo, source, paths, true)
+        .map_err(|e| GitError::failed("checkout", e))
+}
+
+pub fn read_tree(args: &[String]) -> Result<(), GitError> {
+    let mut merge = false;
+    let mut force = false;
+    let mut update = false;
+    let mut rev = None;
+    for arg in args {
+        match arg.as_str() {
+            "-m" => merge = true,
+            "--reset" => force = true,
+            "-u" => update = true,
+            _ if rev.is_none() && !arg.starts_with('-') => rev = Some(arg),
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid read-tree command", arg))),
+        }
+    }
+    let rev = rev.ok_or_else(|| GitError::InvalidArgs("Error: usage: read-tree [(-m | --reset) -u] <tree-ish>".to_string()))?;
+    if update && !merge && !force {
+        return Err(GitError::InvalidArgs("Error: usage: read-tree (-m | --reset) -u <tree-ish>".to_string()));
+    }
+    let repo = open_repository()?;
+    reset::read_tree(&repo, rev, update, force)
+        .map_err(|e| GitError::failed("read-tree", e))
+}
+
+pub fn write_tree(args: &[String]) -> Result<(), GitError> {
+    let mut dry_run = false;
+    for arg in args {
+        match arg.as_str() {
+            "-n" | "--dry-run" => dry_run = true,
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid write-tree command", arg))),
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("write-tree", e);
+    let repo = open_repository()?;
+    let index = index::Index::read(&repo).map_err(fail)?;
+    let unmerged: Vec<_> = index.entries.iter().filter(|entry| entry.stage() != 0).collect();
+    if !unmerged.is_empty() {
+        for entry in unmerged {
+            eprintln!("error: {}: unmerged ({})", entry.path, entry.sha);
+        }
+        return Err(GitError::Fatal("fatal: git-write-tree: error building trees".to_string()));
+    }
+    let files = index.files();
+    let sha = match dry_run {
+        true => tree::hash_tree(&files),
+        false => tree::write_tree(&repo, &files),
+    };
+    outln!("{}", sha.map_err(fail)?)?;
+    Ok(())
+}
+
+pub fn checkout_index(args: &[String]) -> Result<(), GitError> {
+    let mut all = false;
+    let mut force = false;
+    let mut prefix = "";
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-a" | "--all" => all = true,
+            "-f" | "--force" => force = true,
+            _ if arg.starts_with("--prefix=") => prefix = &arg["--prefix=".len()..],
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid checkout-index command", arg))),
+            path => paths.push(path),
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("checkout-index", e);
+    let repo = open_repository()?;
+    let index = index::Index::read(&repo).map_err(fail)?;
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    if all {
+        // like git, conflicts and what a sparse checkout leaves out are skipped quietly
+        entries.extend(index.entries.iter().filter(|entry| entry.stage() == 0 && !entry.skip_worktree()));
+    }
+    for path in paths {
+        match index.entries.iter().find(|entry| entry.path == path) {
+            Some(entry) if entry.stage() == 0 => entries.push(entry),
+            Some(_) => errors.push(format!("git checkout-index: {} is unmerged", path)),
+            None => errors.push(format!("git checkout-index: {} is not in the cache", path)),
+        }
+    }
+    // the prefix is prepended as is, so a directory needs its trailing slash
+    let root = repo.work_tree();
+    for entry in entries {
+        let path = root.join(format!("{}{}", prefix, entry.path));
+        if !force && fs::symlink_metadata(&path).is_ok() {
+            // a file that is already up to date isn't worth a complaint
+            let recorded = (entry.mode_string(), entry.sha.clone());
+            if !worktree::is_unchanged(&path, Some(&recorded)).map_err(fail)? {
+                errors.push(format!("{}{} already exists, no checkout", prefix, entry.path));
+            }
+            continue;
+        }
+        worktree::checkout(&repo, entry, &path).map_err(fail)?;
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(GitError::Failed(errors.join("\n"))),
+    }
+}
+
+// Removes the files of the working tree that are neither tracked nor ignored,
+// or only lists them with `-n`.
+pub fn clean(args: &[String]) -> Result<(), GitError> {
+    let mut dry_run = false;
+    let mut force = false;
+    let mut directories = false;
+    let mut no_ignores = false;
+    let mut excludes = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(pattern) = arg.strip_prefix("--exclude=") {
+            excludes.push(pattern.to_string());
+            continue;
+        }
+        let flags = match arg.as_str() {
+            "--dry-run" => "n",
+            "--force" => "f",
+            "-e" | "--exclude" => match args.next() {
+                Some(pattern) => {
+                    excludes.push(pattern.clone());
+                    continue;
+                }
+                None => return Err(GitError::InvalidArgs("Error: usage: clean [-n] [-f] [-d] [-x] [-e <pattern>]".to_string())),
+            },
+            _ if arg.len() > 1 && arg.starts_with('-') && !arg.starts_with("--") => &arg[1..],
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid clean option", arg))),
+        };
+        for flag in flags.chars() {
+            match flag {
+                'n' => dry_run = true,
+                'f' => force = true,
+                'd' => directories = true,
+                'x' => no_ignores = true,
+                _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid clean option", arg))),
+            }
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("clean", e);
+    let repo = open_repository()?;
+    if !dry_run && !force {
+        let required = match repo.config().get_bool("clean.requireforce").map_err(fail)? {
+            Some(false) => None,
+            Some(true) => Some("set to true"),
+            None => Some("defaults to true"),
+        };
+        if let Some(required) = required {
+            return Err(GitError::Fatal(format!("fatal: clean.requireForce {} and neither -n nor -f given; refusing to clean", required)));
+        }
+    }
+    let index = index::Index::read(&repo).map_err(fail)?;
+    let mut ignores = match no_ignores {
+        true => ignore::Ignores::command_line(&repo),
+        false => ignore::Ignores::new(&repo).map_err(fail)?,
+    };
+    for pattern in &excludes {
+        ignores.exclude(pattern);
+    }
+    let ignores = if no_ignores && excludes.is_empty() { None } else { Some(&mut ignores) };
+    let root = repo.work_tree();
+    let directories = match directories {
+        true => worktree::Directories::Whole,
+        false => worktree::Directories::Skip,
+    };
+    for path in worktree::untracked(&repo, &index, ignores, directories).map_err(fail)? {
+        if dry_run {
+            outln!("Would remove {}", path)?;
+            continue;
+        }
+        outln!("Removing {}", path)?;
+        match path.ends_with('/') {
+            true => fs::remove_dir_all(root.join(&path)),
+            false => fs::remove_file(root.join(&path)),
+        }
+        .map_err(fail)?;
+    }
+    Ok(())
+}
+
+// Removes the loose objects that are also in a pack, checking each against
+// the pack first. With -n they are only listed, as `rm -f <path>`.
+pub fn prune_packed(args: &[String]) -> Result<(), GitError> {
+    let mut dry_run = false;
+    for arg in args {
+        match arg.as_str() {
+            "-n" | "--dry-run" => dry_run = true,
+            // there is no progress to keep quiet
+            "-q" | "--quiet" => {}
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid prune-packed option", arg))),
+        }
+    }
+    let repo = open_repository()?;
+    let objects_dir = repo.objects_dir();
+    let pruned = pack::prune_packed(&objects_dir, dry_run).map_err(|e| GitError::failed("prune-packed", e))?;
+    if dry_run {
+        for sha in pruned {
+            outln!("rm -f {}", objects_dir.join(&sha[..2]).join(&sha[2..]).display())?;
+        }
+    }
+    Ok(())
+}
+
+// Checks packs, given by their `.idx` or `.pack`, as `verify-pack` does. With
+// -v each entry is listed, as `<sha> <type> <size> <size in pack> <offset>`
+// and for a delta its depth and base, and with -s or -v the lengths of the
+// delta chains are counted.
+pub fn verify_pack(args: &[String]) -> Result<(), GitError> {
+    let mut verbose = false;
+    let mut stat_only = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "-s" | "--stat-only" => stat_only = true,
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid verify-pack option", arg))),
+            _ => paths.push(Path::new(arg)),
+        }
+    }
+    if paths.is_empty() {
+        return Err(GitError::InvalidArgs("Error: usage: verify-pack [-v | -s] <pack>.idx...".to_string()));
+    }
+    for path in paths {
+        let fail = |e: std::io::Error| GitError::Failed(format!("Error: verify-pack command failed with error: '{}: {}'", path.display(), e));
+        let pack = pack::Pack::open(&path.with_extension("idx")).map_err(fail)?;
+        pack.verify_checksum().map_err(fail)?;
+        let entries = pack.entries().map_err(fail)?;
+        if verbose && !stat_only {
+            for entry in &entries {
+                let listed = format!(
+                    "{} {:<6} {} {} {}",
+                    entry.id,
+                    entry.object_type.as_str(),
+                    entry.size,
+                    entry.packed_size,
+                    entry.offset
+                );
+                match &entry.base {
+                    Some(base) => outln!("{} {} {}", listed, entry.depth, base)?,
+                    None => outln!("{}", listed)?,
+                }
+            }
+        }
+        if verbose || stat_only {
+            let objects = |count: usize| format!("{} {}", count, if count == 1 { "object" } else { "objects" });
+            for (depth, count) in pack::PackStats::of(&entries).chain_lengths {
+                match depth {
+                    0 => outln!("non delta: {}", objects(count))?,
+                    _ => outln!("chain length = {}: {}", depth, objects(count))?,
+                }
+            }
+        }
+        if verbose && !stat_only {
+            outln!("{}: ok", path.with_extension("pack").display())?;
+        }
+    }
+    Ok(())
+}
+
+// Packs the loose objects into a new pack, leaving the other packs alone;
+// with -d the loose copies are then removed, as by prune-packed.
+pub fn repack(args: &[String]) -> Result<(), GitError> {
+    let mut delete = false;
+    let mut quiet = false;
+    for arg in args {
+        match arg.as_str() {
+            "-d" => delete = true,
+            "-q" | "--quiet" => quiet = true,
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid repack option", arg))),
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("repack", e);
+    let repo = open_repository()?;
+    if pack::repack_loose(&repo).map_err(fail)?.is_none() && !quiet {
+        outln!("Nothing new to pack.")?;
+    }
+    if delete {
+        pack::prune_packed(&repo.objects_dir(), false).map_err(fail)?;
+    }
+    Ok(())
+}
+
+// Removes the loose objects that nothing reaches, not the refs, HEAD, the
+// reflogs nor the index. With -n they are only listed, as `<sha> <type>`.
+pub fn prune(args: &[String]) -> Result<(), GitError> {
+    let mut dry_run = false;
+    let mut verbose = false;
+    for arg in args {
+        match arg.as_str() {
+            "-n" | "--dry-run" => dry_run = true,
+            "-v" | "--verbose" => verbose = true,
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid prune option", arg))),
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("prune", e);
+    let repo = open_repository()?;
+    if repo.precious_objects().map_err(fail)? {
+        return Err(GitError::Fatal("fatal: cannot prune in a precious-objects repo".to_string()));
+    }
+    let objects_dir = repo.objects_dir();
+    for sha in reachable::unreachable(&repo).map_err(fail)? {
+        if dry_run || verbose {
+            let object = Blob::from_sha_in(&objects_dir, &sha).map_err(fail)?;
+            outln!("{} {}", sha, object.object_type().as_str())?;
+        }
+        if dry_run {
+            continue;
+        }
+        let fan_out = objects_dir.join(&sha[..2]);
+        fs::remove_file(fan_out.join(&sha[2..])).map_err(fail)?;
+        if fs::read_dir(&fan_out).map_err(fail)?.next().is_none() {
+            fs::remove_dir(&fan_out).map_err(fail)?;
+        }
+    }
+    Ok(())
+}
+
+// Shows how HEAD, the index and the working tree differ in one of the
+// machine-readable formats, `--porcelain` (v1) or `--porcelain=v2`.
+pub fn status(args: &[String]) -> Result<(), GitError> {
+    let mut version = None;
+    let mut z = false;
+    let mut directories = Some(worktree::Directories::Shown);
+    for arg in args {
+        let untracked = arg.strip_prefix("--untracked-files").map(|mode| mode.trim_start_matches('='));
+        match arg.as_str() {
+            "--porcelain" | "--porcelain=v1" | "--porcelain=1" | "-s" | "--short" => version = Some(1),
+            "--porcelain=v2" | "--porcelain=2" => version = Some(2),
+            "-z" => z = true,
+            _ => match untracked.or_else(|| arg.strip_prefix("-u")) {
+                Some("no") => directories = None,
+                Some("normal") => directories = Some(worktree::Directories::Shown),
+                Some("all") | Some("") => directories = Some(worktree::Directories::Files),
+                _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid status option", arg))),
+            },
+        }
+    }
+    let version = version.ok_or_else(|| GitError::InvalidArgs("Error: usage: status (--porcelain[=v1|v2] | -s) [-z] [-u<mode>]".to_string()))?;
+    let repo = open_repository()?;
+    let status = status::status(&repo, directories)
+        .map_err(|e| GitError::failed("status", e))?;
+    let non_ascii = quotes_non_ascii(&repo).map_err(|e| GitError::failed("status", e))?;
+    let lines = match version {
+        1 => status::porcelain_v1(&status, z, non_ascii),
+        _ => status::porcelain_v2(&status, z, non_ascii),
+    };
+    let terminator = if z { '\0' } else { '\n' };
+    let out: String = lines.iter().map(|line| format!("{}{}", line, terminator)).collect();
+    out!("{}", out)?;
+    Ok(())
+}
+
+// Prints the state of the attributes asked for, or with `-a` of all those
+// given to it in the order git defines them, for each path: `set`, `unset`,
+// `unspecified` or the value.
+pub fn check_attr(args: &[String]) -> Result<(), GitError> {
+    let usage = || GitError::InvalidArgs("Error: usage: check-attr (-a | <attr>...) [--] <path>...".to_string());
+    let mut all = false;
+    let mut words = Vec::new();
+    let mut dashes = None;
+    for arg in args {
+        match arg.as_str() {
+            "-a" | "--all" if dashes.is_none() => all = true,
+            "--" if dashes.is_none() => dashes = Some(words.len()),
+            _ if arg.starts_with('-') && dashes.is_none() => {
+                return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid check-attr option", arg)))
+            }
+            _ => words.push(arg.as_str()),
+        }
+    }
+    // without `--` the first word is the only attribute, unless `-a` is given
+    let (names, paths) = match (all, dashes) {
+        (true, Some(0)) | (true, None) => (Vec::new(), &words[..]),
+        (false, Some(at)) if at > 0 => (words[..at].to_vec(), &words[at..]),
+        (false, None) if words.len() > 1 => (words[..1].to_vec(), &words[1..]),
+        _ => return Err(usage()),
+    };
+    if paths.is_empty() {
+        return Err(usage());
+    }
+    let repo = open_repository()?;
+    for path in paths {
+        let attributes = attributes::lookup_ordered(&repo, path)
+            .map_err(|e| GitError::failed("check-attr", e))?;
+        let state = |name: &str| attributes.iter().find(|(defined, _)| defined == name).map(|(_, state)| state);
+        let shown: Vec<(&str, Option<&attributes::State>)> = match all {
+            true => attributes.iter().map(|(name, state)| (name.as_str(), Some(state))).collect(),
+            false => names.iter().map(|name| (*name, state(name))).collect(),
+        };
+        for (name, state) in shown {
+            let state = match state {
+                Some(attributes::State::Set) => "set",
+                Some(attributes::State::Unset) => "unset",
+                Some(attributes::State::Value(value)) => value,
+                None => "unspecified",
+            };
+            outln!("{}: {}: {}", path, name, state)?;
+        }
+    }
+    Ok(())
+}
+
+// Prints the paths the ignore rules exclude, with `-v` along with the rule
+// deciding, and tells whether there was any.
+pub fn check_ignore(args: &[String]) -> Result<bool, GitError> {
+    let mut verbose = false;
+    let mut non_matching = false;
+    let mut quiet = false;
+    let mut no_index = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "-n" | "--non-matching" => non_matching = true,
+            "-q" | "--quiet" => quiet = true,
+            "--no-index" => no_index = true,
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid check-ignore option", arg))),
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(GitError::Fatal("fatal: no path specified".to_string()));
+    } else if quiet && verbose {
+        return Err(GitError::Fatal("fatal: cannot have both --quiet and --verbose".to_string()));
+    } else if quiet && paths.len() > 1 {
+        return Err(GitError::Fatal("fatal: --quiet is only valid with a single pathname".to_string()));
+    } else if non_matching && !verbose {
+        return Err(GitError::Fatal("fatal: --non-matching is only valid with --verbose".to_string()));
+    }
+    let fail = |e: std::io::Error| GitError::failed("check-ignore", e);
+    let repo = open_repository()?;
+    let index = match no_index {
+        true => index::Index::default(),
+        false => index::Index::read(&repo).map_err(fail)?,
+    };
+    let mut any = false;
+    for path in paths {
+        let rule = match index.entries.iter().any(|entry| entry.path == path) {
+            true => None,
+            false => ignore::check(&repo, path).map_err(fail)?,
+        };
+        // without -v a negated rule is as good as none
+        let rule = rule.filter(|rule| verbose || !rule.negated);
+        any |= rule.is_some();
+        match rule {
+            _ if quiet => {}
+            Some(rule) if verbose => outln!("{}:{}:{}\t{}", rule.source, rule.line, rule.pattern, path)?,
+            Some(_) => outln!("{}", path)?,
+            None if non_matching => outln!("::\t{}", path)?,
+            None => {}
+        }
+    }
+    Ok(any)
+}
+
+// Writes the tag object read from the standard input once it has been
+// validated, and prints its id.
+pub fn mktag(args: &[String]) -> Result<(), GitError> {
+    if !args.is_empty() {
+        return Err(GitError::InvalidArgs("Error: usage: mktag < <tag-object>".to_string()));
+    }
+    let repo = open_repository()?;
+    let mut content = Vec::new();
+    let result = std::io::stdin().read_to_end(&mut content).and_then(|_| {
+        tag::validate(&repo, &content)?;
+        let blob = Blob::new(ObjectType::Tag, content);
+        hash::write_to_database(&repo, &blob)?;
+        outln!("{}", blob.hash_string)?;
+        Ok(())
+    });
+    result.map_err(|e| GitError::failed("mktag", e))
+}
+
+// The tool's version and the formats it reads and writes, for scripts to check.
+pub fn version(args: &[String]) -> Result<(), GitError> {
+    if !args.is_empty() {
+        return Err(GitError::InvalidArgs("Error: usage: version".to_string()));
+    }
+    let versions = |versions: &[u32]| versions.iter().map(u32::to_string).collect::<Vec<_>>().join(", ");
+    outln!("git-starter-rust version {}", env!("CARGO_PKG_VERSION"))?;
+    outln!("repository format versions: 0, {}", MAX_REPOSITORY_FORMAT_VERSION)?;
+    outln!("repository extensions: {}", KNOWN_EXTENSIONS.join(", "))?;
+    outln!("object format: sha1")?;
+    outln!("index versions: {}", versions(index::VERSIONS))?;
+    outln!("commit-graph version: {}", commit_graph::VERSION)?;
+    // what real git has that isn't implemented here
+    outln!("packfiles: yes")?;
+    outln!("sha256: no")?;
+    outln!("http transport: smart, fetch and clone only")?;
+    Ok(())
+}
+
+pub fn update_index(args: &[String]) -> Result<(), GitError> {
+    let usage = "Error: usage: update-index [--add] [--chmod=(+|-)x] [--[no-]assume-unchanged | --[no-]skip-worktree] \
+                     [--cacheinfo <mode>,<sha>,<path>]... [path]...";
+    let fail = |e: std::io::Error| GitError::failed("update-index", e);
+    let repo = open_repository()?;
+    let mut index = index::Index::read(&repo).map_err(fail)?;
+    // names typed on macOS come decomposed, the index has them composed
+    let precompose = cfg!(target_os = "macos") && repo.config().get_bool("core.precomposeunicode").map_err(fail)?.unwrap_or(false);
+    // on case-folding file systems a path is the entry whatever its case
+    let ignore_case = repo.config().get_bool("core.ignorecase").map_err(fail)?.unwrap_or(false);
+    let fold = |index: &index::Index, path: &str| match ignore_case {
+        true => index.fold_case(path),
+        false => path.to_string(),
+    };
+    // each flag applies to the paths after it
+    let mut add = false;
+    let mut mark = None;
+    let mut chmod = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--add" => add = true,
+            "--assume-unchanged" => mark = Some((index::ASSUME_VALID, 0, true)),
+            "--no-assume-unchanged" => mark = Some((index::ASSUME_VALID, 0, false)),
+            "--skip-worktree" => mark = Some((0, index::SKIP_WORKTREE, true)),
+            "--no-skip-worktree" => mark = Some((0, index::SKIP_WORKTREE, false)),
+            "--chmod=+x" => chmod = Some(("100755", "+x")),
+            "--chmod=-x" => chmod = Some(("100644", "-x")),
+            "--cacheinfo" => {
+                // either one `<mode>,<sha>,<path>` argument or three
+                let first = args.next().ok_or_else(|| GitError::InvalidArgs(usage.to_string()))?;
+                let info: Vec<String> = match first.splitn(3, ',').collect::<Vec<_>>()[..] {
+                    [mode, sha, path] => vec![mode.to_string(), sha.to_string(), path.to_string()],
+                    _ => vec![first.clone(), args.next().ok_or_else(|| GitError::InvalidArgs(usage.to_string()))?.clone(), args.next().ok_or_else(|| GitError::InvalidArgs(usage.to_string()))?.clone()],
+                };
+                let (mode, sha, path) = (&info[0], &info[1], &info[2]);
+                if !matches!(mode.as_str(), "100644" | "100755" | "120000" | "160000") {
+                    return Err(GitError::Failed(format!("Error: git update-index: --cacheinfo cannot add {}", path)));
+                }
+                let sha = revision::resolve(&repo, sha).map_err(fail)?;
+                let path = &fold(&index, path);
+                if !add && !index.entries.iter().any(|entry| entry.path == *path) {
+                    return Err(GitError::Failed(format!("Error: {}: cannot add to the index - missing --add option?", path)));
+                }
+                index.add(index::Entry::new(path, mode, &sha));
+            }
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid update-index command", arg))),
+            given => {
+                let given = &match precompose {
+                    true => unicode::precompose(given),
+                    false => given.to_string(),
+                };
+                let path = &fold(&index, given)[..];
+                let entries = index.entries.iter_mut().filter(|entry| entry.path == path && entry.stage() == 0);
+                match mark {
+                    Some((flag, extended_flag, set)) => {
+                        let mut found = false;
+                        for entry in entries {
+                            match set {
+                                true => {
+                                    entry.flags |= flag;
+                                    entry.extended_flags |= extended_flag;
+                                }
+                                false => {
+                                    entry.flags &= !flag;
+                                    entry.extended_flags &= !extended_flag;
+                                }
+                            }
+                            found = true;
+                        }
+                        if !found {
+                            return Err(GitError::Failed(format!("Error: Unable to mark file {}", path)));
+                        }
+                        continue;
+                    }
+                    None => {
+                        if !add && !index.entries.iter().any(|entry| entry.path == *path) {
+                            return Err(GitError::Failed(format!("Error: {}: cannot add to the index - missing --add option?", path)));
+                        }
+                        // the file as named, but under the index's spelling of it
+                        let entry = worktree::stage(&repo, given).map_err(fail)?;
+                        index.add(index::Entry { path: path.to_string(), ..entry });
+                    }
+                }
+                if let Some((mode, flip)) = chmod {
+                    let entry = index.entries.iter_mut().find(|entry| entry.path == *path && entry.stage() == 0);
+                    match entry {
+                        Some(entry) if entry.mode == 0o100644 || entry.mode == 0o100755 => {
+                            entry.mode = u32::from_str_radix(mode, 8).unwrap()
+                        }
+                        _ => return Err(GitError::Failed(format!("Error: git update-index: cannot chmod {} '{}'", flip, path))),
+                    }
+                }
+            }
+        }
+    }
+    index.write(&repo).map_err(fail)
+}
+
+pub fn stash(args: &[String]) -> Result<(), GitError> {
+    let repo = open_repository()?;
+    let result = match args {
+        [] => stash::save(&repo, None),
+        [command] if command == "push" || command == "save" => stash::save(&repo, None),
+        [command, flag, message] if command == "push" && (flag == "-m" || flag == "--message") => {
+            stash::save(&repo, Some(message))
+        }
+        [command, message] if command == "save" => stash::save(&repo, Some(message)),
+        [command] if command == "pop" => stash::pop(&repo),
+        [command] if command == "list" => stash::list(&repo),
+        _ => return Err(GitError::InvalidArgs("Error: usage: stash [push [-m <message>] | save [<message>] | pop | list]".to_string())),
+    };
+    result.map_err(|e| GitError::failed("stash", e))
+}
+
+pub fn notes(args: &[String]) -> Result<(), GitError> {
+    let usage = "Error: usage: notes (add [-f] -m <message>... | show) [<object>]";
+    let (command, mut args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args.iter()),
+        None => return Err(GitError::InvalidArgs(usage.to_string())),
+    };
+    let mut force = false;
+    let mut paragraphs = Vec::new();
+    let mut object = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" | "--force" if command == "add" => force = true,
+            "-m" | "--message" if command == "add" => match args.next() {
+                Some(message) => paragraphs.push(message),
+                None => return Err(GitError::InvalidArgs(usage.to_string())),
+            },
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid notes option", arg))),
+            _ if object.is_none() => object = Some(arg.as_str()),
+            _ => return Err(GitError::InvalidArgs(usage.to_string())),
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("notes", e);
+    let repo = open_repository()?;
+    let object = revision::resolve(&repo, object.unwrap_or("HEAD")).map_err(fail)?;
+    match command {
+        "add" if !paragraphs.is_empty() => {
+            let message = paragraphs.iter().map(|p| format!("{}\n", p.trim_end_matches('\n'))).collect::<Vec<_>>().join("\n");
+            notes::add(&repo, &object, message, force).map_err(fail)
+        }
+        "show" => match notes::show(&repo, &object).map_err(fail)? {
+            Some(note) => std::io::stdout().write_all(&note).map_err(fail),
+            None => Err(GitError::Failed(format!("error: no note found for object {}.", object))),
+        },
+        _ => Err(GitError::InvalidArgs(usage.to_string())),
+    }
+}
+
+// Creates, deletes or lists the refs under `refs/replace/`, which make reads of
+// an object return another one.
+pub fn fast_export(args: &[String]) -> Result<(), GitError> {
+    let fail = |e: std::io::Error| GitError::failed("fast-export", e);
+    let repo = open_repository()?;
+    let mut names = Vec::new();
+    let mut revs = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--all" => {
+                names.extend(refs::list(&repo).map_err(fail)?.into_iter().map(|(name, _)| name));
+                names.push("HEAD".to_string());
+            }
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid fast-export command", arg))),
+            _ => {
+                revs.push(arg.as_str());
+                if arg.starts_with('^') {
+                    continue;
+                }
+                // the refs a range ends at are the ones exported
+                let sides = match arg.split_once("...") {
+                    Some((a, b)) => vec![a, b],
+                    None => vec![arg.split_once("..").map_or(arg.as_str(), |(_, b)| b)],
+                };
+                names.extend(sides.into_iter().map(|side| if side.is_empty() { "HEAD" } else { side }.to_string()));
+            }
+        }
+    }
+    let mut exported = Vec::new();
+    for name in names {
+        let full_name = refs::full_name(&repo, &name)
+            .map_err(fail)?
+            .ok_or_else(|| GitError::Fatal(format!("fatal: '{}' is not a ref, fast-export needs refs to name the commits", name)))?;
+        let sha = refs::resolve(&repo, &full_name).map_err(fail)?.expect("the ref was found");
+        exported.push((full_name, sha));
+    }
+    let range = revision::Range::parse(&repo, &revs).map_err(fail)?;
+    let stdout = std::io::stdout();
+    fast_export::export(&repo, &exported, &range.excluded, &mut stdout.lock()).map_err(fail)
+}
+
+pub fn fast_import(args: &[String]) -> Result<(), GitError> {
+    let mut force = false;
+    for arg in args {
+        match arg.as_str() {
+            "--force" => force = true,
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid fast-import command", arg))),
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("fast-import", e);
+    let repo = open_repository()?;
+    let mut stream = Vec::new();
+    std::io::stdin().read_to_end(&mut stream).map_err(fail)?;
+    let refused = fast_import::import(&repo, &stream, force).map_err(fail)?;
+    match refused.is_empty() {
+        true => Ok(()),
+        false => Err(GitError::Fatal(format!("fatal: {} ref(s) not updated, use --force to overwrite them", refused.len()))),
+    }
+}
+
+pub fn replace(args: &[String]) -> Result<(), GitError> {
+    let fail = |e: std::io::Error| GitError::failed("replace", e);
+    let repo = open_repository()?;
+    let (force, args) = match args.split_first() {
+        Some((flag, rest)) if flag == "-f" || flag == "--force" => (true, rest),
+        _ => (false, args),
+    };
+    match args {
+        [] if !force => {
+            for (name, _) in refs::list(&repo).map_err(fail)? {
+                if let Some(replaced) = name.strip_prefix("refs/replace/") {
+                    outln!("{}", replaced)?;
+                }
+            }
+            Ok(())
+        }
+        [flag] if flag == "-l" || flag == "--list" => replace(&[]),
+        [flag, objects @ ..] if (flag == "-d" || flag == "--delete") && !objects.is_empty() => {
+            let mut errors = Vec::new();
+            for object in objects {
+                let sha = revision::resolve(&repo, object).map_err(fail)?;
+                let name = format!("refs/replace/{}", sha);
+                match refs::resolve(&repo, &name).map_err(fail)? {
+                    Some(_) => {
+                        refs::delete(&repo, &name).map_err(fail)?;
+                        outln!("Deleted replace ref '{}'", sha)?;
+                    }
+                    None => errors.push(format!("error: replace ref '{}' not found", sha)),
+                }
+            }
+            match errors.is_empty() {
+                true => Ok(()),
+                false => Err(GitError::Failed(errors.join("\n"))),
+            }
+        }
+        [object, replacement] if !object.starts_with('-') && !replacement.starts_with('-') => {
+            let (object, replacement) = (
+                revision::resolve(&repo, object).map_err(fail)?,
+                revision::resolve(&repo, replacement).map_err(fail)?,
+            );
+            if object == replacement {
+                return Err(GitError::Failed(format!("error: new object is the same as the old one: '{}'", object)));
+            }
+            let name = format!("refs/replace/{}", object);
+            if !force && refs::resolve(&repo, &name).map_err(fail)?.is_some() {
+                return Err(GitError::Failed(format!("error: replace ref '{}' already exists", name)));
+            }
+            // the types of the objects themselves, whatever replaces them now
+            let type_of = |sha: &str| Blob::from_store(repo.objects(), sha).map(|object| object.object_type());
+            let (object_type, replacement_type) = (type_of(&object).map_err(fail)?, type_of(&replacement).map_err(fail)?);
+            if !force && object_type != replacement_type {
+                return Err(GitError::Failed(format!(
+                    "error: Objects must be of the same type.\n'{}' points to a replaced object of type '{}'\n\
+                         while '{}' points to a replacement object of type '{}'.",
+                    object, object_type, replacement, replacement_type
+                )));
+            }
+            refs::update(&repo, &name, &replacement, None).map_err(fail)
+        }
+        _ => Err(GitError::InvalidArgs("Error: usage: replace [-f] <object> <replacement> | replace -d <object>... | replace [-l]".to_string())),
+    }
+}
+
+// Prints a line in the `--format` for each ref, the refs limited to those
+// matching one of the patterns: a glob, or a prefix ending at a `/`.
+pub fn for_each_ref(args: &[String]) -> Result<(), GitError> {
+    let mut format = format::REF_FORMAT;
+    let mut patterns = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--format=") {
+            Some(value) => format = value,
+            None if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid for-each-ref option", arg))),
+            None => patterns.push(arg.trim_end_matches('/')),
+        }
+    }
+    let fail = |e: std::io::Error| GitError::failed("for-each-ref", e);
+    let pieces = format::parse(format, format::REF_ATOMS).map_err(|e| GitError::Fatal(format!("fatal: {}", e)))?;
+    let repo = open_repository()?;
+    for (name, sha) in refs::list(&repo).map_err(fail)? {
+        let matched = patterns.is_empty()
+            || patterns.iter().any(|pattern| {
+                name == *pattern
+                    || matches!(name.strip_prefix(pattern), Some(rest) if rest.starts_with('/'))
+                    || wildmatch::wildmatch(pattern.as_bytes(), name.as_bytes(), false)
+            });
+        if !matched {
+            continue;
+        }
+        let object = Blob::from_sha(&repo, &sha).map_err(fail)?;
+        outln!("{}", format::expand(&pieces, |atom| format::ref_atom(&name, &object, atom)).map_err(fail)?)?;
+    }
+    Ok(())
+}
+
+// Shows the commits reachable from the revisions (`HEAD` when there are
+// none), newest first, in the `--format` asked for. With `--graph` the
+// commits come in topological order, next to the history drawn in ascii.
+// Paths after `--` limit it to the commits changing them.
+pub fn log(args: &[String]) -> Result<(), GitError> {
+    let mut pretty = format::Pretty::Medium;
+    let mut graph = None;
+    let mut range = ancestry::DateRange::default();
+    let mut patterns = ancestry::Patterns::default();
+    let mut revs = Vec::new();
+    let paths: Vec<String> = match args.iter().position(|arg| arg == "--") {
+        Some(dashes) => args[dashes + 1..].iter().map(|path| path.trim_end_matches('/').to_string()).collect(),
+        None => Vec::new(),
+    };
+    for arg in args.iter().take_while(|arg| *arg != "--") {
+        let value = arg.strip_prefix("--format=").or_else(|| arg.strip_prefix("--pretty="));
+        match (arg.as_str(), value) {
+            ("--oneline", _) => pretty = format::Pretty::Oneline,
+            ("--graph", _) => graph = Some(graph::Graph::new()),
+            _ if date_range_option(&mut range, arg)? => {}
+            _ if arg.starts_with("--author=") => patterns.authors.push(arg["--author=".len()..].to_string()),
+            _ if arg.starts_with("--grep=") => patterns.greps.push(arg["--grep=".len()..].to_string()),
+            (_, Some(value)) => {
+                pretty = format::Pretty::parse(value).ok_or(GitError::Fatal(format!("fatal: invalid --pretty format: {}", value)))?
+            }
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid log option", arg))),
+            (rev, _) => revs.push(rev),
+        }
+    }
+    if revs.is_empty() {
+        revs.push("HEAD");
+    }
+    let fail = |e: std::io::Error| GitError::failed("log", e);
+    let repo = open_repository()?;
+    let revs = revision::Range::parse(&repo, &revs).map_err(fail)?;
+    let mut history = match paths.is_empty() {
+        true => ancestry::history(&repo, &revs.tips, &revs.excluded),
+        false => ancestry::touching(&repo, &revs.tips, &revs.excluded, &paths),
+    }
+    .map_err(fail)?;
+    if graph.is_some() {
+        history = ancestry::topo_order(history);
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut previous: Option<String> = None;
+    for (sha, parents) in history {
+        let commit = commit::Commit::read(&repo, &sha).map_err(fail)?;
+        if !range.contains(commit.committer.time) || !patterns.matches(&commit) {
+            continue;
+        }
+        let text = pretty.show(&sha, &commit);
+        let mut shown = String::new();
+        if let Some(graph) = &mut graph {
+            graph.update(&sha, parents);
+        }
+        if let (Some(previous), false) = (&previous, pretty.separator().is_empty()) {
+            // a separating newline gets the graph, or it would be a gap in it
+            if let (Some(graph), true) = (&mut graph, previous.ends_with('\n')) {
+                shown += &graph.padding();
+            }
+            shown += pretty.separator();
+        }
+        match &mut graph {
+            Some(graph) => shown += &graph.show(&text),
+            None => shown += &text,
+        }
+        if !pretty.terminator().is_empty() {
+            if let (Some(graph), true) = (&mut graph, text.ends_with('\n')) {
+                shown += &graph.padding();
+            }
+            shown += pretty.terminator();
+        }
+        write!(out, "{}", shown).map_err(fail)?;
+        previous = Some(text);
+    }
+    Ok(())
+}
+
+pub fn rev_parse(args: &[String]) -> Result<(), GitError> {
+    let revs = match args {
+        [flag, rev] if flag == "--verify" => std::slice::from_ref(rev),
+        [flag, ..] if flag == "--verify" => return Err(GitError::InvalidArgs("Error: usage: rev-parse --verify <revision>".to_string())),
+        revs => revs,
+    };
+    let repo = open_repository()?;
+    for rev in revs {
+        let sha = revision::resolve(&repo, rev)
+            .map_err(|e| GitError::failed("rev-parse", e))?;
+        outln!("{}", sha)?;
+    }
+    Ok(())
+}
+
+pub fn merge_base(args: &[String]) -> Result<(), GitError> {
+    let (all, a, b) = match args {
+        [flag, a, b] if flag == "--all" || flag == "-a" => (true, a, b),
+        [a, b] => (false, a, b),
+        _ => return Err(GitError::InvalidArgs("Error: usage: merge-base [--all] <commit> <commit>".to_string())),
+    };
+    let repo = open_repository()?;
+    let commit = |name: &str| commit::Commit::peel(&repo, &revision::resolve(&repo, name)?);
+    let bases = commit(a)
+        .and_then(|a| Ok((a, commit(b)?)))
+        .and_then(|(a, b)| ancestry::merge_bases(&repo, &a, &b))
+        .map_err(|e| GitError::failed("merge-base", e))?;
+    if bases.is_empty() {
+        return Err(GitError::Failed("Error: merge-base command failed with error: 'no common ancestor'".to_string()));
+    }
+    for base in bases.iter().take(if all { bases.len() } else { 1 }) {
+        outln!("{}", base)?;
+    }
+    Ok(())
+}
+
+// Prints how far `<commit>` and `<upstream>` have diverged, as `ahead N, behind M`
+// from the point of view of `<commit>`.
+pub fn ahead_behind(args: &[String]) -> Result<(), GitError> {
+    let (a, b) = match args {
+        [a, b] => (a, b),
+        _ => return Err(GitError::InvalidArgs("Error: usage: ahead-behind <commit> <upstream>".to_string())),
+    };
+    let repo = open_repository()?;
+    let commit = |name: &str| commit::Commit::peel(&repo, &revision::resolve(&repo, name)?);
+    let (ahead, behind) = commit(a)
+        .and_then(|a| Ok((a, commit(b)?)))
+        .and_then(|(a, b)| ancestry::ahead_behind(&repo, &a, &b))
+        .map_err(|e| GitError::failed("ahead-behind", e))?;
+    outln!("ahead {}, behind {}", ahead, behind)?;
+    Ok(())
+}
+
+// Reads a `--since`/`--after` or `--until`/`--before` date into `range`,
+// returning whether `arg` was one of them.
+fn date_range_option(range: &mut ancestry::DateRange, arg: &str) -> Result<bool, GitError> {
+    let (bound, date) = match arg.split_once('=') {
+        Some(("--since", date)) | Some(("--after", date)) => (&mut range.since, date),
+        Some(("--until", date)) | Some(("--before", date)) => (&mut range.until, date),
+        _ => return Ok(false),
+    };
+    *bound = Some(identity::approxidate(date, identity::now()).ok_or(GitError::Fatal(format!("fatal: invalid date '{}'", date)))?);
+    Ok(true)
+}
+
+// Prints the commits reachable from the given ones, and with `--objects` the
+// trees and blobs they hold after them, each followed by its path.
+pub fn rev_list(args: &[String]) -> Result<(), GitError> {
+    let mut objects = false;
+    let mut range = ancestry::DateRange::default();
+    let mut revs = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--objects" => objects = true,
+            _ if date_range_option(&mut range, arg)? => {}
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid rev-list option", arg))),
+            rev => revs.push(rev),
+        }
+    }
+    if revs.is_empty() {
+        return Err(GitError::InvalidArgs("Error: usage: rev-list [--objects] [--since=<date>] [--until=<date>] <commit>...".to_string()));
+    }
+    let fail = |e: std::io::Error| GitError::failed("rev-list", e);
+    let repo = open_repository()?;
+    let revs = revision::Range::parse(&repo, &revs).map_err(fail)?;
+    let commits = ancestry::rev_list(&repo, &revs.tips, &revs.excluded).map_err(fail)?;
+    let commits = range.filter(&repo, commits).map_err(fail)?;
+    for commit in &commits {
+        outln!("{}", commit)?;
+    }
+    if objects {
+        for (sha, path) in ancestry::objects(&repo, &commits).map_err(fail)? {
+            outln!("{} {}", sha, path)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn commit_graph(args: &[String]) -> Result<(), GitError> {
+    let repo = open_repository()?;
+    let result = match args {
+        [command] if command == "write" => commit_graph::write(&repo).map(|_| ()),
+        [command] if command == "verify" => commit_graph::verify(&repo),
+        _ => return Err(GitError::InvalidArgs("Error: usage: commit-graph (write | verify)".to_string())),
+    };
+    result.map_err(|e| GitError::failed("commit-graph", e))
+}
+
+pub fn verify_commit(args: &[String]) -> Result<(), GitError> {
+    verify(args, "verify-commit", commit::verify_commit)
+}
+
+pub fn verify_tag(args: &[String]) -> Result<(), GitError> {
+    verify(args, "verify-tag", tag::verify_tag)
+}
+
+fn verify(
+    args: &[String],
+    command: &str,
+    verify_object: fn(&Repository, &str, bool) -> std::io::Result<()>,
+) -> Result<(), GitError> {
+    let (verbose, sha) = match args {
+        [flag, sha] if flag == "-v" || flag == "--verbose" => (true, sha),
+        [sha] => (false, sha),
+        _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid {} command", args.join(" "), command))),
+    };
+    let repo = open_repository()?;
+    match revision::resolve(&repo, sha).and_then(|sha| verify_object(&repo, &sha, verbose)) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::failed(command, e)),
+    }
+}
+
+// Lists the paths of the index, once per stage, with `-s` as
+// `<mode> <id> <stage>\t<path>`.
+pub fn ls_files(args: &[String]) -> Result<(), GitError> {
+    let mut stage = false;
+    let mut terminator = '\n';
+    for arg in args {
+        match arg.as_str() {
+            "-s" | "--stage" => stage = true,
+            "-z" => terminator = '\0',
+            "-c" | "--cached" => {}
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid ls-files command", arg))),
+        }
+    }
+    let repo = open_repository()?;
+    let index = index::Index::read(&repo).map_err(|e| GitError::failed("ls-files", e))?;
+    let non_ascii = quotes_non_ascii(&repo).map_err(|e| GitError::failed("ls-files", e))?;
+    let mut out = String::new();
+    for entry in &index.entries {
+        if stage {
+            out.push_str(&format!("{} {} {}\t", entry.mode_string(), entry.sha, entry.stage()));
+        }
+        let path = match terminator {
+            '\0' => entry.path.clone(),
+            _ => quote_path(&entry.path, non_ascii),
+        };
+        out.push_str(&format!("{}{}", path, terminator));
+    }
+    out!("{}", out)?;
+    Ok(())
+}
+
+pub fn ls_tree(args: &[String]) -> Result<(), GitError> {
+    let mut listing = tree::Listing {
+        name_only: false,
+        recursive: false,
+        trees_only: false,
+        long: false,
+        terminator: '\n',
+        paths: Vec::new(),
+    };
+    let mut sha = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name-only" => listing.name_only = true,
+            "-r" => listing.recursive = true,
+            "-d" => listing.trees_only = true,
+            "-l" | "--long" => listing.long = true,
+            "-z" => listing.terminator = '\0',
+            "--" => listing.paths.extend(args.by_ref().cloned()),
+            _ if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid ls-tree command", arg))),
+            _ if sha.is_some() => listing.paths.push(arg.clone()),
+            _ => sha = Some(arg),
+        }
+    }
+    let usage = "Error: usage: ls-tree [-d] [-r] [-l] [-z] [--name-only] <tree-ish> [--] [<path>...]";
+    let sha = sha.ok_or_else(|| GitError::InvalidArgs(usage.to_string()))?;
+    let repo = open_repository()?;
+    let tree = revision::resolve(&repo, sha).and_then(|sha| diff::peel_to_tree(&repo, &sha));
+    match tree.and_then(|tree| tree::print_tree(&repo, &tree, &listing)) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::failed("ls-tree", e)),
+    }
+}
+
+pub fn apply(args: &[String]) -> Result<(), GitError> {
+    let mut reverse = false;
+    let mut check = false;
+    let mut patch_file = None;
+    for arg in args {
+        match arg.as_str() {
+            "-R" | "--reverse" => reverse = true,
+            "--check" => check = true,
+            _ if patch_file.is_none() && !arg.starts_with('-') => patch_file = Some(arg),
+            _ => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid apply option", arg))),
+        }
+    }
+    let patch = match patch_file {
+        Some(file) => fs::read_to_string(file),
+        None => {
+            let mut patch = String::new();
+            std::io::stdin().read_to_string(&mut patch).map(|_| patch)
+        }
+    };
+    match patch.and_then(|patch| apply::apply(Path::new("."), &patch, reverse, check)) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::failed("apply", e)),
+    }
+}
+
+pub fn diff(args: &[String]) -> Result<(), GitError> {
+    let mut rename_score = None;
+    let mut cached = false;
+    let mut name_only = false;
+    let mut terminator = '\n';
+    let mut revs = Vec::new();
+    for arg in args {
+        if arg == "--cached" || arg == "--staged" {
+            cached = true;
+        } else if arg == "--name-only" {
+            name_only = true;
+        } else if arg == "-z" {
+            terminator = '\0';
+        } else if let Some(score) = arg.strip_prefix("--find-renames").map(|s| s.trim_start_matches('=')).or_else(|| arg.strip_prefix("-M")) {
+            rename_score = Some(
+                diff::parse_rename_score(score)
+                    .ok_or_else(|| GitError::InvalidArgs(format!("Error: args {}, not a valid rename score", arg)))?,
+            );
+        } else {
+            revs.push(arg);
+        }
+    }
+    if let Some(arg) = revs.iter().find(|rev| rev.starts_with('-')) {
+        return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid diff command", arg)));
+    }
+    let format = match name_only {
+        true => diff::Format::NameOnly { terminator },
+        false => diff::Format::Patch,
+    };
+    let repo = open_repository()?;
+    let result = match (cached, &revs[..]) {
+        (false, []) => diff::diff_worktree(&repo, format),
+        (true, []) => {
+            revision::resolve(&repo, "HEAD").and_then(|head| diff::diff_cached(&repo, &head, rename_score, format))
+        }
+        (true, [rev]) => {
+            revision::resolve(&repo, rev).and_then(|rev| diff::diff_cached(&repo, &rev, rename_score, format))
+        }
+        (false, [old, new]) => revision::resolve(&repo, old)
+            .and_then(|old| Ok((old, revision::resolve(&repo, new)?)))
+            .and_then(|(old, new)| diff::diff_trees(&repo, &old, &new, rename_score, format)),
+        _ => {
+            return Err(
+                GitError::InvalidArgs("Error: usage: diff [-M[<n>]] [--name-only [-z]] [--cached [<commit>] | <commit> <commit>]".to_string()),
+            )
+        }
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::failed("diff", e)),
+    }
+}
+
+// Returns the number of conflicts, which git reports as the exit status.
+pub fn merge_file(args: &[String]) -> Result<usize, GitError> {
+    let mut to_stdout = false;
+    let mut labels = Vec::new();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--stdout" => to_stdout = true,
+            "-L" => labels.push(args.next().ok_or_else(|| GitError::InvalidArgs("Error: -L needs a label".to_string()))?.clone()),
+            _ => files.push(arg.clone()),
+        }
+    }
+    let (current, base, other) = match &files[..] {
+        [current, base, other] => (current, base, other),
+        _ => return Err(GitError::InvalidArgs("Error: usage: merge-file [-p] [-L <label>]... <current> <base> <other>".to_string())),
+    };
+    let read = |file: &String| {
+        fs::read(file).map_err(|e| GitError::Failed(format!("Error: merge-file command failed with error: '{}: {}'", file, e)))
+    };
+    let labels = merge::Labels {
+        ours: labels.first().unwrap_or(current),
+        theirs: labels.get(2).unwrap_or(other),
+    };
+    let (merged, conflicts) =
+        match merge::merge_blobs(Some(&read(base)?), Some(&read(current)?), Some(&read(other)?), &labels) {
+            Ok(merged) => (merged, 0),
+            Err(conflicts) => (conflicts.merged, conflicts.count),
+        };
+    let merged = merged.unwrap_or_default();
+    let written = if to_stdout {
+        std::io::stdout().write_all(&merged).and_then(|_| std::io::stdout().flush())
+    } else {
+        fs::write(current, &merged)
+    };
+    match written {
+        Ok(()) => Ok(conflicts),
+        Err(e) => Err(GitError::failed("merge-file", e)),
+    }
+}
+
+pub fn cherry_pick(args: &[String]) -> Result<(), GitError> {
+    let rev = match args {
+        [rev] => rev,
+        _ => return Err(GitError::InvalidArgs("Error: usage: cherry-pick <commit>".to_string())),
+    };
+    let repo = open_repository()?;
+    match cherry_pick::cherry_pick(&repo, rev) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::failed("cherry-pick", e)),
+    }
+}
+
+pub fn merge(args: &[String]) -> Result<(), GitError> {
+    let (name, verify) = match args {
+        [name] => (name, true),
+        [option, name] | [name, option] if option == "--no-verify" => (name, false),
+        _ => return Err(GitError::InvalidArgs("Error: usage: merge [--no-verify] <commit>".to_string())),
+    };
+    let repo = open_repository()?;
+    match merge::merge_branch(&repo, name, verify) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::failed("merge", e)),
+    }
+}
+
+// `clone [--depth <n>] <url> [<dir>]` clones into `<dir>`, by default the last
+// part of the url without `.git`, with only the last `<n>` commits of history
+// given a depth.
+pub fn clone(args: &[String]) -> Result<(), GitError> {
+    let usage = "Error: usage: clone [--depth <n>] <url> [<dir>]";
+    let mut depth = None;
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "--depth" => Some(args.next().ok_or_else(|| GitError::InvalidArgs(usage.to_string()))?.as_str()),
+            _ => arg.strip_prefix("--depth="),
+        };
+        match value {
+            Some(value) => match value.parse::<u32>() {
+                Ok(n) if n > 0 => depth = Some(n),
+                _ => return Err(GitError::Fatal(format!("fatal: depth {} is not a positive number", value))),
+            },
+            None if arg.starts_with('-') => return Err(GitError::InvalidArgs(format!("Error: args {}, not a valid clone option", arg))),
+            None => positional.push(arg.as_str()),
+        }
+    }
+    let (url, dir) = match positional[..] {
+        [url] => {
+            let name = url.trim_end_matches('/').rsplit(['/', ':']).next().unwrap_or_default();
+            (url, name.strip_suffix(".git").unwrap_or(name).to_string())
+        }
+        [url, dir] => (url, dir.to_string()),
+        _ => return Err(GitError::InvalidArgs(usage.to_string())),
+    };
+    eprintln!("Cloning into '{}'...", dir);
+    let repo = fetch::clone(url, Path::new(&dir), depth).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => GitError::Fatal(format!("fatal: {}.", e)),
+        _ => GitError::failed("clone", e),
+    })?;
+    if refs::resolve(&repo, "HEAD").map_err(|e| GitError::failed("clone", e))?.is_none() {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn tesing_tree_object() {
+        let blob = Blob::from_sha_in(Path::new("tests/objects"), "51912bb58e69f15db8f8b214bc97de8430cfdc02").unwrap();
+        assert_eq!(blob.object_type(), ObjectType::Tree);
+        let tree = Tree::try_pars(&blob).unwrap();
+        let entries = tree.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].mode, "100644");
+        assert_eq!(entries[0].name, "file_test.txt");
+        assert_eq!(
+            Blob::string_hash(&entries[0].sha),
+            "cd591dba9391e2cdfbae51a51800b9689c7ea360"
+        );
+        assert_eq!(entries[1].name, "whats_up.txt");
+    }
+
+    #[test]
+    fn testing_exit_statuses() {
+        let unknown = std::io::Error::new(std::io::ErrorKind::NotFound, refs::UnknownRevision("nosuch".to_string()));
+        let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "unknown revision: nosuch");
+        let fatal = GitError::failed("log", unknown);
+        let failed = GitError::failed("log", missing);
+        assert_eq!((fatal.exit_status(), fatal.to_string()), (128, "fatal: unknown revision: nosuch".to_string()));
+        // only the error's type counts, not its message
+        assert_eq!(failed.exit_status(), 1);
+        assert_eq!(GitError::InvalidArgs("Error: usage: log".to_string()).exit_status(), 129);
+    }
+
+    #[test]
+    fn testing_object_type_of_blob() {
+        let blob = Blob::from_sha_in(Path::new("tests/objects"), "cd591dba9391e2cdfbae51a51800b9689c7ea360").unwrap();
+        assert_eq!(blob.object_type(), ObjectType::Blob);
+        assert_eq!(blob.content, b"bla bla bla\n");
+        assert!(Tree::try_pars(&blob).is_err());
+        for sha in ["cd591d", "cd591dba9391e2cdfbae51a51800b9689c7ea36", "+d591dba9391e2cdfbae51a51800b9689c7ea360"] {
+            let error = Blob::from_sha_in(Path::new("tests/objects"), sha).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput, "{}", sha);
+        }
+    }
+
+    #[test]
+    fn testing_all_objects_listing() {
+        let ids = AllObjects::new(vec![PathBuf::from("tests/objects"), PathBuf::from("tests/objects")])
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            ids,
+            vec![
+                "51912bb58e69f15db8f8b214bc97de8430cfdc02",
+                "7108f7ecb345ee9d0084193f147cdad4d2998293",
+                "cd591dba9391e2cdfbae51a51800b9689c7ea360",
+            ]
+        );
+    }
+
+    // Objects of random types and contents, many starting with a space or
+    // holding NULs, read back as they were written.
+    #[test]
+    fn testing_object_header_round_trip() {
+        let dir = TempDir::new("headers");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let types = [ObjectType::Blob, ObjectType::Tree, ObjectType::Commit, ObjectType::Tag];
+        // xorshift, so that a failure can be replayed
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut contents: Vec<Vec<u8>> = vec![Vec::new(), b" ".to_vec(), b"\0".to_vec(), b" 12\0blob 3\0".to_vec()];
+        for _ in 0..200 {
+            let len = (next() % 300) as usize;
+            let alphabet = b" \0blobtrecmiag0123456789\n";
+            contents.push((0..len).map(|_| alphabet[(next() % alphabet.len() as u64) as usize]).collect());
+        }
+        let mut read = Vec::new();
+        for (i, content) in contents.iter().enumerate() {
+            let object_type = types[(next() % 4) as usize];
+            let blob = Blob::new(object_type, content.clone());
+            let sha = repo.write_object(object_type, content.clone()).unwrap();
+            let back = Blob::from_sha(&repo, &sha).unwrap();
+            let mut streamed = repo.open_object(&sha).unwrap();
+            let mut streamed_content = Vec::new();
+            streamed.read_to_end(&mut streamed_content).unwrap();
+            read.push((i, sha == blob.hash_string, back.object_type() == object_type, back.content == *content));
+            read.push((i, streamed.object_type() == object_type, streamed.size() == content.len() as u64, streamed_content == *content));
+        }
+
+        // a header without a space, and sizes that don't match
+        let write_raw = |raw: &[u8]| {
+            let sha = Blob::string_hash(&Blob::hash(raw, b""));
+            fs::create_dir_all(dir.join("objects").join(&sha[..2])).unwrap();
+            let mut z = ZlibEncoder::new(Vec::new(), Compression::fast());
+            z.write_all(raw).unwrap();
+            fs::write(dir.join("objects").join(&sha[..2]).join(&sha[2..]), z.finish().unwrap()).unwrap();
+            Blob::from_sha(&repo, &sha).map(|_| ())
+        };
+        let broken: Vec<bool> = [&b"blob5\0a b c"[..], b"blob 4\0a b c", b"blob 6\0a b c", b"blob \0", b"blob 1x\0a"]
+            .iter()
+            .map(|raw| write_raw(raw).is_err())
+            .collect();
+        let sound = write_raw(b"blob 5\0a b c");
+
+        let failed: Vec<_> = read.iter().filter(|(_, a, b, c)| !(*a && *b && *c)).collect();
+        assert!(failed.is_empty(), "{:?}", failed);
+        assert_eq!(broken, vec![true; 5]);
+        assert!(sound.is_ok());
+    }
+
+    #[test]
+    fn testing_object_writing() {
+        let repo = Repository::in_memory();
+        let empty = repo.write_blob(b"").unwrap();
+        let again = repo.write_blob(b"").unwrap();
+        let subtree = repo.write_tree(&[]).unwrap();
+        let entry = |name: &str, entry_type: EntryType, mode: &str, sha: &str| TreeEntry {
+            mode: mode.to_string(),
+            entry_type,
+            sha: Blob::decode_hex(sha).unwrap().try_into().unwrap(),
+            name: name.to_string(),
+        };
+        let entries = [entry("a.b", EntryType::Blob, "100644", &empty), entry("a", EntryType::Tree, "040000", &subtree)];
+        let tree = repo.write_tree(&entries).unwrap();
+        let listed: Vec<String> = Tree::try_pars(&Blob::from_sha(&repo, &tree).unwrap())
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        let bad = repo.write_tree(&[entry("a/b", EntryType::Blob, "100644", &empty)]);
+
+        assert_eq!(empty, "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        assert_eq!(again, empty);
+        assert_eq!(subtree, "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+        assert_eq!(tree, "4748f64e371b23af90aa080a2707e7ec7b5cd731");
+        assert_eq!(listed, vec!["a.b", "a"]);
+        assert_eq!(bad.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn testing_malformed_object_ids() {
+        let dir = TempDir::new("malformed-ids");
+        fs::create_dir_all(dir.join("objects/pack")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let loose = LooseObjects::new(dir.join("objects"));
+        for sha in ["", "e69de2", "e69de29bb2d1d6434b8b29ae775ad8c2e48c539", "g69de29bb2d1d6434b8b29ae775ad8c2e48c5391"] {
+            assert!(!repo.objects().contains(sha), "{}", sha);
+            assert_eq!(repo.objects().open(sha).err().unwrap().kind(), std::io::ErrorKind::InvalidInput, "{}", sha);
+            assert_eq!(loose.write(sha, b"").unwrap_err().kind(), std::io::ErrorKind::InvalidInput, "{}", sha);
+        }
+        assert_eq!(Blob::decode_hex("00ff").unwrap(), vec![0, 255]);
+        for hex in ["abc", "+f", "0g", "\u{e9}0"] {
+            assert!(Blob::decode_hex(hex).is_err(), "{}", hex);
+        }
+    }
+
+    // Loose objects are renamed into place whole, and one already stored is
+    // left as it is.
+    #[test]
+    fn testing_loose_object_writes() {
+        let dir = TempDir::new("loose-writes");
+        let loose = LooseObjects::new(dir.join("objects"));
+        let sha = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        loose.write(sha, b"first").unwrap();
+        loose.write(sha, b"second").unwrap();
+        let stored = fs::read(dir.join("objects/e6").join(&sha[2..])).unwrap();
+        let names: Vec<_> = fs::read_dir(dir.join("objects/e6")).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+
+        assert_eq!(stored, b"first");
+        assert_eq!(names, vec![std::ffi::OsString::from(&sha[2..])]);
+    }
+
+    #[test]
+    fn testing_linked_worktree() {
+        let dir = TempDir::new("linked-worktree");
+        let common_dir = dir.join("main/.git");
+        let git_dir = common_dir.join("worktrees/linked");
+        let work_tree = dir.join("linked");
+        fs::create_dir_all(common_dir.join("objects")).unwrap();
+        fs::create_dir_all(&git_dir).unwrap();
+        fs::create_dir_all(work_tree.join("sub")).unwrap();
+        fs::write(common_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/linked\n").unwrap();
+        fs::write(git_dir.join("commondir"), "../..\n").unwrap();
+        fs::write(work_tree.join(".git"), format!("gitdir: {}\n", git_dir.display())).unwrap();
+        let main = Repository::open(&common_dir).unwrap();
+        let master = main.write_blob(b"master\n").unwrap();
+        let linked = main.write_blob(b"linked\n").unwrap();
+        refs::update(&main, "refs/heads/master", &master, None).unwrap();
+        refs::update(&main, "refs/heads/linked", &linked, None).unwrap();
+
+        let repo = Repository::open(work_tree.join(".git")).unwrap();
+        let head = refs::resolve(&repo, "HEAD").unwrap();
+        let main_head = refs::resolve(&main, "HEAD").unwrap();
+        let content = Blob::from_sha(&repo, &linked).unwrap().content;
+        let bisect = repo.write_blob(b"bad\n").unwrap();
+        refs::update(&repo, "refs/bisect/bad", &bisect, None).unwrap();
+        let linked_refs: Vec<String> = refs::list(&repo).unwrap().into_iter().map(|(name, _)| name).collect();
+        let main_refs: Vec<String> = refs::list(&main).unwrap().into_iter().map(|(name, _)| name).collect();
+        let found = Repository::discover(work_tree.join("sub")).unwrap();
+        let canonical = fs::canonicalize(&work_tree).unwrap();
+        fs::write(work_tree.join(".git"), "not a gitfile\n").unwrap();
+        let bad = Repository::open(work_tree.join(".git")).err().map(|e| e.kind());
+
+        assert_eq!(head.as_deref(), Some(linked.as_str()));
+        assert_eq!(main_head.as_deref(), Some(master.as_str()));
+        assert_eq!(content, b"linked\n");
+        assert_eq!(repo.work_tree(), work_tree);
+        assert_eq!(repo.git_dir(), git_dir);
+        assert_eq!(repo.common_dir(), git_dir.join("../.."));
+        assert_eq!(linked_refs, vec!["refs/bisect/bad", "refs/heads/linked", "refs/heads/master"]);
+        assert_eq!(main_refs, vec!["refs/heads/linked", "refs/heads/master"]);
+        assert_eq!(found.work_tree(), canonical);
+        assert_eq!(found.git_dir(), git_dir);
+        assert_eq!(bad, Some(std::io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn testing_entry_types() {
+        use std::convert::TryFrom;
+
+        let modes = ["100644", "100755", "40000", "040000", "120000", "160000"];
+        let types: Vec<EntryType> = modes.iter().map(|mode| EntryType::from_mode(mode)).collect();
+        let words: Vec<Option<EntryType>> =
+            [&b"blob"[..], b"tree", b"commit", b"tag", b"Blob"].iter().map(|word| EntryType::try_from(*word).ok()).collect();
+        let mut content = b"100755 run\0".to_vec();
+        content.extend_from_slice(&[1; 20]);
+        content.extend_from_slice(b"160000 sub\0");
+        content.extend_from_slice(&[2; 20]);
+        let tree = Tree::try_pars(&Blob::new(ObjectType::Tree, content)).unwrap();
+
+        let all = [EntryType::Blob, EntryType::Tree, EntryType::Symlink, EntryType::Gitlink];
+        assert_eq!(types, vec![all[0], all[0], all[1], all[1], all[2], all[3]]);
+        assert_eq!(all.map(|entry_type| entry_type.mode_str()), ["100644", "40000", "120000", "160000"]);
+        assert!(all.iter().all(|entry_type| EntryType::from_mode(entry_type.mode_str()) == *entry_type));
+        assert_eq!(EntryType::Gitlink.object_type(), ObjectType::Commit);
+        assert_eq!(EntryType::Symlink.object_type(), ObjectType::Blob);
+        assert_eq!(words, vec![Some(EntryType::Blob), Some(EntryType::Tree), Some(EntryType::Gitlink), None, None]);
+        let parsed: Vec<EntryType> = tree.entries().iter().map(|entry| entry.entry_type).collect();
+        assert_eq!(parsed, vec![EntryType::Blob, EntryType::Gitlink]);
+    }
+
+    #[test]
+    fn testing_tree_lookup() {
+        let entry = |mode: &str, name: &str| {
+            let mut entry = format!("{} {}\0", mode, name).into_bytes();
+            entry.extend_from_slice(&[name.len() as u8; 20]);
+            entry
+        };
+        let parse = |entries: &[(&str, &str)]| {
+            let content = entries.iter().flat_map(|(mode, name)| entry(mode, name)).collect();
+            Tree::try_pars(&Blob::new(ObjectType::Tree, content)).unwrap()
+        };
+        let names = |tree: &Tree, wanted: &[&str]| -> Vec<Option<String>> {
+            wanted.iter().map(|name| tree.lookup(name).map(|entry| entry.name.clone())).collect()
+        };
+        // git's order: `a.b` sorts before the subtree `a`, which sorts as `a/`
+        let sorted = parse(&[("100644", "a-"), ("100644", "a.b"), ("40000", "a"), ("100644", "a0"), ("120000", "b")]);
+        let unsorted = parse(&[("100644", "z"), ("160000", "m"), ("100644", "a")]);
+
+        assert_eq!(
+            names(&sorted, &["a", "a.b", "a-", "a0", "b", "a/", "c"]),
+            vec![Some("a".into()), Some("a.b".into()), Some("a-".into()), Some("a0".into()), Some("b".into()), None, None]
+        );
+        assert_eq!(names(&unsorted, &["a", "m", "z", "y"]), vec![Some("a".into()), Some("m".into()), Some("z".into()), None]);
+        assert_eq!(sorted.lookup("a").unwrap().mode, "40000");
+    }
+
+    #[test]
+    fn testing_corrupt_objects() {
+        let dir = TempDir::new("corrupt-objects");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let garbage = repo.write_blob(b"garbage\n").unwrap();
+        let path = dir.join("objects").join(&garbage[..2]).join(&garbage[2..]);
+        fs::write(&path, b"not zlib at all").unwrap();
+        let read = Blob::from_sha(&repo, &garbage).err().unwrap().to_string();
+        let opened = repo.open_object(&garbage).err().unwrap().to_string();
+        // cut short after the header, so it only fails while streaming
+        let content: String = (0..20000).map(|n| format!("{}\n", n)).collect();
+        let cut = repo.write_blob(content.as_bytes()).unwrap();
+        let cut_path = dir.join("objects").join(&cut[..2]).join(&cut[2..]);
+        let deflated = fs::read(&cut_path).unwrap();
+        fs::write(&cut_path, &deflated[..deflated.len() / 2]).unwrap();
+        let mut object = repo.open_object(&cut).unwrap();
+        let streamed = std::io::copy(&mut object, &mut std::io::sink()).err().unwrap();
+        let memory = Repository::in_memory();
+        memory.objects().write(&garbage, b"not zlib either").unwrap();
+        let in_memory = Blob::from_sha(&memory, &garbage).err().unwrap().to_string();
+        // valid zlib streams holding less or more than their headers say
+        let mismatched: Vec<String> = [&b"blob 10\0short"[..], &b"blob 2\0longer"[..]]
+            .iter()
+            .enumerate()
+            .map(|(n, object)| {
+                let sha = format!("{:040}", n);
+                let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+                z.write_all(object).unwrap();
+                memory.objects().write(&sha, &z.finish().unwrap()).unwrap();
+                let mut content = Vec::new();
+                memory.open_object(&sha).unwrap().read_to_end(&mut content).unwrap_err().to_string()
+            })
+            .collect();
+        let mut exact = Vec::new();
+        let blob = memory.write_blob(b"exactly\n").unwrap();
+        memory.open_object(&blob).unwrap().read_to_end(&mut exact).unwrap();
+
+        let corrupt = format!("object {} (stored in {}) is corrupt: ", garbage, path.display());
+        assert!(read.starts_with(&corrupt), "{}", read);
+        assert!(opened.starts_with(&corrupt), "{}", opened);
+        assert_eq!(streamed.kind(), std::io::ErrorKind::InvalidData);
+        assert!(streamed.to_string().starts_with(&format!("object {} (stored in ", cut)), "{}", streamed);
+        assert!(in_memory.starts_with(&format!("object {} is corrupt: ", garbage)), "{}", in_memory);
+        for (n, error) in mismatched.iter().enumerate() {
+            assert_eq!(*error, format!("object {:040} is corrupt: size doesn't match the content", n));
+        }
+        assert_eq!(exact, b"exactly\n");
+    }
+
+    #[test]
+    fn testing_in_memory_objects() {
+        let repo = std::sync::Arc::new(Repository::in_memory());
+        let writers: Vec<_> = (0..4)
+            .map(|n| {
+                let repo = repo.clone();
+                std::thread::spawn(move || repo.write_blob(format!("blob {}\n", n).as_bytes()).unwrap())
+            })
+            .collect();
+        let blobs: Vec<String> = writers.into_iter().map(|writer| writer.join().unwrap()).collect();
+        let entries: Vec<TreeEntry> = blobs
+            .iter()
+            .enumerate()
+            .map(|(n, sha)| TreeEntry {
+                mode: "100644".to_string(),
+                entry_type: EntryType::Blob,
+                sha: Blob::decode_hex(sha).unwrap().try_into().unwrap(),
+                name: format!("file{}", n),
+            })
+            .collect();
+        let tree = repo.write_tree(&entries).unwrap();
+        let parsed = Tree::try_pars(&Blob::from_sha(&repo, &tree).unwrap()).unwrap();
+        let mut streamed = String::new();
+        let mut object = repo.open_object(&blobs[2]).unwrap();
+        object.read_to_string(&mut streamed).unwrap();
+        let missing = Blob::from_sha(&repo, "0000000000000000000000000000000000000000").err().map(|e| e.kind());
+
+        assert_eq!(blobs[0], Blob::from_vec(b"blob 0\n".to_vec()).hash_string);
+        assert!(blobs.iter().all(|sha| repo.has_object(sha)));
+        assert_eq!(parsed.lookup("file3").map(|entry| Blob::string_hash(&entry.sha)), Some(blobs[3].clone()));
+        assert_eq!((object.object_type(), object.size(), streamed.as_str()), (ObjectType::Blob, 7, "blob 2\n"));
+        assert_eq!(missing, Some(std::io::ErrorKind::NotFound));
+        assert!(!Path::new("objects").join(&tree[..2]).join(&tree[2..]).exists());
+    }
+
+    #[test]
+    fn testing_follow_symlinks() {
+        let repo = Repository::in_memory();
+        let entry = |name: &str, mode: &str, sha: &str| TreeEntry {
+            mode: mode.to_string(),
+            entry_type: EntryType::from_mode(mode),
+            sha: Blob::decode_hex(sha).unwrap().try_into().unwrap(),
+            name: name.to_string(),
+        };
+        let file = repo.write_blob(b"file\n").unwrap();
+        let link = |target: &str| repo.write_blob(target.as_bytes()).unwrap();
+        let sub = repo.write_tree(&[entry("up", "120000", &link("../file")), entry("out", "120000", &link("../../x"))]).unwrap();
+        let tree = repo
+            .write_tree(&[
+                entry("file", "100644", &file),
+                entry("sub", "40000", &sub),
+                entry("to-sub", "120000", &link("sub")),
+                entry("chain", "120000", &link("to-sub/up")),
+                entry("absolute", "120000", &link("/etc/passwd")),
+                entry("loop", "120000", &link("loop")),
+            ])
+            .unwrap();
+        let follow = |path: &str| repo.resolve_path_following_symlinks(&tree, path);
+        let chain = follow("chain").unwrap();
+        let through = follow("to-sub/up").unwrap();
+        let unfollowed = repo.resolve_path(&tree, "chain").unwrap();
+        let errors: Vec<std::io::ErrorKind> =
+            ["sub/out", "absolute", "loop", "file/x", "missing"].iter().map(|path| follow(path).unwrap_err().kind()).collect();
+
+        assert_eq!(chain, ("100644".to_string(), file.clone()));
+        assert_eq!(through, ("100644".to_string(), file));
+        assert_eq!(unfollowed.0, "120000");
+        assert_eq!(
+            errors,
+            vec![
+                std::io::ErrorKind::InvalidInput,
+                std::io::ErrorKind::InvalidInput,
+                std::io::ErrorKind::InvalidInput,
+                std::io::ErrorKind::NotFound,
+                std::io::ErrorKind::NotFound,
+            ]
+        );
+    }
+
+    #[test]
+    fn testing_path_quoting() {
+        assert_eq!(quote_path("src/main.rs", true), "src/main.rs");
+        assert_eq!(quote_path("a b", true), "a b");
+        assert_eq!(quote_spaced_path("a b", true), "\"a b\"");
+        assert_eq!(quote_path("tab\there\n", true), "\"tab\\there\\n\"");
+        assert_eq!(quote_path("q\"b\\\x01\x7f", true), "\"q\\\"b\\\\\\001\\177\"");
+        assert_eq!(quote_path("caf\u{e9}", true), "\"caf\\303\\251\"");
+        assert_eq!(quote_path("caf\u{e9}", false), "caf\u{e9}");
+        assert_eq!(quote_path("caf\u{e9}\t", false), "\"caf\u{e9}\\t\"");
+    }
+
+    #[test]
+    fn testing_init_templates() {
+        let root = TempDir::new("init");
+        let template = root.join("template");
+        fs::create_dir_all(template.join("hooks")).unwrap();
+        fs::write(template.join("description"), "shared\n").unwrap();
+        fs::write(template.join(".hidden"), "").unwrap();
+        fs::write(template.join("hooks/pre-commit"), "#!/bin/sh\n").unwrap();
+        let mut permissions = fs::metadata(template.join("hooks/pre-commit")).unwrap().permissions();
+        std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+        fs::set_permissions(template.join("hooks/pre-commit"), permissions).unwrap();
+        init_repository(&root.join("templated"), Some(&template)).unwrap();
+        init_repository(&root.join("plain"), None).unwrap();
+        let read = |path: &str| fs::read_to_string(root.join(path)).unwrap();
+        let description = (read("templated/description"), read("plain/description"));
+        let excludes = (read("templated/info/exclude"), read("plain/info/exclude"));
+        let mode = fs::metadata(root.join("templated/hooks/pre-commit")).unwrap().permissions();
+        let hidden = root.join("templated/.hidden").exists();
+        let head = read("plain/HEAD");
+        let again = init_repository(&root.join("plain"), None).is_err();
+
+        assert_eq!(description, ("shared\n".to_string(), DESCRIPTION.to_string()));
+        assert_eq!(excludes, (EXCLUDE.to_string(), EXCLUDE.to_string()));
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777, 0o755);
+        assert!(!hidden);
+        assert_eq!(head, "ref: refs/heads/master\n");
+        assert!(again);
+    }
+
+    #[test]
+    fn testing_tree_writing() {
+        let dir = TempDir::new("write-tree");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let mut files = std::collections::BTreeMap::new();
+        for (path, sha) in &[
+            ("file_test.txt", "cd591dba9391e2cdfbae51a51800b9689c7ea360"),
+            ("whats_up.txt", "7108f7ecb345ee9d0084193f147cdad4d2998293"),
+            ("sub/file_test.txt", "cd591dba9391e2cdfbae51a51800b9689c7ea360"),
+            ("sub/whats_up.txt", "7108f7ecb345ee9d0084193f147cdad4d2998293"),
+        ] {
+            files.insert(path.to_string(), ("100644".to_string(), sha.to_string()));
+        }
+        let hashed = tree::hash_tree(&files).unwrap();
+        let nothing_written = fs::read_dir(dir.join("objects")).unwrap().count() == 0;
+        let sha = tree::write_tree(&repo, &files).unwrap();
+        let mut written = std::collections::BTreeMap::new();
+        tree::flatten(&repo, &sha, "", &mut written).unwrap();
+        // the subtree is the fixture tree with the same two files
+        assert!(dir.join("objects/51/912bb58e69f15db8f8b214bc97de8430cfdc02").exists());
+        assert_eq!(written, files);
+        assert_eq!(hashed, sha);
+        assert!(nothing_written);
+    }
+
+    #[test]
+    fn testing_replace_refs() {
+        let dir = TempDir::new("replace");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let blobs: Vec<String> = ["original\n", "first\n", "second\n"]
+            .iter()
+            .map(|content| {
+                let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+                hash::write_to_database(&repo, &blob).unwrap();
+                blob.hash_string
+            })
+            .collect();
+        refs::update(&repo, &format!("refs/replace/{}", blobs[0]), &blobs[1], None).unwrap();
+        refs::update(&repo, &format!("refs/replace/{}", blobs[1]), &blobs[2], None).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let replaced = Blob::from_sha(&repo, &blobs[0]).unwrap();
+        // a replacement cycle
+        refs::update(&repo, &format!("refs/replace/{}", blobs[2]), &blobs[0], None).unwrap();
+        let cycle = Blob::from_sha(&Repository::open(&dir).unwrap(), &blobs[0]).is_err();
+        let ignored = Blob::from_sha(&Repository::open(&dir).unwrap().without_replacements(), &blobs[0]).unwrap();
+
+        assert_eq!((replaced.content, replaced.hash_string), (b"second\n".to_vec(), blobs[0].clone()));
+        assert!(cycle);
+        assert_eq!(ignored.content, b"original\n");
+    }
+
+    #[test]
+    fn testing_object_streaming() {
+        let dir = TempDir::new("stream");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let blob = Blob::new(ObjectType::Blob, content.clone());
+        hash::write_to_database(&repo, &blob).unwrap();
+        let mut object = repo.open_object(&blob.hash_string).unwrap();
+        let header = (object.object_type(), object.size());
+        let mut streamed = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            match object.read(&mut chunk).unwrap() {
+                0 => break,
+                n => streamed.extend_from_slice(&chunk[..n]),
+            }
+        }
+        let missing = repo.open_object("0000000000000000000000000000000000000000").err().unwrap().kind();
+        // a packed commit, read whole
+        let name = "pack-19094bd7d5ac143343c5f48080d364e41504a1ca";
+        fs::create_dir_all(dir.join("objects/pack")).unwrap();
+        for extension in ["pack", "idx"] {
+            let file = format!("{}.{}", name, extension);
+            fs::copy(Path::new("tests/packed/objects/pack").join(&file), dir.join("objects/pack").join(&file)).unwrap();
+        }
+        let mut packed = repo.open_object("b2b505b0bb03d95f64aece6db662943223707080").unwrap();
+        let mut commit = Vec::new();
+        packed.read_to_end(&mut commit).unwrap();
+
+        assert_eq!(header, (ObjectType::Blob, content.len() as u64));
+        assert!(streamed == content);
+        assert_eq!(missing, std::io::ErrorKind::NotFound);
+        assert_eq!((packed.object_type(), packed.size()), (ObjectType::Commit, commit.len() as u64));
+        assert!(commit.starts_with(b"tree "));
+    }
+
+    #[test]
+    fn testing_path_resolution() {
+        let repo = Repository::in_memory();
+        let mut files = std::collections::BTreeMap::new();
+        for (path, mode, sha) in &[
+            ("sub/file_test.txt", "100644", "cd591dba9391e2cdfbae51a51800b9689c7ea360"),
+            ("run", "100755", "7108f7ecb345ee9d0084193f147cdad4d2998293"),
+        ] {
+            files.insert(path.to_string(), (mode.to_string(), sha.to_string()));
+        }
+        let root = tree::write_tree(&repo, &files).unwrap();
+        let resolved = (
+            repo.resolve_path(&root, "sub/file_test.txt").unwrap(),
+            repo.resolve_path(&root, "run").unwrap().0,
+            repo.resolve_path(&root, "sub/").unwrap().0,
+            repo.resolve_path(&root, "").unwrap().1,
+            repo.resolve_path(&root, "sub/missing").unwrap_err().kind(),
+            repo.resolve_path(&root, "run/file").unwrap_err().kind(),
+        );
+        assert_eq!(resolved.0, files["sub/file_test.txt"]);
+        assert_eq!((resolved.1.as_str(), resolved.2.as_str()), ("100755", "40000"));
+        assert_eq!(resolved.3, root);
+        assert_eq!(resolved.4, std::io::ErrorKind::NotFound);
+        assert_eq!(resolved.5, std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn testing_alternates_lookup() {
+        let root = TempDir::new("alternates");
+        let primary = root.join("primary/objects");
+        let shared = root.join("shared/objects");
+        fs::create_dir_all(primary.join("info")).unwrap();
+        fs::create_dir_all(shared.join("info")).unwrap();
+        fs::write(primary.join("info/alternates"), "# shared store\n../../shared/objects\n").unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/objects");
+        fs::write(shared.join("info/alternates"), format!("{}\n", fixtures.display())).unwrap();
+
+        assert_eq!(object_dirs(&primary).len(), 3);
+        let blob = Blob::from_sha_in(&primary, "7108f7ecb345ee9d0084193f147cdad4d2998293").unwrap();
+        assert_eq!(blob.content, b"what is up, doc?\n");
+        let missing = Blob::from_sha_in(&primary, "0000000000000000000000000000000000000000");
+        assert_eq!(missing.err().unwrap().kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn testing_raw_read_of_unknown_type() {
+        let objects = TempDir::new("raw");
+        let sha = "1234567890123456789012345678901234567890";
+        fs::create_dir_all(objects.join("12")).unwrap();
+        let mut z = ZlibEncoder::new(Vec::new(), Compression::fast());
+        z.write_all(b"bogus 3\0abc").unwrap();
+        fs::write(objects.join("12").join(&sha[2..]), z.finish().unwrap()).unwrap();
+
+        let err = Blob::from_sha_in(&objects, sha).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(Blob::read_from(&LooseObjects::new(&objects), sha).unwrap(), b"bogus 3\0abc");
+    }
+
+    #[test]
+    fn testing_repository_format_checks() {
+        let git_dir = TempDir::new("format");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        let open_with = |config: &str| {
+            fs::write(git_dir.join("config"), config).unwrap();
+            Repository::open(&git_dir).map(|_| ()).map_err(|e| e.to_string())
+        };
+        assert!(open_with("[core]\n\trepositoryformatversion = 0\n").is_ok());
+        assert!(open_with("[core]\n\trepositoryformatversion = 1\n[extensions]\n\tnoop = true\n").is_ok());
+        assert_eq!(
+            open_with("[core]\n\trepositoryformatversion = 2\n").unwrap_err(),
+            "unsupported repository version: 2"
+        );
+        assert_eq!(
+            open_with("[core]\n\trepositoryformatversion = 1\n[extensions]\n\tpartialClone = origin\n").unwrap_err(),
+            "unknown repository extension: partialclone"
+        );
+        assert_eq!(
+            open_with("[core]\n\trepositoryformatversion = 1\n[extensions]\n\tobjectFormat = sha256\n").unwrap_err(),
+            "unsupported object format: sha256"
+        );
+        // version 0 repositories predate extensions, git ignores them there
+        assert!(open_with("[extensions]\n\tpartialClone = origin\n").is_ok());
+        let precious = |config: &str| {
+            fs::write(git_dir.join("config"), config).unwrap();
+            Repository::open(&git_dir).unwrap().precious_objects().unwrap()
+        };
+        assert!(precious("[core]\n\trepositoryformatversion = 1\n[extensions]\n\tpreciousObjects = true\n"));
+        assert!(!precious("[core]\n\trepositoryformatversion = 1\n[extensions]\n\tpreciousObjects = false\n"));
+        assert!(!precious("[extensions]\n\tpreciousObjects = true\n"));
+    }
+}
@@ -0,0 +1,97 @@
+use super::commit::Commit;
+use super::identity::Identity;
+use super::*;
+use std::collections::BTreeMap;
+
+const NOTES: &str = "refs/notes/commits";
+
+// The notes commit and the files of its tree, each named after the object
+// it annotates, either whole or fanned out as `<ab>/<cdef...>` the way git
+// splits them once there are many notes.
+type Notes = (Option<String>, BTreeMap<String, (String, String)>);
+
+fn read(repo: &Repository) -> std::io::Result<Notes> {
+    match refs::resolve(repo, NOTES)? {
+        Some(commit) => {
+            let tree = Commit::read(repo, &commit)?.tree;
+            Ok((Some(commit), tree::files(repo, &tree)?))
+        }
+        None => Ok((None, BTreeMap::new())),
+    }
+}
+
+fn path_of(files: &BTreeMap<String, (String, String)>, object: &str) -> Option<String> {
+    files.keys().find(|path| path.replace('/', "") == object).cloned()
+}
+
+// the note attached to `object`, if any
+pub fn show(repo: &Repository, object: &str) -> std::io::Result<Option<Vec<u8>>> {
+    let (_, files) = read(repo)?;
+    match path_of(&files, object) {
+        Some(path) => Ok(Some(Blob::from_sha(repo, &files[&path].1)?.content)),
+        None => Ok(None),
+    }
+}
+
+// Attaches `message` to `object` with a new notes commit. An existing note is
+// only replaced with `force`.
+pub fn add(repo: &Repository, object: &str, message: String, force: bool) -> std::io::Result<()> {
+    let (parent, mut files) = read(repo)?;
+    if let Some(path) = path_of(&files, object) {
+        if !force {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "Cannot add notes. Found existing notes for object {}. Use '-f' to overwrite existing notes",
+                    object
+                ),
+            ));
+        }
+        eprintln!("Overwriting existing notes for object {}", object);
+        files.remove(&path);
+    }
+    let blob = Blob::new(ObjectType::Blob, message.into_bytes());
+    hash::write_to_database(repo, &blob)?;
+    files.insert(object.to_string(), ("100644".to_string(), blob.hash_string));
+    let commit = Commit {
+        tree: tree::write_tree(repo, &files)?,
+        parents: parent.into_iter().collect(),
+        author: Identity::from_env("AUTHOR", repo.config())?,
+        committer: Identity::from_env("COMMITTER", repo.config())?,
+        extra_headers: Vec::new(),
+        message: "Notes added by 'git notes add'\n".to_string(),
+    };
+    refs::update(repo, NOTES, &commit.write(repo)?, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_notes() {
+        let dir = TempDir::new("notes");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        fs::write(dir.join("config"), "[user]\n\tname = A\n\temail = a@b\n").unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let object = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        let fanned_out = "ce013625030ba8dba906f756967f9e9ca394464a";
+        add(&repo, object, "first\n".to_string(), false).unwrap();
+        let refused = add(&repo, object, "second\n".to_string(), false).is_err();
+        add(&repo, object, "second\n".to_string(), true).unwrap();
+        let history = Commit::read(&repo, &refs::resolve(&repo, NOTES).unwrap().unwrap()).unwrap();
+        // git's layout for many notes
+        let mut files = tree::files(&repo, &history.tree).unwrap();
+        let blob = Blob::new(ObjectType::Blob, b"fanned\n".to_vec());
+        hash::write_to_database(&repo, &blob).unwrap();
+        files.insert(format!("{}/{}", &fanned_out[..2], &fanned_out[2..]), ("100644".to_string(), blob.hash_string));
+        let tree = tree::write_tree(&repo, &files).unwrap();
+        let commit = Commit { tree, parents: vec![], extra_headers: Vec::new(), ..history };
+        refs::update(&repo, NOTES, &commit.write(&repo).unwrap(), None).unwrap();
+        let notes = (show(&repo, object).unwrap(), show(&repo, fanned_out).unwrap(), show(&repo, &"0".repeat(40)).unwrap());
+
+        assert!(refused);
+        assert_eq!(history.parents.len(), 1);
+        assert_eq!(notes, (Some(b"second\n".to_vec()), Some(b"fanned\n".to_vec()), None));
+    }
+}
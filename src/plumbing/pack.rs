@@ -0,0 +1,1605 @@
+use super::*;
+
+const INDEX_SIGNATURE: &[u8] = b"\xfftOc";
+const INDEX_VERSION: u32 = 2;
+const PACK_SIGNATURE: &[u8] = b"PACK";
+const MIDX_SIGNATURE: &[u8] = b"MIDX";
+const REVERSE_INDEX_SIGNATURE: &[u8] = b"RIDX";
+const MIDX_PACK_NAMES: &[u8] = b"PNAM";
+const MIDX_OID_FANOUT: &[u8] = b"OIDF";
+const MIDX_OID_LOOKUP: &[u8] = b"OIDL";
+const MIDX_OBJECT_OFFSETS: &[u8] = b"OOFF";
+const MIDX_LARGE_OFFSETS: &[u8] = b"LOFF";
+// the entry types of a pack besides the four object types
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+// 32-bit offsets with this bit set index the table of 64-bit ones
+const LARGE_OFFSET: u32 = 0x8000_0000;
+// a longer chain of deltas can only be a cycle in a corrupt pack
+const MAX_DELTA_CHAIN: usize = 10_000;
+
+fn corrupt(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("corrupt pack: {}", what))
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+// The object type of the pack entry type `code`, `None` for deltas and the
+// reserved codes.
+fn object_type(code: u8) -> Option<ObjectType> {
+    match code {
+        1 => Some(ObjectType::Commit),
+        2 => Some(ObjectType::Tree),
+        3 => Some(ObjectType::Blob),
+        4 => Some(ObjectType::Tag),
+        _ => None,
+    }
+}
+
+// The ids of a table sorted by id with a 256 entry fan-out before it, as in
+// pack indexes and the multi-pack-index: `fanout[b]` counts the ids whose
+// first byte is at most `b`.
+struct Lookup {
+    fanout: usize,
+    ids: usize,
+    count: usize,
+}
+
+impl Lookup {
+    // the range of positions whose ids start with the byte `first`
+    fn range(&self, data: &[u8], first: u8) -> std::ops::Range<usize> {
+        let start = match first {
+            0 => 0,
+            _ => u32_at(data, self.fanout + (first as usize - 1) * 4) as usize,
+        };
+        let end = u32_at(data, self.fanout + first as usize * 4) as usize;
+        start.min(self.count)..end.min(self.count)
+    }
+
+    fn id<'a>(&self, data: &'a [u8], position: usize) -> &'a [u8] {
+        &data[self.ids + position * 20..self.ids + position * 20 + 20]
+    }
+
+    fn position(&self, data: &[u8], id: &[u8]) -> Option<usize> {
+        let range = self.range(data, *id.first()?);
+        let (mut low, mut high) = (range.start, range.end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.id(data, middle).cmp(id) {
+                std::cmp::Ordering::Equal => return Some(middle),
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+            }
+        }
+        None
+    }
+}
+
+// A version 2 `.idx` file: the ids of the objects in a pack, sorted, with
+// the offset of each one's entry in the `.pack`.
+pub struct PackIndex {
+    data: Vec<u8>,
+    lookup: Lookup,
+    offsets: usize,
+    large_offsets: usize,
+}
+
+impl PackIndex {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Self::parse(fs::read(path)?)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(data: Vec<u8>) -> std::io::Result<Self> {
+        let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("bad pack index: {}", what));
+        if data.len() < 8 + 256 * 4 || &data[..4] != INDEX_SIGNATURE {
+            return Err(invalid("bad signature"));
+        }
+        if u32_at(&data, 4) != INDEX_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let count = u32_at(&data, 8 + 255 * 4) as usize;
+        let ids = 8 + 256 * 4;
+        // the ids, their CRC32s and the 32-bit offsets, then the pack's and the
+        // index's checksums
+        let offsets = ids + count * 24;
+        let large_offsets = offsets + count * 4;
+        if data.len() < large_offsets + 40 {
+            return Err(invalid("truncated"));
+        }
+        Ok(PackIndex { data, lookup: Lookup { fanout: 8, ids, count }, offsets, large_offsets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.lookup.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lookup.count == 0
+    }
+
+    // the id of the object at `position` in id order
+    pub fn id(&self, position: usize) -> &[u8] {
+        self.lookup.id(&self.data, position)
+    }
+
+    // where in the pack the entry of the object at `position` starts
+    pub fn offset(&self, position: usize) -> std::io::Result<u64> {
+        let offset = u32_at(&self.data, self.offsets + position * 4);
+        if offset & LARGE_OFFSET == 0 {
+            return Ok(offset as u64);
+        }
+        let at = self.large_offsets + (offset & !LARGE_OFFSET) as usize * 8;
+        match at + 8 + 40 <= self.data.len() {
+            true => Ok(u64_at(&self.data, at)),
+            false => Err(corrupt("large offset out of bounds")),
+        }
+    }
+
+    // the offset of the object `id`'s entry, `None` when the pack doesn't have it
+    pub fn find(&self, id: &[u8]) -> std::io::Result<Option<u64>> {
+        match self.lookup.position(&self.data, id) {
+            Some(position) => self.offset(position).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // the checksum of the pack the index is of, which ends the pack too
+    pub fn pack_checksum(&self) -> &[u8] {
+        &self.data[self.data.len() - 40..self.data.len() - 20]
+    }
+
+    // the ids of the objects whose id starts with the byte `first`, in order
+    pub fn ids_starting_with(&self, first: u8) -> impl Iterator<Item = String> + '_ {
+        self.lookup.range(&self.data, first).map(move |position| Blob::string_hash(self.id(position)))
+    }
+}
+
+// The entries of a pack in the order they are in the pack: for each one, the
+// position of its object in the index. Read from the `.rev` file git
+// writes next to the pack when there is one, built by sorting the index's
+// offsets otherwise.
+pub struct ReverseIndex {
+    positions: Vec<u32>,
+    // the offset of each entry, sorted
+    offsets: Vec<u64>,
+}
+
+impl ReverseIndex {
+    // the reverse index of `index`, read from `path` when that is its `.rev`
+    pub fn open(index: &PackIndex, path: &Path) -> std::io::Result<Self> {
+        let positions = match fs::read(path) {
+            Ok(data) => Self::parse(index, &data)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut positions: Vec<u32> = (0..index.len() as u32).collect();
+                let offsets = (0..index.len()).map(|position| index.offset(position)).collect::<std::io::Result<Vec<_>>>()?;
+                positions.sort_by_key(|&position| offsets[position as usize]);
+                positions
+            }
+            Err(e) => return Err(e),
+        };
+        let offsets = positions.iter().map(|&position| index.offset(position as usize)).collect::<std::io::Result<Vec<_>>>()?;
+        if offsets.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(corrupt("reverse index out of order"));
+        }
+        Ok(ReverseIndex { positions, offsets })
+    }
+
+    // `RIDX`, version 1 and hash version 1, the positions, then the checksums
+    // of the pack and of the file
+    fn parse(index: &PackIndex, data: &[u8]) -> std::io::Result<Vec<u32>> {
+        let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("bad reverse index: {}", what));
+        if data.len() < 12 || &data[..4] != REVERSE_INDEX_SIGNATURE || u32_at(data, 4) != 1 || u32_at(data, 8) != 1 {
+            return Err(invalid("bad signature or version"));
+        }
+        if data.len() != 12 + index.len() * 4 + 40 {
+            return Err(invalid("size doesn't match the index"));
+        }
+        if &data[data.len() - 40..data.len() - 20] != index.pack_checksum() {
+            return Err(invalid("made for another pack"));
+        }
+        let positions: Vec<u32> = (0..index.len()).map(|i| u32_at(data, 12 + i * 4)).collect();
+        match positions.iter().all(|&position| (position as usize) < index.len()) {
+            true => Ok(positions),
+            false => Err(invalid("position out of range")),
+        }
+    }
+
+    // the position in the index of the object whose entry starts at `offset`
+    pub fn position(&self, offset: u64) -> Option<usize> {
+        let found = self.offsets.binary_search(&offset).ok()?;
+        Some(self.positions[found] as usize)
+    }
+
+    // where the entry after the one at `offset` starts, `None` for the last one
+    pub fn next_offset(&self, offset: u64) -> Option<u64> {
+        let found = self.offsets.binary_search(&offset).ok()?;
+        self.offsets.get(found + 1).copied()
+    }
+}
+
+// An `objects/pack/multi-pack-index`: the objects of several packs in one
+// table, each with the pack it is taken from and its offset there.
+pub struct MultiPackIndex {
+    data: Vec<u8>,
+    // the `.idx` files of the packs, in the order the table numbers them
+    packs: Vec<String>,
+    lookup: Lookup,
+    offsets: usize,
+    large_offsets: Option<usize>,
+}
+
+impl MultiPackIndex {
+    // The multi-pack-index of the pack directory `pack_dir`, `None` when it
+    // has none.
+    pub fn open(pack_dir: &Path) -> std::io::Result<Option<Self>> {
+        match fs::read(pack_dir.join("multi-pack-index")) {
+            Ok(data) => Self::parse(data).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(data: Vec<u8>) -> std::io::Result<Self> {
+        let invalid = |what: &str| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("corrupt multi-pack-index: {}", what))
+        };
+        if data.len() < 12 || &data[..4] != MIDX_SIGNATURE {
+            return Err(invalid("bad signature"));
+        }
+        if data[4] != 1 || data[5] != 1 {
+            return Err(invalid("unsupported version or hash"));
+        }
+        let chunks = data[6] as usize;
+        let pack_count = u32_at(&data, 8) as usize;
+        if data.len() < 12 + (chunks + 1) * 12 {
+            return Err(invalid("truncated chunk table"));
+        }
+        // each chunk runs up to the offset of the next table entry
+        let mut offsets = std::collections::HashMap::new();
+        for i in 0..chunks {
+            let entry = 12 + i * 12;
+            let (offset, end) = (u64_at(&data, entry + 4) as usize, u64_at(&data, entry + 16) as usize);
+            if offset > end || end > data.len() {
+                return Err(invalid("chunk out of bounds"));
+            }
+            offsets.insert(data[entry..entry + 4].to_vec(), (offset, end - offset));
+        }
+        let chunk = |id: &[u8]| offsets.get(id).copied();
+        let (names, names_size) = chunk(MIDX_PACK_NAMES).ok_or_else(|| invalid("missing pack names"))?;
+        let (fanout, fanout_size) = chunk(MIDX_OID_FANOUT).ok_or_else(|| invalid("missing OID fanout"))?;
+        let (ids, ids_size) = chunk(MIDX_OID_LOOKUP).ok_or_else(|| invalid("missing OID lookup"))?;
+        let (object_offsets, offsets_size) = chunk(MIDX_OBJECT_OFFSETS).ok_or_else(|| invalid("missing object offsets"))?;
+        if fanout_size != 256 * 4 {
+            return Err(invalid("bad OID fanout size"));
+        }
+        let count = u32_at(&data, fanout + 255 * 4) as usize;
+        if ids_size != count * 20 || offsets_size != count * 8 {
+            return Err(invalid("chunk sizes don't match the object count"));
+        }
+        // the names are NUL terminated, padded with more NULs to a multiple of 4
+        let packs: Vec<String> = data[names..names + names_size]
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+        if packs.len() != pack_count {
+            return Err(invalid("pack names don't match the pack count"));
+        }
+        let large_offsets = chunk(MIDX_LARGE_OFFSETS).map(|(offset, _)| offset);
+        Ok(MultiPackIndex { data, packs, lookup: Lookup { fanout, ids, count }, offsets: object_offsets, large_offsets })
+    }
+
+    // the `.idx` file names of the packs the index covers
+    pub fn packs(&self) -> &[String] {
+        &self.packs
+    }
+
+    pub fn len(&self) -> usize {
+        self.lookup.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lookup.count == 0
+    }
+
+    // The pack, by its number in `packs`, the object `id` is taken from and
+    // its offset there.
+    pub fn find(&self, id: &[u8]) -> std::io::Result<Option<(usize, u64)>> {
+        let position = match self.lookup.position(&self.data, id) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let at = self.offsets + position * 8;
+        let pack = u32_at(&self.data, at) as usize;
+        let offset = u32_at(&self.data, at + 4);
+        if pack >= self.packs.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt multi-pack-index: bad pack number"));
+        }
+        if offset & LARGE_OFFSET == 0 {
+            return Ok(Some((pack, offset as u64)));
+        }
+        let at = self.large_offsets.map(|large| large + (offset & !LARGE_OFFSET) as usize * 8);
+        match at.filter(|&at| at + 8 <= self.data.len()) {
+            Some(at) => Ok(Some((pack, u64_at(&self.data, at)))),
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt multi-pack-index: bad large offset")),
+        }
+    }
+}
+
+// Applies the delta `delta` to `base`: the sizes of the base and of the
+// result, then instructions copying ranges of the base or inserting the
+// bytes that follow them.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> std::io::Result<Vec<u8>> {
+    let bad = || corrupt("bad delta");
+    let mut at = 0;
+    let mut size = || -> std::io::Result<usize> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(at).ok_or_else(bad)?;
+            at += 1;
+            size |= ((byte & 0x7f) as usize).checked_shl(shift).ok_or_else(bad)?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+    let (base_size, result_size) = (size()?, size()?);
+    if base_size != base.len() {
+        return Err(corrupt("delta base size doesn't match"));
+    }
+    let mut result = Vec::with_capacity(result_size);
+    while at < delta.len() {
+        let instruction = delta[at];
+        at += 1;
+        if instruction & 0x80 == 0 {
+            // 0 is reserved, anything else inserts that many bytes
+            let end = at + instruction as usize;
+            if instruction == 0 || end > delta.len() {
+                return Err(bad());
+            }
+            result.extend_from_slice(&delta[at..end]);
+            at = end;
+            continue;
+        }
+        // the bits 0-3 say which offset bytes follow, 4-6 which size bytes
+        let mut fields = [0usize; 2];
+        for (bit, field) in (0..7).map(|bit| (bit, if bit < 4 { 0 } else { 1 })) {
+            if instruction & (1 << bit) != 0 {
+                let byte = *delta.get(at).ok_or_else(bad)? as usize;
+                at += 1;
+                fields[field] |= byte << (8 * (bit % 4));
+            }
+        }
+        let (offset, length) = (fields[0], if fields[1] == 0 { 0x10000 } else { fields[1] });
+        let copied = base.get(offset..offset + length).ok_or_else(bad)?;
+        result.extend_from_slice(copied);
+    }
+    if result.len() != result_size {
+        return Err(corrupt("delta result size doesn't match"));
+    }
+    Ok(result)
+}
+
+// git's default `core.deltaBaseCacheLimit`
+pub const DEFAULT_DELTA_BASE_CACHE_LIMIT: u64 = 96 << 20;
+
+// The objects last rebuilt as bases of deltas, by the pack they're in (its
+// number among the packs sharing the cache) and the offset of their entry,
+// so a chain of deltas doesn't rebuild its bases once for each delta. It
+// holds at most `limit` bytes, dropping the least recently used first.
+pub struct DeltaBaseCache {
+    limit: u64,
+    size: u64,
+    // counts the uses, to tell which was last
+    clock: u64,
+    // each object with when it was last used, the keys by when that was
+    objects: std::collections::HashMap<(usize, u64), (ObjectType, Vec<u8>, u64)>,
+    uses: std::collections::BTreeMap<u64, (usize, u64)>,
+}
+
+impl DeltaBaseCache {
+    pub fn new(limit: u64) -> Self {
+        DeltaBaseCache { limit, size: 0, clock: 0, objects: Default::default(), uses: Default::default() }
+    }
+
+    fn get(&mut self, key: (usize, u64)) -> Option<(ObjectType, Vec<u8>)> {
+        self.clock += 1;
+        let clock = self.clock;
+        let (object_type, content, used) = self.objects.get_mut(&key)?;
+        self.uses.remove(used);
+        self.uses.insert(clock, key);
+        *used = clock;
+        Some((*object_type, content.clone()))
+    }
+
+    fn insert(&mut self, key: (usize, u64), object_type: ObjectType, content: &[u8]) {
+        let size = content.len() as u64;
+        if size > self.limit || self.objects.contains_key(&key) {
+            return;
+        }
+        while self.size + size > self.limit {
+            let (&used, &oldest) = match self.uses.iter().next() {
+                Some(first) => first,
+                None => break,
+            };
+            self.uses.remove(&used);
+            let (_, dropped, _) = self.objects.remove(&oldest).unwrap();
+            self.size -= dropped.len() as u64;
+        }
+        self.clock += 1;
+        self.uses.insert(self.clock, key);
+        self.objects.insert(key, (object_type, content.to_vec(), self.clock));
+        self.size += size;
+    }
+}
+
+// the `size` bytes `deflated` inflates to
+fn inflate<R: Read>(deflated: R, size: u64) -> std::io::Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size as usize);
+    ZlibDecoder::new(deflated).take(size + 1).read_to_end(&mut content)?;
+    match content.len() as u64 == size {
+        true => Ok(content),
+        false => Err(corrupt("entry size doesn't match its content")),
+    }
+}
+
+// An entry of a pack: a whole object, or a delta on a base found at an
+// earlier offset or by its id.
+enum Entry {
+    Whole(ObjectType, Vec<u8>),
+    OffsetDelta(u64, Vec<u8>),
+    RefDelta(Vec<u8>, Vec<u8>),
+}
+
+// A `.pack` file, read at the offsets asked for instead of all at once, so
+// only the parts of a large pack that are used are ever read. memmap2 isn't
+// among the dependencies; positional reads get the same effect where the
+// platform has them, and seeking a shared handle is the fallback elsewhere.
+struct PackFile {
+    #[cfg(any(unix, windows))]
+    file: fs::File,
+    #[cfg(not(any(unix, windows)))]
+    file: std::sync::Mutex<fs::File>,
+    len: u64,
+}
+
+impl PackFile {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        #[cfg(not(any(unix, windows)))]
+        let file = std::sync::Mutex::new(file);
+        Ok(PackFile { file, len })
+    }
+
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(&self.file, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(&self.file, buf, offset)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        file.read(buf)
+    }
+}
+
+// Reads a pack from `offset` on, to its end.
+struct PackReader<'a> {
+    file: &'a PackFile,
+    offset: u64,
+}
+
+impl Read for PackReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.file.read_at(buf, self.offset)?;
+        self.offset += count as u64;
+        Ok(count)
+    }
+}
+
+// A `.pack` file with its `.idx`.
+pub struct Pack {
+    path: PathBuf,
+    index: PackIndex,
+    file: PackFile,
+    // the cache of delta bases, which other packs may share, and the
+    // pack's number in it
+    cache: std::sync::Arc<std::sync::Mutex<DeltaBaseCache>>,
+    number: usize,
+    reverse_index: std::sync::Mutex<Option<std::sync::Arc<ReverseIndex>>>,
+}
+
+impl Pack {
+    // Opens the pack whose index is `index_path`, the `.pack` next to it.
+    pub fn open(index_path: &Path) -> std::io::Result<Self> {
+        let index = PackIndex::open(index_path)?;
+        let path = index_path.with_extension("pack");
+        let file = PackFile::open(&path)?;
+        let mut header = [0; 8];
+        let read = match file.len >= 12 + 20 {
+            true => PackReader { file: &file, offset: 0 }.read_exact(&mut header),
+            false => Err(std::io::ErrorKind::UnexpectedEof.into()),
+        };
+        if read.is_err() || &header[..4] != PACK_SIGNATURE || !matches!(u32_at(&header, 4), 2 | 3) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a version 2 or 3 pack", path.display()),
+            ));
+        }
+        let cache = std::sync::Arc::new(std::sync::Mutex::new(DeltaBaseCache::new(DEFAULT_DELTA_BASE_CACHE_LIMIT)));
+        Ok(Pack { path, index, file, cache, number: 0, reverse_index: Default::default() })
+    }
+
+    // the pack's reverse index, read or built the first time it is needed
+    pub fn reverse_index(&self) -> std::io::Result<std::sync::Arc<ReverseIndex>> {
+        let mut reverse_index = self.reverse_index.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if reverse_index.is_none() {
+            let opened = ReverseIndex::open(&self.index, &self.path.with_extension("rev"))?;
+            *reverse_index = Some(std::sync::Arc::new(opened));
+        }
+        Ok(reverse_index.as_ref().unwrap().clone())
+    }
+
+    // The id of the object whose entry starts at `offset`, through the reverse
+    // index, `None` when no entry starts there.
+    pub fn id_at(&self, offset: u64) -> std::io::Result<Option<String>> {
+        let position = self.reverse_index()?.position(offset);
+        Ok(position.map(|position| Blob::string_hash(self.index.id(position))))
+    }
+
+    // The id of the base of the delta at `offset`, `None` when it isn't a delta.
+    // The base of an offset delta is found by its offset in the reverse index.
+    pub fn delta_base(&self, offset: u64) -> std::io::Result<Option<String>> {
+        match self.entry(offset)? {
+            Entry::Whole(..) => Ok(None),
+            Entry::RefDelta(base, _) => Ok(Some(Blob::string_hash(&base))),
+            Entry::OffsetDelta(base, _) => match self.id_at(base)? {
+                Some(id) => Ok(Some(id)),
+                None => Err(corrupt("no entry at a delta base offset")),
+            },
+        }
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, DeltaBaseCache> {
+        // the cache is never left half updated, so a panic elsewhere doesn't spoil it
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    // The entry at `offset`: a header with the type and the inflated size, the
+    // base of a delta, then the zlib deflated object or delta.
+    fn entry(&self, offset: u64) -> std::io::Result<Entry> {
+        // the trailing checksum is no entry's
+        if offset >= self.file.len.saturating_sub(20) {
+            return Err(corrupt("entry out of bounds"));
+        }
+        let mut reader = std::io::BufReader::new(PackReader { file: &self.file, offset });
+        let mut byte = || -> std::io::Result<u8> {
+            let mut byte = [0];
+            reader.read_exact(&mut byte).map_err(|_| corrupt("entry out of bounds"))?;
+            Ok(byte[0])
+        };
+        let first = byte()?;
+        let code = (first >> 4) & 7;
+        let mut size = (first & 0x0f) as u64;
+        let mut shift = 4;
+        let mut last = first;
+        while last & 0x80 != 0 {
+            last = byte()?;
+            size |= ((last & 0x7f) as u64).checked_shl(shift).ok_or_else(|| corrupt("entry size too large"))?;
+            shift += 7;
+        }
+        let base = match code {
+            // the distance back to the base, in a varint with a bias per byte
+            OFS_DELTA => {
+                let mut last = byte()?;
+                let mut distance = (last & 0x7f) as u64;
+                while last & 0x80 != 0 {
+                    last = byte()?;
+                    distance = ((distance + 1) << 7) | (last & 0x7f) as u64;
+                }
+                match offset.checked_sub(distance).filter(|_| distance > 0) {
+                    Some(base) => Some(base),
+                    None => return Err(corrupt("delta base offset out of range")),
+                }
+            }
+            _ => None,
+        };
+        let mut base_id = Vec::new();
+        if code == REF_DELTA {
+            for _ in 0..20 {
+                base_id.push(byte()?);
+            }
+        }
+        let content = inflate(reader, size)?;
+        match (code, base) {
+            (OFS_DELTA, Some(base)) => Ok(Entry::OffsetDelta(base, content)),
+            (REF_DELTA, _) => Ok(Entry::RefDelta(base_id, content)),
+            _ => match object_type(code) {
+                Some(object_type) => Ok(Entry::Whole(object_type, content)),
+                None => Err(corrupt(&format!("unknown entry type {}", code))),
+            },
+        }
+    }
+
+    // The object whose entry is at `offset`, its deltas applied. The bases of
+    // ref deltas are looked up in the pack, then with `base` elsewhere.
+    pub fn read_at<F>(&self, offset: u64, base: F) -> std::io::Result<(ObjectType, Vec<u8>)>
+    where
+        F: Fn(&[u8]) -> std::io::Result<Option<(ObjectType, Vec<u8>)>>,
+    {
+        // the deltas from the object down to its base, with the offsets of
+        // their entries, applied from the base up
+        let mut deltas = Vec::new();
+        let mut offset = offset;
+        let (object_type, mut content) = loop {
+            if deltas.len() > MAX_DELTA_CHAIN {
+                return Err(corrupt("delta chain too long"));
+            }
+            if !deltas.is_empty() {
+                if let Some(cached) = self.cache().get((self.number, offset)) {
+                    break cached;
+                }
+            }
+            match self.entry(offset)? {
+                Entry::Whole(object_type, content) => {
+                    if !deltas.is_empty() {
+                        self.cache().insert((self.number, offset), object_type, &content);
+                    }
+                    break (object_type, content);
+                }
+                Entry::OffsetDelta(base, delta) => {
+                    deltas.push((offset, delta));
+                    offset = base;
+                }
+                Entry::RefDelta(base_id, delta) => {
+                    deltas.push((offset, delta));
+                    if let Some(base) = self.index.find(&base_id)? {
+                        offset = base;
+                        continue;
+                    }
+                    match base(&base_id)? {
+                        Some(found) => break found,
+                        None => return Err(corrupt(&format!("missing delta base {}", Blob::string_hash(&base_id)))),
+                    }
+                }
+            }
+        };
+        for (i, (offset, delta)) in deltas.iter().enumerate().rev() {
+            content = apply_delta(&content, delta)?;
+            // all but the object asked for were the bases of the deltas above them
+            if i > 0 {
+                self.cache().insert((self.number, *offset), object_type, &content);
+            }
+        }
+        Ok((object_type, content))
+    }
+
+    // Checks the checksum ending the pack, and that it is the one the index
+    // was made for.
+    pub fn verify_checksum(&self) -> std::io::Result<()> {
+        let body = self.file.len - 20;
+        let mut hasher = sha1::Sha1::new();
+        std::io::copy(&mut PackReader { file: &self.file, offset: 0 }.take(body), &mut hasher)?;
+        let mut checksum = [0; 20];
+        PackReader { file: &self.file, offset: body }.read_exact(&mut checksum)?;
+        if hasher.finalize()[..] != checksum || checksum[..] != *self.index.pack_checksum() {
+            return Err(corrupt("checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    // The entries of the pack in the order they're in, as `verify-pack -v`
+    // walks them. Each object is read whole and checked against its id.
+    pub fn entries(&self) -> std::io::Result<Vec<PackEntry>> {
+        let reverse_index = self.reverse_index()?;
+        let mut entries: Vec<PackEntry> = Vec::with_capacity(reverse_index.offsets.len());
+        // the entry of each base's offset, and the offset of each delta's base
+        let mut at_offset = std::collections::HashMap::new();
+        let mut bases = Vec::with_capacity(reverse_index.offsets.len());
+        for (i, &offset) in reverse_index.offsets.iter().enumerate() {
+            let id = self.index.id(reverse_index.positions[i] as usize);
+            let (size, base) = match self.entry(offset)? {
+                Entry::Whole(_, content) => (content.len(), None),
+                Entry::OffsetDelta(base, delta) => (delta.len(), Some(base)),
+                Entry::RefDelta(base_id, delta) => match self.index.find(&base_id)? {
+                    Some(base) => (delta.len(), Some(base)),
+                    None => return Err(corrupt(&format!("missing delta base {}", Blob::string_hash(&base_id)))),
+                },
+            };
+            let (object_type, content) = self.read_at(offset, |_| Ok(None))?;
+            if Blob::hash(&Blob::header(object_type, &content), &content)[..] != *id {
+                return Err(corrupt(&format!("object {} doesn't match its content", Blob::string_hash(id))));
+            }
+            let next = reverse_index.next_offset(offset).unwrap_or(self.file.len - 20);
+            at_offset.insert(offset, i);
+            bases.push(base);
+            entries.push(PackEntry {
+                id: Blob::string_hash(id),
+                object_type,
+                size: size as u64,
+                object_size: content.len() as u64,
+                packed_size: next - offset,
+                offset,
+                depth: 0,
+                base: None,
+            });
+        }
+        // a delta is one deeper than its base, which a ref delta may come before
+        for i in 0..entries.len() {
+            let mut chain = Vec::new();
+            let mut at = i;
+            while let Some(base) = bases[at] {
+                let base = *at_offset.get(&base).ok_or_else(|| corrupt("no entry at a delta base offset"))?;
+                chain.push(at);
+                if chain.len() > MAX_DELTA_CHAIN {
+                    return Err(corrupt("delta chain too long"));
+                }
+                at = base;
+                if entries[at].depth > 0 {
+                    break;
+                }
+            }
+            let mut depth = entries[at].depth;
+            for &delta in chain.iter().rev() {
+                depth += 1;
+                entries[delta].depth = depth;
+                entries[delta].base = Some(entries[at_offset[&bases[delta].unwrap()]].id.clone());
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn stats(&self) -> std::io::Result<PackStats> {
+        Ok(PackStats::of(&self.entries()?))
+    }
+}
+
+// An entry of a pack: its object, with its type and size, the size of what
+// the entry inflates to (the delta, for a delta), the bytes it takes in the
+// pack and where, and for a delta the length of its chain and its base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackEntry {
+    pub id: String,
+    pub object_type: ObjectType,
+    pub object_size: u64,
+    pub size: u64,
+    pub packed_size: u64,
+    pub offset: u64,
+    pub depth: usize,
+    pub base: Option<String>,
+}
+
+// What `verify-pack -s` sums up about a pack: how many objects of each type
+// it holds, their sizes and what their entries take, how long the delta
+// chains get and how many objects have chains of each length, 0 for those
+// that aren't deltas.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PackStats {
+    pub objects: usize,
+    pub counts: std::collections::BTreeMap<&'static str, usize>,
+    pub size: u64,
+    pub packed_size: u64,
+    pub max_depth: usize,
+    pub chain_lengths: std::collections::BTreeMap<usize, usize>,
+}
+
+impl PackStats {
+    pub fn of(entries: &[PackEntry]) -> Self {
+        let mut stats = PackStats { objects: entries.len(), ..Default::default() };
+        for entry in entries {
+            *stats.counts.entry(entry.object_type.as_str()).or_insert(0) += 1;
+            *stats.chain_lengths.entry(entry.depth).or_insert(0) += 1;
+            stats.size += entry.object_size;
+            stats.packed_size += entry.packed_size;
+            stats.max_depth = stats.max_depth.max(entry.depth);
+        }
+        stats
+    }
+}
+
+// the number of an object type in a pack entry header
+fn type_code(object_type: ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Commit => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+        ObjectType::Tag => 4,
+    }
+}
+
+// Writes a version 2 pack holding the objects `ids` of `repo`, each one whole
+// and as stored, to `out`, and returns its checksum, which ends the pack.
+pub fn write<W: Write>(repo: &Repository, ids: &[String], out: W) -> std::io::Result<Vec<u8>> {
+    let mut out = HashingWriter { out, hasher: sha1::Sha1::new() };
+    out.write_all(PACK_SIGNATURE)?;
+    out.write_all(&2u32.to_be_bytes())?;
+    out.write_all(&(ids.len() as u32).to_be_bytes())?;
+    for id in ids {
+        let object = Blob::from_store(repo.objects(), id)?;
+        // the type and the size, 4 bits of it in the first byte and 7 in the others
+        let mut size = object.content.len() as u64;
+        let mut header = vec![(type_code(object.object_type()) << 4) | (size & 0x0f) as u8];
+        size >>= 4;
+        while size > 0 {
+            *header.last_mut().unwrap() |= 0x80;
+            header.push((size & 0x7f) as u8);
+            size >>= 7;
+        }
+        out.write_all(&header)?;
+        let mut z = ZlibEncoder::new(&mut out, Compression::default());
+        z.write_all(&object.content)?;
+        z.finish()?;
+    }
+    let checksum = out.hasher.finalize().to_vec();
+    out.out.write_all(&checksum)?;
+    Ok(checksum)
+}
+
+// A writer hashing what goes through it, for the checksum ending a pack.
+struct HashingWriter<W: Write> {
+    out: W,
+    hasher: sha1::Sha1,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// An object of a pack being indexed: where its entry starts, the CRC32 of
+// the entry and, once it is known, its id.
+pub struct IndexedObject {
+    pub id: [u8; 20],
+    pub offset: u64,
+    pub crc32: u32,
+}
+
+// What an entry holds until the delta it may be is resolved.
+#[derive(Clone, Copy)]
+enum Parsed {
+    Whole(ObjectType),
+    OffsetDelta(usize),
+    RefDelta([u8; 20]),
+}
+
+// Reads the pack `data` through and finds the id of each object in it,
+// applying deltas, as index-pack does. Every delta's base has to be in the
+// pack itself. Returns the objects in the order of the pack and its checksum.
+pub fn index(data: &[u8]) -> std::io::Result<(Vec<IndexedObject>, Vec<u8>)> {
+    if data.len() < 12 + 20 || &data[..4] != PACK_SIGNATURE || !matches!(u32_at(data, 4), 2 | 3) {
+        return Err(corrupt("not a version 2 or 3 pack"));
+    }
+    let body = data.len() - 20;
+    if sha1::Sha1::digest(&data[..body])[..] != data[body..] {
+        return Err(corrupt("checksum mismatch"));
+    }
+    let count = u32_at(data, 8) as usize;
+    let mut offsets = std::collections::HashMap::new();
+    // each entry's start, CRC32, what it is and its inflated content
+    let mut entries = Vec::with_capacity(count);
+    let mut at = 12;
+    for _ in 0..count {
+        let start = at;
+        let mut byte = || -> std::io::Result<u8> {
+            let byte = *data[..body].get(at).ok_or_else(|| corrupt("entry out of bounds"))?;
+            at += 1;
+            Ok(byte)
+        };
+        let mut last = byte()?;
+        let code = (last >> 4) & 7;
+        let mut size = (last & 0x0f) as u64;
+        let mut shift = 4;
+        while last & 0x80 != 0 {
+            last = byte()?;
+            size |= ((last & 0x7f) as u64).checked_shl(shift).ok_or_else(|| corrupt("entry size too large"))?;
+            shift += 7;
+        }
+        let parsed = match code {
+            OFS_DELTA => {
+                let mut last = byte()?;
+                let mut distance = (last & 0x7f) as u64;
+                while last & 0x80 != 0 {
+                    last = byte()?;
+                    distance = ((distance + 1) << 7) | (last & 0x7f) as u64;
+                }
+                let base = (start as u64).checked_sub(distance).filter(|_| distance > 0);
+                match base.and_then(|base| offsets.get(&base)) {
+                    Some(&base) => Parsed::OffsetDelta(base),
+                    None => return Err(corrupt("delta base offset out of range")),
+                }
+            }
+            REF_DELTA => {
+                let mut base = [0; 20];
+                for byte_of_id in base.iter_mut() {
+                    *byte_of_id = byte()?;
+                }
+                Parsed::RefDelta(base)
+            }
+            _ => Parsed::Whole(object_type(code).ok_or_else(|| corrupt(&format!("unknown entry type {}", code)))?),
+        };
+        let mut inflated = flate2::bufread::ZlibDecoder::new(&data[at..body]);
+        let mut content = Vec::with_capacity(size as usize);
+        (&mut inflated).take(size + 1).read_to_end(&mut content)?;
+        if content.len() as u64 != size {
+            return Err(corrupt("entry size doesn't match its content"));
+        }
+        at += inflated.total_in() as usize;
+        let mut crc = flate2::Crc::new();
+        crc.update(&data[start..at]);
+        offsets.insert(start as u64, entries.len());
+        entries.push((start as u64, crc.sum(), parsed, Some(content)));
+    }
+    if at != body {
+        return Err(corrupt("garbage after the last entry"));
+    }
+
+    // Whole objects are hashed as they are; a delta once its base is known,
+    // which ref deltas may only be after a later entry. The content of an
+    // entry is kept only while deltas still need it as a base.
+    let mut uses = vec![0usize; entries.len()];
+    let mut ref_bases = std::collections::HashSet::new();
+    for (_, _, parsed, _) in &entries {
+        match parsed {
+            Parsed::OffsetDelta(base) => uses[*base] += 1,
+            Parsed::RefDelta(base) => {
+                ref_bases.insert(*base);
+            }
+            Parsed::Whole(_) => {}
+        }
+    }
+    let mut resolved: Vec<Option<(ObjectType, [u8; 20])>> = vec![None; entries.len()];
+    let mut bases: std::collections::HashMap<usize, Vec<u8>> = std::collections::HashMap::new();
+    let mut by_id = std::collections::HashMap::new();
+    let mut left = entries.len();
+    while left > 0 {
+        let before = left;
+        for i in 0..entries.len() {
+            if resolved[i].is_some() {
+                continue;
+            }
+            let parsed = entries[i].2;
+            let (object_type, content) = match parsed {
+                Parsed::Whole(object_type) => (object_type, entries[i].3.take().unwrap()),
+                _ => {
+                    let base = match parsed {
+                        Parsed::OffsetDelta(base) => Some(base),
+                        Parsed::RefDelta(id) => by_id.get(&id).copied(),
+                        Parsed::Whole(_) => None,
+                    };
+                    let base = match base.filter(|&base| resolved[base].is_some()) {
+                        Some(base) => base,
+                        None => continue,
+                    };
+                    let object_type = resolved[base].unwrap().0;
+                    let content = apply_delta(&bases[&base], entries[i].3.as_ref().unwrap())?;
+                    entries[i].3 = None;
+                    if let Parsed::OffsetDelta(base) = parsed {
+                        uses[base] -= 1;
+                        if uses[base] == 0 && !ref_bases.contains(&resolved[base].unwrap().1) {
+                            bases.remove(&base);
+                        }
+                    }
+                    (object_type, content)
+                }
+            };
+            let id = Blob::hash(&Blob::header(object_type, &content), &content);
+            if uses[i] > 0 || ref_bases.contains(&id) {
+                bases.insert(i, content);
+            }
+            resolved[i] = Some((object_type, id));
+            by_id.insert(id, i);
+            left -= 1;
+        }
+        if left == before {
+            return Err(corrupt(&format!("{} deltas have no base in the pack", left)));
+        }
+    }
+    let objects = entries
+        .iter()
+        .zip(resolved)
+        .map(|((offset, crc32, _, _), resolved)| IndexedObject { id: resolved.unwrap().1, offset: *offset, crc32: *crc32 })
+        .collect();
+    Ok((objects, data[body..].to_vec()))
+}
+
+// The version 2 `.idx` of a pack with `objects` and `checksum`.
+pub fn write_index(objects: &[IndexedObject], checksum: &[u8]) -> Vec<u8> {
+    let mut sorted: Vec<&IndexedObject> = objects.iter().collect();
+    sorted.sort_by_key(|object| object.id);
+    let mut index = INDEX_SIGNATURE.to_vec();
+    index.extend(INDEX_VERSION.to_be_bytes());
+    let mut counted = 0;
+    for first in 0..=255u8 {
+        counted += sorted[counted..].iter().take_while(|object| object.id[0] == first).count();
+        index.extend((counted as u32).to_be_bytes());
+    }
+    for object in &sorted {
+        index.extend(object.id);
+    }
+    for object in &sorted {
+        index.extend(object.crc32.to_be_bytes());
+    }
+    // offsets past 31 bits go in a table of 64-bit ones, referred to by position
+    let mut large = Vec::new();
+    for object in &sorted {
+        match object.offset < LARGE_OFFSET as u64 {
+            true => index.extend((object.offset as u32).to_be_bytes()),
+            false => {
+                index.extend((LARGE_OFFSET | (large.len() / 8) as u32).to_be_bytes());
+                large.extend(object.offset.to_be_bytes());
+            }
+        }
+    }
+    index.extend(large);
+    index.extend(checksum);
+    let own = sha1::Sha1::digest(&index);
+    index.extend(own);
+    index
+}
+
+// Indexes the pack `data` and stores it in `objects_dir` as
+// `pack/pack-<checksum>.pack`, with its `.idx`. Returns the checksum.
+pub fn store(objects_dir: &Path, data: &[u8]) -> std::io::Result<String> {
+    let (objects, checksum) = index(data)?;
+    let pack_dir = objects_dir.join("pack");
+    fs::create_dir_all(&pack_dir)?;
+    let name = Blob::string_hash(&checksum);
+    let path = pack_dir.join(format!("pack-{}.pack", name));
+    // the index goes last, a pack without one is never read
+    write_locked(&path, data)?;
+    write_locked(&path.with_extension("idx"), &write_index(&objects, &checksum))?;
+    Ok(name)
+}
+
+// The packs of an objects directory, with its multi-pack-index when it
+// has one, which is consulted before the packs' own indexes.
+pub struct Packs {
+    packs: Vec<Pack>,
+    multi_pack_index: Option<MultiPackIndex>,
+    // the pack each of the multi-pack-index's packs is in `packs`
+    indexed: Vec<usize>,
+}
+
+// The names of the `.idx` files in `pack_dir` that have their pack, in order.
+// An index whose pack is gone is left over from a repack.
+pub fn index_names(pack_dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let entries = match fs::read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.starts_with("pack-") && name.ends_with(".idx") && pack_dir.join(&name).with_extension("pack").is_file() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+impl Packs {
+    pub fn open(objects_dir: &Path) -> std::io::Result<Self> {
+        Self::open_with_cache_limit(objects_dir, DEFAULT_DELTA_BASE_CACHE_LIMIT)
+    }
+
+    // Opens the packs with one cache of delta bases for all of them, holding
+    // at most `limit` bytes, as `core.deltaBaseCacheLimit` says.
+    pub fn open_with_cache_limit(objects_dir: &Path, limit: u64) -> std::io::Result<Self> {
+        let pack_dir = objects_dir.join("pack");
+        let cache = std::sync::Arc::new(std::sync::Mutex::new(DeltaBaseCache::new(limit)));
+        let mut packs = Vec::new();
+        for (number, name) in index_names(&pack_dir)?.into_iter().enumerate() {
+            let pack = Pack { cache: cache.clone(), number, ..Pack::open(&pack_dir.join(&name))? };
+            packs.push((name, pack));
+        }
+        let mut multi_pack_index = MultiPackIndex::open(&pack_dir)?;
+        let mut indexed = Vec::new();
+        if let Some(midx) = &multi_pack_index {
+            for name in midx.packs() {
+                match packs.iter().position(|(pack, _)| pack == name) {
+                    Some(position) => indexed.push(position),
+                    // a stale index listing a pack that was removed is not used
+                    None => break,
+                }
+            }
+            if indexed.len() != midx.packs().len() {
+                indexed.clear();
+                multi_pack_index = None;
+            }
+        }
+        let packs = packs.into_iter().map(|(_, pack)| pack).collect();
+        Ok(Packs { packs, multi_pack_index, indexed })
+    }
+
+    pub fn packs(&self) -> &[Pack] {
+        &self.packs
+    }
+
+    // the pack the object `id` is in and the offset of its entry there
+    pub fn find(&self, id: &[u8]) -> std::io::Result<Option<(&Pack, u64)>> {
+        if let Some(midx) = &self.multi_pack_index {
+            if let Some((pack, offset)) = midx.find(id)? {
+                return Ok(Some((&self.packs[self.indexed[pack]], offset)));
+            }
+        }
+        for (i, pack) in self.packs.iter().enumerate() {
+            if self.indexed.contains(&i) {
+                continue;
+            }
+            if let Some(offset) = pack.index.find(id)? {
+                return Ok(Some((pack, offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    // The object `id`, `None` when none of the packs has it. The bases of ref
+    // deltas may be in any of them.
+    pub fn read(&self, id: &[u8]) -> std::io::Result<Option<(ObjectType, Vec<u8>)>> {
+        self.read_with_depth(id, 0)
+    }
+
+    fn read_with_depth(&self, id: &[u8], depth: usize) -> std::io::Result<Option<(ObjectType, Vec<u8>)>> {
+        let (pack, offset) = match self.find(id)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        if depth > MAX_DELTA_CHAIN {
+            return Err(corrupt("delta chain too long"));
+        }
+        pack.read_at(offset, |base| self.read_with_depth(base, depth + 1)).map(Some)
+    }
+}
+
+// Packs the loose objects of `repo` into one new pack and leaves the packs
+// already there as they are, like `repack` without `-a`. Returns the
+// pack's name, `None` when there's nothing loose to pack. The loose
+// copies stay until `prune_packed` removes them.
+pub fn repack_loose(repo: &Repository) -> std::io::Result<Option<String>> {
+    let ids = AllObjects::new(vec![repo.objects_dir()]).collect::<std::io::Result<Vec<_>>>()?;
+    if ids.is_empty() {
+        return Ok(None);
+    }
+    let mut data = Vec::new();
+    write(repo, &ids, &mut data)?;
+    store(&repo.objects_dir(), &data).map(Some)
+}
+
+// Removes the loose objects of `objects_dir` that a pack there or in an
+// alternate also has, like `prune-packed`, and returns their ids; with
+// `dry_run` nothing is removed. An object only counts as packed when
+// reading it from the pack gives back its id, not when an index lists it.
+pub fn prune_packed(objects_dir: &Path, dry_run: bool) -> std::io::Result<Vec<String>> {
+    let packs = object_dirs(objects_dir).iter().map(|dir| Packs::open(dir)).collect::<std::io::Result<Vec<_>>>()?;
+    let mut pruned = Vec::new();
+    for sha in AllObjects::new(vec![objects_dir.to_path_buf()]) {
+        let sha = sha?;
+        let id = match Blob::decode_hex(&sha) {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let mut packed = false;
+        for packs in &packs {
+            // a pack that can't give the object back doesn't have it
+            if let Ok(Some((object_type, content))) = packs.read(&id) {
+                packed = Blob::hash(&Blob::header(object_type, &content), &content)[..] == id[..];
+            }
+            if packed {
+                break;
+            }
+        }
+        if !packed {
+            continue;
+        }
+        if !dry_run {
+            let fan_out = objects_dir.join(&sha[..2]);
+            fs::remove_file(fan_out.join(&sha[2..]))?;
+            if fs::read_dir(&fan_out)?.next().is_none() {
+                fs::remove_dir(&fan_out)?;
+            }
+        }
+        pruned.push(sha);
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKS: &str = "tests/packed/objects";
+
+    fn id(sha: &str) -> Vec<u8> {
+        Blob::decode_hex(sha).unwrap()
+    }
+
+    #[test]
+    fn testing_pack_lookup() {
+        let packs = Packs::open(Path::new(PACKS)).unwrap();
+        assert_eq!(packs.packs().len(), 2);
+        let index = packs.packs()[0].index();
+        assert_eq!(index.len(), 10);
+        let ids: Vec<String> = (0..=255).flat_map(|first| index.ids_starting_with(first)).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(ids.len(), 10);
+        assert!(ids.contains(&"dea5ea01e31467fbf2ff4214a7da4b67b5f662fc".to_string()));
+        // each pack's own index agrees with the multi-pack-index
+        let midx = MultiPackIndex::open(&Path::new(PACKS).join("pack")).unwrap().unwrap();
+        assert_eq!(midx.len(), 20);
+        for pack in packs.packs() {
+            for sha in (0..=255).flat_map(|first| pack.index().ids_starting_with(first)) {
+                let (number, offset) = midx.find(&id(&sha)).unwrap().unwrap();
+                assert_eq!(pack.path().with_extension("idx").file_name().unwrap().to_str(), Some(&midx.packs()[number][..]));
+                assert_eq!(pack.index().find(&id(&sha)).unwrap(), Some(offset));
+            }
+        }
+        assert!(packs.find(&id("0000000000000000000000000000000000000000")).unwrap().is_none());
+    }
+
+    #[test]
+    fn testing_pack_storing() {
+        let dir = TempDir::new("store-pack");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let data = fs::read(Path::new(PACKS).join("pack/pack-19094bd7d5ac143343c5f48080d364e41504a1ca.pack")).unwrap();
+        let stored = store(&repo.objects_dir(), &data);
+        // two offset deltas on the same base, and whole objects
+        let read: Vec<(ObjectType, String)> = [
+            "dea5ea01e31467fbf2ff4214a7da4b67b5f662fc",
+            "bf57a504b9963157c8f93817cd05cb55e3131016",
+            "637083173d666b8fcc61ed49bf29a5ffa35d1bc8",
+            "b2b505b0bb03d95f64aece6db662943223707080",
+        ]
+        .iter()
+        .map(|sha| {
+            let object = Blob::from_sha(&repo, sha).unwrap();
+            (object.object_type(), Blob::new(object.object_type(), object.content).hash_string)
+        })
+        .collect();
+        let mut corrupted = data.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        let refused = store(&repo.objects_dir(), &corrupted).is_err();
+
+        assert_eq!(stored.unwrap(), "19094bd7d5ac143343c5f48080d364e41504a1ca");
+        assert_eq!(read[0].1, "dea5ea01e31467fbf2ff4214a7da4b67b5f662fc");
+        assert_eq!(read[1].1, "bf57a504b9963157c8f93817cd05cb55e3131016");
+        assert_eq!(read[2].1, "637083173d666b8fcc61ed49bf29a5ffa35d1bc8");
+        assert_eq!(read[3], (ObjectType::Commit, "b2b505b0bb03d95f64aece6db662943223707080".to_string()));
+        assert!(refused);
+    }
+
+    #[test]
+    fn testing_pack_deltas() {
+        let packs = Packs::open(Path::new(PACKS)).unwrap();
+        // an offset delta, a ref delta and whole objects, checked against their ids
+        for sha in [
+            "dea5ea01e31467fbf2ff4214a7da4b67b5f662fc",
+            "19f117339e5fe331b9d92a460af0c27b2088d55c",
+            "6abac3f09edfe2957a918876fd0c7fbf934be96d",
+            "d1e8b2fe427d6593791719ece02781897b767f7e",
+        ] {
+            let (object_type, content) = packs.read(&id(sha)).unwrap().unwrap();
+            assert_eq!(Blob::new(object_type, content).hash_string, sha);
+        }
+        let (object_type, content) = packs.read(&id("d7b30ee07d4b9819a77a3b122282b4c04528ec21")).unwrap().unwrap();
+        assert_eq!(object_type, ObjectType::Blob);
+        assert_eq!(content, b"d4\n");
+        // copy 2 bytes from offset 1, insert "!", on a 4 byte base
+        assert_eq!(apply_delta(b"abcd", &[4, 3, 0x91, 1, 2, 1, b'!']).unwrap(), b"bc!");
+        assert!(apply_delta(b"abc", &[4, 3, 0x91, 1, 2, 1, b'!']).is_err());
+        assert!(apply_delta(b"abcd", &[4, 3, 0x91, 3, 2, 1, b'!']).is_err());
+        assert!(apply_delta(b"abcd", &[4, 3, 0]).is_err());
+    }
+
+    #[test]
+    fn testing_delta_base_cache() {
+        let packs = Packs::open(Path::new(PACKS)).unwrap();
+        let pack = &packs.packs()[0];
+        let cached = || pack.cache().objects.keys().copied().collect::<Vec<_>>();
+        let delta = id("dea5ea01e31467fbf2ff4214a7da4b67b5f662fc");
+        let (object_type, content) = packs.read(&delta).unwrap().unwrap();
+        // the base, at 583, is kept for the other delta on it, which is then
+        // rebuilt the same from the cached copy
+        assert_eq!(cached(), vec![(0, 583)]);
+        assert_eq!(packs.read(&delta).unwrap().unwrap(), (object_type, content));
+        let other = packs.read(&id("bf57a504b9963157c8f93817cd05cb55e3131016")).unwrap().unwrap();
+        assert_eq!(Blob::new(other.0, other.1).hash_string, "bf57a504b9963157c8f93817cd05cb55e3131016");
+        let none = Packs::open_with_cache_limit(Path::new(PACKS), 0).unwrap();
+        none.read(&delta).unwrap().unwrap();
+        assert!(none.packs()[0].cache().objects.is_empty());
+
+        // the least recently used object goes first
+        let mut cache = DeltaBaseCache::new(10);
+        cache.insert((0, 1), ObjectType::Blob, b"aaaa");
+        cache.insert((0, 2), ObjectType::Blob, b"bbbb");
+        assert!(cache.get((0, 1)).is_some());
+        cache.insert((1, 1), ObjectType::Blob, b"cccc");
+        assert_eq!(cache.get((0, 2)), None);
+        assert_eq!(cache.get((0, 1)), Some((ObjectType::Blob, b"aaaa".to_vec())));
+        assert_eq!(cache.size, 8);
+        cache.insert((1, 2), ObjectType::Blob, b"too large to keep");
+        assert_eq!(cache.objects.len(), 2);
+    }
+
+    #[test]
+    fn testing_reverse_index() {
+        let packs = Packs::open(Path::new(PACKS)).unwrap();
+        // the first pack has a .rev, the second's is built from its index
+        for pack in packs.packs() {
+            let reverse = pack.reverse_index().unwrap();
+            let built = ReverseIndex::open(pack.index(), Path::new("/nonexistent.rev")).unwrap();
+            assert_eq!(reverse.positions, built.positions);
+            assert_eq!(reverse.offsets[0], 12);
+            for (&position, &offset) in reverse.positions.iter().zip(&reverse.offsets) {
+                assert_eq!(pack.id_at(offset).unwrap(), Some(Blob::string_hash(pack.index().id(position as usize))));
+            }
+        }
+        let pack = &packs.packs()[0];
+        assert!(pack.path.with_extension("rev").is_file());
+        assert_eq!(pack.id_at(13).unwrap(), None);
+        assert_eq!(pack.reverse_index().unwrap().next_offset(583), Some(820));
+        assert_eq!(pack.reverse_index().unwrap().next_offset(860), None);
+        assert_eq!(pack.delta_base(820).unwrap().as_deref(), Some("637083173d666b8fcc61ed49bf29a5ffa35d1bc8"));
+        assert_eq!(pack.delta_base(583).unwrap(), None);
+        let ref_delta = packs.packs()[1].index().find(&id("19f117339e5fe331b9d92a460af0c27b2088d55c")).unwrap().unwrap();
+        assert_eq!(
+            packs.packs()[1].delta_base(ref_delta).unwrap().as_deref(),
+            Some("7525fe6d2688da36757a75742ad83ea450a7f1c3")
+        );
+        // a .rev made for another pack is refused
+        let other = packs.packs()[1].index();
+        assert!(ReverseIndex::open(other, &pack.path.with_extension("rev")).is_err());
+    }
+
+    #[test]
+    fn testing_pack_writing() {
+        // indexing git's packs gives back git's indexes, byte for byte
+        for pack in Packs::open(Path::new(PACKS)).unwrap().packs() {
+            let data = fs::read(pack.path()).unwrap();
+            let (objects, checksum) = index(&data).unwrap();
+            assert_eq!(write_index(&objects, &checksum), fs::read(pack.path().with_extension("idx")).unwrap());
+        }
+        let mut data = fs::read(Path::new(PACKS).join("pack/pack-aaa105d5d416e444be34d3b30aff60b22e2f639a.pack")).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(index(&data).is_err());
+
+        let repo = Repository::in_memory();
+        let ids: Vec<String> = ["one", "two", &"three".repeat(1000)]
+            .iter()
+            .map(|content| {
+                let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+                hash::write_to_database(&repo, &blob).unwrap();
+                blob.hash_string
+            })
+            .collect();
+        let mut written = Vec::new();
+        let checksum = write(&repo, &ids, &mut written).unwrap();
+        let (objects, indexed) = index(&written).unwrap();
+        assert_eq!(indexed, checksum);
+        let found: Vec<String> = objects.iter().map(|object| Blob::string_hash(&object.id)).collect();
+        assert_eq!(found, ids);
+        let index = PackIndex::parse(write_index(&objects, &checksum)).unwrap();
+        assert_eq!(index.find(&objects[2].id).unwrap(), Some(objects[2].offset));
+    }
+
+    #[test]
+    fn testing_prune_packed() {
+        let dir = TempDir::new("prune-packed");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let ids: Vec<String> = ["one", "two", "three", "four"]
+            .iter()
+            .map(|content| {
+                let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+                hash::write_to_database(&repo, &blob).unwrap();
+                blob.hash_string
+            })
+            .collect();
+        let mut data = Vec::new();
+        write(&repo, &ids[..2], &mut data).unwrap();
+        store(&repo.objects_dir(), &data).unwrap();
+        // an index saying the third object is where the pack has the fourth
+        let mut lying = Vec::new();
+        let checksum = write(&repo, &ids[3..], &mut lying).unwrap();
+        let (mut objects, _) = index(&lying).unwrap();
+        objects[0].id.copy_from_slice(&Blob::decode_hex(&ids[2]).unwrap());
+        let lying_pack = dir.join("objects/pack").join(format!("pack-{}.pack", Blob::string_hash(&checksum)));
+        fs::write(&lying_pack, &lying).unwrap();
+        fs::write(lying_pack.with_extension("idx"), write_index(&objects, &checksum)).unwrap();
+
+        let mut would = prune_packed(&repo.objects_dir(), true).unwrap();
+        let loose_before: Vec<bool> = ids.iter().map(|sha| repo.objects_dir().join(&sha[..2]).join(&sha[2..]).exists()).collect();
+        let mut pruned = prune_packed(&repo.objects_dir(), false).unwrap();
+        let loose: Vec<bool> = ids.iter().map(|sha| repo.objects_dir().join(&sha[..2]).join(&sha[2..]).exists()).collect();
+        let reopened = Repository::open(&dir).unwrap();
+        let contents: Vec<Vec<u8>> = ids.iter().map(|sha| Blob::from_sha(&reopened, sha).unwrap().content).collect();
+        let again = prune_packed(&repo.objects_dir(), false).unwrap();
+
+        let mut packed = ids[..2].to_vec();
+        packed.sort();
+        would.sort();
+        pruned.sort();
+        assert_eq!(would, packed);
+        assert_eq!(loose_before, vec![true; 4]);
+        assert_eq!(pruned, packed);
+        assert_eq!(loose, vec![false, false, true, true]);
+        assert_eq!(contents, vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec(), b"four".to_vec()]);
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn testing_repack_loose() {
+        let dir = TempDir::new("repack");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let write_blob = |content: &[u8]| {
+            let blob = Blob::new(ObjectType::Blob, content.to_vec());
+            hash::write_to_database(&repo, &blob).unwrap();
+            blob.hash_string
+        };
+        let nothing = repack_loose(&repo).unwrap();
+        let first = write_blob(b"first");
+        let old = repack_loose(&repo).unwrap().unwrap();
+        prune_packed(&repo.objects_dir(), false).unwrap();
+        let second = write_blob(b"second");
+        let new = repack_loose(&repo).unwrap().unwrap();
+        let pruned = prune_packed(&repo.objects_dir(), false).unwrap();
+        let names = index_names(&dir.join("objects/pack")).unwrap();
+        let packs = Packs::open(&dir.join("objects")).unwrap();
+        let counts: Vec<usize> = packs.packs().iter().map(|pack| pack.index().len()).collect();
+        let reopened = Repository::open(&dir).unwrap();
+        let contents = [&first, &second].iter().map(|sha| Blob::from_sha(&reopened, sha).unwrap().content).collect::<Vec<_>>();
+
+        assert_eq!(nothing, None);
+        let mut expected = vec![format!("pack-{}.idx", old), format!("pack-{}.idx", new)];
+        expected.sort();
+        assert_eq!(names, expected);
+        // the new pack holds only what was loose
+        assert_eq!(counts, vec![1, 1]);
+        assert_eq!(pruned, vec![second]);
+        assert_eq!(contents, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[test]
+    fn testing_pack_stats() {
+        let pack = Pack::open(&Path::new(PACKS).join("pack/pack-19094bd7d5ac143343c5f48080d364e41504a1ca.idx")).unwrap();
+        pack.verify_checksum().unwrap();
+        let listed: Vec<String> = pack
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|entry| match &entry.base {
+                Some(base) => format!("{} {} {} {} {} {}", entry.id, entry.size, entry.packed_size, entry.offset, entry.depth, base),
+                None => format!("{} {} {} {}", entry.id, entry.size, entry.packed_size, entry.offset),
+            })
+            .collect();
+        // as `git verify-pack -v` lists them, without the types
+        assert_eq!(
+            listed,
+            vec![
+                "b2b505b0bb03d95f64aece6db662943223707080 171 124 12",
+                "d1e8b2fe427d6593791719ece02781897b767f7e 107 97 136",
+                "7629d2d53a45336ce697df5448779dd0605fdc6b 118 94 233",
+                "688a1335a6ea6d6ef20c0bfb8fd00a9fba417244 171 124 327",
+                "fe748e6aa1783b244090297dda49c836668cfec6 33 44 451",
+                "eb30e8c588547e1f7f7dd9a8c3129c15b0a17bd5 33 44 495",
+                "e9c0bd9c3139c7549260e53d0d232a21f380569c 33 44 539",
+                "637083173d666b8fcc61ed49bf29a5ffa35d1bc8 3188 237 583",
+                "dea5ea01e31467fbf2ff4214a7da4b67b5f662fc 27 40 820 1 637083173d666b8fcc61ed49bf29a5ffa35d1bc8",
+                "bf57a504b9963157c8f93817cd05cb55e3131016 19 32 860 1 637083173d666b8fcc61ed49bf29a5ffa35d1bc8",
+            ]
+        );
+        let stats = pack.stats().unwrap();
+        let counts: Vec<(&str, usize)> = stats.counts.iter().map(|(name, count)| (*name, *count)).collect();
+        assert_eq!(stats.objects, 10);
+        assert_eq!(counts, vec![("blob", 3), ("commit", 3), ("tag", 1), ("tree", 3)]);
+        assert_eq!(stats.packed_size, 892 - 12);
+        assert_eq!(stats.max_depth, 1);
+        assert_eq!(stats.chain_lengths.into_iter().collect::<Vec<_>>(), vec![(0, 8), (1, 2)]);
+        let repo = Repository::open("tests/packed").unwrap();
+        let sizes: u64 = pack.entries().unwrap().iter().map(|entry| Blob::from_sha(&repo, &entry.id).unwrap().content.len() as u64).sum();
+        assert_eq!(stats.size, sizes);
+
+        // the other pack's delta is a ref delta
+        let pack = Pack::open(&Path::new(PACKS).join("pack/pack-aaa105d5d416e444be34d3b30aff60b22e2f639a.idx")).unwrap();
+        let delta = pack.entries().unwrap().pop().unwrap();
+        assert_eq!((delta.depth, delta.base.as_deref()), (1, Some("7525fe6d2688da36757a75742ad83ea450a7f1c3")));
+    }
+
+    #[test]
+    fn testing_packed_lookups() {
+        // thousands of objects looked up one at a time, each through the index
+        // and by inflating its own entry only
+        let dir = TempDir::new("packed-lookups");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let loose = Repository::open(&dir).unwrap();
+        let ids: Vec<String> = (0..3000)
+            .map(|i| {
+                let blob = Blob::new(ObjectType::Blob, format!("object {}\n", i).into_bytes());
+                hash::write_to_database(&loose, &blob).unwrap();
+                blob.hash_string
+            })
+            .collect();
+        repack_loose(&loose).unwrap();
+        prune_packed(&loose.objects_dir(), false).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let started = std::time::Instant::now();
+        let read: Vec<bool> = ids
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, sha)| {
+                let mut content = Vec::new();
+                repo.open_object(sha).unwrap().read_to_end(&mut content).unwrap();
+                content == format!("object {}\n", i).into_bytes()
+            })
+            .collect();
+        let elapsed = started.elapsed();
+        let missing = repo.open_object(&"0".repeat(40)).map(|_| ()).map_err(|e| e.kind());
+        let loose_left = AllObjects::new(vec![repo.objects_dir()]).count();
+
+        assert_eq!(loose_left, 0);
+        assert!(read.iter().all(|&read| read));
+        assert_eq!(missing, Err(std::io::ErrorKind::NotFound));
+        // far more than it takes, unless each lookup went through the pack
+        assert!(elapsed < std::time::Duration::from_secs(20), "{} lookups took {:?}", ids.len(), elapsed);
+    }
+
+    #[test]
+    fn testing_truncated_pack() {
+        let dir = TempDir::new("truncated-pack");
+        let name = "pack-19094bd7d5ac143343c5f48080d364e41504a1ca";
+        fs::create_dir_all(&dir).unwrap();
+        let source = Path::new(PACKS).join("pack");
+        fs::copy(source.join(name).with_extension("idx"), dir.join(name).with_extension("idx")).unwrap();
+        let data = fs::read(source.join(name).with_extension("pack")).unwrap();
+        fs::write(dir.join(name).with_extension("pack"), &data[..600]).unwrap();
+        let pack = Pack::open(&dir.join(name).with_extension("idx"));
+
+        let pack = pack.unwrap();
+        let no_base = |_: &[u8]| Ok(None);
+        // a commit at the start is still there, the delta at 820 and its base are cut off
+        let commit = pack.index().find(&id("b2b505b0bb03d95f64aece6db662943223707080")).unwrap().unwrap();
+        assert_eq!(pack.read_at(commit, no_base).unwrap().0, ObjectType::Commit);
+        let delta = pack.index().find(&id("dea5ea01e31467fbf2ff4214a7da4b67b5f662fc")).unwrap().unwrap();
+        assert_eq!(delta, 820);
+        assert!(pack.read_at(delta, no_base).is_err());
+        assert!(pack.read_at(10_000, no_base).is_err());
+    }
+}
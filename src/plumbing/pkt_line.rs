@@ -0,0 +1,227 @@
+use super::*;
+
+// the longest a pkt-line may be, its four length digits included
+pub const MAX_LENGTH: usize = 65520;
+
+// the side-band channels: the pack, progress messages and a fatal error
+const PACK_DATA: u8 = 1;
+const PROGRESS: u8 = 2;
+const ERROR: u8 = 3;
+
+fn invalid(what: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("protocol error: {}", what))
+}
+
+// Appends `data` to `out` as one pkt-line: four hex digits of length,
+// themselves counted, then the data.
+pub fn write(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend(format!("{:04x}", data.len() + 4).into_bytes());
+    out.extend(data);
+}
+
+// appends `line` and a newline as one pkt-line
+pub fn write_line(out: &mut Vec<u8>, line: &str) {
+    write(out, format!("{}\n", line).as_bytes());
+}
+
+// appends a flush-pkt, `0000`, which ends a section
+pub fn flush(out: &mut Vec<u8>) {
+    out.extend(b"0000");
+}
+
+// Reads the pkt-lines of a stream one at a time.
+pub struct Reader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(input: R) -> Self {
+        Reader { input }
+    }
+
+    // The data of the next pkt-line, `None` for a flush-pkt. The stream may not
+    // end in the middle of one, nor before it is asked for.
+    pub fn read(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        match self.read_or_end()? {
+            Some(packet) => Ok(packet),
+            None => Err(invalid("the remote end hung up unexpectedly".to_string())),
+        }
+    }
+
+    // the next pkt-line like `read`, `None` when the stream ends before it
+    fn read_or_end(&mut self) -> std::io::Result<Option<Option<Vec<u8>>>> {
+        let mut length = [0; 4];
+        if self.input.read(&mut length[..1])? == 0 {
+            return Ok(None);
+        }
+        self.input.read_exact(&mut length[1..]).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => invalid("the remote end hung up unexpectedly".to_string()),
+            _ => e,
+        })?;
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|digits| usize::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| invalid(format!("bad line length '{}'", String::from_utf8_lossy(&length))))?;
+        match length {
+            0 => return Ok(Some(None)),
+            1..=3 => return Err(invalid(format!("bad line length {}", length))),
+            _ if length > MAX_LENGTH => return Err(invalid(format!("line of {} bytes is too long", length))),
+            _ => {}
+        }
+        let mut data = vec![0; length - 4];
+        self.input.read_exact(&mut data).map_err(|_| invalid("the remote end hung up unexpectedly".to_string()))?;
+        Ok(Some(Some(data)))
+    }
+
+    // the next pkt-line as text, without the newline ending it
+    pub fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let data = self.read()?;
+        Ok(data.map(|data| String::from_utf8_lossy(data.strip_suffix(b"\n").unwrap_or(&data)).into_owned()))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+// Writes the progress messages of the remote to `out`, each line prefixed
+// with `remote: `. Lines ending with a carriage return are updated in place
+// by the next one, as git shows `Counting objects: 42%`; a message may
+// stop in the middle of a line, which the next one goes on with.
+pub struct Progress<W: Write> {
+    out: W,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Progress<W> {
+    pub fn new(out: W) -> Self {
+        Progress { out, pending: Vec::new() }
+    }
+
+    pub fn message(&mut self, message: &[u8]) -> std::io::Result<()> {
+        for &byte in message {
+            self.pending.push(byte);
+            if byte == b'\r' || byte == b'\n' {
+                self.out.write_all(b"remote: ")?;
+                self.out.write_all(&self.pending)?;
+                self.pending.clear();
+            }
+        }
+        self.out.flush()
+    }
+
+    // ends a line the remote left unfinished
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            self.pending.push(b'\n');
+            self.out.write_all(b"remote: ")?;
+            self.out.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+        self.out.flush()
+    }
+}
+
+// Splits the side-band stream of `reader` up to its flush-pkt, or its end
+// when the remote just closes it: the pack data of channel 1 goes to
+// `data`, the progress of channel 2 to `progress`, and a message on
+// channel 3 is the remote's error and ends it.
+pub fn demultiplex<R: Read, D: Write, P: Write>(
+    reader: &mut Reader<R>,
+    data: &mut D,
+    progress: &mut Progress<P>,
+) -> std::io::Result<()> {
+    // a pack sent in full is often followed by nothing at all
+    while let Some(packet) = reader.read_or_end()? {
+        let packet = match packet {
+            Some(packet) => packet,
+            None => break,
+        };
+        match packet.split_first() {
+            Some((&PACK_DATA, pack)) => data.write_all(pack)?,
+            Some((&PROGRESS, message)) => progress.message(message)?,
+            Some((&ERROR, message)) => {
+                progress.finish()?;
+                let message = String::from_utf8_lossy(message);
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    format!("remote error: {}", message.trim_end()),
+                ));
+            }
+            Some((band, _)) => return Err(invalid(format!("bad band #{}", band))),
+            None => return Err(invalid("empty side-band packet".to_string())),
+        }
+    }
+    progress.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band(out: &mut Vec<u8>, channel: u8, data: &[u8]) {
+        write(out, &[&[channel][..], data].concat());
+    }
+
+    #[test]
+    fn testing_pkt_lines() {
+        let mut stream = Vec::new();
+        write_line(&mut stream, "want 0123");
+        write(&mut stream, b"");
+        flush(&mut stream);
+        write(&mut stream, b"no newline");
+        assert_eq!(&stream[..13], b"000ewant 0123");
+        let mut reader = Reader::new(&stream[..]);
+        assert_eq!(reader.read_line().unwrap().as_deref(), Some("want 0123"));
+        assert_eq!(reader.read().unwrap(), Some(Vec::new()));
+        assert_eq!(reader.read().unwrap(), None);
+        assert_eq!(reader.read_line().unwrap().as_deref(), Some("no newline"));
+        let ended = reader.read().unwrap_err().to_string();
+        assert_eq!(ended, "protocol error: the remote end hung up unexpectedly");
+
+        for bad in [&b"00zz"[..], b"0002", b"fff1", b"0009abc"].iter() {
+            assert!(Reader::new(*bad).read().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn testing_side_band() {
+        let mut stream = Vec::new();
+        band(&mut stream, 2, b"Counting objects:  50% (1/2)\r");
+        band(&mut stream, 1, b"PACK");
+        band(&mut stream, 2, b"Counting objects: 100% (2/2), done.\nCompress");
+        band(&mut stream, 1, b" data");
+        band(&mut stream, 2, b"ing objects: 100% (1/1)");
+        flush(&mut stream);
+        stream.extend(b"left for the caller");
+        let mut reader = Reader::new(&stream[..]);
+        let mut data = Vec::new();
+        let mut progress = Progress::new(Vec::new());
+        demultiplex(&mut reader, &mut data, &mut progress).unwrap();
+        let mut left = Vec::new();
+        reader.into_inner().read_to_end(&mut left).unwrap();
+
+        assert_eq!(data, b"PACK data");
+        assert_eq!(
+            String::from_utf8(progress.out).unwrap(),
+            "remote: Counting objects:  50% (1/2)\rremote: Counting objects: 100% (2/2), done.\n\
+                     remote: Compressing objects: 100% (1/1)\n"
+        );
+        assert_eq!(left, b"left for the caller");
+
+        // a stream that just ends, and one ended by the remote's error
+        let mut ended = Vec::new();
+        band(&mut ended, 1, b"PACK");
+        let mut data = Vec::new();
+        demultiplex(&mut Reader::new(&ended[..]), &mut data, &mut Progress::new(std::io::sink())).unwrap();
+        assert_eq!(data, b"PACK");
+        let mut failed = Vec::new();
+        band(&mut failed, 3, b"upload-pack: not our ref 0123\n");
+        let error = demultiplex(&mut Reader::new(&failed[..]), &mut Vec::new(), &mut Progress::new(std::io::sink()));
+        assert_eq!(error.unwrap_err().to_string(), "remote error: upload-pack: not our ref 0123");
+        let mut unknown = Vec::new();
+        band(&mut unknown, 4, b"?");
+        assert!(demultiplex(&mut Reader::new(&unknown[..]), &mut Vec::new(), &mut Progress::new(std::io::sink())).is_err());
+    }
+}
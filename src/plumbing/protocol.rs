@@ -0,0 +1,557 @@
+use super::commit::Commit;
+use super::*;
+
+// the `have` lines sent in each round of negotiation, and how many may go
+// unacknowledged before the histories are taken to share nothing
+const HAVE_BATCH: usize = 32;
+const MAX_IN_VAIN: usize = 256;
+const AGENT: &str = concat!("git/rust-git-", env!("CARGO_PKG_VERSION"));
+
+// The two requests the smart protocol makes of a remote's upload-pack, each
+// on its own as over http: its ref advertisement, and one round of
+// negotiation, answered with acks and, once the request says `done`, a pack.
+pub trait Transport {
+    fn advertisement(&mut self) -> std::io::Result<Vec<u8>>;
+    fn upload_pack(&mut self, request: Vec<u8>) -> std::io::Result<Box<dyn Read>>;
+}
+
+// upload-pack over smart http, at `<url>/info/refs` and `<url>/git-upload-pack`
+pub struct Http {
+    client: reqwest::blocking::Client,
+    url: String,
+}
+
+impl Http {
+    pub fn new(url: &str) -> Self {
+        Http { client: reqwest::blocking::Client::new(), url: url.trim_end_matches('/').to_string() }
+    }
+
+    // fails unless the server answered with `content_type`
+    fn check(&self, response: &reqwest::blocking::Response, content_type: &str) -> std::io::Result<()> {
+        let unable = |kind, what: String| std::io::Error::new(kind, format!("unable to access '{}': {}", self.url, what));
+        if !response.status().is_success() {
+            let status = response.status();
+            let kind = match status.as_u16() {
+                401 | 403 => std::io::ErrorKind::PermissionDenied,
+                404 => std::io::ErrorKind::NotFound,
+                _ => std::io::ErrorKind::InvalidData,
+            };
+            return Err(unable(kind, format!("the requested URL returned error: {}", status.as_u16())));
+        }
+        let received = response.headers().get(reqwest::header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+        if received != Some(content_type) {
+            // a dumb http server just serves the files of the repository
+            return Err(unable(std::io::ErrorKind::Unsupported, "not a smart http server".to_string()));
+        }
+        Ok(())
+    }
+
+    fn error(&self, e: reqwest::Error) -> std::io::Error {
+        let kind = match () {
+            _ if e.is_timeout() => std::io::ErrorKind::TimedOut,
+            _ if e.is_connect() => std::io::ErrorKind::ConnectionRefused,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, format!("unable to access '{}': {}", self.url, e))
+    }
+}
+
+impl Transport for Http {
+    fn advertisement(&mut self) -> std::io::Result<Vec<u8>> {
+        let response = self
+            .client
+            .get(&format!("{}/info/refs?service=git-upload-pack", self.url))
+            .header(reqwest::header::USER_AGENT, AGENT)
+            .send()
+            .map_err(|e| self.error(e))?;
+        self.check(&response, "application/x-git-upload-pack-advertisement")?;
+        let body = response.bytes().map_err(|e| self.error(e))?;
+        Ok(body.to_vec())
+    }
+
+    fn upload_pack(&mut self, request: Vec<u8>) -> std::io::Result<Box<dyn Read>> {
+        let response = self
+            .client
+            .post(&format!("{}/git-upload-pack", self.url))
+            .header(reqwest::header::USER_AGENT, AGENT)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-git-upload-pack-request")
+            .header(reqwest::header::ACCEPT, "application/x-git-upload-pack-result")
+            .body(request)
+            .send()
+            .map_err(|e| self.error(e))?;
+        self.check(&response, "application/x-git-upload-pack-result")?;
+        Ok(Box::new(response))
+    }
+}
+
+// The refs a remote has, `(name, id)` as `refs::list` gives them and HEAD
+// among them, and what its upload-pack can do.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Advertisement {
+    pub refs: Vec<(String, String)>,
+    pub capabilities: Vec<String>,
+}
+
+impl Advertisement {
+    // The refs of a version 0 advertisement, after the `# service=` line
+    // smart http starts it with: `<id> <name>`, the first one followed by a
+    // NUL and the capabilities. The peeled ids of tags, `<name>^{}`, are left
+    // out, and a repository without refs sends `capabilities^{}` alone.
+    pub fn parse(data: &[u8]) -> std::io::Result<Self> {
+        let invalid = |what: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("protocol error: {}", what));
+        let mut reader = pkt_line::Reader::new(data);
+        let mut advertisement = Advertisement::default();
+        let mut first = true;
+        while let Some(line) = reader.read_line()? {
+            if first && line.starts_with("# service=") {
+                // its own section, ended by a flush
+                reader.read()?;
+                continue;
+            }
+            if line == "version 2" {
+                return Err(invalid("protocol version 2 isn't supported".to_string()));
+            }
+            let (line, capabilities) = match line.split_once('\0') {
+                Some((line, capabilities)) if first => (line, Some(capabilities)),
+                _ => (line.as_str(), None),
+            };
+            first = false;
+            if let Some(capabilities) = capabilities {
+                advertisement.capabilities = capabilities.split(' ').filter(|c| !c.is_empty()).map(String::from).collect();
+            }
+            let (sha, name) = line.split_once(' ').ok_or_else(|| invalid(format!("bad ref line '{}'", line)))?;
+            if !is_object_id(sha) {
+                return Err(invalid(format!("bad ref line '{}'", line)));
+            }
+            if name.ends_with("^{}") {
+                continue;
+            }
+            advertisement.refs.push((name.to_string(), sha.to_string()));
+        }
+        Ok(advertisement)
+    }
+
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|offered| offered == capability || offered.split('=').next() == Some(capability))
+    }
+
+    // the ref the remote's HEAD points to, when it says so with `symref=HEAD:<ref>`
+    pub fn head_ref(&self) -> Option<&str> {
+        self.capabilities.iter().find_map(|capability| capability.strip_prefix("symref=HEAD:"))
+    }
+}
+
+// What a request of upload-pack says before its haves: the wants, the
+// first one with the capabilities asked for, the shallow commits the
+// repository has and how deep the history is to go, if not all the way.
+struct Wants<'a> {
+    wants: &'a [String],
+    capabilities: &'a [&'a str],
+    shallow: &'a [String],
+    depth: Option<u32>,
+}
+
+// A request of upload-pack: the `wants`, then the haves and `done` when no
+// more rounds are to come.
+fn request(wants: &Wants, haves: &[String], done: bool) -> Vec<u8> {
+    let Wants { wants, capabilities, shallow, depth } = *wants;
+    let mut request = Vec::new();
+    for (i, want) in wants.iter().enumerate() {
+        match i {
+            0 => pkt_line::write_line(&mut request, &format!("want {} {}", want, capabilities.join(" "))),
+            _ => pkt_line::write_line(&mut request, &format!("want {}", want)),
+        }
+    }
+    for sha in shallow {
+        pkt_line::write_line(&mut request, &format!("shallow {}", sha));
+    }
+    if let Some(depth) = depth {
+        pkt_line::write_line(&mut request, &format!("deepen {}", depth));
+    }
+    pkt_line::flush(&mut request);
+    for have in haves {
+        pkt_line::write_line(&mut request, &format!("have {}", have));
+    }
+    match done {
+        true => pkt_line::write_line(&mut request, "done"),
+        false => pkt_line::flush(&mut request),
+    }
+    request
+}
+
+// The answer to the haves of a round: `ACK <id>` for the first one the
+// remote has, or `NAK`.
+fn read_ack<R: Read>(reader: &mut pkt_line::Reader<R>) -> std::io::Result<Option<String>> {
+    let line = reader.read_line()?.unwrap_or_default();
+    match line.strip_prefix("ACK ") {
+        Some(ack) => Ok(Some(ack.split(' ').next().unwrap_or_default().to_string())),
+        None if line == "NAK" => Ok(None),
+        None => match line.strip_prefix("ERR ") {
+            Some(error) => Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, format!("remote error: {}", error))),
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("protocol error: expected ACK/NAK, got '{}'", line))),
+        },
+    }
+}
+
+// The `shallow <id>` and `unshallow <id>` lines upload-pack answers with
+// before its acks when the request deepens or has shallow lines of its
+// own, ended by a flush: the commits that become the boundary, and those
+// that stop being one as their parents come along.
+fn read_shallow_section<R: Read>(reader: &mut pkt_line::Reader<R>) -> std::io::Result<(Vec<String>, Vec<String>)> {
+    let (mut shallow, mut unshallow) = (Vec::new(), Vec::new());
+    while let Some(line) = reader.read_line()? {
+        match (line.strip_prefix("shallow "), line.strip_prefix("unshallow ")) {
+            (Some(sha), _) if is_object_id(sha) => shallow.push(sha.to_string()),
+            (_, Some(sha)) if is_object_id(sha) => unshallow.push(sha.to_string()),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("protocol error: expected shallow/unshallow, got '{}'", line),
+                ))
+            }
+        }
+    }
+    Ok((shallow, unshallow))
+}
+
+// The commits the repository has, most recent first as rev-list lists them,
+// to tell the remote about: those its refs and HEAD reach.
+fn haves(repo: &Repository) -> std::io::Result<Vec<String>> {
+    let mut tips: Vec<String> = Vec::new();
+    let head = refs::resolve(repo, "HEAD")?;
+    for sha in refs::list(repo)?.into_iter().map(|(_, sha)| sha).chain(head) {
+        // refs to trees and blobs, or to what a shallow fetch never got, say nothing
+        if let Ok(commit) = Commit::peel(repo, &sha) {
+            if !tips.contains(&commit) {
+                tips.push(commit);
+            }
+        }
+    }
+    match tips.is_empty() {
+        true => Ok(Vec::new()),
+        false => ancestry::rev_list(repo, &tips, &[]),
+    }
+}
+
+// Fetches the objects `wants` from the remote as fetch-pack does, and stores
+// the pack it sends; returns the pack's name, `None` when it is empty.
+// The commits the repository has are sent as `have` lines, a batch per
+// round, until the remote acknowledges one it has too, which then leaves
+// out what that commit reaches. A remote that acknowledges none of them
+// sends everything the wants reach. The remote's progress goes to
+// `progress` when it sends it on a side band. With a `depth`, the history
+// is cut that many commits down from the wants, and `shallow` updated
+// with the boundary the remote answers with.
+pub fn fetch_pack<T: Transport, P: Write>(
+    repo: &Repository,
+    transport: &mut T,
+    advertisement: &Advertisement,
+    wants: &[String],
+    depth: Option<u32>,
+    progress: P,
+) -> std::io::Result<Option<String>> {
+    let mut capabilities = Vec::new();
+    let side_band = ["side-band-64k", "side-band"].iter().copied().find(|band| advertisement.has(band));
+    capabilities.extend(side_band);
+    if advertisement.has("ofs-delta") {
+        capabilities.push("ofs-delta");
+    }
+    let agent = format!("agent={}", AGENT);
+    if advertisement.has("agent") {
+        capabilities.push(&agent);
+    }
+    let mut known_shallow = repo.shallow_commits()?;
+    let mut shallow: Vec<String> = known_shallow.iter().cloned().collect();
+    shallow.sort();
+    let deepens = depth.is_some() || !shallow.is_empty();
+    if deepens {
+        if !advertisement.has("shallow") {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "the remote doesn't support shallow clients"));
+        }
+        capabilities.push("shallow");
+    }
+    let wants = Wants { wants, capabilities: &capabilities, shallow: &shallow, depth };
+
+    let haves = haves(repo)?;
+    let mut common = None;
+    let mut sent = 0;
+    while common.is_none() && sent < haves.len().min(MAX_IN_VAIN) {
+        let batch = &haves[sent..haves.len().min(sent + HAVE_BATCH)];
+        sent += batch.len();
+        let mut reader = pkt_line::Reader::new(transport.upload_pack(request(&wants, batch, false))?);
+        if deepens {
+            read_shallow_section(&mut reader)?;
+        }
+        common = read_ack(&mut reader)?;
+    }
+
+    let haves: Vec<String> = common.into_iter().collect();
+    let mut reader = pkt_line::Reader::new(transport.upload_pack(request(&wants, &haves, true))?);
+    // the boundary of the last round is the one the pack is cut at
+    let (boundary, unshallow) = match deepens {
+        true => read_shallow_section(&mut reader)?,
+        false => Default::default(),
+    };
+    read_ack(&mut reader)?;
+    let mut pack = Vec::new();
+    match side_band {
+        Some(_) => pkt_line::demultiplex(&mut reader, &mut pack, &mut pkt_line::Progress::new(progress))?,
+        None => {
+            reader.into_inner().read_to_end(&mut pack)?;
+        }
+    }
+    // a pack without objects is only its header and checksum
+    let stored = match pack.len() {
+        32 => None,
+        _ => Some(pack::store(&repo.objects_dir(), &pack)?),
+    };
+    if deepens {
+        known_shallow.extend(boundary);
+        for sha in &unshallow {
+            known_shallow.remove(sha);
+        }
+        repo.set_shallow_commits(&known_shallow)?;
+    }
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::commit::Commit;
+    use super::*;
+
+    // A pack of the objects `ids` of `repo`, each one whole: the header, then
+    // for each its type and size and its zlib deflated content, then the
+    // checksum.
+    fn pack_of(repo: &Repository, ids: &[String]) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend((ids.len() as u32).to_be_bytes());
+        for id in ids {
+            let object = Blob::from_sha(repo, id).unwrap();
+            let code = match object.object_type() {
+                ObjectType::Commit => 1,
+                ObjectType::Tree => 2,
+                ObjectType::Blob => 3,
+                ObjectType::Tag => 4,
+            };
+            let mut size = object.content.len();
+            let mut header = vec![(code << 4) | (size & 0x0f) as u8];
+            size >>= 4;
+            while size > 0 {
+                *header.last_mut().unwrap() |= 0x80;
+                header.push((size & 0x7f) as u8);
+                size >>= 7;
+            }
+            pack.extend(header);
+            let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+            z.write_all(&object.content).unwrap();
+            pack.extend(z.finish().unwrap());
+        }
+        let checksum = sha1::Sha1::digest(&pack);
+        pack.extend(checksum);
+        pack
+    }
+
+    // The ids of the commits `tips` reach and of their trees and files, the
+    // parents of the `boundary` commits left out.
+    fn objects(repo: &Repository, tips: &[String], boundary: &[String]) -> Vec<String> {
+        let mut ids = Vec::new();
+        let mut pending = tips.to_vec();
+        while let Some(sha) = pending.pop() {
+            if ids.contains(&sha) {
+                continue;
+            }
+            let commit = Commit::read(repo, &sha).unwrap();
+            let tree = Tree::try_pars(&Blob::from_sha(repo, &commit.tree).unwrap()).unwrap();
+            ids.push(sha.clone());
+            ids.push(commit.tree);
+            ids.extend(tree.entries().iter().map(|entry| Blob::string_hash(&entry.sha)));
+            if !boundary.contains(&sha) {
+                pending.extend(commit.parents);
+            }
+        }
+        ids
+    }
+
+    // An upload-pack answering from `remote`, which has the objects of the
+    // wants and of the commits it acknowledges, with `boundary` as the
+    // shallow commits when asked to deepen. It keeps the lines of the
+    // requests, and each one's wants, haves and whether it was done.
+    struct Scripted<'a> {
+        remote: &'a Repository,
+        boundary: Vec<String>,
+        lines: Vec<String>,
+        requests: Vec<(Vec<String>, Vec<String>, bool)>,
+    }
+
+    impl<'a> Transport for Scripted<'a> {
+        fn advertisement(&mut self) -> std::io::Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn upload_pack(&mut self, request: Vec<u8>) -> std::io::Result<Box<dyn Read>> {
+            let mut reader = pkt_line::Reader::new(&request[..]);
+            let (mut wants, mut deepen) = (Vec::new(), false);
+            while let Some(line) = reader.read_line()? {
+                if let Some(want) = line.strip_prefix("want ") {
+                    wants.push(want[..40].to_string());
+                }
+                deepen |= line.starts_with("deepen ");
+                self.lines.push(line);
+            }
+            let (mut haves, mut done) = (Vec::new(), false);
+            while let Some(line) = reader.read_line()? {
+                self.lines.push(line.clone());
+                match line.strip_prefix("have ") {
+                    Some(have) => haves.push(have.to_string()),
+                    None => {
+                        done = line == "done";
+                        break;
+                    }
+                }
+            }
+            let mut response = Vec::new();
+            if deepen {
+                for sha in &self.boundary {
+                    pkt_line::write_line(&mut response, &format!("shallow {}", sha));
+                }
+                pkt_line::flush(&mut response);
+            }
+            let common: Vec<String> = haves.iter().filter(|sha| self.remote.has_object(sha)).take(1).cloned().collect();
+            match common.first() {
+                Some(sha) => pkt_line::write_line(&mut response, &format!("ACK {}", sha)),
+                None => pkt_line::write_line(&mut response, "NAK"),
+            }
+            if done {
+                let known = objects(self.remote, &common, &[]);
+                let ids: Vec<String> = objects(self.remote, &wants, &self.boundary).into_iter().filter(|sha| !known.contains(sha)).collect();
+                let data = pack_of(self.remote, &ids);
+                pkt_line::write(&mut response, &[&[2][..], b"Enumerating objects: done.\n"].concat());
+                for chunk in data.chunks(100) {
+                    pkt_line::write(&mut response, &[&[1][..], chunk].concat());
+                }
+                pkt_line::flush(&mut response);
+            }
+            self.requests.push((wants, haves, done));
+            Ok(Box::new(std::io::Cursor::new(response)))
+        }
+    }
+
+    #[test]
+    fn testing_advertisement() {
+        let mut data = Vec::new();
+        pkt_line::write_line(&mut data, "# service=git-upload-pack");
+        pkt_line::flush(&mut data);
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+        pkt_line::write_line(&mut data, &format!("{} HEAD\0multi_ack side-band-64k symref=HEAD:refs/heads/main agent=git/2", a));
+        pkt_line::write_line(&mut data, &format!("{} refs/heads/main", a));
+        pkt_line::write_line(&mut data, &format!("{} refs/tags/v1", b));
+        pkt_line::write_line(&mut data, &format!("{} refs/tags/v1^{{}}", a));
+        pkt_line::flush(&mut data);
+        let advertisement = Advertisement::parse(&data).unwrap();
+        let mut empty = Vec::new();
+        pkt_line::write_line(&mut empty, &format!("{} capabilities^{{}}\0ofs-delta", "0".repeat(40)));
+        pkt_line::flush(&mut empty);
+        let empty = Advertisement::parse(&empty).unwrap();
+
+        let refs = |refs: &[(&str, &str)]| refs.iter().map(|(name, sha)| (name.to_string(), sha.to_string())).collect::<Vec<_>>();
+        assert_eq!(advertisement.refs, refs(&[("HEAD", &a), ("refs/heads/main", &a), ("refs/tags/v1", &b)]));
+        assert!(advertisement.has("side-band-64k") && advertisement.has("agent") && !advertisement.has("shallow"));
+        assert_eq!(advertisement.head_ref(), Some("refs/heads/main"));
+        assert!(empty.refs.is_empty());
+        assert!(empty.has("ofs-delta"));
+        assert!(Advertisement::parse(b"0010not a ref\n0000").is_err());
+    }
+
+    // The pack the remote sends is unpacked and its progress shown; asked to
+    // deepen, the remote's boundary is written to `shallow`.
+    #[test]
+    fn testing_fetch_pack() {
+        let dir = TempDir::new("fetch-pack");
+        fs::create_dir_all(dir.join("remote/objects")).unwrap();
+        fs::create_dir_all(dir.join("local/objects")).unwrap();
+        let remote = Repository::open(dir.join("remote")).unwrap();
+        let local = Repository::open(dir.join("local")).unwrap();
+        let blob = Blob::new(ObjectType::Blob, b"content\n".to_vec());
+        hash::write_to_database(&remote, &blob).unwrap();
+        let mut files = std::collections::BTreeMap::new();
+        files.insert("file".to_string(), ("100644".to_string(), blob.hash_string.clone()));
+        let tree = tree::write_tree(&remote, &files).unwrap();
+        let commit = test_commit(&remote, &tree, &["1".repeat(40)], 0, "shallow\n");
+
+        let advertisement = Advertisement {
+            refs: Vec::new(),
+            capabilities: vec!["side-band-64k".to_string(), "shallow".to_string()],
+        };
+        let mut scripted = Scripted { remote: &remote, boundary: vec![commit.clone()], lines: Vec::new(), requests: Vec::new() };
+        let mut progress = Vec::new();
+        let unpacked = fetch_pack(&local, &mut scripted, &advertisement, std::slice::from_ref(&commit), Some(1), &mut progress);
+        let content = Blob::from_sha(&local, &blob.hash_string).map(|blob| blob.content);
+        let shallow = fs::read_to_string(dir.join("local/shallow"));
+        let packed = pack::Packs::open(&dir.join("local/objects")).unwrap().packs()[0].index().len();
+
+        assert!(unpacked.unwrap().is_some());
+        assert_eq!(packed, 3);
+        assert_eq!(
+            scripted.lines,
+            vec![format!("want {} side-band-64k shallow", commit), "deepen 1".to_string(), "done".to_string()]
+        );
+        assert_eq!(content.unwrap(), b"content\n");
+        assert_eq!(shallow.unwrap(), format!("{}\n", commit));
+        assert_eq!(String::from_utf8(progress).unwrap(), "remote: Enumerating objects: done.\n");
+    }
+
+    #[test]
+    fn testing_negotiation() {
+        let dir = TempDir::new("negotiation");
+        for name in ["local", "unrelated", "remote"] {
+            fs::create_dir_all(dir.join(name).join("objects")).unwrap();
+        }
+        let local = Repository::open(dir.join("local")).unwrap();
+        let unrelated = Repository::open(dir.join("unrelated")).unwrap();
+        let remote = Repository::open(dir.join("remote")).unwrap();
+        let commit = |repo: &Repository, message: &str, parents: Vec<String>, time: i64| {
+            let blob = Blob::new(ObjectType::Blob, message.as_bytes().to_vec());
+            hash::write_to_database(repo, &blob).unwrap();
+            let mut files = std::collections::BTreeMap::new();
+            files.insert(format!("{}.txt", message), ("100644".to_string(), blob.hash_string.clone()));
+            let tree = tree::write_tree(repo, &files).unwrap();
+            test_commit(repo, &tree, &parents, time, &format!("{}\n", message))
+        };
+        // the history both have, then 40 commits only the repository has and
+        // one only the remote has
+        let base = commit(&local, "base", Vec::new(), 0);
+        assert_eq!(commit(&remote, "base", Vec::new(), 0), base);
+        let mut tip = base.clone();
+        for i in 1..=40 {
+            tip = commit(&local, &format!("local {}", i), vec![tip], i);
+        }
+        refs::update(&local, "refs/heads/main", &tip, None).unwrap();
+        let wanted = commit(&remote, "remote", vec![base.clone()], 50);
+        refs::update(&unrelated, "refs/heads/main", &commit(&unrelated, "unrelated", Vec::new(), 0), None).unwrap();
+
+        let advertisement = Advertisement { refs: Vec::new(), capabilities: vec!["side-band-64k".to_string()] };
+        let mut scripted = Scripted { remote: &remote, boundary: Vec::new(), lines: Vec::new(), requests: Vec::new() };
+        let stored = fetch_pack(&local, &mut scripted, &advertisement, std::slice::from_ref(&wanted), None, std::io::sink());
+        let rounds: Vec<(usize, bool)> = scripted.requests.iter().map(|(_, haves, done)| (haves.len(), *done)).collect();
+        let last = scripted.requests.pop().unwrap();
+        let fetched = Commit::read(&local, &wanted).map(|commit| commit.parents);
+        let packed = pack::Packs::open(&dir.join("local/objects")).unwrap().packs()[0].index().len();
+
+        let mut scripted = Scripted { remote: &remote, boundary: Vec::new(), lines: Vec::new(), requests: Vec::new() };
+        fetch_pack(&unrelated, &mut scripted, &advertisement, std::slice::from_ref(&wanted), None, std::io::sink()).unwrap();
+        let unrelated_rounds: Vec<(usize, bool)> = scripted.requests.iter().map(|(_, haves, done)| (haves.len(), *done)).collect();
+        let everything = pack::Packs::open(&dir.join("unrelated/objects")).unwrap().packs()[0].index().len();
+
+        // 32 haves, then the other 8 and the base, which is acknowledged
+        assert_eq!(rounds, vec![(32, false), (9, false), (1, true)]);
+        assert_eq!(last, (vec![wanted], vec![base.clone()], true));
+        assert!(stored.unwrap().is_some());
+        assert_eq!(fetched.unwrap(), vec![base]);
+        // the commit, its tree and its blob, not what the base reaches
+        assert_eq!(packed, 3);
+        assert_eq!(unrelated_rounds, vec![(1, false), (0, true)]);
+        assert_eq!(everything, 6);
+    }
+}
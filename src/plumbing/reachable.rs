@@ -0,0 +1,132 @@
+use super::commit::Commit;
+use super::index::Index;
+use super::refs::NULL_ID;
+use super::*;
+use std::collections::HashSet;
+
+// The ids of the objects nothing may be pruned from under: the refs, every
+// old and new id of each reflog (the stash's entries among them), and the
+// HEAD, the blobs and the cached trees of the index of each worktree,
+// linked ones included.
+pub fn roots(repo: &Repository) -> std::io::Result<Vec<String>> {
+    let mut roots: Vec<String> = refs::list(repo)?.into_iter().map(|(_, sha)| sha).collect();
+    let mut logs = vec![repo.common_dir().join("logs/refs")];
+    for worktree in worktrees::list(repo)? {
+        roots.extend(worktree.head);
+        // HEAD's log is the worktree's own, those of the other refs are shared
+        logs.push(worktree.git_dir.join("logs/HEAD"));
+        let opened = Repository::open(&worktree.git_dir)?;
+        let index = Index::read(&opened)?;
+        roots.extend(index.entries.iter().filter(|entry| entry.mode != 0o160000).map(|entry| entry.sha.clone()));
+        roots.extend(Index::cached_trees(&opened)?);
+    }
+    while let Some(path) = logs.pop() {
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if metadata.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                logs.push(entry?.path());
+            }
+            continue;
+        }
+        for line in fs::read_to_string(&path)?.lines() {
+            let ids = line.split(' ').take(2).filter(|id| is_object_id(id) && *id != NULL_ID);
+            roots.extend(ids.map(String::from));
+        }
+    }
+    roots.sort();
+    roots.dedup();
+    Ok(roots)
+}
+
+// Every object the roots lead to. Objects are read as stored, so an object
+// a replace ref stands in for is kept along with its replacement.
+pub fn objects(repo: &Repository) -> std::io::Result<HashSet<String>> {
+    let mut pending = roots(repo)?;
+    let mut reached = HashSet::new();
+    while let Some(sha) = pending.pop() {
+        if !reached.insert(sha.clone()) {
+            continue;
+        }
+        let object = Blob::from_store(repo.objects(), &sha)?;
+        match object.object_type() {
+            ObjectType::Commit => {
+                let commit = Commit::parse(&object.content)?;
+                pending.push(commit.tree);
+                pending.extend(commit.parents);
+            }
+            ObjectType::Tag => pending.push(tag::Tag::parse(&object.content)?.object),
+            ObjectType::Tree => {
+                let tree = Tree::try_pars(&object)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+                let entries = tree.entries().iter().filter(|entry| entry.entry_type != EntryType::Gitlink);
+                pending.extend(entries.map(|entry| Blob::string_hash(&entry.sha)));
+            }
+            ObjectType::Blob => {}
+        }
+    }
+    Ok(reached)
+}
+
+// The loose objects of the repository itself, not its alternates, that
+// nothing reaches.
+pub fn unreachable(repo: &Repository) -> std::io::Result<Vec<String>> {
+    let reached = objects(repo)?;
+    let mut unreachable = Vec::new();
+    for sha in AllObjects::new(vec![repo.objects_dir()]) {
+        let sha = sha?;
+        if !reached.contains(&sha) {
+            unreachable.push(sha);
+        }
+    }
+    Ok(unreachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::index::Entry;
+    use super::*;
+
+    #[test]
+    fn testing_reachable_roots() {
+        let dir = TempDir::new("reachable");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::create_dir_all(git_dir.join("logs/refs/heads")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let blob = |content: &str| repo.write_blob(content.as_bytes()).unwrap();
+        let commit = |content: &str| {
+            let sha = blob(content);
+            let entry = TreeEntry {
+                mode: "100644".to_string(),
+                entry_type: EntryType::Blob,
+                sha: Blob::decode_hex(&sha).unwrap().try_into().unwrap(),
+                name: "a".to_string(),
+            };
+            let tree = repo.write_tree(&[entry]).unwrap();
+            test_commit(&repo, &tree, &[] as &[&str], 0, content)
+        };
+        let current = commit("current\n");
+        let amended = commit("amended away\n");
+        let replacement = commit("replacement\n");
+        let staged = blob("staged\n");
+        let dangling = blob("dangling\n");
+        refs::update(&repo, "refs/heads/master", &current, None).unwrap();
+        refs::update(&repo, &format!("refs/replace/{}", current), &replacement, None).unwrap();
+        let log = format!("{} {} A <a@b> 0 +0000\tcommit\n{} {} A <a@b> 0 +0000\tamend\n", NULL_ID, amended, amended, current);
+        fs::write(git_dir.join("logs/refs/heads/master"), log).unwrap();
+        Index { entries: vec![Entry::new("a", "100644", &staged)] }.write(&repo).unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let unreachable = unreachable(&repo).unwrap();
+        let reached = objects(&repo).unwrap();
+
+        assert_eq!(unreachable, vec![dangling]);
+        for sha in [&current, &amended, &replacement, &staged] {
+            assert!(reached.contains(sha));
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use super::*;
+
+// git gives up on chains of symbolic refs longer than this
+const MAX_SYMREF_DEPTH: usize = 5;
+
+// the id a ref that doesn't exist is at, in reflogs and for `update`
+pub const NULL_ID: &str = "0000000000000000000000000000000000000000";
+
+fn not_found_is_none(read: std::io::Result<String>) -> std::io::Result<Option<String>> {
+    match read {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// The ref a symbolic ref such as HEAD points to, `None` when it holds an
+// object id or doesn't exist.
+pub fn symbolic_target(repo: &Repository, name: &str) -> std::io::Result<Option<String>> {
+    let content = not_found_is_none(fs::read_to_string(repo.ref_path(name)))?;
+    Ok(content.and_then(|content| content.strip_prefix("ref: ").map(|target| target.trim_end().to_string())))
+}
+
+// The object id the ref `name` (`HEAD`, `refs/heads/master`...) points to,
+// following symbolic refs and falling back to packed-refs.
+pub fn resolve(repo: &Repository, name: &str) -> std::io::Result<Option<String>> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        let path = repo.ref_path(&name);
+        let content = match path.is_dir() {
+            true => None,
+            false => not_found_is_none(fs::read_to_string(&path))?,
+        };
+        match content {
+            None => return packed_ref(repo, &name),
+            Some(content) => match content.strip_prefix("ref: ") {
+                Some(target) => name = target.trim_end().to_string(),
+                None => return Ok(Some(content.trim_end().to_string())),
+            },
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("symbolic ref loop at {}", name),
+    ))
+}
+
+// Looks `name` up in `packed-refs`, whose lines are `<sha> <ref>`, with `^<sha>`
+// lines for the objects annotated tags point to.
+fn packed_ref(repo: &Repository, name: &str) -> std::io::Result<Option<String>> {
+    let packed = match not_found_is_none(fs::read_to_string(repo.common_dir().join("packed-refs")))? {
+        Some(packed) => packed,
+        None => return Ok(None),
+    };
+    Ok(packed
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| line.split_once(' '))
+        .find(|(_, ref_name)| *ref_name == name)
+        .map(|(sha, _)| sha.to_string()))
+}
+
+// Resolves a full object id, or a ref name the way git expands a short one: as
+// given, then under refs/, refs/tags/, refs/heads/ and refs/remotes/.
+pub fn object_id(repo: &Repository, name: &str) -> std::io::Result<String> {
+    if is_object_id(name) {
+        return Ok(name.to_ascii_lowercase());
+    }
+    for candidate in &candidates(name) {
+        if let Some(sha) = resolve(repo, candidate)? {
+            return Ok(sha);
+        }
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::NotFound, UnknownRevision(name.to_string())))
+}
+
+// What `object_id` fails with for a name that is neither an object id
+// nor a ref.
+#[derive(Debug, thiserror::Error)]
+#[error("unknown revision: {0}")]
+pub struct UnknownRevision(pub String);
+
+fn candidates(name: &str) -> [String; 6] {
+    [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ]
+}
+
+// The full name of the ref a short `name` stands for, expanded the way
+// `object_id` does and with symbolic refs followed: `master` is
+// `refs/heads/master`, and so is `HEAD` while master is checked out.
+pub fn full_name(repo: &Repository, name: &str) -> std::io::Result<Option<String>> {
+    for candidate in &candidates(name) {
+        if resolve(repo, candidate)?.is_some() {
+            let mut name = candidate.clone();
+            for _ in 0..MAX_SYMREF_DEPTH {
+                match symbolic_target(repo, &name)? {
+                    Some(target) => name = target,
+                    None => break,
+                }
+            }
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+// Every ref under `refs/`, loose or packed, with the object id it points to,
+// sorted by name.
+pub fn list(repo: &Repository) -> std::io::Result<Vec<(String, String)>> {
+    let mut refs = std::collections::BTreeMap::new();
+    if let Some(packed) = not_found_is_none(fs::read_to_string(repo.common_dir().join("packed-refs")))? {
+        for line in packed.lines().filter(|line| !line.starts_with('#') && !line.starts_with('^')) {
+            if let Some((sha, name)) = line.split_once(' ') {
+                refs.insert(name.to_string(), sha.to_string());
+            }
+        }
+    }
+    collect_loose(repo, "refs", &mut refs)?;
+    if repo.git_dir() != repo.common_dir() {
+        for dir in WORKTREE_REFS {
+            collect_loose(repo, dir, &mut refs)?;
+        }
+    }
+    Ok(refs.into_iter().collect())
+}
+
+// loose refs take precedence over packed ones of the same name
+fn collect_loose(
+    repo: &Repository,
+    dir: &str,
+    refs: &mut std::collections::BTreeMap<String, String>,
+) -> std::io::Result<()> {
+    let entries = match fs::read_dir(repo.ref_path(dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose(repo, &name, refs)?;
+        } else if let Some(sha) = resolve(repo, &name)? {
+            refs.insert(name, sha);
+        }
+    }
+    Ok(())
+}
+
+// Points `name` at `sha`. When `name` is a symbolic ref like HEAD, the ref it
+// points to is the one updated. With `old`, the ref has to be at `old` once
+// it is locked, or not exist when `old` is `NULL_ID`, so that what another
+// process wrote meanwhile isn't lost.
+pub fn update(repo: &Repository, name: &str, sha: &str, old: Option<&str>) -> std::io::Result<()> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match symbolic_target(repo, &name)? {
+            Some(target) => name = target,
+            None => break,
+        }
+    }
+    let path = repo.ref_path(&name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let (lock, mut file) = lock(&path)?;
+    let written = expect(repo, &name, old)
+        .and_then(|_| file.write_all(format!("{}\n", sha).as_bytes()))
+        .and_then(|_| fs::rename(&lock, &path));
+    if written.is_err() {
+        let _ = fs::remove_file(&lock);
+    }
+    written
+}
+
+// fails unless the ref `name` is at `old`, as `update` is asked to check
+fn expect(repo: &Repository, name: &str, old: Option<&str>) -> std::io::Result<()> {
+    let old = match old {
+        Some(old) => old,
+        None => return Ok(()),
+    };
+    let problem = match (resolve(repo, name)?, old) {
+        (None, NULL_ID) => return Ok(()),
+        (Some(current), _) if current == old => return Ok(()),
+        (Some(_), NULL_ID) => "reference already exists".to_string(),
+        (Some(current), _) => format!("is at {} but expected {}", current, old),
+        (None, _) => format!("unable to resolve reference '{}'", name),
+    };
+    Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("cannot lock ref '{}': {}", name, problem)))
+}
+
+// Removes the loose ref `name`, a packed one is left alone. The ref is locked
+// meanwhile, so that it isn't removed under a process updating it.
+pub fn delete(repo: &Repository, name: &str) -> std::io::Result<()> {
+    let path = repo.ref_path(name);
+    let (lock, _) = match lock(&path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        locked => locked?,
+    };
+    let removed = match fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+    fs::remove_file(&lock)?;
+    removed
+}
+
+// Whether `name` is a well-formed ref name as `git check-ref-format` sees
+// it: slash separated components that don't start with a dot or end with
+// `.lock`, without `..`, `@{`, control characters, spaces or any of `~^:?*[\`.
+pub fn is_valid_name(name: &str) -> bool {
+    let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    !name.is_empty()
+        && name != "@"
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.chars().any(forbidden)
+        && name
+            .split('/')
+            .all(|component| !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_ref_locks() {
+        let dir = TempDir::new("ref-locks");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        fs::create_dir_all(dir.join("refs/heads")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let (one, two) = ("1".repeat(40), "2".repeat(40));
+        update(&repo, "refs/heads/master", &one, None).unwrap();
+        fs::write(dir.join("refs/heads/master.lock"), "").unwrap();
+        let blocked_update = update(&repo, "refs/heads/master", &two, None).is_err();
+        let blocked_delete = delete(&repo, "refs/heads/master").is_err();
+        let kept = resolve(&repo, "refs/heads/master").unwrap();
+        fs::remove_file(dir.join("refs/heads/master.lock")).unwrap();
+        delete(&repo, "refs/heads/master").unwrap();
+        let deleted = resolve(&repo, "refs/heads/master").unwrap();
+        let unlocked = !dir.join("refs/heads/master.lock").exists();
+
+        assert!(blocked_update && blocked_delete);
+        assert_eq!(kept, Some(one));
+        assert_eq!(deleted, None);
+        assert!(unlocked);
+    }
+
+    // An update expecting an old value only goes through while the ref still
+    // has it, and leaves the ref unlocked either way.
+    #[test]
+    fn testing_expected_old_values() {
+        let dir = TempDir::new("ref-old-values");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        fs::write(dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let (one, two, three) = ("1".repeat(40), "2".repeat(40), "3".repeat(40));
+        let created = update(&repo, "HEAD", &one, Some(NULL_ID)).is_ok();
+        let existing = update(&repo, "HEAD", &two, Some(NULL_ID)).map_err(|e| e.to_string());
+        let stale = update(&repo, "HEAD", &three, Some(&two)).map_err(|e| e.to_string());
+        let missing = update(&repo, "refs/heads/other", &two, Some(&one)).is_err();
+        let moved = update(&repo, "HEAD", &two, Some(&one)).is_ok();
+
+        assert!(created && moved && missing);
+        assert_eq!(existing, Err("cannot lock ref 'refs/heads/master': reference already exists".to_string()));
+        assert_eq!(stale, Err(format!("cannot lock ref 'refs/heads/master': is at {} but expected {}", one, two)));
+        assert_eq!(resolve(&repo, "HEAD").unwrap(), Some(two));
+        assert!(!dir.join("refs/heads/master.lock").exists());
+        assert_eq!(resolve(&repo, "refs/heads/other").unwrap(), None);
+    }
+}
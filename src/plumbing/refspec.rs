@@ -0,0 +1,130 @@
+// A fetch refspec such as `+refs/heads/*:refs/remotes/origin/*`: the remote refs
+// matching `src` are stored under `dst`, `force` allowing updates that aren't
+// fast-forwards. Without `dst` the matching refs are fetched but not stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefSpec {
+    pub force: bool,
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+// One ref a fetch writes: the remote ref `src` goes to the local ref `dst`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    pub src: String,
+    pub dst: Option<String>,
+    pub force: bool,
+}
+
+impl RefSpec {
+    pub fn parse(spec: &str) -> std::io::Result<Self> {
+        let invalid = || {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid refspec '{}'", spec))
+        };
+        let (force, rest) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (src, dst) = match rest.split_once(':') {
+            Some((src, dst)) => (src, Some(dst).filter(|dst| !dst.is_empty())),
+            None => (rest, None),
+        };
+        let globs = |s: &str| s.matches('*').count();
+        let bad = |s: &str| s.is_empty() || s.contains(':') || s.chars().any(|c| c.is_whitespace() || c.is_control());
+        // a glob source needs a glob destination and the other way around
+        if bad(src) || matches!(dst, Some(dst) if bad(dst)) || globs(src) > 1 || dst.map_or(globs(src), globs) != globs(src) {
+            return Err(invalid());
+        }
+        Ok(RefSpec { force, src: src.to_string(), dst: dst.map(String::from) })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    // Whether the remote ref `name` matches `src`. A full source must name the
+    // ref exactly, a short one like `master` matches it the way git expands names.
+    pub fn matches(&self, name: &str) -> bool {
+        if let Some((prefix, suffix)) = self.src.split_once('*') {
+            return name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix);
+        }
+        let src = &self.src;
+        [src.to_string(), format!("refs/{}", src), format!("refs/tags/{}", src), format!("refs/heads/{}", src)]
+            .iter()
+            .any(|candidate| candidate == name)
+    }
+
+    // The local ref the remote ref `name` is stored in, `None` when it doesn't
+    // match or the spec has no destination.
+    pub fn map(&self, name: &str) -> Option<String> {
+        if !self.matches(name) {
+            return None;
+        }
+        let dst = self.dst.as_ref()?;
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => Some(dst.replacen('*', &name[prefix.len()..name.len() - suffix.len()], 1)),
+            None => Some(dst.clone()),
+        }
+    }
+}
+
+// The updates the `specs` make for the `advertised` remote refs, in the order of
+// the refs. A remote ref matched by several specs is fetched once per distinct
+// destination, and a non-glob spec matches at most one ref, the first one.
+pub fn match_refs(specs: &[RefSpec], advertised: &[String]) -> Vec<Update> {
+    let mut updates: Vec<Update> = Vec::new();
+    let mut matched_exact = vec![false; specs.len()];
+    for name in advertised {
+        for (i, spec) in specs.iter().enumerate() {
+            if !spec.matches(name) || (!spec.is_glob() && matched_exact[i]) {
+                continue;
+            }
+            matched_exact[i] = !spec.is_glob();
+            let update = Update { src: name.clone(), dst: spec.map(name), force: spec.force };
+            match updates.iter_mut().find(|u| u.src == update.src && u.dst == update.dst) {
+                Some(existing) => existing.force |= update.force,
+                None => updates.push(update),
+            }
+        }
+    }
+    updates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_refspec_matching() {
+        let specs = [
+            RefSpec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap(),
+            RefSpec::parse("refs/tags/v*.0:refs/tags/release-*").unwrap(),
+            RefSpec::parse("main").unwrap(),
+        ];
+        assert!(specs[0].force && !specs[1].force);
+        assert_eq!(specs[2].dst, None);
+        let advertised: Vec<String> = ["HEAD", "refs/heads/main", "refs/heads/topic/x", "refs/tags/v1.0", "refs/tags/v1.1"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let update = |src: &str, dst: Option<&str>, force| Update {
+            src: src.to_string(),
+            dst: dst.map(String::from),
+            force,
+        };
+        assert_eq!(
+            match_refs(&specs, &advertised),
+            vec![
+                update("refs/heads/main", Some("refs/remotes/origin/main"), true),
+                update("refs/heads/main", None, false),
+                update("refs/heads/topic/x", Some("refs/remotes/origin/topic/x"), true),
+                update("refs/tags/v1.0", Some("refs/tags/release-1"), false),
+            ]
+        );
+        for bad in &["refs/heads/*:refs/remotes/origin/main", "", "+:x", "a b:c", "refs/*/*:refs/*/*"] {
+            assert!(RefSpec::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use super::*;
+
+// Adds the remote `name`, fetching its branches into `refs/remotes/<name>/`.
+pub fn add(repo: &Repository, name: &str, url: &str) -> std::io::Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| !c.is_whitespace() && !c.is_control() && !"~^:?*[\\".contains(c));
+    if !valid {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{}' is not a valid remote name", name),
+        ));
+    }
+    if repo.config().subsections("remote").contains(&name) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("remote {} already exists", name),
+        ));
+    }
+    let path = repo.common_dir().join("config");
+    config::set(&path, &format!("remote.{}.url", name), url)?;
+    config::set(&path, &format!("remote.{}.fetch", name), &format!("+refs/heads/*:refs/remotes/{}/*", name))
+}
+
+// Prints the names of the remotes, with `verbose` followed by the urls they're
+// fetched from and pushed to, like `remote -v`.
+pub fn list(repo: &Repository, verbose: bool) -> std::io::Result<()> {
+    let config = repo.config();
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for name in config.subsections("remote") {
+        if !verbose {
+            writeln!(out, "{}", name)?;
+            continue;
+        }
+        let url = config.get(&format!("remote.{}.url", name)).unwrap_or("");
+        let push_url = config.get(&format!("remote.{}.pushurl", name)).unwrap_or(url);
+        writeln!(out, "{}\t{} (fetch)", name, url)?;
+        writeln!(out, "{}\t{} (push)", name, push_url)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use super::commit::Commit;
+use super::index::Index;
+use super::sparse::Sparse;
+use super::*;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // only moves the branch
+    Soft,
+    // resets the index too
+    Mixed,
+    // resets the index and the working tree
+    Hard,
+}
+
+// Points HEAD's branch at `rev`, saving the old commit as ORIG_HEAD, and
+// resets the index and working tree to it as far as `mode` says.
+pub fn reset(repo: &Repository, mode: Mode, rev: &str) -> std::io::Result<()> {
+    let target = Commit::peel(repo, &revision::resolve(repo, rev)?)?;
+    let commit = Commit::read(repo, &target)?;
+    let files = tree::files(repo, &commit.tree)?;
+    let index = Index::read(repo)?;
+    let head = refs::resolve(repo, "HEAD")?;
+    match mode {
+        Mode::Soft => {}
+        Mode::Mixed => {
+            let mut new_index = index.with_files(&files);
+            new_index.apply_sparse(Sparse::read(repo)?.as_ref());
+            new_index.refresh(repo)?;
+            new_index.write(repo)?;
+            let unstaged = worktree::changes(repo, &new_index)?;
+            if !unstaged.is_empty() {
+                outln!("Unstaged changes after reset:")?;
+                for (path, file) in unstaged {
+                    outln!("{}\t{}", if file.is_some() { "M" } else { "D" }, path)?;
+                }
+            }
+        }
+        Mode::Hard => {
+            // everything tracked goes, conflicted paths included
+            let mut tracked: BTreeMap<String, (String, String)> = match index.entries.is_empty() {
+                true => match &head {
+                    Some(head) => tree::files(repo, &Commit::read(repo, head)?.tree)?,
+                    None => BTreeMap::new(),
+                },
+                false => BTreeMap::new(),
+            };
+            for entry in &index.entries {
+                tracked.insert(entry.path.clone(), (entry.mode_string(), entry.sha.clone()));
+            }
+            let mut new_index = index.with_files(&files);
+            new_index.apply_sparse(Sparse::read(repo)?.as_ref());
+            worktree::reset(repo, &tracked, &new_index.checked_out())?;
+            new_index.refresh(repo)?;
+            new_index.write(repo)?;
+            for name in ["MERGE_HEAD", "MERGE_MSG", "CHERRY_PICK_HEAD"] {
+                refs::delete(repo, name)?;
+            }
+        }
+    }
+    if let Some(head) = &head {
+        write_locked(&repo.git_dir().join("ORIG_HEAD"), format!("{}\n", head).as_bytes())?;
+    }
+    refs::update(repo, "HEAD", &target, Some(head.as_deref().unwrap_or(refs::NULL_ID)))?;
+    if mode == Mode::Hard {
+        outln!("HEAD is now at {} {}", &target[..7], commit.subject())?;
+    }
+    Ok(())
+}
+
+// Replaces the index with the tree `rev`. With `update` the working tree
+// follows, refusing to lose local changes unless `force`. Either way paths
+// outside a sparse checkout are marked skip-worktree and not written.
+pub fn read_tree(repo: &Repository, rev: &str, update: bool, force: bool) -> std::io::Result<()> {
+    let files = tree::files(repo, &diff::peel_to_tree(repo, &revision::resolve(repo, rev)?)?)?;
+    let index = Index::read(repo)?;
+    let mut new_index = index.with_files(&files);
+    new_index.apply_sparse(Sparse::read(repo)?.as_ref());
+    if update {
+        match force {
+            true => worktree::reset(repo, &index.files(), &new_index.checked_out())?,
+            false => worktree::update(repo, &index.checked_out(), &new_index.checked_out(), &[])?,
+        }
+        new_index.refresh(repo)?;
+    }
+    new_index.write(repo)
+}
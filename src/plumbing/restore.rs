@@ -0,0 +1,100 @@
+use super::index::{Entry, Index};
+use super::*;
+use std::collections::BTreeMap;
+
+// the files of `files` at or under one of `paths`
+fn matching(
+    files: &BTreeMap<String, (String, String)>,
+    paths: &[String],
+) -> BTreeMap<String, (String, String)> {
+    let matches = |file: &str| {
+        paths.iter().any(|path| {
+            let path = path.trim_end_matches('/');
+            path == "." || file == path || matches!(file.strip_prefix(path), Some(rest) if rest.starts_with('/'))
+        })
+    };
+    files.iter().filter(|(file, _)| matches(file)).map(|(file, entry)| (file.clone(), entry.clone())).collect()
+}
+
+// the files at or under `paths` in the tree `tree`, only their subtrees read
+fn from_tree(
+    repo: &Repository,
+    tree: &str,
+    paths: &[String],
+) -> std::io::Result<BTreeMap<String, (String, String)>> {
+    let mut files = BTreeMap::new();
+    for path in paths {
+        let path = match path.trim_matches('/') {
+            "." => "",
+            path => path,
+        };
+        match repo.resolve_path(tree, path) {
+            Ok((mode, sha)) if mode == "40000" => {
+                let prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+                tree::flatten(repo, &sha, &prefix, &mut files)?;
+            }
+            Ok(entry) => {
+                files.insert(path.to_string(), entry);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(files)
+}
+
+// Overwrites the working tree files at `paths` with their version in the
+// index, or in the commit or tree `source`. Tracked files that `source`
+// doesn't have are removed, unless `checkout`: then they're kept, and the
+// index gets the `source` versions too, as `checkout <commit> -- <path>`
+// does.
+pub fn restore(repo: &Repository, source: Option<&str>, paths: &[String], checkout: bool) -> std::io::Result<()> {
+    let mut index = Index::read(repo)?;
+    let mut tracked = matching(&index.files(), paths);
+    let files = match source {
+        Some(rev) => from_tree(repo, &diff::peel_to_tree(repo, &revision::resolve(repo, rev)?)?, paths)?,
+        None => tracked.clone(),
+    };
+    for path in paths {
+        let known = |files: &BTreeMap<String, (String, String)>| !matching(files, std::slice::from_ref(path)).is_empty();
+        if !known(&tracked) && !known(&files) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("pathspec '{}' did not match any file(s) known to git", path),
+            ));
+        }
+    }
+    if checkout {
+        tracked.retain(|path, _| files.contains_key(path));
+    }
+    worktree::reset(repo, &tracked, &files)?;
+    if checkout && source.is_some() {
+        index.entries.retain(|entry| !files.contains_key(&entry.path));
+        index.entries.extend(files.iter().map(|(path, (mode, sha))| Entry::new(path, mode, sha)));
+    } else if index.entries.is_empty() {
+        return Ok(());
+    }
+    index.refresh(repo)?;
+    index.write(repo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_path_matching() {
+        let files: BTreeMap<String, (String, String)> = ["a", "ab", "a/b", "a/b/c", "b"]
+            .iter()
+            .map(|path| (path.to_string(), (String::new(), String::new())))
+            .collect();
+        let matched = |paths: &[&str]| {
+            let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+            matching(&files, &paths).into_keys().collect::<Vec<_>>()
+        };
+        assert_eq!(matched(&["a"]), vec!["a", "a/b", "a/b/c"]);
+        assert_eq!(matched(&["a/b/", "b"]), vec!["a/b", "a/b/c", "b"]);
+        assert_eq!(matched(&["."]).len(), 5);
+        assert!(matched(&["c"]).is_empty());
+    }
+}
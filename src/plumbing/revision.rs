@@ -0,0 +1,280 @@
+use super::commit::Commit;
+use super::index::Index;
+use super::*;
+
+// Resolves an object name as git does for any object argument: a full
+// id or a ref name, `<rev>:<path>` for the object at `path` in the tree of
+// `<rev>`, and `:<path>` or `:<stage>:<path>` for the blob the index has at
+// `path`.
+pub fn resolve(repo: &Repository, spec: &str) -> std::io::Result<String> {
+    resolve_with(repo, spec, false)
+}
+
+// Like `resolve`, but with the symlinks in the path of a `<rev>:<path>`
+// followed to what they point at in the tree of `<rev>`.
+pub fn resolve_following_symlinks(repo: &Repository, spec: &str) -> std::io::Result<String> {
+    resolve_with(repo, spec, true)
+}
+
+fn resolve_with(repo: &Repository, spec: &str, follow_symlinks: bool) -> std::io::Result<String> {
+    if let Some(rest) = spec.strip_prefix(':') {
+        let (stage, path) = match rest.as_bytes() {
+            [stage @ b'0'..=b'3', b':', ..] => ((stage - b'0') as u16, &rest[2..]),
+            _ => (0, rest),
+        };
+        return from_index(repo, stage, path);
+    }
+    match spec.split_once(':') {
+        Some((rev, path)) => {
+            let tree = diff::peel_to_tree(repo, &resolve(repo, rev)?)?;
+            let found = match follow_symlinks {
+                true => repo.resolve_path_following_symlinks(&tree, path),
+                false => repo.resolve_path(&tree, path),
+            };
+            match found {
+                Ok((_, sha)) => Ok(sha),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("path '{}' does not exist in '{}'", path, rev),
+                )),
+                Err(e) => Err(e),
+            }
+        }
+        None => with_suffixes(repo, spec),
+    }
+}
+
+// the path a `<rev>:<path>`, `:<path>` or `:<stage>:<path>` name gives
+pub fn path_of(spec: &str) -> Option<&str> {
+    match spec.strip_prefix(':') {
+        Some(rest) => match rest.as_bytes() {
+            [b'0'..=b'3', b':', ..] => Some(&rest[2..]),
+            _ => Some(rest),
+        },
+        None => spec.split_once(':').map(|(_, path)| path),
+    }
+}
+
+// `<name>` followed by any of `~<n>` (the n-th first parent), `^<n>` (the
+// n-th parent, `^0` peels to the commit), `^{<type>}` (peels to that type)
+// and `^{}` (peels tags).
+fn with_suffixes(repo: &Repository, spec: &str) -> std::io::Result<String> {
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid revision: {}", spec));
+    let base = spec.find(['^', '~']).unwrap_or(spec.len());
+    let mut sha = refs::object_id(repo, &spec[..base])?;
+    let mut rest = &spec[base..];
+    while !rest.is_empty() {
+        let operator = rest.as_bytes()[0];
+        rest = &rest[1..];
+        if operator == b'^' && rest.starts_with('{') {
+            let end = rest.find('}').ok_or_else(invalid)?;
+            sha = match &rest[1..end] {
+                "" => peel(repo, &sha, None)?,
+                "object" => sha,
+                name => {
+                    let wanted = ObjectType::from_bytes(name.as_bytes()).ok_or_else(invalid)?;
+                    peel(repo, &sha, Some(wanted))?
+                }
+            };
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let count: usize = match digits {
+            0 => 1,
+            _ => rest[..digits].parse().map_err(|_| invalid())?,
+        };
+        rest = &rest[digits..];
+        let commit = peel(repo, &sha, Some(ObjectType::Commit))?;
+        let no_parent = || std::io::Error::new(std::io::ErrorKind::NotFound, format!("{}: no such parent", spec));
+        sha = match operator {
+            b'^' if count == 0 => commit,
+            b'^' => Commit::read(repo, &commit)?.parents.get(count - 1).cloned().ok_or_else(no_parent)?,
+            _ => {
+                let mut sha = commit;
+                for _ in 0..count {
+                    sha = Commit::read(repo, &sha)?.parents.first().cloned().ok_or_else(no_parent)?;
+                }
+                sha
+            }
+        };
+    }
+    Ok(sha)
+}
+
+// Follows tags, and commits to their tree, until an object of the `wanted`
+// type, or the first one that isn't a tag without `wanted`.
+fn peel(repo: &Repository, sha: &str, wanted: Option<ObjectType>) -> std::io::Result<String> {
+    let mut sha = sha.to_string();
+    loop {
+        let object = Blob::from_sha(repo, &sha)?;
+        let object_type = object.object_type();
+        let done = match wanted {
+            Some(wanted) => wanted == object_type,
+            None => object_type != ObjectType::Tag,
+        };
+        if done {
+            return Ok(sha);
+        }
+        sha = match object_type {
+            ObjectType::Tag => tag::Tag::parse(&object.content)?.object,
+            ObjectType::Commit if wanted == Some(ObjectType::Tree) => Commit::parse(&object.content)?.tree,
+            _ => {
+                let wanted = wanted.map_or("any", |wanted| wanted.as_str());
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("expected {} type, but the object dereferences to {} type", wanted, object_type),
+                ));
+            }
+        };
+    }
+}
+
+fn from_index(repo: &Repository, stage: u16, path: &str) -> std::io::Result<String> {
+    let index = Index::read(repo)?;
+    let mut entries = index.entries.iter().filter(|entry| entry.path == path);
+    if let Some(entry) = entries.clone().find(|entry| entry.stage() == stage) {
+        return Ok(entry.sha.clone());
+    }
+    let message = match entries.next() {
+        Some(_) => format!("path '{}' is in the index, but not at stage {}", path, stage),
+        None => format!("path '{}' is not in the index", path),
+    };
+    Err(std::io::Error::new(std::io::ErrorKind::NotFound, message))
+}
+
+// The commits revision arguments ask a history walk for: those it starts
+// from, and those whose ancestors it leaves out, named with a leading `^`.
+// `A..B` is `^A B`, and `A...B` starts from both and leaves out their merge
+// bases. A side left empty is `HEAD`.
+#[derive(Default)]
+pub struct Range {
+    pub tips: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+impl Range {
+    pub fn parse(repo: &Repository, revs: &[&str]) -> std::io::Result<Self> {
+        let commit = |rev: &str| Commit::peel(repo, &resolve(repo, if rev.is_empty() { "HEAD" } else { rev })?);
+        let mut range = Self::default();
+        for rev in revs {
+            if let Some(excluded) = rev.strip_prefix('^') {
+                range.excluded.push(commit(excluded)?);
+            } else if let Some((a, b)) = rev.split_once("...") {
+                let (a, b) = (commit(a)?, commit(b)?);
+                range.excluded.extend(ancestry::merge_bases(repo, &a, &b)?);
+                range.tips.extend([a, b]);
+            } else if let Some((a, b)) = rev.split_once("..") {
+                range.excluded.push(commit(a)?);
+                range.tips.push(commit(b)?);
+            } else {
+                range.tips.push(commit(rev)?);
+            }
+        }
+        Ok(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index::Entry;
+    use super::*;
+
+    #[test]
+    fn testing_revision_paths() {
+        let dir = TempDir::new("revision");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let file = "cd591dba9391e2cdfbae51a51800b9689c7ea360";
+        let other = "7108f7ecb345ee9d0084193f147cdad4d2998293";
+        let mut files = std::collections::BTreeMap::new();
+        files.insert("sub/file".to_string(), ("100644".to_string(), file.to_string()));
+        let tree = tree::write_tree(&repo, &files).unwrap();
+        refs::update(&repo, "refs/tags/snapshot", &tree, None).unwrap();
+        let mut conflicted = Entry::new("both", "100644", other);
+        conflicted.flags = 2 << 12;
+        let index = Index { entries: vec![Entry::new("sub/file", "100644", file), conflicted] };
+        index.write(&repo).unwrap();
+
+        let specs = ["snapshot:sub/file", &format!("{}:sub", tree), ":sub/file", ":0:sub/file", ":2:both"];
+        let resolved: Vec<_> = specs
+            .iter()
+            .map(|spec| resolve(&repo, spec).unwrap())
+            .collect();
+        let errors: Vec<_> = ["snapshot:nope", ":both", ":nope"]
+            .iter()
+            .map(|spec| resolve(&repo, spec).unwrap_err().to_string())
+            .collect();
+        let subtree = repo.resolve_path(&tree, "sub").unwrap().1;
+        assert_eq!(resolved, vec![file, &subtree, file, file, other]);
+        assert_eq!(
+            errors,
+            vec![
+                "path 'nope' does not exist in 'snapshot'",
+                "path 'both' is in the index, but not at stage 0",
+                "path 'nope' is not in the index",
+            ]
+        );
+    }
+
+    #[test]
+    fn testing_revision_suffixes() {
+        let dir = TempDir::new("suffixes");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let tree = EMPTY_TREE.to_string();
+        hash::write_to_database(&repo, &Blob::new(ObjectType::Tree, Vec::new())).unwrap();
+        let commit = |parents: &[&String], message: &str| test_commit(&repo, &tree, parents, 0, message);
+        let root = commit(&[], "root");
+        let side = commit(&[&root], "side");
+        let main = commit(&[&root], "main");
+        let merge = commit(&[&main, &side], "merge");
+        refs::update(&repo, "refs/heads/master", &merge, None).unwrap();
+        let tag = tag::Tag {
+            object: merge.clone(),
+            object_type: ObjectType::Commit,
+            tag: "v1".to_string(),
+            tagger: None,
+            extra_headers: Vec::new(),
+            message: "v1\n".to_string(),
+        };
+        let tag_object = Blob::new(ObjectType::Tag, tag.serialize());
+        hash::write_to_database(&repo, &tag_object).unwrap();
+        refs::update(&repo, "refs/tags/v1", &tag_object.hash_string, None).unwrap();
+
+        let specs = ["master^", "master^2", "master~2", "v1^{}", "v1^0", "v1~1^{tree}", "v1^{tag}", "master^2~"];
+        let resolved: Vec<_> = specs.iter().map(|spec| resolve(&repo, spec).unwrap()).collect();
+        let errors: Vec<_> = ["master^3", "master~3", "v1^{blob}", "master^{nope}", "master^{tree"]
+            .iter()
+            .map(|spec| resolve(&repo, spec).is_err())
+            .collect();
+        assert_eq!(resolved, vec![main, side, root.clone(), merge.clone(), merge, tree, tag_object.hash_string, root]);
+        assert!(errors.iter().all(|&failed| failed));
+    }
+
+    #[test]
+    fn testing_revision_ranges() {
+        let dir = TempDir::new("ranges");
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let commit = |parents: &[&String], message: &str| test_commit(&repo, EMPTY_TREE, parents, 0, message);
+        let root = commit(&[], "root");
+        let side = commit(&[&root], "side");
+        let main = commit(&[&root], "main");
+        refs::update(&repo, "refs/heads/side", &side, None).unwrap();
+        refs::update(&repo, "refs/heads/master", &main, None).unwrap();
+        fs::write(dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        let walk = |revs: &[&str]| {
+            let range = Range::parse(&repo, revs).unwrap();
+            let mut commits = ancestry::rev_list(&repo, &range.tips, &range.excluded).unwrap();
+            commits.sort();
+            commits
+        };
+        let walks = (walk(&["side..master"]), walk(&["^side", "HEAD"]), walk(&["master...side"]), walk(&["..side", "master"]));
+
+        let mut both = vec![main.clone(), side.clone()];
+        both.sort();
+        // what a range leaves out stays out when another argument names it
+        assert_eq!(walks, (vec![main.clone()], vec![main], both, vec![side]));
+    }
+}
@@ -0,0 +1,128 @@
+use super::wildmatch::wildmatch;
+use super::*;
+use std::collections::HashSet;
+
+// `.git/info/sparse-checkout`, the paths the working tree is limited to when
+// core.sparseCheckout is set.
+pub enum Sparse {
+    // core.sparseCheckoutCone: every top level file, every file directly in a
+    // parent of a listed directory and everything below a listed directory
+    Cone { recursive: HashSet<String>, parents: HashSet<String> },
+    // gitignore style patterns, the last one matching a path or else its
+    // closest directory deciding
+    Patterns(Vec<Pattern>),
+}
+
+pub struct Pattern {
+    pub negated: bool,
+    dir_only: bool,
+    anchored: bool,
+    glob: String,
+}
+
+impl Pattern {
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let glob = line.trim_start_matches('/').to_string();
+        Some(Pattern { negated, dir_only, anchored, glob })
+    }
+
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let text = match self.anchored {
+            true => path,
+            false => path.rsplit('/').next().unwrap_or(path),
+        };
+        wildmatch(self.glob.as_bytes(), text.as_bytes(), false)
+    }
+}
+
+impl Sparse {
+    // the repository's sparse-checkout set, `None` when it isn't enabled
+    pub fn read(repo: &Repository) -> std::io::Result<Option<Self>> {
+        if !repo.config().get_bool("core.sparsecheckout")?.unwrap_or(false) {
+            return Ok(None);
+        }
+        let patterns = match fs::read_to_string(repo.git_dir().join("info/sparse-checkout")) {
+            Ok(patterns) => patterns,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let cone = repo.config().get_bool("core.sparsecheckoutcone")?.unwrap_or(false);
+        Ok(Some(Self::parse(&patterns, cone)))
+    }
+
+    // Cone mode files hold `/<dir>/` for the listed directories and their
+    // parents, each parent followed by `!/<dir>/*/`.
+    pub fn parse(patterns: &str, cone: bool) -> Self {
+        if !cone {
+            return Sparse::Patterns(patterns.lines().filter_map(Pattern::parse).collect());
+        }
+        let mut recursive = HashSet::new();
+        let mut parents = HashSet::new();
+        for line in patterns.lines().map(str::trim) {
+            if let Some(dir) = line.strip_prefix("!/").and_then(|line| line.strip_suffix("/*/")) {
+                parents.insert(dir.to_string());
+            } else if let Some(dir) = line.strip_prefix('/').and_then(|line| line.strip_suffix('/')) {
+                if !dir.is_empty() && dir != "*" {
+                    recursive.insert(dir.to_string());
+                }
+            }
+        }
+        recursive.retain(|dir| !parents.contains(dir));
+        Sparse::Cone { recursive, parents }
+    }
+
+    // whether the file `path` belongs in the working tree
+    pub fn includes(&self, path: &str) -> bool {
+        let dirs: Vec<&str> = path.match_indices('/').map(|(i, _)| &path[..i]).collect();
+        match self {
+            Sparse::Cone { recursive, parents } => match dirs.last() {
+                None => true,
+                Some(parent) => parents.contains(*parent) || dirs.iter().any(|dir| recursive.contains(*dir)),
+            },
+            Sparse::Patterns(patterns) => {
+                let decide = |path: &str, is_dir: bool| {
+                    patterns.iter().rev().find(|pattern| pattern.matches(path, is_dir)).map(|pattern| !pattern.negated)
+                };
+                decide(path, false)
+                    .or_else(|| dirs.iter().rev().find_map(|dir| decide(dir, true)))
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_sparse_patterns() {
+        let cone = Sparse::parse("/*\n!/*/\n/a/\n!/a/*/\n/a/b/\n/c/\n", true);
+        let included: Vec<bool> =
+            ["top", "a/file", "a/b/c/deep", "a/other/file", "c/d/e", "d/file"].iter().map(|path| cone.includes(path)).collect();
+        assert_eq!(included, vec![true, true, true, false, true, false]);
+
+        let patterns = Sparse::parse("/*\n!/*/\ndocs/\n!docs/internal/\n*.md\n", false);
+        let included: Vec<bool> = ["README", "src/main.rs", "docs/guide", "docs/internal/x", "src/NOTES.md"]
+            .iter()
+            .map(|path| patterns.includes(path))
+            .collect();
+        assert_eq!(included, vec![true, false, true, false, true]);
+    }
+}
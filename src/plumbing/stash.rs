@@ -0,0 +1,222 @@
+use super::commit::Commit;
+use super::identity::Identity;
+use super::index::Index;
+use super::*;
+
+const STASH: &str = "refs/stash";
+
+// The stash entries are the reflog of refs/stash, newest last, one
+// `<old> <new> <identity>\t<message>` line each.
+fn entries(repo: &Repository) -> std::io::Result<Vec<String>> {
+    match fs::read_to_string(repo.common_dir().join("logs").join(STASH)) {
+        Ok(log) => Ok(log.lines().map(str::to_string).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_entries(repo: &Repository, entries: &[String]) -> std::io::Result<()> {
+    let path = repo.common_dir().join("logs").join(STASH);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let log: String = entries.iter().map(|entry| format!("{}\n", entry)).collect();
+    write_locked(&path, log.as_bytes())
+}
+
+fn head(repo: &Repository) -> std::io::Result<(String, Commit)> {
+    let head = refs::resolve(repo, "HEAD")?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "you do not have the initial commit yet")
+    })?;
+    let commit = Commit::read(repo, &head)?;
+    Ok((head, commit))
+}
+
+// Records the tracked files as git does: a commit of the index on top of
+// HEAD, and a commit of the working tree with HEAD and that one as parents.
+// The working tree and the index are then reset to HEAD.
+pub fn save(repo: &Repository, message: Option<&str>) -> std::io::Result<()> {
+    let (head, head_commit) = head(repo)?;
+    let tracked = tree::files(repo, &head_commit.tree)?;
+    let index = Index::read(repo)?;
+    if let Some(entry) = index.entries.iter().find(|entry| entry.stage() != 0) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{}: needs merge, cannot save the current index state", entry.path),
+        ));
+    }
+    let staged = index.files();
+    let files = worktree::snapshot(repo, &staged)?;
+    if files == tracked && staged == tracked {
+        outln!("No local changes to save")?;
+        return Ok(());
+    }
+    let branch = match refs::symbolic_target(repo, "HEAD")? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => "(no branch)".to_string(),
+    };
+    let on = format!("{}: {} {}", branch, &head[..7], head_commit.subject());
+    let author = Identity::from_env("AUTHOR", repo.config())?;
+    let committer = Identity::from_env("COMMITTER", repo.config())?;
+    let commit = |tree: String, parents: Vec<String>, message: String| {
+        let commit = Commit {
+            tree,
+            parents,
+            author: author.clone(),
+            committer: committer.clone(),
+            extra_headers: Vec::new(),
+            message,
+        };
+        commit.write(repo)
+    };
+    // git ends the index commit's message with a newline but not the other one
+    let index = commit(tree::write_tree(repo, &staged)?, vec![head.clone()], format!("index on {}\n", on))?;
+    let message = match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", on),
+    };
+    let stash = commit(tree::write_tree(repo, &files)?, vec![head, index], message.clone())?;
+
+    let mut entries = entries(repo)?;
+    let old = refs::resolve(repo, STASH)?.unwrap_or_else(|| refs::NULL_ID.to_string());
+    entries.push(format!("{} {} {}\t{}", old, stash, committer, message));
+    write_entries(repo, &entries)?;
+    refs::update(repo, STASH, &stash, Some(&old))?;
+    worktree::update(repo, &files, &tracked, &[])?;
+    merge::write_index(repo, &tracked, &[])?;
+    outln!("Saved working directory and index state {}", message)?;
+    Ok(())
+}
+
+// Three-way merges the newest entry into the working tree, against the commit
+// it was made on, and drops it. On conflicts the entry is kept. Back on that
+// commit the index is the one stashed; elsewhere only the files it added
+// are staged again, as git does without `--index`.
+pub fn pop(repo: &Repository) -> std::io::Result<()> {
+    let mut entries = entries(repo)?;
+    let stash = match refs::resolve(repo, STASH)? {
+        Some(stash) if !entries.is_empty() => stash,
+        _ => return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no stash entries found")),
+    };
+    let stash_commit = Commit::read(repo, &stash)?;
+    let base_commit = stash_commit.parents.first().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} is not a stash commit", stash))
+    })?;
+    let base = tree::files(repo, &Commit::read(repo, base_commit)?.tree)?;
+    let (_, head_commit) = head(repo)?;
+    let ours = tree::files(repo, &head_commit.tree)?;
+    let theirs = tree::files(repo, &stash_commit.tree)?;
+    let labels = merge::Labels { ours: "Updated upstream", theirs: "Stashed changes" };
+    let merged = merge::merge_trees(repo, &base, &ours, &theirs, &labels)?;
+    worktree::update(repo, &ours, &merged.files, &merged.conflicts)?;
+    if !merged.conflicts.is_empty() {
+        merge::write_index(repo, &merged.files, &merged.conflicts)?;
+        for conflict in &merged.conflicts {
+            outln!("CONFLICT ({}): Merge conflict in {}", conflict.kind, conflict.path)?;
+        }
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the stash entry is kept in case you need it again",
+        ));
+    }
+    let stashed = match stash_commit.parents.get(1) {
+        Some(index) => tree::files(repo, &Commit::read(repo, index)?.tree)?,
+        None => base.clone(),
+    };
+    let staged = match ours == base {
+        true => stashed,
+        false => {
+            let mut staged = ours.clone();
+            for path in stashed.keys().filter(|path| !base.contains_key(*path)) {
+                if let Some(entry) = merged.files.get(path) {
+                    staged.insert(path.clone(), entry.clone());
+                }
+            }
+            staged
+        }
+    };
+    merge::write_index(repo, &staged, &[])?;
+
+    entries.pop();
+    match entries.last().and_then(|entry| entry.split(' ').nth(1)) {
+        Some(previous) => refs::update(repo, STASH, previous, Some(&stash))?,
+        None => refs::delete(repo, STASH)?,
+    }
+    write_entries(repo, &entries)?;
+    outln!("Dropped refs/stash@{{0}} ({})", stash)?;
+    Ok(())
+}
+
+// `stash@{<n>}: <message>` for each entry, newest first
+pub fn list(repo: &Repository) -> std::io::Result<()> {
+    for (n, entry) in entries(repo)?.iter().rev().enumerate() {
+        let message = entry.split_once('\t').map(|(_, message)| message).unwrap_or("");
+        outln!("stash@{{{}}}: {}", n, message)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn testing_stash_round_trip() {
+        let dir = TempDir::new("stash");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(git_dir.join("config"), "[user]\n\tname = A\n\temail = a@b\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let commit = |content: &str, parents: Vec<String>| {
+            let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+            hash::write_to_database(&repo, &blob).unwrap();
+            let files: BTreeMap<String, (String, String)> =
+                vec![("a".to_string(), ("100644".to_string(), blob.hash_string))].into_iter().collect();
+            let tree = tree::write_tree(&repo, &files).unwrap();
+            let sha = test_commit(&repo, &tree, &parents, 0, "message\n");
+            refs::update(&repo, "HEAD", &sha, None).unwrap();
+            fs::write(dir.join("a"), content).unwrap();
+            merge::write_index(&repo, &files, &[]).unwrap();
+            sha
+        };
+        let first = commit("1\n2\n3\n4\n5\n", Vec::new());
+        let staged = || Index::read(&repo).unwrap().files().into_keys().collect::<Vec<_>>();
+        // what `git add` would do for the one file
+        let stage = |path: &str, content: &str| {
+            let blob = Blob::new(ObjectType::Blob, content.as_bytes().to_vec());
+            hash::write_to_database(&repo, &blob).unwrap();
+            let mut files = Index::read(&repo).unwrap().files();
+            files.insert(path.to_string(), ("100644".to_string(), blob.hash_string));
+            merge::write_index(&repo, &files, &[]).unwrap();
+        };
+
+        fs::write(dir.join("a"), "1\n2\n3\n4\n5\nsix\n").unwrap();
+        fs::write(dir.join("b"), "new\n").unwrap();
+        stage("b", "new\n");
+        save(&repo, None).unwrap();
+        let stashed = (fs::read_to_string(dir.join("a")).unwrap(), dir.join("b").exists(), staged());
+        let stash = Commit::read(&repo, &refs::resolve(&repo, STASH).unwrap().unwrap()).unwrap();
+        let index_commit = Commit::read(&repo, &stash.parents[1]).unwrap();
+        let index_tree = tree::files(&repo, &index_commit.tree).unwrap().into_keys().collect::<Vec<_>>();
+        commit("one\n2\n3\n4\n5\n", vec![first]);
+        pop(&repo).unwrap();
+        let popped = (fs::read_to_string(dir.join("a")).unwrap(), fs::read_to_string(dir.join("b")).unwrap(), staged());
+        let staged_a = Index::read(&repo).unwrap().files()["a"].1.clone();
+        let left = (entries(&repo).unwrap().len(), refs::resolve(&repo, STASH).unwrap());
+        let empty = pop(&repo).is_err();
+        // popped where it was saved, the index comes back as it was
+        stage("a", &fs::read_to_string(dir.join("a")).unwrap());
+        let before = Index::read(&repo).unwrap().files();
+        save(&repo, None).unwrap();
+        pop(&repo).unwrap();
+        let restored = Index::read(&repo).unwrap().files() == before;
+        assert_eq!(stashed, ("1\n2\n3\n4\n5\n".to_string(), false, vec!["a".to_string()]));
+        assert_eq!(index_tree, vec!["a", "b"]);
+        assert_eq!(popped, ("one\n2\n3\n4\n5\nsix\n".to_string(), "new\n".to_string(), vec!["a".to_string(), "b".to_string()]));
+        // away from the stash's commit only the added b is staged again
+        assert_eq!(staged_a, Blob::new(ObjectType::Blob, b"one\n2\n3\n4\n5\n".to_vec()).hash_string);
+        assert_eq!(left, (0, None));
+        assert!(empty);
+        assert!(restored);
+    }
+}
@@ -0,0 +1,266 @@
+use super::index::Index;
+use super::worktree::Directories;
+use super::*;
+use std::collections::BTreeMap;
+
+pub const NULL_MODE: &str = "000000";
+pub const NULL_ID: &str = "0000000000000000000000000000000000000000";
+
+// A tracked path that differs somewhere: the letters of the short format for
+// the index against HEAD and the working tree against the index, ' ' when
+// they agree, with the modes and ids `--porcelain=v2` shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: String,
+    pub staged: char,
+    pub unstaged: char,
+    pub head: Option<(String, String)>,
+    pub index: Option<(String, String)>,
+    // the mode of the working tree file, `None` when it is gone
+    pub worktree: Option<String>,
+    // the HEAD path and score, out of 100, of a staged rename
+    pub renamed: Option<(String, u64)>,
+    // stages 1 to 3 of an unmerged path
+    pub unmerged: Option<[Option<(String, String)>; 3]>,
+}
+
+// What `status` reports: the changes by path, then the untracked paths.
+pub struct Status {
+    pub changes: Vec<Change>,
+    pub untracked: Vec<String>,
+}
+
+// What a mode is a mode of, a change between those being a type change
+fn kind(mode: &str) -> &str {
+    match mode {
+        "120000" => "link",
+        "160000" => "gitlink",
+        _ => "file",
+    }
+}
+
+fn letter(old: Option<&str>, new: Option<&str>) -> char {
+    match (old, new) {
+        (None, Some(_)) => 'A',
+        (Some(_), None) => 'D',
+        (Some(old), Some(new)) if kind(old) != kind(new) => 'T',
+        _ => 'M',
+    }
+}
+
+// the two letters git gives an unmerged path from the stages it has
+fn conflict(stages: &[Option<(String, String)>; 3]) -> (char, char) {
+    match (stages[0].is_some(), stages[1].is_some(), stages[2].is_some()) {
+        (true, false, false) => ('D', 'D'),
+        (false, true, false) => ('A', 'U'),
+        (true, true, false) => ('U', 'D'),
+        (false, false, true) => ('U', 'A'),
+        (true, false, true) => ('D', 'U'),
+        (false, true, true) => ('A', 'A'),
+        _ => ('U', 'U'),
+    }
+}
+
+// Compares HEAD, the index and the working tree, staged renames detected
+// as `diff` does by default, and lists the untracked paths as `directories`
+// says, or none without it.
+pub fn status(repo: &Repository, directories: Option<Directories>) -> std::io::Result<Status> {
+    let head = match refs::resolve(repo, "HEAD")? {
+        Some(head) => tree::files(repo, &diff::peel_to_tree(repo, &head)?)?,
+        None => BTreeMap::new(),
+    };
+    let index = Index::read(repo)?;
+    let files = index.files();
+    let mut changes: BTreeMap<String, Change> = BTreeMap::new();
+    let change = |path: &str| Change {
+        path: path.to_string(),
+        staged: ' ',
+        unstaged: ' ',
+        head: head.get(path).cloned(),
+        index: files.get(path).cloned(),
+        worktree: files.get(path).map(|(mode, _)| mode.clone()),
+        renamed: None,
+        unmerged: None,
+    };
+
+    let mut unmerged: BTreeMap<&str, [Option<(String, String)>; 3]> = BTreeMap::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage() != 0) {
+        let stages = unmerged.entry(&entry.path).or_default();
+        stages[entry.stage() as usize - 1] = Some((entry.mode_string(), entry.sha.clone()));
+    }
+    for (path, stages) in unmerged {
+        let (staged, unstaged) = conflict(&stages);
+        let metadata = fs::symlink_metadata(repo.work_tree().join(path)).ok();
+        let worktree = metadata.and_then(|metadata| worktree::read_file(&repo.work_tree().join(path), &metadata).ok()?);
+        let entry = Change {
+            staged,
+            unstaged,
+            worktree: worktree.map(|(mode, _)| mode.to_string()),
+            unmerged: Some(stages),
+            ..change(path)
+        };
+        changes.insert(path.to_string(), entry);
+    }
+
+    let deleted: Vec<_> = head.iter().filter(|(path, _)| !files.contains_key(*path) && !changes.contains_key(*path)).collect();
+    let added: Vec<_> = files.iter().filter(|(path, _)| !head.contains_key(*path)).collect();
+    let renames = diff::find_renames(repo, &deleted, &added, diff::DEFAULT_RENAME_SCORE)?;
+    for (s, d, score) in renames.iter().copied() {
+        let mut entry = change(added[d].0);
+        entry.staged = 'R';
+        entry.head = Some(deleted[s].1.clone());
+        entry.renamed = Some((deleted[s].0.clone(), score * 100 / diff::MAX_SCORE));
+        changes.insert(added[d].0.clone(), entry);
+    }
+    for (s, (path, _)) in deleted.iter().enumerate() {
+        if !renames.iter().any(|rename| rename.0 == s) {
+            changes.insert(path.to_string(), Change { staged: 'D', worktree: None, ..change(path) });
+        }
+    }
+    for (path, entry) in &files {
+        let old = head.get(path);
+        if !changes.contains_key(path) && old != Some(entry) {
+            let staged = letter(old.map(|(mode, _)| mode.as_str()), Some(entry.0.as_str()));
+            changes.insert(path.clone(), Change { staged, ..change(path) });
+        }
+    }
+    for (path, file) in worktree::changes(repo, &index)? {
+        let new = file.map(|(mode, _)| mode);
+        let entry = changes.entry(path.clone()).or_insert_with(|| change(&path));
+        entry.unstaged = letter(Some(&files[&path].0), new.as_deref());
+        entry.worktree = new;
+    }
+
+    let untracked = match directories {
+        Some(directories) => {
+            let mut ignores = ignore::Ignores::new(repo)?;
+            worktree::untracked(repo, &index, Some(&mut ignores), directories)?
+        }
+        None => Vec::new(),
+    };
+    Ok(Status { changes: changes.into_values().collect(), untracked })
+}
+
+// paths as they are with `z`, else quoted by `quote`
+fn quoting(z: bool, non_ascii: bool, quote: fn(&str, bool) -> String) -> impl Fn(&str) -> String {
+    move |path| match z {
+        true => path.to_string(),
+        false => quote(path, non_ascii),
+    }
+}
+
+// The lines of `status --porcelain`: `XY path`, with `from -> to` for a
+// rename, then `?? path` for the untracked ones. With `z` the lines are
+// meant to be ended by NULs, and a rename is `to`, NUL, `from`.
+pub fn porcelain_v1(status: &Status, z: bool, non_ascii: bool) -> Vec<String> {
+    // spaces are quoted too, as what follows a path could be read as part of it
+    let quote = quoting(z, non_ascii, quote_spaced_path);
+    let mut lines = Vec::new();
+    for change in &status.changes {
+        let path = match &change.renamed {
+            Some((from, _)) if z => format!("{}\0{}", change.path, from),
+            Some((from, _)) => format!("{} -> {}", quote(from), quote(&change.path)),
+            None => quote(&change.path),
+        };
+        lines.push(format!("{}{} {}", change.staged, change.unstaged, path));
+    }
+    lines.extend(status.untracked.iter().map(|path| format!("?? {}", quote(path))));
+    lines
+}
+
+// The lines of `status --porcelain=v2`, which also give the modes and ids
+// of each side. With `z` the path of a rename is separated from the one
+// it had by a NUL rather than a tab.
+pub fn porcelain_v2(status: &Status, z: bool, non_ascii: bool) -> Vec<String> {
+    let quote = quoting(z, non_ascii, quote_path);
+    let separator = if z { '\0' } else { '\t' };
+    let mode = |side: &Option<(String, String)>| side.as_ref().map_or(NULL_MODE, |(mode, _)| mode.as_str()).to_string();
+    let id = |side: &Option<(String, String)>| side.as_ref().map_or(NULL_ID, |(_, id)| id.as_str()).to_string();
+    let dot = |letter: char| if letter == ' ' { '.' } else { letter };
+    let mut lines = Vec::new();
+    for change in &status.changes {
+        let xy = format!("{}{}", dot(change.staged), dot(change.unstaged));
+        let submodule = match (&change.index, &change.head) {
+            (Some((mode, _)), _) | (None, Some((mode, _))) if mode == "160000" => "S...",
+            _ => "N...",
+        };
+        let worktree = change.worktree.as_deref().unwrap_or(NULL_MODE);
+        let line = match (&change.unmerged, &change.renamed) {
+            (Some(stages), _) => format!(
+                "u {} {} {} {} {} {} {} {} {} {}",
+                xy,
+                submodule,
+                mode(&stages[0]),
+                mode(&stages[1]),
+                mode(&stages[2]),
+                worktree,
+                id(&stages[0]),
+                id(&stages[1]),
+                id(&stages[2]),
+                quote(&change.path)
+            ),
+            (None, Some((from, score))) => format!(
+                "2 {} {} {} {} {} {} {} R{} {}{}{}",
+                xy,
+                submodule,
+                mode(&change.head),
+                mode(&change.index),
+                worktree,
+                id(&change.head),
+                id(&change.index),
+                score,
+                quote(&change.path),
+                separator,
+                quote(from)
+            ),
+            (None, None) => format!(
+                "1 {} {} {} {} {} {} {} {}",
+                xy,
+                submodule,
+                mode(&change.head),
+                mode(&change.index),
+                worktree,
+                id(&change.head),
+                id(&change.index),
+                quote(&change.path)
+            ),
+        };
+        lines.push(line);
+    }
+    lines.extend(status.untracked.iter().map(|path| format!("? {}", quote(path))));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::index::Entry;
+    use super::*;
+
+    #[test]
+    fn testing_porcelain_status() {
+        let dir = TempDir::new("status");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        for (path, content) in [("a", "x"), ("m", "changed"), ("c", "<<<<<<<"), ("new", "")] {
+            fs::write(dir.join(path), content).unwrap();
+        }
+        let repo = Repository::open(&git_dir).unwrap();
+        let blob = Blob::new(ObjectType::Blob, b"x".to_vec());
+        hash::write_to_database(&repo, &blob).unwrap();
+        let blob = blob.hash_string;
+        let mut ours = Entry::new("c", "100644", &blob);
+        ours.flags = 2 << 12;
+        let mut theirs = Entry::new("c", "100644", &blob);
+        theirs.flags = 3 << 12;
+        let entries = vec![Entry::new("a", "100644", &blob), ours, theirs, Entry::new("gone", "100644", &blob), Entry::new("m", "100644", &blob)];
+        Index { entries }.write(&repo).unwrap();
+        let status = status(&repo, Some(Directories::Shown)).unwrap();
+
+        assert_eq!(porcelain_v1(&status, false, true), vec!["A  a", "AA c", "AD gone", "AM m", "?? new"]);
+        let v2 = porcelain_v2(&status, true, true);
+        assert_eq!(v2[2], format!("1 AD N... 000000 100644 000000 {} {} gone", NULL_ID, blob));
+        assert!(v2[1].starts_with("u AA N... 000000 100644 100644 100644 "));
+        assert_eq!(v2[4], "? new");
+    }
+}
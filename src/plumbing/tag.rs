@@ -0,0 +1,190 @@
+use super::identity::Identity;
+use super::*;
+
+const SIGNATURE_START: &str = "-----BEGIN PGP SIGNATURE-----";
+
+pub struct Tag {
+    pub object: String,
+    pub object_type: ObjectType,
+    pub tag: String,
+    // very old tags have no tagger line
+    pub tagger: Option<Identity>,
+    // header lines after the known ones, kept verbatim
+    pub extra_headers: Vec<String>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn parse(content: &[u8]) -> std::io::Result<Self> {
+        let invalid = |msg: &str| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid tag: {}", msg))
+        };
+        let content = String::from_utf8_lossy(content);
+        let (headers, message) = match content.find("\n\n") {
+            Some(index) => (&content[..index], &content[index + 2..]),
+            None => (content.trim_end_matches('\n'), ""),
+        };
+        let mut lines = headers.lines().peekable();
+        let mut header = |name: &str| {
+            lines
+                .next_if(|line| line.starts_with(&format!("{} ", name)))
+                .map(|line| line[name.len() + 1..].to_string())
+                .ok_or_else(|| invalid(&format!("missing {}", name)))
+        };
+        let object = header("object")?;
+        if !is_object_id(&object) {
+            return Err(invalid(&format!("bad object {}", object)));
+        }
+        let object_type = header("type")?;
+        let object_type = ObjectType::from_bytes(object_type.as_bytes())
+            .ok_or_else(|| invalid(&format!("unknown type {}", object_type)))?;
+        let tag = header("tag")?;
+        let tagger = match header("tagger") {
+            Ok(tagger) => Some(Identity::parse(&tagger).ok_or_else(|| invalid("bad tagger line"))?),
+            Err(_) => None,
+        };
+        Ok(Self {
+            object,
+            object_type,
+            tag,
+            tagger,
+            extra_headers: lines.map(String::from).collect(),
+            message: message.to_string(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut content = format!("object {}\ntype {}\ntag {}\n", self.object, self.object_type, self.tag);
+        if let Some(tagger) = &self.tagger {
+            content += &format!("tagger {}\n", tagger);
+        }
+        for header in &self.extra_headers {
+            content += &format!("{}\n", header);
+        }
+        content += "\n";
+        content += &self.message;
+        content.into_bytes()
+    }
+
+    // A signed tag carries its signature at the end of the message. Removes
+    // it from the message, leaving the signed payload, and returns it.
+    pub fn take_signature(&mut self) -> Option<String> {
+        let mut start = 0;
+        for line in self.message.split_inclusive('\n') {
+            if line.starts_with(SIGNATURE_START) {
+                let signature = self.message[start..].to_string();
+                self.message.truncate(start);
+                return Some(signature);
+            }
+            start += line.len();
+        }
+        None
+    }
+}
+
+// Parses `content` as strictly as `git mktag` does: the headers in order
+// with a tagger, nothing else after them, a valid tag name and the tagged
+// object there with the type the tag says.
+pub fn validate(repo: &Repository, content: &[u8]) -> std::io::Result<Tag> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid tag: {}", msg));
+    let tag = Tag::parse(content)?;
+    if tag.tagger.is_none() {
+        return Err(invalid("missing tagger".to_string()));
+    }
+    if !tag.extra_headers.is_empty() {
+        return Err(invalid("extra header(s) after 'tagger'".to_string()));
+    }
+    if !refs::is_valid_name(&format!("refs/tags/{}", tag.tag)) {
+        return Err(invalid(format!("bad tag name {}", tag.tag)));
+    }
+    if !repo.has_object(&tag.object) {
+        return Err(invalid(format!("could not read tagged object '{}'", tag.object)));
+    }
+    let object_type = Blob::from_sha(repo, &tag.object)?.object_type();
+    if object_type != tag.object_type {
+        return Err(invalid(format!(
+            "object '{}' tagged as '{}', but is a '{}' type",
+            tag.object, tag.object_type, object_type
+        )));
+    }
+    Ok(tag)
+}
+
+pub fn verify_tag(repo: &Repository, sha: &str, verbose: bool) -> std::io::Result<()> {
+    let blob = Blob::from_sha(repo, sha)?;
+    if blob.object_type() != ObjectType::Tag {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{}: cannot verify a non-tag object of type {}", sha, blob.object_type()),
+        ));
+    }
+    let mut tag = Tag::parse(&blob.content)?;
+    let signature = tag.take_signature().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, format!("{}: no signature found", sha))
+    })?;
+    let payload = tag.serialize();
+    if verbose {
+        out!("{}", String::from_utf8_lossy(&payload))?;
+    }
+    if gpg::verify(repo.config(), &payload, &signature)? {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: bad signature", sha),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn testing_tag_parsing() {
+        let payload = "object 51912bb58e69f15db8f8b214bc97de8430cfdc02\n\
+                               type tree\n\
+                               tag v1.0\n\
+                               tagger T Agger <tagger@example.com> 1112911993 +0100\n\
+                               \n\
+                               release\n";
+        let signature = "-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----\n";
+        let content = format!("{}{}", payload, signature);
+        let mut tag = Tag::parse(content.as_bytes()).unwrap();
+        assert_eq!(tag.object_type, ObjectType::Tree);
+        assert_eq!(tag.tag, "v1.0");
+        assert_eq!(tag.tagger.as_ref().unwrap().offset, 60);
+        assert_eq!(tag.serialize(), content.as_bytes());
+        assert_eq!(tag.take_signature().unwrap(), signature);
+        assert_eq!(tag.serialize(), payload.as_bytes());
+        assert!(Tag::parse(b"object abc\ntag v1\n\n").is_err());
+        assert!(Tag::parse(b"object abc\ntype blob\ntag v1\n\n").is_err());
+    }
+
+    #[test]
+    fn testing_strict_tag_validation() {
+        let repo = Repository::in_memory();
+        let blob = Blob::new(ObjectType::Blob, b"content\n".to_vec());
+        hash::write_to_database(&repo, &blob).unwrap();
+        let tag = |object: &str, object_type: &str, name: &str, rest: &str| {
+            let content = format!("object {}\ntype {}\ntag {}\n{}", object, object_type, name, rest);
+            validate(&repo, content.as_bytes()).is_ok()
+        };
+        let sha = &blob.hash_string;
+        let tagger = "tagger T <t@example.com> 1 +0000\n";
+        let results = [
+            tag(sha, "blob", "v1", &format!("{}\nmessage\n", tagger)),
+            tag(sha, "blob", "v1", tagger),
+            tag(sha, "blob", "v1", "\nmessage\n"),
+            tag(sha, "blob", "v1", &format!("{}extra header\n\n", tagger)),
+            tag(sha, "tree", "v1", tagger),
+            tag("1234567890123456789012345678901234567890", "blob", "v1", tagger),
+            tag(sha, "blob", "v 1", tagger),
+            tag(sha, "blob", "v1", "tagger T <t@example.com> x +0000\n"),
+        ];
+        assert_eq!(results, [true, true, false, false, false, false, false, false]);
+        assert!(refs::is_valid_name("refs/heads/feature/x"));
+        for name in ["refs/heads/.x", "a..b", "x.lock", "a//b", "a@{1}", "a^", "a b", "end."] {
+            assert!(!refs::is_valid_name(name), "{}", name);
+        }
+    }
+}
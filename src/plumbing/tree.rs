@@ -0,0 +1,282 @@
+use super::*;
+
+// what `ls-tree` shows of a tree
+pub struct Listing {
+    pub name_only: bool,
+    // list the files below the subtrees instead of the subtrees
+    pub recursive: bool,
+    // list only the subtrees, all of them when `recursive`
+    pub trees_only: bool,
+    // add the size of blobs
+    pub long: bool,
+    // what ends each entry, NUL for `-z`
+    pub terminator: char,
+    // Limits the listing to these paths, a path ending in `/` standing for
+    // what is in that directory. Trees leading to them are walked through
+    // without being listed.
+    pub paths: Vec<String>,
+}
+
+impl Listing {
+    // whether `path` is one of the paths or below one
+    fn includes(&self, path: &str) -> bool {
+        self.paths.is_empty()
+            || self.paths.iter().any(|spec| match spec.strip_suffix('/') {
+                Some(dir) => is_below(path, dir),
+                None => path == spec || is_below(path, spec),
+            })
+    }
+
+    // whether the tree `path` has to be walked to reach one of the paths
+    fn leads_to(&self, path: &str) -> bool {
+        self.paths.iter().any(|spec| is_below(spec, path))
+    }
+}
+
+fn is_below(path: &str, dir: &str) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/'
+}
+
+// Prints the entries of the tree `sha`, either only the names or in the
+// `<mode> <type> <sha>\t<name>` long format, which `listing.long` extends
+// with the size of blobs, or `-`, right aligned before the tab.
+pub fn print_tree(repo: &Repository, sha: &str, listing: &Listing) -> std::io::Result<()> {
+    let non_ascii = quotes_non_ascii(repo)?;
+    let quote = |path: &str| match listing.terminator {
+        '\0' => path.to_string(),
+        _ => quote_path(path, non_ascii),
+    };
+    print_entries(repo, sha, listing, &quote)
+}
+
+// The trees being listed are kept on a stack, each with the entry to go
+// on from, so that `-r` goes as deep as the trees do.
+fn print_entries(repo: &Repository, sha: &str, listing: &Listing, quote: &dyn Fn(&str) -> String) -> std::io::Result<()> {
+    let mut open: Vec<(Tree, usize, String)> = Vec::new();
+    let mut next = Some((sha.to_string(), String::new()));
+    loop {
+        if let Some((sha, prefix)) = next.take() {
+            let blob = Blob::from_sha(repo, &sha)?;
+            let tree = Tree::try_pars(&blob).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+            })?;
+            open.push((tree, 0, prefix));
+        }
+        let (tree, at, prefix) = match open.last_mut() {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+        let entry = match tree.entries().get(*at) {
+            Some(entry) => entry,
+            None => {
+                open.pop();
+                continue;
+            }
+        };
+        *at += 1;
+        let path = format!("{}{}", prefix, entry.name);
+        let sha = Blob::string_hash(&entry.sha);
+        let is_tree = entry.entry_type == EntryType::Tree;
+        let included = listing.includes(&path);
+        if is_tree && !included && listing.leads_to(&path) {
+            next = Some((sha, format!("{}/", path)));
+            continue;
+        }
+        let shown = included
+            && match is_tree {
+                true => !listing.recursive || listing.trees_only,
+                false => !listing.trees_only,
+            };
+        if shown && listing.name_only {
+            out!("{}{}", quote(&path), listing.terminator)?;
+        } else if shown {
+            let entry_type = entry.entry_type.object_type();
+            let size = match listing.long {
+                false => String::new(),
+                true if entry_type != ObjectType::Blob => format!(" {:>7}", "-"),
+                true => format!(" {:>7}", Blob::from_sha(repo, &sha)?.content.len()),
+            };
+            out!("{:0>6} {} {}{}\t{}{}", entry.mode, entry_type, sha, size, quote(&path), listing.terminator)?;
+        }
+        if is_tree && included && listing.recursive {
+            next = Some((sha, format!("{}/", path)));
+        }
+    }
+}
+
+// Collects `path -> (mode, sha)` for every non-tree entry below the tree `sha`.
+// The subtrees still to read are kept on a stack, not the call stack.
+pub fn flatten(
+    repo: &Repository,
+    sha: &str,
+    prefix: &str,
+    files: &mut std::collections::BTreeMap<String, (String, String)>,
+) -> std::io::Result<()> {
+    let mut pending = vec![(sha.to_string(), prefix.to_string())];
+    while let Some((sha, prefix)) = pending.pop() {
+        let blob = Blob::from_sha(repo, &sha)?;
+        let tree = Tree::try_pars(&blob)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+        for entry in tree.entries() {
+            let path = format!("{}{}", prefix, entry.name);
+            let entry_sha = Blob::string_hash(&entry.sha);
+            match entry.entry_type {
+                EntryType::Tree => pending.push((entry_sha, format!("{}/", path))),
+                _ => {
+                    files.insert(path, (entry.mode.clone(), entry_sha));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// the files `path -> (mode, sha)` below the tree `sha`
+pub fn files(
+    repo: &Repository,
+    sha: &str,
+) -> std::io::Result<std::collections::BTreeMap<String, (String, String)>> {
+    let mut files = std::collections::BTreeMap::new();
+    flatten(repo, sha, "", &mut files)?;
+    Ok(files)
+}
+
+// Writes the trees for the files `path -> (mode, sha)` and returns the id of
+// the top one. Entries are sorted the way git sorts them, a tree as if its
+// name ended with `/`.
+pub fn write_tree(
+    repo: &Repository,
+    files: &std::collections::BTreeMap<String, (String, String)>,
+) -> std::io::Result<String> {
+    let entries: Vec<(&str, &str, &str)> =
+        files.iter().map(|(path, (mode, sha))| (path.as_str(), mode.as_str(), sha.as_str())).collect();
+    write_subtree(Some(repo), &entries)
+}
+
+// The id `write_tree` would return for `files`, without writing any object.
+pub fn hash_tree(files: &std::collections::BTreeMap<String, (String, String)>) -> std::io::Result<String> {
+    let entries: Vec<(&str, &str, &str)> =
+        files.iter().map(|(path, (mode, sha))| (path.as_str(), mode.as_str(), sha.as_str())).collect();
+    write_subtree(None, &entries)
+}
+
+// `entries` holds `(path, mode, sha)` sorted by path, so that the files of
+// a directory come one after the other; the trees are only hashed when
+// there is no `repo` to write them to. The directories being filled are
+// kept on a stack of their own rather than the call stack, however deep
+// they nest.
+fn write_subtree(repo: Option<&Repository>, entries: &[(&str, &str, &str)]) -> std::io::Result<String> {
+    // the root first, then each directory inside the one before
+    let mut open: Vec<Directory> = vec![(String::new(), Vec::new())];
+    for &(path, mode, sha) in entries {
+        while !path.starts_with(open[open.len() - 1].0.as_str()) {
+            close_directory(repo, &mut open)?;
+        }
+        let mut rest = &path[open[open.len() - 1].0.len()..];
+        while let Some(slash) = rest.find('/') {
+            let dir = format!("{}{}", open[open.len() - 1].0, &rest[..=slash]);
+            open.push((dir, Vec::new()));
+            rest = &rest[slash + 1..];
+        }
+        let last = open.len() - 1;
+        open[last].1.push((rest.to_string(), mode.to_string(), sha.to_string()));
+    }
+    while open.len() > 1 {
+        close_directory(repo, &mut open)?;
+    }
+    build_tree(repo, open.pop().unwrap().1)
+}
+
+// a directory being filled: `<path>/` and its `(name, mode, sha)` entries so far
+type Directory = (String, Vec<(String, String, String)>);
+
+// writes the innermost open directory, which becomes an entry of its parent
+fn close_directory(repo: Option<&Repository>, open: &mut Vec<Directory>) -> std::io::Result<()> {
+    let (dir, items) = open.pop().unwrap();
+    let sha = build_tree(repo, items)?;
+    let name = dir[..dir.len() - 1].rsplit('/').next().unwrap_or_default();
+    let last = open.len() - 1;
+    open[last].1.push((format!("{}/", name), "40000".to_string(), sha));
+    Ok(())
+}
+
+fn build_tree(repo: Option<&Repository>, mut items: Vec<(String, String, String)>) -> std::io::Result<String> {
+    // trees carry their `/` until the entries are sorted
+    items.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+    let mut content = Vec::new();
+    for (name, mode, sha) in items {
+        content.extend_from_slice(format!("{} {}\0", mode, name.trim_end_matches('/')).as_bytes());
+        let sha = Blob::decode_hex(&sha)?;
+        content.extend_from_slice(&sha);
+    }
+    let blob = Blob::new(ObjectType::Tree, content);
+    if let Some(repo) = repo {
+        hash::write_to_database(repo, &blob)?;
+    }
+    Ok(blob.hash_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_deep_trees() {
+        let repo = Repository::in_memory();
+        let blob = repo.write_blob(b"deep\n").unwrap();
+        let mut files = std::collections::BTreeMap::new();
+        let mut path = String::new();
+        for depth in 0..20_000 {
+            path.push_str("d/");
+            if depth % 2000 == 0 {
+                files.insert(format!("{}file", path), ("100644".to_string(), blob.clone()));
+            }
+        }
+        files.insert(format!("{}file", path), ("100644".to_string(), blob.clone()));
+        files.insert("d.txt".to_string(), ("100644".to_string(), blob.clone()));
+        files.insert("e".to_string(), ("100755".to_string(), blob));
+        let deepest = format!("{}file", path);
+        let deepest_tree = path.trim_end_matches('/').to_string();
+        // a small stack, which walking the tree 20000 calls deep would overflow
+        let walked = std::thread::Builder::new()
+            .stack_size(1024 * 1024)
+            .spawn(move || {
+                let sha = write_tree(&repo, &files).unwrap();
+                let hashed = hash_tree(&files).unwrap();
+                let round_trip = self::files(&repo, &sha).unwrap() == files;
+                // as `ls-tree -r <tree> <path>` and `rev-list --objects` walk it
+                let paths = vec![deepest.clone()];
+                let listing = Listing { name_only: true, recursive: true, trees_only: false, long: false, terminator: '\n', paths };
+                let listed = print_tree(&repo, &sha, &listing).is_ok();
+                let commit = test_commit(&repo, &sha, &[] as &[&str], 0, "deep\n");
+                let objects = ancestry::objects(&repo, &[commit]).unwrap();
+                let reached = objects.iter().any(|(_, path)| *path == deepest_tree);
+                (sha, hashed, round_trip, listed, objects.len(), reached)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let (sha, hashed, round_trip, listed, objects, reached) = walked;
+        assert_eq!(sha, hashed);
+        assert!(round_trip);
+        assert!(listed);
+        // the root tree, one more for each level and the blob all the files share
+        assert_eq!(objects, 1 + 20_000 + 1);
+        assert!(reached);
+    }
+
+    #[test]
+    fn testing_listing_paths() {
+        let paths = vec!["src/".to_string(), "docs/guide".to_string(), "README".to_string()];
+        let listing = Listing { name_only: false, recursive: false, trees_only: false, long: false, terminator: '\n', paths };
+        let included: Vec<bool> =
+            ["src", "src/main.rs", "docs", "docs/guide", "docs/guide/intro", "docs/guidebook", "README", "READMEs"]
+                .iter()
+                .map(|path| listing.includes(path))
+                .collect();
+        assert_eq!(included, vec![false, true, false, true, true, false, true, false]);
+        let leading: Vec<bool> = ["src", "docs", "doc", "docs/guide"].iter().map(|path| listing.leads_to(path)).collect();
+        assert_eq!(leading, vec![true, true, false, false]);
+    }
+}
@@ -0,0 +1,134 @@
+// Matches `text` against a glob `pattern` in git's wildmatch style: `*` and
+// `?` don't match `/`, `**` matches across directories, `[...]` is a
+// character class (negated with `!` or `^`) and `\\` escapes a character.
+pub fn wildmatch(pattern: &[u8], text: &[u8], icase: bool) -> bool {
+    let eq = |a: u8, b: u8| if icase { a.eq_ignore_ascii_case(&b) } else { a == b };
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let stars = pattern[p..].iter().take_while(|&&c| c == b'*').count();
+                let rest = &pattern[p + stars..];
+                let starts_component = p == 0 || pattern[p - 1] == b'/';
+                if stars >= 2 && starts_component {
+                    // `**/` also matches no directory at all
+                    if let Some(after) = rest.strip_prefix(b"/") {
+                        return (t..=text.len()).any(|i| {
+                            (i == t || text[i - 1] == b'/') && wildmatch(after, &text[i..], icase)
+                        });
+                    }
+                    if rest.is_empty() {
+                        return true;
+                    }
+                }
+                for i in t..=text.len() {
+                    if wildmatch(rest, &text[i..], icase) {
+                        return true;
+                    }
+                    if i < text.len() && text[i] == b'/' && stars < 2 {
+                        return false;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if t >= text.len() || text[t] == b'/' {
+                    return false;
+                }
+                p += 1;
+            }
+            b'[' => match match_class(&pattern[p..], text.get(t).copied(), icase) {
+                Some((true, len)) => p += len,
+                Some((false, _)) => return false,
+                // an unterminated class is a literal `[`
+                None if text.get(t) == Some(&b'[') => p += 1,
+                None => return false,
+            },
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || !eq(pattern[p + 1], text[t]) {
+                    return false;
+                }
+                p += 2;
+            }
+            c => {
+                if t >= text.len() || !eq(c, text[t]) {
+                    return false;
+                }
+                p += 1;
+            }
+        }
+        t += 1;
+    }
+    t == text.len()
+}
+
+// Matches `c` against the class at the start of `pattern`, returning whether
+// it matched and the length of the class, or None if the class isn't closed.
+fn match_class(pattern: &[u8], c: Option<u8>, icase: bool) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+    let c = c.filter(|&c| c != b'/').map(|c| if icase { c.to_ascii_lowercase() } else { c });
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *pattern.get(i)?;
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+        if start == b'\\' {
+            i += 1;
+            start = *pattern.get(i)?;
+        }
+        let mut end = start;
+        if pattern.get(i + 1) == Some(&b'-') && matches!(pattern.get(i + 2), Some(&e) if e != b']') {
+            end = pattern[i + 2];
+            i += 2;
+        }
+        i += 1;
+        if let Some(c) = c {
+            let (start, end) = if icase {
+                (start.to_ascii_lowercase(), end.to_ascii_lowercase())
+            } else {
+                (start, end)
+            };
+            matched |= start <= c && c <= end;
+        }
+    }
+    Some((c.is_some() && matched != negated, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn testing_wildmatch() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("foo", "foo", true),
+            ("f?o", "foo", true),
+            ("*.rs", "main.rs", true),
+            ("*.rs", "src/main.rs", false),
+            ("**/main.rs", "main.rs", true),
+            ("**/main.rs", "a/b/main.rs", true),
+            ("src/**", "src/a/b", true),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "a/xb", false),
+            ("[a-c]at", "bat", true),
+            ("[!a-c]at", "bat", false),
+            ("[]]", "]", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("a?b", "a/b", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(wildmatch(pattern.as_bytes(), text.as_bytes(), false), *expected, "{} {}", pattern, text);
+        }
+        assert!(wildmatch(b"/Work/**", b"/work/x/.git", true));
+        assert!(!wildmatch(b"/Work/**", b"/work/x/.git", false));
+    }
+}
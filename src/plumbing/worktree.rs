@@ -0,0 +1,450 @@
+use super::ignore::Ignores;
+use super::index::{Entry, Index};
+use super::merge::PathConflict;
+use super::*;
+use std::collections::{BTreeMap, HashSet};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+// Whether the file at `path` holds what `entry` records, or is absent when
+// `entry` is `None`.
+pub fn is_unchanged(path: &Path, entry: Option<&(String, String)>) -> std::io::Result<bool> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entry.is_none()),
+        Err(e) => return Err(e),
+    };
+    let (mode, sha) = match entry {
+        Some(entry) => entry,
+        None => return Ok(false),
+    };
+    let content = if metadata.file_type().is_symlink() {
+        if mode != "120000" {
+            return Ok(false);
+        }
+        fs::read_link(path)?.to_string_lossy().into_owned().into_bytes()
+    } else if metadata.is_dir() {
+        // a checked out submodule
+        return Ok(mode == "160000");
+    } else {
+        let executable = metadata.permissions().mode() & 0o111 != 0;
+        if executable != (mode == "100755") || mode == "120000" {
+            return Ok(false);
+        }
+        fs::read(path)?
+    };
+    Ok(Blob::new(ObjectType::Blob, content).hash_string == *sha)
+}
+
+fn write_file(path: &Path, mode: &str, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    match mode {
+        "120000" => std::os::unix::fs::symlink(String::from_utf8_lossy(content).as_ref(), path),
+        _ => {
+            fs::write(path, content)?;
+            let permissions = if mode == "100755" { 0o755 } else { 0o644 };
+            fs::set_permissions(path, fs::Permissions::from_mode(permissions))
+        }
+    }
+}
+
+// Writes the blob of `entry` to `path`, converted as its attributes say, and
+// an empty directory for a submodule.
+pub fn checkout(repo: &Repository, entry: &Entry, path: &Path) -> std::io::Result<()> {
+    match entry.mode {
+        0o160000 => fs::create_dir_all(path),
+        0o120000 => write_file(path, "120000", &Blob::from_sha(repo, &entry.sha)?.content),
+        _ => {
+            let content = convert::to_worktree(repo, &entry.path, Blob::from_sha(repo, &entry.sha)?.content)?;
+            write_file(path, &entry.mode_string(), &content)
+        }
+    }
+}
+
+// removes `path` and then its parent directories up to `root` while they're empty
+fn remove_file(root: &Path, path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut dir = path.parent();
+    while let Some(parent) = dir {
+        if parent == root || parent.as_os_str().is_empty() || fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+// The mode and content of the working tree file described by `metadata`,
+// `None` for a directory.
+pub fn read_file(path: &Path, metadata: &fs::Metadata) -> std::io::Result<Option<(&'static str, Vec<u8>)>> {
+    Ok(Some(if metadata.file_type().is_symlink() {
+        ("120000", fs::read_link(path)?.to_string_lossy().into_owned().into_bytes())
+    } else if metadata.is_dir() {
+        return Ok(None);
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        ("100755", fs::read(path)?)
+    } else {
+        ("100644", fs::read(path)?)
+    }))
+}
+
+// The index entry for the working tree file `path`, with its blob written,
+// cleaned as its attributes say, and its stat data recorded.
+pub fn stage(repo: &Repository, path: &str) -> std::io::Result<Entry> {
+    let file = repo.work_tree().join(path);
+    let metadata = fs::symlink_metadata(&file)?;
+    let (mode, mut content) = read_file(&file, &metadata)?.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is a directory", path))
+    })?;
+    if mode != "120000" {
+        content = convert::to_git(repo, path, content)?;
+    }
+    let blob = Blob::new(ObjectType::Blob, content);
+    hash::write_to_database(repo, &blob)?;
+    let mut entry = Entry::new(path, mode, &blob.hash_string);
+    entry.set_stat(&metadata);
+    Ok(entry)
+}
+
+// a path with the working tree's mode and content, `None` when it is gone
+pub type Change = (String, Option<(String, Vec<u8>)>);
+
+// The merged entries of `index` that the working tree has changed. Entries marked
+// assume-unchanged or skip-worktree aren't looked at, and a file whose stat
+// data matches its entry is only read when it could have changed in the
+// same instant the index was written.
+pub fn changes(repo: &Repository, index: &Index) -> std::io::Result<Vec<Change>> {
+    let root = repo.work_tree();
+    let written = fs::metadata(repo.git_dir().join("index"))
+        .ok()
+        .map(|metadata| (metadata.mtime() as u32, metadata.mtime_nsec() as u32));
+    let mut changes = Vec::new();
+    for entry in &index.entries {
+        if entry.stage() != 0 || entry.assume_valid() || entry.skip_worktree() {
+            continue;
+        }
+        let path = root.join(&entry.path);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                changes.push((entry.path.clone(), None));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if entry.stat_matches(&metadata) && matches!(written, Some(written) if entry.mtime < written) {
+            continue;
+        }
+        match read_file(&path, &metadata)? {
+            Some((mode, content)) => {
+                if mode != entry.mode_string() || Blob::new(ObjectType::Blob, content.clone()).hash_string != entry.sha {
+                    changes.push((entry.path.clone(), Some((mode.to_string(), content))));
+                }
+            }
+            // a checked out submodule, or a directory where a file was
+            None if entry.mode == 0o160000 => {}
+            None => changes.push((entry.path.clone(), None)),
+        }
+    }
+    Ok(changes)
+}
+
+// whether `dir` holds a repository of its own, which is left alone
+fn is_repository(dir: &Path) -> bool {
+    dir.join(".git").is_file() || dir.join(".git/HEAD").is_file()
+}
+
+// What `untracked` does with a directory the index has nothing in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Directories {
+    // leaves it out, as `clean` without `-d`
+    Skip,
+    // lists it as `dir/` when everything in it is untracked, as `clean -d`
+    Whole,
+    // lists it as `dir/` when it holds an untracked file, as `status`
+    Shown,
+    // lists the untracked files in it, as `status -uall`
+    Files,
+}
+
+struct Walk<'a> {
+    root: PathBuf,
+    tracked: HashSet<&'a str>,
+    tracked_dirs: HashSet<&'a str>,
+    ignores: Option<&'a mut Ignores>,
+    directories: Directories,
+    // names are precomposed, as the index has them on macOS
+    precompose: bool,
+}
+
+impl Walk<'_> {
+    // Adds the untracked paths below `dir` to `found`, and tells whether
+    // everything there was.
+    fn dir(&mut self, dir: &str, found: &mut Vec<String>) -> std::io::Result<bool> {
+        if let Some(ignores) = self.ignores.as_mut() {
+            ignores.enter(dir)?;
+        }
+        let path = self.root.join(dir);
+        let path = if path.as_os_str().is_empty() { Path::new(".") } else { &path };
+        let mut names = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .collect::<std::io::Result<Vec<String>>>()?;
+        if self.precompose {
+            names = names.iter().map(|name| unicode::precompose(name)).collect();
+        }
+        names.sort();
+        let mut all = true;
+        for name in names {
+            if name == ".git" {
+                all = false;
+                continue;
+            }
+            let path = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+            let is_dir = fs::symlink_metadata(self.root.join(&path))?.is_dir();
+            let ignored = matches!(&self.ignores, Some(ignores) if ignores.is_ignored(&path, is_dir));
+            if self.tracked.contains(path.as_str()) || ignored {
+                all = false;
+            } else if !is_dir {
+                found.push(path);
+            } else if self.tracked_dirs.contains(path.as_str()) {
+                self.dir(&path, found)?;
+                all = false;
+            } else if is_repository(&self.root.join(&path)) {
+                match self.directories {
+                    Directories::Shown | Directories::Files => found.push(format!("{}/", path)),
+                    Directories::Skip | Directories::Whole => all = false,
+                }
+            } else {
+                match self.directories {
+                    Directories::Skip => all = false,
+                    Directories::Whole => {
+                        let mut inside = Vec::new();
+                        if self.dir(&path, &mut inside)? {
+                            found.push(format!("{}/", path));
+                        } else {
+                            found.extend(inside);
+                            all = false;
+                        }
+                    }
+                    Directories::Shown => {
+                        let mut inside = Vec::new();
+                        self.dir(&path, &mut inside)?;
+                        if !inside.is_empty() {
+                            found.push(format!("{}/", path));
+                        }
+                    }
+                    Directories::Files => {
+                        self.dir(&path, found)?;
+                    }
+                }
+            }
+        }
+        Ok(all)
+    }
+}
+
+// The paths of the working tree that `index` doesn't have and `ignores`
+// doesn't ignore, in order, untracked directories listed as `directories`
+// says.
+pub fn untracked(
+    repo: &Repository,
+    index: &Index,
+    ignores: Option<&mut Ignores>,
+    directories: Directories,
+) -> std::io::Result<Vec<String>> {
+    let mut tracked = HashSet::new();
+    let mut tracked_dirs = HashSet::new();
+    for entry in &index.entries {
+        tracked.insert(entry.path.as_str());
+        tracked_dirs.extend(entry.path.match_indices('/').map(|(i, _)| &entry.path[..i]));
+    }
+    let precompose = cfg!(target_os = "macos") && repo.config().get_bool("core.precomposeunicode")?.unwrap_or(false);
+    let mut walk = Walk { root: repo.work_tree(), tracked, tracked_dirs, ignores, directories, precompose };
+    let mut found = Vec::new();
+    walk.dir("", &mut found)?;
+    Ok(found)
+}
+
+// The files of `tracked` as the working tree has them, with their blobs
+// written. Files that are gone are left out.
+pub fn snapshot(
+    repo: &Repository,
+    tracked: &BTreeMap<String, (String, String)>,
+) -> std::io::Result<BTreeMap<String, (String, String)>> {
+    let root = repo.work_tree();
+    let mut files = BTreeMap::new();
+    for (path, entry) in tracked {
+        let metadata = match fs::symlink_metadata(root.join(path)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let (mode, content) = match read_file(&root.join(path), &metadata)? {
+            Some(file) => file,
+            None => {
+                if entry.0 == "160000" {
+                    files.insert(path.clone(), entry.clone());
+                }
+                continue;
+            }
+        };
+        let blob = Blob::new(ObjectType::Blob, content);
+        hash::write_to_database(repo, &blob)?;
+        files.insert(path.clone(), (mode.to_string(), blob.hash_string));
+    }
+    Ok(files)
+}
+
+// Makes the working tree hold the files `to` whatever it has now, as `reset
+// --hard` does: local changes are lost and the files of `from` that `to`
+// doesn't have are removed.
+pub fn reset(
+    repo: &Repository,
+    from: &BTreeMap<String, (String, String)>,
+    to: &BTreeMap<String, (String, String)>,
+) -> std::io::Result<()> {
+    let root = repo.work_tree();
+    for (path, (mode, _)) in from {
+        if mode != "160000" && !to.contains_key(path) {
+            remove_file(&root, &root.join(path))?;
+        }
+    }
+    for (path, entry) in to {
+        if entry.0 != "160000" && !is_unchanged(&root.join(path), Some(entry))? {
+            write_file(&root.join(path), &entry.0, &Blob::from_sha(repo, &entry.1)?.content)?;
+        }
+    }
+    Ok(())
+}
+
+// Moves the working tree from the files `from` to `to`, writing `conflicts`
+// with their conflict markers. Nothing is touched when a file that would
+// change has local modifications or isn't tracked.
+pub fn update(
+    repo: &Repository,
+    from: &BTreeMap<String, (String, String)>,
+    to: &BTreeMap<String, (String, String)>,
+    conflicts: &[PathConflict],
+) -> std::io::Result<()> {
+    let root = repo.work_tree();
+    let mut changed: Vec<&String> = from
+        .keys()
+        .chain(to.keys())
+        .filter(|path| from.get(*path) != to.get(*path))
+        .chain(conflicts.iter().map(|conflict| &conflict.path))
+        .collect();
+    changed.sort();
+    changed.dedup();
+    for path in &changed {
+        if matches!(from.get(*path), Some((mode, _)) if mode == "160000") {
+            continue;
+        }
+        if !is_unchanged(&root.join(path), from.get(*path))? {
+            let message = match from.get(*path) {
+                Some(_) => format!("your local changes to '{}' would be overwritten", path),
+                None => format!("the untracked file '{}' would be overwritten", path),
+            };
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message));
+        }
+    }
+    // removals first, a directory can replace a file being removed
+    for path in &changed {
+        let conflicted = conflicts.iter().any(|conflict| conflict.path == **path);
+        if !conflicted && !to.contains_key(*path) {
+            remove_file(&root, &root.join(path))?;
+        }
+    }
+    for path in changed {
+        if let Some(conflict) = conflicts.iter().find(|conflict| conflict.path == *path) {
+            write_file(&root.join(path), &conflict.mode, &conflict.content)?;
+        } else if let Some((mode, sha)) = to.get(path) {
+            if mode != "160000" {
+                write_file(&root.join(path), mode, &Blob::from_sha(repo, sha)?.content)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index::{ASSUME_VALID, SKIP_WORKTREE};
+    use super::*;
+
+    #[test]
+    fn testing_worktree_changes() {
+        let dir = TempDir::new("changes");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let blob = Blob::new(ObjectType::Blob, b"old\n".to_vec()).hash_string;
+        let mut index = Index {
+            entries: ["assumed", "changed", "gone", "same", "skipped"]
+                .iter()
+                .map(|path| Entry::new(path, "100644", &blob))
+                .collect(),
+        };
+        for entry in &index.entries {
+            let content = if entry.path == "same" { "old\n" } else { "new\n" };
+            if entry.path != "gone" {
+                fs::write(dir.join(&entry.path), content).unwrap();
+            }
+        }
+        index.entries[0].flags |= ASSUME_VALID;
+        index.entries[4].extended_flags |= SKIP_WORKTREE;
+        let changes = super::changes(&repo, &index).unwrap();
+        // matching stat data is trusted only once the index is older than the file
+        let mut stale = index.clone();
+        stale.entries[1].set_stat(&fs::symlink_metadata(dir.join("changed")).unwrap());
+        let racy = super::changes(&repo, &stale).unwrap();
+        // past the file system's timestamp granularity
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        stale.write(&repo).unwrap();
+        let trusted = super::changes(&repo, &stale).unwrap();
+
+        let paths: Vec<&str> = changes.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["changed", "gone"]);
+        assert_eq!(changes[0].1, Some(("100644".to_string(), b"new\n".to_vec())));
+        assert_eq!(changes[1].1, None);
+        assert_eq!(racy, changes);
+        assert_eq!(trusted.len(), 1);
+    }
+
+    #[test]
+    fn testing_untracked_files() {
+        let dir = TempDir::new("untracked");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        for path in ["src/deep", "build", "new/inner", "empty", "nested/.git"] {
+            fs::create_dir_all(dir.join(path)).unwrap();
+        }
+        let files = ["tracked", "src/main.rs", "src/deep/x.o", "src/deep/junk", "build/out", "new/inner/f", "keep.o", "a.o"];
+        for path in files.iter().chain(&["nested/.git/HEAD"]) {
+            fs::write(dir.join(path), "").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "build/\n*.o\n!keep.o\n").unwrap();
+        fs::write(dir.join("src/.gitignore"), "!x.o\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let blob = Blob::new(ObjectType::Blob, Vec::new()).hash_string;
+        let index = Index { entries: vec![Entry::new("src/main.rs", "100644", &blob), Entry::new("tracked", "100644", &blob)] };
+        let files = untracked(&repo, &index, Some(&mut Ignores::new(&repo).unwrap()), Directories::Skip).unwrap();
+        let directories = untracked(&repo, &index, Some(&mut Ignores::new(&repo).unwrap()), Directories::Whole).unwrap();
+        let everything = untracked(&repo, &index, None, Directories::Whole).unwrap();
+        let shown = untracked(&repo, &index, Some(&mut Ignores::new(&repo).unwrap()), Directories::Shown).unwrap();
+        let all = untracked(&repo, &index, Some(&mut Ignores::new(&repo).unwrap()), Directories::Files).unwrap();
+
+        assert_eq!(files, vec![".gitignore", "keep.o", "src/.gitignore"]);
+        assert_eq!(directories, vec![".gitignore", "empty/", "keep.o", "new/", "src/.gitignore", "src/deep/"]);
+        assert_eq!(everything, vec![".gitignore", "a.o", "build/", "empty/", "keep.o", "new/", "src/.gitignore", "src/deep/"]);
+        assert_eq!(shown, vec![".gitignore", "keep.o", "nested/", "new/", "src/.gitignore", "src/deep/"]);
+        let listed = vec![".gitignore", "keep.o", "nested/", "new/inner/f", "src/.gitignore", "src/deep/junk", "src/deep/x.o"];
+        assert_eq!(all, listed);
+    }
+}
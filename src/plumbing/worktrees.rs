@@ -0,0 +1,217 @@
+use super::commit::Commit;
+use super::*;
+
+// One of the work trees of a repository, the main one or a linked one.
+pub struct Worktree {
+    pub path: PathBuf,
+    pub git_dir: PathBuf,
+    // the commit checked out, `None` on an unborn branch
+    pub head: Option<String>,
+    // the branch checked out, `None` when HEAD is detached
+    pub branch: Option<String>,
+}
+
+// The main worktree first, then the linked ones by path, each at the path
+// its `worktrees/<name>/gitdir` points back at.
+pub fn list(repo: &Repository) -> std::io::Result<Vec<Worktree>> {
+    let common_dir = fs::canonicalize(repo.common_dir())?;
+    let main = common_dir.parent().map(Path::to_path_buf).unwrap_or_else(|| common_dir.clone());
+    let mut found = vec![(main, common_dir.clone())];
+    let names = match fs::read_dir(common_dir.join("worktrees")) {
+        Ok(entries) => entries.map(|entry| Ok(entry?.file_name())).collect::<std::io::Result<Vec<_>>>()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    for name in names {
+        let git_dir = common_dir.join("worktrees").join(name);
+        let dot_git = match fs::read_to_string(git_dir.join("gitdir")) {
+            Ok(dot_git) => PathBuf::from(dot_git.trim_end_matches(['\n', '\r'])),
+            // not a worktree git made, or one half removed
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        found.push((dot_git.parent().map(Path::to_path_buf).unwrap_or_default(), git_dir));
+    }
+    found[1..].sort();
+    let mut worktrees = Vec::new();
+    for (path, git_dir) in found {
+        let opened = Repository::open(&git_dir)?;
+        let head = refs::resolve(&opened, "HEAD")?;
+        let branch = refs::symbolic_target(&opened, "HEAD")?;
+        worktrees.push(Worktree { path, git_dir, head, branch });
+    }
+    Ok(worktrees)
+}
+
+// Checks `commit` out in a new worktree at `path`, which must not exist
+// or be empty, with its metadata in `worktrees/<name>` under the common
+// dir. With `new_branch` the commit goes on a new branch first. A
+// `commit` naming a branch checks out the branch unless `detach`; without
+// a `commit` the branch named like the last component of `path` is
+// checked out, and created at HEAD if there isn't one.
+pub fn add(
+    repo: &Repository,
+    path: &Path,
+    commit: Option<&str>,
+    new_branch: Option<&str>,
+    detach: bool,
+) -> std::io::Result<()> {
+    let invalid = |kind, message: String| std::io::Error::new(kind, message);
+    let occupied = match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(_) => true,
+    };
+    if occupied {
+        return Err(invalid(std::io::ErrorKind::AlreadyExists, format!("'{}' already exists", path.display())));
+    }
+    let base = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| invalid(std::io::ErrorKind::InvalidInput, format!("'{}' is not a valid path", path.display())))?;
+    let existing = |name: &str| -> std::io::Result<bool> {
+        Ok(refs::is_valid_name(name) && refs::resolve(repo, &format!("refs/heads/{}", name))?.is_some())
+    };
+    // the branch to check out, whether it is a new one, and the commit
+    let (branch, create, sha) = match (new_branch, commit) {
+        (Some(branch), commit) => {
+            if !refs::is_valid_name(&format!("refs/heads/{}", branch)) {
+                return Err(invalid(std::io::ErrorKind::InvalidInput, format!("'{}' is not a valid branch name", branch)));
+            }
+            if existing(branch)? {
+                let message = format!("a branch named '{}' already exists", branch);
+                return Err(invalid(std::io::ErrorKind::AlreadyExists, message));
+            }
+            let sha = Commit::peel(repo, &revision::resolve(repo, commit.unwrap_or("HEAD"))?)?;
+            (Some(branch.to_string()), true, sha)
+        }
+        (None, Some(branch)) if !detach && existing(branch)? => {
+            (Some(branch.to_string()), false, refs::object_id(repo, &format!("refs/heads/{}", branch))?)
+        }
+        (None, None) if !detach && existing(&base)? => {
+            (Some(base.clone()), false, refs::object_id(repo, &format!("refs/heads/{}", base))?)
+        }
+        (None, None) if !detach => (Some(base.clone()), true, Commit::peel(repo, &revision::resolve(repo, "HEAD")?)?),
+        (None, commit) => (None, false, Commit::peel(repo, &revision::resolve(repo, commit.unwrap_or("HEAD"))?)?),
+    };
+    match (&branch, create) {
+        (Some(branch), true) => eprintln!("Preparing worktree (new branch '{}')", branch),
+        (Some(branch), false) => eprintln!("Preparing worktree (checking out '{}')", branch),
+        (None, _) => eprintln!("Preparing worktree (detached HEAD {})", &sha[..7]),
+    }
+    if let Some(branch) = &branch {
+        let full = format!("refs/heads/{}", branch);
+        if let Some(worktree) = list(repo)?.iter().find(|worktree| worktree.branch.as_deref() == Some(&full)) {
+            let message = format!("'{}' is already checked out at '{}'", branch, worktree.path.display());
+            return Err(invalid(std::io::ErrorKind::AlreadyExists, message));
+        }
+    }
+    // the name is the last component of the path, numbered when taken
+    let worktrees = fs::canonicalize(repo.common_dir())?.join("worktrees");
+    let mut git_dir = worktrees.join(&base);
+    let mut number = 1;
+    while fs::symlink_metadata(&git_dir).is_ok() {
+        git_dir = worktrees.join(format!("{}{}", base, number));
+        number += 1;
+    }
+    fs::create_dir_all(&git_dir)?;
+    fs::create_dir_all(path)?;
+    let path = fs::canonicalize(path)?;
+    fs::write(git_dir.join("gitdir"), format!("{}\n", path.join(GIT_DIR).display()))?;
+    fs::write(git_dir.join("commondir"), "../..\n")?;
+    match &branch {
+        Some(branch) => fs::write(git_dir.join("HEAD"), format!("ref: refs/heads/{}\n", branch))?,
+        None => fs::write(git_dir.join("HEAD"), format!("{}\n", sha))?,
+    }
+    match &branch {
+        Some(branch) if create => refs::update(repo, &format!("refs/heads/{}", branch), &sha, None)?,
+        _ => {}
+    }
+    fs::write(path.join(GIT_DIR), format!("gitdir: {}\n", git_dir.display()))?;
+    let linked = Repository::open(path.join(GIT_DIR))?;
+    reset::read_tree(&linked, &sha, true, true)?;
+    outln!("HEAD is now at {} {}", &sha[..7], Commit::read(repo, &sha)?.subject())?;
+    Ok(())
+}
+
+// Prints each worktree as `<path> <commit> [<branch>]`, the paths padded to
+// the longest, or with `porcelain` as a `worktree`, `HEAD`, `branch` or
+// `detached` block of lines each.
+pub fn print(repo: &Repository, porcelain: bool) -> std::io::Result<()> {
+    let worktrees = list(repo)?;
+    let width = worktrees.iter().map(|worktree| worktree.path.display().to_string().len()).max().unwrap_or(0);
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for worktree in worktrees {
+        let head = worktree.head.as_deref().unwrap_or("0000000000000000000000000000000000000000");
+        if porcelain {
+            writeln!(out, "worktree {}", worktree.path.display())?;
+            writeln!(out, "HEAD {}", head)?;
+            match &worktree.branch {
+                Some(branch) => writeln!(out, "branch {}", branch)?,
+                None => writeln!(out, "detached")?,
+            }
+            writeln!(out)?;
+            continue;
+        }
+        let branch = match &worktree.branch {
+            Some(branch) => format!("[{}]", branch.strip_prefix("refs/heads/").unwrap_or(branch)),
+            None => "(detached HEAD)".to_string(),
+        };
+        let path = worktree.path.display().to_string();
+        writeln!(out, "{:<width$}  {} {}", path, &head[..7], branch, width = width)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testing_worktree_add() {
+        let dir = TempDir::new("worktrees");
+        let git_dir = dir.join("main/.git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        let repo = Repository::open(&git_dir).unwrap();
+        let file = repo.write_blob(b"file\n").unwrap();
+        let entry = TreeEntry {
+            mode: "100644".to_string(),
+            entry_type: EntryType::Blob,
+            sha: Blob::decode_hex(&file).unwrap().try_into().unwrap(),
+            name: "file".to_string(),
+        };
+        let tree = repo.write_tree(&[entry]).unwrap();
+        let commit = test_commit(&repo, &tree, &[] as &[&str], 0, "one\n");
+        refs::update(&repo, "refs/heads/master", &commit, None).unwrap();
+
+        add(&repo, &dir.join("topic"), None, None, false).unwrap();
+        add(&repo, &dir.join("detached"), Some("master"), None, true).unwrap();
+        let taken = add(&repo, &dir.join("again"), Some("master"), None, false).unwrap_err().kind();
+        let exists = add(&repo, &dir.join("topic"), None, Some("other"), false).unwrap_err().kind();
+        let checked_out = fs::read_to_string(dir.join("topic/file")).unwrap();
+        let linked = Repository::open(dir.join("topic/.git")).unwrap();
+        let linked_head = refs::symbolic_target(&linked, "HEAD").unwrap();
+        let topic = refs::resolve(&repo, "refs/heads/topic").unwrap();
+        let listed: Vec<(String, Option<String>)> = list(&linked)
+            .unwrap()
+            .into_iter()
+            .map(|worktree| (worktree.path.file_name().unwrap().to_string_lossy().into_owned(), worktree.branch))
+            .collect();
+
+        assert_eq!(taken, std::io::ErrorKind::AlreadyExists);
+        assert_eq!(exists, std::io::ErrorKind::AlreadyExists);
+        assert_eq!(checked_out, "file\n");
+        assert_eq!(linked_head.as_deref(), Some("refs/heads/topic"));
+        assert_eq!(topic.as_deref(), Some(commit.as_str()));
+        assert_eq!(
+            listed,
+            vec![
+                ("main".to_string(), Some("refs/heads/master".to_string())),
+                ("detached".to_string(), None),
+                ("topic".to_string(), Some("refs/heads/topic".to_string())),
+            ]
+        );
+    }
+}
@@ -0,0 +1,630 @@
+// Round trips between this crate and the real `git`: each writes objects the
+// other reads, and both must agree on every id. Skipped when `git` isn't on
+// the PATH.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn has_git() -> bool {
+    matches!(Command::new("git").arg("--version").output(), Ok(output) if output.status.success())
+}
+
+// a command run in `dir`, away from the user's and the system's git config
+fn command(program: &str, dir: &Path) -> Command {
+    let mut command = Command::new(program);
+    command
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join(".config"))
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_WORK_TREE")
+        .env_remove("GIT_INDEX_FILE")
+        .env_remove("GIT_OBJECT_DIRECTORY");
+    command
+}
+
+// the standard output of `program`, which has to succeed
+fn run(program: &str, dir: &Path, args: &[&str]) -> Vec<u8> {
+    let output = command(program, dir).args(args).output().unwrap();
+    assert!(output.status.success(), "{} {:?} failed: {}", program, args, String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+// the standard output of `program` given `input`, which has to succeed
+fn run_with_input(program: &str, dir: &Path, args: &[&str], input: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut child = command(program, dir)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success(), "{} {:?} failed: {}", program, args, String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+fn line(output: Vec<u8>) -> String {
+    String::from_utf8(output).unwrap().trim_end().to_string()
+}
+
+fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+    run("git", dir, args)
+}
+
+fn rust_git(dir: &Path, args: &[&str]) -> Vec<u8> {
+    run(env!("CARGO_BIN_EXE_git-starter-rust"), dir, args)
+}
+
+// Serves the repository `dir` over smart http on a port of its own, the way
+// `git http-backend` does, running `git upload-pack` for each request. Returns
+// the url of the repository.
+fn serve(dir: &Path) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/repo", listener.local_addr().unwrap());
+    let dir = dir.to_path_buf();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => respond(&dir, stream),
+                Err(_) => break,
+            }
+        }
+    });
+    url
+}
+
+fn respond(dir: &Path, mut stream: std::net::TcpStream) {
+    use std::io::{BufRead, Read, Write};
+    let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+    let mut request = String::new();
+    reader.read_line(&mut request).unwrap();
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    let (content_type, response) = match request.split(' ').nth(1).unwrap_or_default() {
+        "/repo/info/refs?service=git-upload-pack" => {
+            let mut response = b"001e# service=git-upload-pack\n0000".to_vec();
+            response.extend(git(dir, &["upload-pack", "--stateless-rpc", "--advertise-refs", "."]));
+            ("application/x-git-upload-pack-advertisement", response)
+        }
+        "/repo/git-upload-pack" => {
+            ("application/x-git-upload-pack-result", run_with_input("git", dir, &["upload-pack", "--stateless-rpc", "."], &body))
+        }
+        _ => {
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+            return;
+        }
+    };
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        content_type,
+        response.len()
+    );
+    stream.write_all(head.as_bytes()).unwrap();
+    stream.write_all(&response).unwrap();
+}
+
+const PATHS: [&str; 4] = ["README", "src/main.rs", "src/empty", "binary"];
+
+// A directory of its own for a test, removed when it goes out of scope, even
+// when the test fails.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rust-git-interop-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn repository(name: &str) -> TempDir {
+    let dir = TempDir::new(name);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("README"), "hello\n").unwrap();
+    fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.join("src/empty"), "").unwrap();
+    fs::write(dir.join("binary"), b"\0\x01 space\0\n").unwrap();
+    dir
+}
+
+#[test]
+fn testing_objects_git_writes() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("from-git");
+    git(&dir, &["init", "-q"]);
+    git(&dir, &["add", "."]);
+    let tree = line(git(&dir, &["write-tree"]));
+    let commit = line(git(&dir, &["-c", "user.name=a", "-c", "user.email=a@b", "commit-tree", &tree, "-m", "first"]));
+    let blobs: Vec<String> = PATHS.iter().map(|path| line(git(&dir, &["hash-object", "-w", path]))).collect();
+
+    let hashed: Vec<String> = PATHS.iter().map(|path| line(rust_git(&dir, &["hash-object", path]))).collect();
+    let written = line(rust_git(&dir, &["write-tree"]));
+    let printed: Vec<bool> = blobs
+        .iter()
+        .chain([&tree, &commit])
+        .map(|sha| rust_git(&dir, &["cat-file", "-p", sha]) == git(&dir, &["cat-file", "-p", sha]))
+        .collect();
+    // flags given together, as `-r -l` and `-r -l -z`
+    let listed: Vec<bool> =
+        ["-rl", "-rlz"].iter().map(|flags| rust_git(&dir, &["ls-tree", flags, &tree]) == git(&dir, &["ls-tree", flags, &tree])).collect();
+
+    assert_eq!(hashed, blobs);
+    assert_eq!(written, tree);
+    assert_eq!(printed, vec![true; 6]);
+    assert_eq!(listed, vec![true; 2]);
+}
+
+#[test]
+fn testing_objects_git_reads() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("to-git");
+    rust_git(&dir, &["init"]);
+    let mut blobs = Vec::new();
+    for path in PATHS {
+        blobs.push(line(rust_git(&dir, &["hash-object", "-w", path])));
+        rust_git(&dir, &["update-index", "--add", path]);
+    }
+    let tree = line(rust_git(&dir, &["write-tree"]));
+
+    let hashed: Vec<String> = PATHS.iter().map(|path| line(git(&dir, &["hash-object", path]))).collect();
+    let types: Vec<String> = blobs.iter().chain([&tree]).map(|sha| line(git(&dir, &["cat-file", "-t", sha]))).collect();
+    let content = git(&dir, &["cat-file", "blob", &blobs[3]]);
+    let git_tree = line(git(&dir, &["write-tree"]));
+    git(&dir, &["fsck", "--strict", "--no-dangling", "--no-progress"]);
+
+    assert_eq!(hashed, blobs);
+    assert_eq!(types, vec!["blob", "blob", "blob", "blob", "tree"]);
+    assert_eq!(content, b"\0\x01 space\0\n");
+    assert_eq!(git_tree, tree);
+}
+
+#[test]
+fn testing_packed_git_reads() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("packed");
+    let identity = ["-c", "user.name=a", "-c", "user.email=a@b"];
+    git(&dir, &["init", "-q"]);
+    for i in 0..3 {
+        fs::write(dir.join("README"), "hello\n".repeat(50 + i)).unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &[&identity[..], &["commit", "-q", "-m", &format!("commit {}", i)]].concat());
+    }
+    git(&dir, &["repack", "-adq"]);
+    let url = format!("file://{}", dir.display());
+    let shallow = dir.join("shallow");
+    git(&dir, &["clone", "-q", "--depth", "1", &url, shallow.to_str().unwrap()]);
+    // a clone sharing the packed objects through its alternates
+    let shared = dir.join("shared");
+    git(&dir, &["clone", "-q", "--shared", ".", shared.to_str().unwrap()]);
+
+    let logs: Vec<bool> = [&*dir, &shallow, &shared].iter().map(|dir| rust_git(dir, &["log"]) == git(dir, &["log"])).collect();
+    let objects = ["cat-file", "--batch-all-objects", "--batch-check"];
+    let listed = rust_git(&dir, &objects) == git(&dir, &objects);
+    let blob = rust_git(&shared, &["cat-file", "-p", "HEAD:README"]);
+
+    assert_eq!(logs, vec![true; 3]);
+    assert!(listed);
+    assert_eq!(blob, "hello\n".repeat(52).as_bytes());
+}
+
+#[test]
+fn testing_packed_batch() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    // thousands of objects in one pack, many of them deltas in long chains
+    let dir = repository("packed-batch");
+    git(&dir, &["init", "-q"]);
+    fs::create_dir_all(dir.join("objects")).unwrap();
+    for i in 0..2000 {
+        fs::write(dir.join(format!("objects/{}", i)), format!("{}{}\n", "shared line\n".repeat(100), i)).unwrap();
+    }
+    git(&dir, &["add", "."]);
+    git(&dir, &["-c", "user.name=a", "-c", "user.email=a@b", "commit", "-q", "-m", "many"]);
+    git(&dir, &["repack", "-adq", "--depth=50"]);
+    let ids = git(&dir, &["cat-file", "--batch-all-objects", "--batch-check=%(objectname)"]);
+    // the ids in another order than the pack's, with one that's missing
+    let mut asked: Vec<&[u8]> = ids.split(|&b| b == b'\n').filter(|id| !id.is_empty()).rev().collect();
+    asked.insert(1000, b"0000000000000000000000000000000000000000");
+    let input = asked.join(&b'\n');
+
+    let batch = ["cat-file", "--batch"];
+    let ours = run_with_input(env!("CARGO_BIN_EXE_git-starter-rust"), &dir, &batch, &input);
+    let theirs = run_with_input("git", &dir, &batch, &input);
+    let loose = fs::read_dir(dir.join(".git/objects")).unwrap().count();
+
+    // only `pack` and `info` are left
+    assert_eq!(loose, 2);
+    assert!(ours == theirs, "cat-file --batch differs from git's");
+}
+
+#[test]
+fn testing_http_fetch() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("http-fetch");
+    let identity = ["-c", "user.name=a", "-c", "user.email=a@b"];
+    let commit = |message: &str| {
+        fs::write(dir.join("README"), message).unwrap();
+        git(&dir, &["add", "README"]);
+        git(&dir, &[&identity[..], &["commit", "-q", "-m", message]].concat());
+    };
+    git(&dir, &["init", "-q", "-b", "main"]);
+    commit("first");
+    commit("second");
+    git(&dir, &[&identity[..], &["tag", "-a", "v1", "-m", "v1"]].concat());
+    let url = serve(&dir);
+    let local = dir.join("local");
+    git(&dir, &["init", "-q", local.to_str().unwrap()]);
+    git(&local, &["config", "remote.origin.url", &url]);
+    git(&local, &["config", "remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*"]);
+    git(&local, &["config", "--add", "remote.origin.fetch", "refs/tags/*:refs/tags/*"]);
+
+    rust_git(&local, &["fetch"]);
+    let packed = |dir: &Path| {
+        let counted = String::from_utf8(git(dir, &["count-objects", "-v"])).unwrap();
+        counted.lines().find_map(|line| line.strip_prefix("in-pack: ").map(|count| count.parse::<usize>().unwrap())).unwrap()
+    };
+    let first = packed(&local);
+    commit("third");
+    rust_git(&local, &["fetch"]);
+    let second = packed(&local);
+    let fetched: Vec<bool> = ["refs/remotes/origin/main", "refs/tags/v1"]
+        .iter()
+        .map(|name| git(&local, &["rev-parse", name]) == git(&dir, &["rev-parse", &name.replace("remotes/origin", "heads")]))
+        .collect();
+    git(&local, &["fsck", "--strict"]);
+    let wrong = command(env!("CARGO_BIN_EXE_git-starter-rust"), &local)
+        .args(["fetch", "elsewhere"])
+        .output()
+        .unwrap();
+    git(&local, &["config", "remote.missing.url", &url.replace("/repo", "/nothing")]);
+    let missing = command(env!("CARGO_BIN_EXE_git-starter-rust"), &local).args(["fetch", "missing"]).output().unwrap();
+
+    assert_eq!(fetched, vec![true; 2]);
+    // two commits, a tag, two trees and two blobs, then only the new commit, tree and blob
+    assert_eq!((first, second), (7, 10));
+    assert!(!wrong.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("returned error: 404"), "{:?}", missing);
+}
+
+#[test]
+fn testing_http_shallow_clone() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("http-shallow-clone");
+    let identity = ["-c", "user.name=a", "-c", "user.email=a@b"];
+    git(&dir, &["init", "-q", "-b", "main"]);
+    for message in ["first", "second", "third"].iter() {
+        fs::write(dir.join("README"), message).unwrap();
+        git(&dir, &["add", "README", "src"]);
+        git(&dir, &[&identity[..], &["commit", "-q", "-m", message]].concat());
+    }
+    let url = serve(&dir);
+    let clones = dir.join("clones");
+    fs::create_dir(&clones).unwrap();
+
+    git(&clones, &["clone", "-q", "--depth", "2", &url, "by-git"]);
+    rust_git(&clones, &["clone", "--depth", "2", &url, "by-rust-git"]);
+    let (by_git, by_rust_git) = (clones.join("by-git"), clones.join("by-rust-git"));
+    let shallow = [&by_git, &by_rust_git].map(|clone| fs::read_to_string(clone.join(".git/shallow")).unwrap());
+    let logs = [&by_git, &by_rust_git].map(|clone| git(clone, &["log", "--format=%H %P"]));
+    let own_log = rust_git(&by_rust_git, &["log", "--format=%H"]);
+    git(&by_rust_git, &["fsck", "--strict"]);
+    let status = git(&by_rust_git, &["status", "--porcelain"]);
+    let branch = line(git(&by_rust_git, &["symbolic-ref", "HEAD"]));
+    let readme = fs::read_to_string(by_rust_git.join("README")).unwrap();
+
+    assert_eq!(shallow[0], shallow[1]);
+    assert_eq!(shallow[1].lines().count(), 1);
+    assert_eq!(logs[0], logs[1]);
+    assert_eq!(String::from_utf8(own_log).unwrap().lines().count(), 2);
+    assert!(status.is_empty(), "{}", String::from_utf8_lossy(&status));
+    assert_eq!(branch, "refs/heads/main");
+    assert_eq!(readme, "third");
+}
+
+#[test]
+fn testing_discovery() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("discovery");
+    git(&dir, &["init", "-q"]);
+    git(&dir, &["add", "."]);
+    git(&dir, &["-c", "user.name=a", "-c", "user.email=a@b", "commit", "-q", "-m", "first"]);
+    let linked = TempDir::new("discovery-linked");
+    git(&dir, &["worktree", "add", "-q", "-b", "linked", linked.to_str().unwrap()]);
+
+    // the linked worktree has a HEAD of its own
+    fs::write(linked.join("README"), "linked\n").unwrap();
+    git(&linked, &["-c", "user.name=a", "-c", "user.email=a@b", "commit", "-q", "-a", "-m", "linked"]);
+    let head = line(git(&dir, &["rev-parse", "HEAD"]));
+    let linked_commit = line(git(&linked, &["rev-parse", "HEAD"]));
+    // from below the top of the working tree, and in a linked worktree, whose
+    // `.git` is a file
+    let from_below = line(rust_git(&dir.join("src"), &["rev-parse", "HEAD"]));
+    let linked_head = line(rust_git(&linked.join("src"), &["rev-parse", "HEAD"]));
+
+    assert_eq!(from_below, head);
+    assert_eq!(linked_head, linked_commit);
+    assert_ne!(linked_commit, head);
+}
+
+// `update-index` stages the same entries as git: a blob given by id with no
+// file behind it, and the executable bit set and cleared.
+#[test]
+fn testing_update_index() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let stage = |dir: &Path, update_index: fn(&Path, &[&str]) -> Vec<u8>| {
+        git(dir, &["init", "-q"]);
+        let blob = line(git(dir, &["hash-object", "-w", "README"]));
+        update_index(dir, &["update-index", "--add", "--cacheinfo", &format!("100644,{},copy/README", blob)]);
+        update_index(dir, &["update-index", "--add", "--cacheinfo", "100755", &blob, "other/README"]);
+        update_index(dir, &["update-index", "--add", "README", "src/main.rs", "binary"]);
+        update_index(dir, &["update-index", "--chmod=+x", "src/main.rs", "binary"]);
+        update_index(dir, &["update-index", "--chmod=-x", "binary"]);
+        git(dir, &["ls-files", "-s"])
+    };
+    let ours = repository("update-index");
+    let theirs = repository("update-index-git");
+
+    let staged = String::from_utf8(stage(&ours, rust_git)).unwrap();
+    let git_staged = String::from_utf8(stage(&theirs, git)).unwrap();
+
+    assert_eq!(staged, git_staged);
+    let modes: Vec<&str> = staged.lines().map(|line| &line[..6]).collect();
+    assert_eq!(modes, vec!["100644", "100644", "100644", "100755", "100755"]);
+}
+
+// the files below `dir`, sorted, with their content and whether they are
+// executable
+fn files(dir: &Path) -> Vec<(PathBuf, Vec<u8>, bool)> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(next) = dirs.pop() {
+        for entry in fs::read_dir(next).unwrap() {
+            let path = entry.unwrap().path();
+            let metadata = fs::metadata(&path).unwrap();
+            if metadata.is_dir() {
+                dirs.push(path);
+            } else {
+                let executable = metadata.permissions().mode() & 0o111 != 0;
+                files.push((path.strip_prefix(dir).unwrap().to_path_buf(), fs::read(&path).unwrap(), executable));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+// `checkout-index` writes out the index as git does, below a prefix or in
+// place, and leaves files that are there alone unless forced.
+#[test]
+fn testing_checkout_index() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("checkout-index");
+    git(&dir, &["init", "-q"]);
+    git(&dir, &["add", "."]);
+    git(&dir, &["update-index", "--chmod=+x", "src/main.rs"]);
+
+    rust_git(&dir, &["checkout-index", "-a", "--prefix=export/"]);
+    git(&dir, &["checkout-index", "-a", "--prefix=git-export/"]);
+    let exported = files(&dir.join("export"));
+    let git_exported = files(&dir.join("git-export"));
+    fs::write(dir.join("README"), "changed\n").unwrap();
+    fs::remove_file(dir.join("src/main.rs")).unwrap();
+    let kept = command(env!("CARGO_BIN_EXE_git-starter-rust"), &dir).args(["checkout-index", "README"]).output().unwrap();
+    let kept_readme = fs::read_to_string(dir.join("README")).unwrap();
+    rust_git(&dir, &["checkout-index", "src/main.rs"]);
+    rust_git(&dir, &["checkout-index", "-f", "README"]);
+    let changed = git(&dir, &["diff", "--name-status"]);
+
+    assert_eq!(exported, git_exported);
+    assert_eq!(exported.iter().filter(|(_, _, executable)| *executable).count(), 1);
+    assert_eq!(kept.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&kept.stderr), "README already exists, no checkout\n");
+    assert_eq!(kept_readme, "changed\n");
+    assert!(changed.is_empty(), "{}", String::from_utf8_lossy(&changed));
+}
+
+// `for-each-ref --format` expands every atom as git does, for commits and
+// annotated tags whose subjects run over more than one line.
+#[test]
+fn testing_for_each_ref_formats() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("for-each-ref");
+    let identity = ["-c", "user.name=a", "-c", "user.email=a@b"];
+    git(&dir, &["init", "-q", "-b", "main"]);
+    git(&dir, &["add", "."]);
+    git(&dir, &[&identity[..], &["commit", "-q", "-m", "first line\nsecond line\n\nbody"]].concat());
+    git(&dir, &[&identity[..], &["tag", "-a", "v1", "-m", "tag subject\ncontinued\n\ntag body"]].concat());
+    git(&dir, &["tag", "light"]);
+    git(&dir, &["branch", "side"]);
+    let formats = [
+        None,
+        Some("--format=%(refname) %(refname:short) %(objecttype) %(objectname)"),
+        Some("--format=%(objectname:short) %(subject)"),
+        Some("--format=x%%y %(authordate)"),
+    ];
+    let patterns: [&[&str]; 3] = [&[], &["refs/tags"], &["refs/heads/m*"]];
+
+    let mut listed = Vec::new();
+    for format in &formats {
+        for patterns in &patterns {
+            let mut args = vec!["for-each-ref"];
+            args.extend(format);
+            args.extend(patterns.iter());
+            listed.push((String::from_utf8(rust_git(&dir, &args)).unwrap(), String::from_utf8(git(&dir, &args)).unwrap()));
+        }
+    }
+    let subjects = String::from_utf8(rust_git(&dir, &["for-each-ref", "--format=%(subject)"])).unwrap();
+
+    for (ours, theirs) in &listed {
+        assert_eq!(ours, theirs);
+    }
+    assert_eq!(subjects, "first line second line\nfirst line second line\nfirst line second line\ntag subject continued\n");
+}
+
+// `check-attr -a` lists the attributes of each path in the order git does,
+// that in which they are first defined, whatever the rules' precedence.
+#[test]
+fn testing_check_attr_all() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("check-attr");
+    git(&dir, &["init", "-q"]);
+    fs::write(dir.join(".gitattributes"), "* zeta text=auto\n*.png binary\n/README eol=crlf alpha\n*.rs diff=rust -text\n").unwrap();
+    fs::write(dir.join("src/.gitattributes"), "*.rs beta !zeta\n").unwrap();
+    fs::write(dir.join(".git/info/attributes"), "*.rs gamma\n").unwrap();
+    let args = ["check-attr", "-a", "src/main.rs", "README", "a.png", "binary"];
+
+    let listed = String::from_utf8(rust_git(&dir, &args)).unwrap();
+    let git_listed = String::from_utf8(git(&dir, &args)).unwrap();
+
+    assert_eq!(listed, git_listed);
+    assert!(listed.starts_with("src/main.rs: diff: rust\nsrc/main.rs: text: unset\nsrc/main.rs: gamma: set\n"));
+}
+
+// `check-ignore` names the same paths and rules as git, with `-v`, `-n` and
+// `-q`, and exits with the same status.
+#[test]
+fn testing_check_ignore() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    let dir = repository("check-ignore");
+    git(&dir, &["init", "-q"]);
+    fs::write(dir.join(".gitignore"), "*.log\n!keep.log\nbuild/\nREADME\n").unwrap();
+    fs::write(dir.join("src/.gitignore"), "# generated\n*.tmp\n").unwrap();
+    fs::write(dir.join(".git/info/exclude"), "secret\n").unwrap();
+    fs::create_dir_all(dir.join("build")).unwrap();
+    git(&dir, &["add", "-f", "README"]);
+    let paths = ["a.log", "keep.log", "build/out", "src/x.tmp", "secret", "README", "plain.txt"];
+    let runs: Vec<Vec<&str>> = vec![
+        paths.to_vec(),
+        [&["-v"][..], &paths].concat(),
+        [&["-v", "-n"][..], &paths].concat(),
+        [&["--no-index", "-v"][..], &paths].concat(),
+        vec!["-q", "a.log"],
+        vec!["-q", "plain.txt"],
+        vec!["-v", "keep.log"],
+        vec!["plain.txt", "README"],
+    ];
+
+    let checked: Vec<_> = runs
+        .iter()
+        .map(|args| {
+            let args: Vec<&str> = ["check-ignore"].iter().chain(args).copied().collect();
+            let ours = command(env!("CARGO_BIN_EXE_git-starter-rust"), &dir).args(&args).output().unwrap();
+            let theirs = command("git", &dir).args(&args).output().unwrap();
+            let output = |output: std::process::Output| (output.status.code(), String::from_utf8(output.stdout).unwrap());
+            (args, output(ours), output(theirs))
+        })
+        .collect();
+
+    for (args, ours, theirs) in &checked {
+        assert_eq!(ours, theirs, "{:?}", args);
+    }
+}
+
+// A reader that stops early, like `head`, ends the output without a word.
+#[test]
+fn testing_closed_pipe() {
+    if !has_git() {
+        eprintln!("git isn't on the PATH, skipping");
+        return;
+    }
+    use std::io::BufRead;
+    let dir = repository("closed-pipe");
+    git(&dir, &["init", "-q"]);
+    git(&dir, &["add", "."]);
+    // more than a pipe holds, so that the reader is gone before the end
+    let subject = "x".repeat(1000);
+    for _ in 0..200 {
+        git(&dir, &["-c", "user.name=a", "-c", "user.email=a@b", "commit", "-q", "--allow-empty", "-m", &subject]);
+    }
+    let outputs: Vec<_> = [&["log"][..], &["log", "--format=%H %s"]]
+        .iter()
+        .map(|args| {
+            let mut child = command(env!("CARGO_BIN_EXE_git-starter-rust"), &dir)
+                .args(*args)
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            let mut first = String::new();
+            std::io::BufReader::new(child.stdout.take().unwrap()).read_line(&mut first).unwrap();
+            child.wait_with_output().unwrap()
+        })
+        .collect();
+
+    for output in outputs {
+        assert_eq!(output.status.code(), Some(141));
+        assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+}